@@ -0,0 +1,122 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Throughput of `check_and_aggregate_receipts`, including the verification
+//! of the receipt signatures, for v1 and v2 receipts.
+//!
+//! Receipts are generated from a fixed seed so that runs can be compared.
+
+use std::collections::HashSet;
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, FixedBytes},
+    signers::local::PrivateKeySigner,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tap_aggregator::aggregator::{self, AggregationOptions};
+use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+use tap_graph::{v2, Receipt};
+
+const RECEIPT_COUNTS: [usize; 3] = [100, 1_000, 10_000];
+const SEED: u64 = 42;
+
+fn wallet(rng: &mut StdRng) -> PrivateKeySigner {
+    PrivateKeySigner::from_bytes(&FixedBytes::from(rng.gen::<[u8; 32]>())).unwrap()
+}
+
+/// Generates `count` signed v1 receipts, always the same for a given seed.
+fn v1_receipts(
+    domain_separator: &Eip712Domain,
+    wallet: &PrivateKeySigner,
+    rng: &mut StdRng,
+    count: usize,
+) -> Vec<Eip712SignedMessage<Receipt>> {
+    let allocation_id = Address::from(rng.gen::<[u8; 20]>());
+    (0..count)
+        .map(|i| {
+            let receipt = Receipt {
+                allocation_id,
+                timestamp_ns: i as u64 + 1,
+                nonce: rng.gen(),
+                value: rng.gen_range(1..1_000_000),
+            };
+            Eip712SignedMessage::new(domain_separator, receipt, wallet).unwrap()
+        })
+        .collect()
+}
+
+/// Generates `count` signed v2 receipts, always the same for a given seed.
+fn v2_receipts(
+    domain_separator: &Eip712Domain,
+    wallet: &PrivateKeySigner,
+    rng: &mut StdRng,
+    count: usize,
+) -> Vec<Eip712SignedMessage<v2::Receipt>> {
+    let allocation_id = Address::from(rng.gen::<[u8; 20]>());
+    let payer = Address::from(rng.gen::<[u8; 20]>());
+    let data_service = Address::from(rng.gen::<[u8; 20]>());
+    let service_provider = Address::from(rng.gen::<[u8; 20]>());
+    (0..count)
+        .map(|i| {
+            let receipt = v2::Receipt {
+                allocation_id,
+                payer,
+                data_service,
+                service_provider,
+                timestamp_ns: i as u64 + 1,
+                nonce: rng.gen(),
+                value: rng.gen_range(1..1_000_000),
+            };
+            Eip712SignedMessage::new(domain_separator, receipt, wallet).unwrap()
+        })
+        .collect()
+}
+
+pub fn aggregation_throughput(c: &mut Criterion) {
+    let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let wallet = wallet(&mut rng);
+    let accepted_addresses = HashSet::from([wallet.address()]);
+
+    let mut group = c.benchmark_group("check_and_aggregate_receipts");
+    for count in RECEIPT_COUNTS {
+        // reported as receipts/sec
+        group.throughput(Throughput::Elements(count as u64));
+
+        let receipts = v1_receipts(&domain_separator, &wallet, &mut rng, count);
+        group.bench_with_input(BenchmarkId::new("v1", count), &receipts, |b, receipts| {
+            b.iter(|| {
+                aggregator::v1::check_and_aggregate_receipts(
+                    black_box(&domain_separator),
+                    black_box(receipts),
+                    None,
+                    &wallet,
+                    &accepted_addresses,
+                    AggregationOptions::default(),
+                )
+                .unwrap()
+            })
+        });
+
+        let receipts = v2_receipts(&domain_separator, &wallet, &mut rng, count);
+        group.bench_with_input(BenchmarkId::new("v2", count), &receipts, |b, receipts| {
+            b.iter(|| {
+                aggregator::v2::check_and_aggregate_receipts(
+                    black_box(&domain_separator),
+                    black_box(receipts),
+                    None,
+                    &wallet,
+                    &accepted_addresses,
+                    AggregationOptions::default(),
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, aggregation_throughput);
+criterion_main!(benches);
@@ -1,5 +1,595 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, sol_types::SolStruct};
+use anyhow::{bail, Result};
+use log::warn;
+use rayon::prelude::*;
+use tap_core::{
+    receipt::{WithAllocationId, WithValueAndTimestamp},
+    signed_message::{
+        ComputedHashes, Eip712Error, Eip712SignedMessage, SignatureBytes, SignatureBytesExt,
+    },
+};
+
 pub mod v1;
 pub mod v2;
+
+/// Fields of the receipts checked before aggregating them, so that the
+/// checks are shared by all the receipt versions.
+pub trait ReceiptFields: SolStruct + WithValueAndTimestamp + WithAllocationId {
+    /// Key shared by the receipts aggregated into the same RAV
+    type Key: Copy + Eq + Debug;
+
+    fn key(&self) -> Self::Key;
+
+    fn nonce(&self) -> u64;
+}
+
+impl ReceiptFields for tap_graph::Receipt {
+    type Key = Address;
+
+    fn key(&self) -> Self::Key {
+        self.allocation_id
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+/// The allocation id, payer, data service and service provider
+impl ReceiptFields for tap_graph::v2::Receipt {
+    type Key = (Address, Address, Address, Address);
+
+    fn key(&self) -> Self::Key {
+        (
+            self.allocation_id,
+            self.payer,
+            self.data_service,
+            self.service_provider,
+        )
+    }
+
+    fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+/// Returns the recovered signer.
+///
+/// The signature is always recovered, so that invalid signatures are refused
+/// even when `accept_any_signer` skips the accepted addresses check.
+fn check_signature_is_from_one_of_addresses<M: SolStruct>(
+    message: &Eip712SignedMessage<M>,
+    domain_separator: &Eip712Domain,
+    accepted_addresses: &HashSet<Address>,
+    accept_any_signer: bool,
+) -> Result<Address> {
+    check_hashed_signature_is_from_one_of_addresses(
+        message,
+        &message.computed_hashes(domain_separator),
+        accepted_addresses,
+        accept_any_signer,
+    )
+}
+
+/// Same as [`check_signature_is_from_one_of_addresses`], recovering the
+/// signer with the `hashes` of `message` computed beforehand.
+fn check_hashed_signature_is_from_one_of_addresses<M: SolStruct>(
+    message: &Eip712SignedMessage<M>,
+    hashes: &ComputedHashes,
+    accepted_addresses: &HashSet<Address>,
+    accept_any_signer: bool,
+) -> Result<Address> {
+    if accept_any_signer {
+        return Ok(message.recover_signer_with_hashes(hashes)?);
+    }
+    match message.recover_if_accepted_with_hashes(hashes, accepted_addresses) {
+        Ok(address) => Ok(address),
+        Err(Eip712Error::SignerNotAccepted { address }) => {
+            bail!(tap_core::Error::InvalidRecoveredSigner { address })
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns `signer`, unless it is the excluded receipt signer, see
+/// [`AggregationOptions::excluded_receipt_signer`].
+fn check_receipt_signer_not_excluded(
+    signer: Address,
+    excluded_signer: Option<Address>,
+) -> Result<Address> {
+    if excluded_signer == Some(signer) {
+        bail!(tap_core::Error::InvalidRecoveredSigner { address: signer })
+    }
+    Ok(signer)
+}
+
+/// Returns the domain of `version`, for the receipts and RAVs aggregated by
+/// the `check_and_aggregate_receipts_multi_domain` functions.
+fn domain_of_version<'a>(
+    domains: &'a HashMap<String, Eip712Domain>,
+    version: &str,
+) -> Result<&'a Eip712Domain> {
+    domains
+        .get(version)
+        .ok_or_else(|| tap_core::Error::UnknownDomainVersion(version.to_string()).into())
+}
+
+fn check_allocation_id<'a, R: ReceiptFields + 'a>(
+    receipts: impl IntoIterator<Item = &'a Eip712SignedMessage<R>>,
+    key: R::Key,
+) -> Result<()> {
+    if receipts
+        .into_iter()
+        .any(|receipt| receipt.message.key() != key)
+    {
+        return Err(tap_core::Error::RavAllocationIdNotUniform.into());
+    }
+    Ok(())
+}
+
+fn check_signatures_unique<'a, M: SolStruct + 'a>(
+    receipts: impl IntoIterator<Item = &'a Eip712SignedMessage<M>>,
+) -> Result<()> {
+    let mut receipt_signatures: HashSet<SignatureBytes> = HashSet::new();
+    for receipt in receipts {
+        let signature = receipt.signature.get_signature_bytes();
+        if !receipt_signatures.insert(signature) {
+            return Err(tap_core::Error::DuplicateReceiptSignature(format!(
+                "{:?}",
+                receipt.signature
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+fn check_previous_rav_value<Rav: SolStruct + WithValueAndTimestamp>(
+    previous_rav: Option<&Eip712SignedMessage<Rav>>,
+    max_previous_rav_value: Option<u128>,
+) -> Result<()> {
+    if let (Some(previous_rav), Some(max_value)) = (previous_rav, max_previous_rav_value) {
+        let value = previous_rav.message.value();
+        if value > max_value {
+            return Err(tap_core::Error::PreviousRavValueTooHigh { value, max_value }.into());
+        }
+    }
+    Ok(())
+}
+
+fn check_nonces_unique<'a, R: ReceiptFields + 'a>(
+    receipts: impl IntoIterator<Item = &'a Eip712SignedMessage<R>>,
+) -> Result<()> {
+    let mut nonces = HashSet::new();
+    for receipt in receipts {
+        let receipt = &receipt.message;
+        if !nonces.insert((receipt.allocation_id(), receipt.nonce())) {
+            return Err(tap_core::Error::DuplicateReceiptNonce {
+                allocation_id: receipt.allocation_id(),
+                nonce: receipt.nonce(),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+fn check_receipt_timestamps<'a, R, Rav>(
+    receipts: impl IntoIterator<Item = &'a Eip712SignedMessage<R>>,
+    previous_rav: Option<&Eip712SignedMessage<Rav>>,
+) -> Result<()>
+where
+    R: ReceiptFields + 'a,
+    Rav: SolStruct + WithValueAndTimestamp,
+{
+    if let Some(previous_rav) = &previous_rav {
+        let rav_ts = previous_rav.message.timestamp_ns();
+        for receipt in receipts {
+            let receipt_ts = receipt.message.timestamp_ns();
+            if rav_ts >= receipt_ts {
+                return Err(
+                    tap_core::Error::ReceiptTimestampLowerThanRav { rav_ts, receipt_ts }.into(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns `receipts` in their canonical order, along with their `hashes`:
+/// by timestamp, then by nonce, then by
+/// [`Eip712SignedMessage::unique_hash`] and by signature. The checks then
+/// report the same receipt whatever the order the receipts were sent in,
+/// including among receipts with identical timestamps. The receipts are not
+/// copied, and nothing is allocated if they are in canonical order already.
+///
+/// `hashes` are the [`ComputedHashes`] of `receipts`, in the same order, so
+/// that the receipts are hashed once for the ordering and the recovery of
+/// their signers.
+fn in_canonical_order<'a, R: ReceiptFields>(
+    receipts: &'a [Eip712SignedMessage<R>],
+    hashes: &'a [ComputedHashes],
+) -> CanonicalOrder<'a, R> {
+    let key = |i: usize| {
+        (
+            receipts[i].message.timestamp_ns(),
+            receipts[i].message.nonce(),
+            hashes[i].message_id().0,
+            receipts[i].signature.as_bytes(),
+        )
+    };
+    let indices = if (1..receipts.len()).all(|i| key(i - 1) <= key(i)) {
+        None
+    } else {
+        let keys: Vec<_> = (0..receipts.len()).map(key).collect();
+        let mut indices: Vec<usize> = (0..receipts.len()).collect();
+        indices.sort_unstable_by_key(|&i| keys[i]);
+        Some(indices)
+    };
+    CanonicalOrder {
+        receipts,
+        hashes,
+        indices,
+    }
+}
+
+/// Receipts and their hashes in canonical order, see [`in_canonical_order`]
+struct CanonicalOrder<'a, R: SolStruct> {
+    receipts: &'a [Eip712SignedMessage<R>],
+    hashes: &'a [ComputedHashes],
+    /// Indices of the receipts in canonical order, `None` if `receipts` are
+    /// in canonical order already
+    indices: Option<Vec<usize>>,
+}
+
+impl<'a, R: SolStruct> CanonicalOrder<'a, R> {
+    fn get(&self, position: usize) -> (&'a Eip712SignedMessage<R>, &'a ComputedHashes) {
+        let i = self
+            .indices
+            .as_ref()
+            .map_or(position, |indices| indices[position]);
+        (&self.receipts[i], &self.hashes[i])
+    }
+
+    fn receipts(&self) -> impl Iterator<Item = &'a Eip712SignedMessage<R>> + '_ {
+        (0..self.receipts.len()).map(|position| self.get(position).0)
+    }
+
+    fn par_iter(
+        &self,
+    ) -> impl IndexedParallelIterator<Item = (&'a Eip712SignedMessage<R>, &'a ComputedHashes)> + '_
+    where
+        R: Sync,
+    {
+        (0..self.receipts.len())
+            .into_par_iter()
+            .map(|position| self.get(position))
+    }
+}
+
+/// Optional checks applied by `check_and_aggregate_receipts`, on top of the
+/// checks always performed (signatures, timestamps, allocation).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AggregationOptions {
+    /// Refuse to chain onto a previous RAV whose value is above this maximum.
+    /// No limit if `None`.
+    pub max_previous_rav_value: Option<u128>,
+    /// Reject batches holding two receipts with the same allocation ID and
+    /// nonce, which points to a nonce reuse bug in the sender.
+    pub check_nonces_unique: bool,
+    /// Aggregate receipts and RAVs signed by any signer, instead of only the
+    /// accepted addresses. Signatures are still verified. For development
+    /// only, see [`AggregationOptions::check_insecure`].
+    pub accept_any_signer_insecure: bool,
+    /// Timestamp given to the aggregated RAVs.
+    pub rav_timestamp_policy: RavTimestampPolicy,
+    /// Refuse receipts signed by this address, even if it is accepted, e.g.
+    /// the address of the wallet signing the RAVs. Previous RAVs signed by it
+    /// are still accepted.
+    pub excluded_receipt_signer: Option<Address>,
+}
+
+/// Timestamp given to the aggregated RAVs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum RavTimestampPolicy {
+    /// The timestamp of the latest receipt aggregated.
+    #[default]
+    MaxReceipt,
+    /// The time of the aggregation, reflecting when the RAV is issued. It
+    /// must not be before the latest receipt.
+    AggregationTime,
+}
+
+impl RavTimestampPolicy {
+    /// Returns the timestamp of a RAV whose latest receipt is stamped
+    /// `max_receipt_timestamp_ns`.
+    ///
+    /// # Errors
+    ///
+    /// With [`RavTimestampPolicy::AggregationTime`], returns an error if the
+    /// current time is before `max_receipt_timestamp_ns`.
+    pub fn rav_timestamp_ns(&self, max_receipt_timestamp_ns: u64) -> Result<u64, tap_core::Error> {
+        match self {
+            RavTimestampPolicy::MaxReceipt => Ok(max_receipt_timestamp_ns),
+            RavTimestampPolicy::AggregationTime => {
+                let now_ns = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|err| tap_core::Error::InvalidSystemTime {
+                        source_error_message: err.to_string(),
+                    })?
+                    .as_nanos() as u64;
+                if now_ns < max_receipt_timestamp_ns {
+                    return Err(tap_core::Error::AggregationTimeBeforeReceipt {
+                        now_ns,
+                        receipt_ts: max_receipt_timestamp_ns,
+                    });
+                }
+                Ok(now_ns)
+            }
+        }
+    }
+}
+
+impl AggregationOptions {
+    /// Refuses the insecure options unless `insecure_confirmed` is set, and
+    /// logs a warning for each insecure option enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `accept_any_signer_insecure` is set without
+    /// `insecure_confirmed`.
+    pub fn check_insecure(&self, insecure_confirmed: bool) -> Result<()> {
+        if self.accept_any_signer_insecure {
+            if !insecure_confirmed {
+                bail!(
+                    "Accepting receipts from any signer is insecure and meant for development \
+                     only. Confirm it explicitly to enable it."
+                );
+            }
+            warn!(
+                "INSECURE: receipts and RAVs signed by any signer are aggregated. \
+                 Never use this setting in production."
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use alloy::{
+        primitives::{Address, ChainId, B256},
+        signers::{local::PrivateKeySigner, Signature, Signer},
+    };
+    use tap_core::{
+        signed_message::{ComputedHashes, Eip712SignedMessage},
+        tap_eip712_domain,
+    };
+    use tap_graph::v2::{DataService, Payer, ServiceProvider};
+
+    use super::{
+        check_allocation_id, check_nonces_unique, in_canonical_order, v1, v2, AggregationOptions,
+        RavTimestampPolicy, ReceiptFields,
+    };
+
+    /// Signer only implementing the asynchronous [`Signer`] API, like a
+    /// remote signer.
+    pub(super) struct RemoteSigner(pub(super) PrivateKeySigner);
+
+    #[async_trait::async_trait]
+    impl Signer for RemoteSigner {
+        async fn sign_hash(&self, hash: &B256) -> alloy::signers::Result<Signature> {
+            // as if the request went over the network
+            tokio::task::yield_now().await;
+            self.0.sign_hash(hash).await
+        }
+
+        fn address(&self) -> Address {
+            self.0.address()
+        }
+
+        fn chain_id(&self) -> Option<ChainId> {
+            Signer::chain_id(&self.0)
+        }
+
+        fn set_chain_id(&mut self, chain_id: Option<ChainId>) {
+            Signer::set_chain_id(&mut self.0, chain_id)
+        }
+    }
+
+    #[test]
+    fn accept_any_signer_requires_confirmation() {
+        let options = AggregationOptions::default();
+        assert!(options.check_insecure(false).is_ok());
+
+        let options = AggregationOptions {
+            accept_any_signer_insecure: true,
+            ..Default::default()
+        };
+        assert!(options.check_insecure(false).is_err());
+        assert!(options.check_insecure(true).is_ok());
+    }
+
+    #[test]
+    fn rav_timestamp_policies() {
+        assert_eq!(
+            RavTimestampPolicy::MaxReceipt.rav_timestamp_ns(42).unwrap(),
+            42
+        );
+
+        let now_ns = RavTimestampPolicy::AggregationTime
+            .rav_timestamp_ns(42)
+            .unwrap();
+        assert!(now_ns > 42);
+
+        // receipts from the future cannot be stamped with the current time
+        assert!(matches!(
+            RavTimestampPolicy::AggregationTime.rav_timestamp_ns(u64::MAX),
+            Err(tap_core::Error::AggregationTimeBeforeReceipt {
+                receipt_ts: u64::MAX,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn both_receipt_versions_aggregate() {
+        let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+        let wallet = PrivateKeySigner::random();
+        let accepted_addresses = HashSet::from([wallet.address()]);
+        let allocation_id = Address::from([0xaau8; 20]);
+
+        let v1_receipts: Vec<_> = (1..=3)
+            .map(|value| {
+                let receipt = tap_graph::Receipt::new(allocation_id, value).unwrap();
+                Eip712SignedMessage::new(&domain_separator, receipt, &wallet).unwrap()
+            })
+            .collect();
+        let v2_receipts: Vec<_> = (1..=3)
+            .map(|value| {
+                let receipt = tap_graph::v2::Receipt::new(
+                    allocation_id,
+                    Payer(Address::from([0xbbu8; 20])),
+                    DataService(Address::from([0xccu8; 20])),
+                    ServiceProvider(Address::from([0xddu8; 20])),
+                    value,
+                )
+                .unwrap();
+                Eip712SignedMessage::new(&domain_separator, receipt, &wallet).unwrap()
+            })
+            .collect();
+
+        let v1_rav = v1::check_and_aggregate_receipts(
+            &domain_separator,
+            &v1_receipts,
+            None,
+            &wallet,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .unwrap();
+        let v2_rav = v2::check_and_aggregate_receipts(
+            &domain_separator,
+            &v2_receipts,
+            None,
+            &wallet,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(v1_rav.message.valueAggregate, 6);
+        assert_eq!(v2_rav.message.valueAggregate, 6);
+        assert_eq!(
+            v1_rav.message.timestampNs,
+            v1_receipts
+                .iter()
+                .map(|r| r.message.timestamp_ns)
+                .max()
+                .unwrap()
+        );
+        assert_eq!(
+            v2_rav.message.timestampNs,
+            v2_receipts
+                .iter()
+                .map(|r| r.message.timestamp_ns)
+                .max()
+                .unwrap()
+        );
+
+        // the shared checks apply to both versions
+        assert!(check_allocation_id(&v1_receipts, v1_receipts[0].message.key()).is_ok());
+        assert!(check_allocation_id(&v2_receipts, v2_receipts[0].message.key()).is_ok());
+        assert!(check_allocation_id(&v1_receipts, Address::ZERO).is_err());
+        assert!(check_nonces_unique(&v1_receipts).is_ok());
+        assert!(check_nonces_unique(&v2_receipts).is_ok());
+        let mut duplicate = v2_receipts[1].clone();
+        duplicate.message.nonce = v2_receipts[0].message.nonce;
+        assert!(check_nonces_unique(&[v2_receipts[0].clone(), duplicate]).is_err());
+    }
+
+    #[test]
+    fn identical_timestamps_aggregate_deterministically() {
+        let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+        let wallet = PrivateKeySigner::random();
+        let accepted_addresses = HashSet::from([wallet.address()]);
+        let allocation_id = Address::from([0xaau8; 20]);
+
+        // the nonces 3 and 5 are used twice, with different values
+        let mut receipts: Vec<_> = [(5, 10), (3, 20), (7, 30), (3, 40), (5, 50), (1, 60)]
+            .into_iter()
+            .map(|(nonce, value)| {
+                let receipt = tap_graph::Receipt {
+                    allocation_id,
+                    timestamp_ns: 1_000,
+                    nonce,
+                    value,
+                };
+                Eip712SignedMessage::new(&domain_separator, receipt, &wallet).unwrap()
+            })
+            .collect();
+        let nonce_check = AggregationOptions {
+            check_nonces_unique: true,
+            ..Default::default()
+        };
+
+        let mut ravs = Vec::new();
+        let mut errors = Vec::new();
+        for _ in 0..receipts.len() {
+            receipts.rotate_left(1);
+            for receipts in [receipts.clone(), receipts.iter().rev().cloned().collect()] {
+                let hashes = ComputedHashes::batch(
+                    &domain_separator,
+                    receipts.iter().map(|receipt| &receipt.message),
+                );
+                let ordered: Vec<_> = in_canonical_order(&receipts, &hashes).receipts().collect();
+                assert!(ordered.windows(2).all(|pair| {
+                    (pair[0].message.nonce, pair[0].unique_hash().0)
+                        <= (pair[1].message.nonce, pair[1].unique_hash().0)
+                }));
+
+                ravs.push(
+                    v1::check_and_aggregate_receipts(
+                        &domain_separator,
+                        &receipts,
+                        None,
+                        &wallet,
+                        &accepted_addresses,
+                        AggregationOptions::default(),
+                    )
+                    .unwrap(),
+                );
+                errors.push(
+                    v1::check_and_aggregate_receipts(
+                        &domain_separator,
+                        &receipts,
+                        None,
+                        &wallet,
+                        &accepted_addresses,
+                        nonce_check,
+                    )
+                    .unwrap_err()
+                    .to_string(),
+                );
+            }
+        }
+
+        assert_eq!(ravs[0].message.timestampNs, 1_000);
+        assert_eq!(ravs[0].message.valueAggregate, 210);
+        assert!(ravs.iter().all(|rav| *rav == ravs[0]));
+        // the duplicate with the lowest nonce is reported
+        assert!(errors[0].starts_with("Duplicate receipt nonce 3 "));
+        assert!(errors.iter().all(|error| *error == errors[0]));
+    }
+}
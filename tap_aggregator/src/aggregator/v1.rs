@@ -1,51 +1,234 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::{hash_set, HashSet};
+use std::collections::{HashMap, HashSet};
 
 use alloy::{
-    dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner,
-    sol_types::SolStruct,
+    dyn_abi::Eip712Domain,
+    primitives::Address,
+    signers::{local::PrivateKeySigner, Signer},
 };
-use anyhow::{bail, Ok, Result};
+use anyhow::{Ok, Result};
 use rayon::prelude::*;
-use tap_core::signed_message::{Eip712SignedMessage, SignatureBytes, SignatureBytesExt};
+use tap_core::signed_message::{ComputedHashes, Eip712SignedMessage};
 use tap_graph::{Receipt, ReceiptAggregateVoucher};
 
+use super::{
+    check_allocation_id, check_hashed_signature_is_from_one_of_addresses, check_nonces_unique,
+    check_previous_rav_value, check_receipt_signer_not_excluded, check_receipt_timestamps,
+    check_signature_is_from_one_of_addresses, check_signatures_unique, domain_of_version,
+    in_canonical_order, AggregationOptions, ReceiptFields,
+};
+
+/// Checks `receipts` and aggregates them, with `previous_rav` if any, into a
+/// RAV signed with `wallet`.
+///
+/// The receipts are checked in their canonical order: by timestamp, then by
+/// nonce and message hash among receipts with identical timestamps. The same
+/// receipts give the same RAV, or fail with the same error, whatever the
+/// order they are sent in.
+///
+/// See [`check_and_aggregate_receipts_async`] to sign with a remote signer.
 pub fn check_and_aggregate_receipts(
     domain_separator: &Eip712Domain,
     receipts: &[Eip712SignedMessage<Receipt>],
     previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
     wallet: &PrivateKeySigner,
     accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<Eip712SignedMessage<ReceiptAggregateVoucher>> {
+    check_and_aggregate_receipts_with_subtotals(
+        domain_separator,
+        receipts,
+        previous_rav,
+        wallet,
+        accepted_addresses,
+        options,
+    )
+    .map(|(rav, _)| rav)
+}
+
+/// Same as [`check_and_aggregate_receipts`], signing the RAV with an
+/// asynchronous `signer` instead of an in-process wallet.
+///
+/// Use [`check_and_aggregate_receipts`] when the key is held by a
+/// [`PrivateKeySigner`], so that callers don't need an async runtime. Use
+/// this variant when the RAVs are signed by a remote signer, e.g. a KMS or a
+/// hardware wallet. The future does not depend on a specific async runtime.
+///
+/// The receipts are checked before the first `.await`, on the calling thread
+/// and the rayon thread pool, exactly as in [`check_and_aggregate_receipts`].
+pub async fn check_and_aggregate_receipts_async<S: Signer + ?Sized>(
+    domain_separator: &Eip712Domain,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+    signer: &S,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
 ) -> Result<Eip712SignedMessage<ReceiptAggregateVoucher>> {
-    check_signatures_unique(receipts)?;
+    let rav = check_and_aggregate_receipts_unsigned(
+        domain_separator,
+        receipts,
+        previous_rav,
+        accepted_addresses,
+        options,
+    )?;
+    Ok(Eip712SignedMessage::new_async(domain_separator, rav, signer).await?)
+}
+
+/// Same as [`check_and_aggregate_receipts`], also returning the total value
+/// of the receipts of each signer, e.g. for gateways splitting the revenue
+/// between their keys.
+///
+/// The signers are the ones recovered while checking the signatures. The
+/// value of the previous RAV is not attributed to any signer.
+pub fn check_and_aggregate_receipts_with_subtotals(
+    domain_separator: &Eip712Domain,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+    wallet: &PrivateKeySigner,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<(
+    Eip712SignedMessage<ReceiptAggregateVoucher>,
+    HashMap<Address, u128>,
+)> {
+    let (rav, subtotals) = check_and_aggregate(
+        domain_separator,
+        receipts,
+        previous_rav.map(|rav| (domain_separator, rav)),
+        accepted_addresses,
+        options,
+    )?;
+    Ok((
+        Eip712SignedMessage::new(domain_separator, rav, wallet)?,
+        subtotals,
+    ))
+}
+
+/// Same as [`check_and_aggregate_receipts`], returning the RAV without
+/// signing it, e.g. to validate receipts without issuing a RAV.
+pub fn check_and_aggregate_receipts_unsigned(
+    domain_separator: &Eip712Domain,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<ReceiptAggregateVoucher> {
+    check_and_aggregate(
+        domain_separator,
+        receipts,
+        previous_rav.map(|rav| (domain_separator, rav)),
+        accepted_addresses,
+        options,
+    )
+    .map(|(rav, _)| rav)
+}
+
+/// Same as [`check_and_aggregate_receipts`], for an allocation whose receipts
+/// are signed under several versions of the EIP-712 domain, e.g. during a
+/// domain migration.
+///
+/// `receipts` are signed under the domain `version`, and the previous RAV
+/// comes with the version of the domain it is signed under. Both versions
+/// must be keys of `domains`. The policy is:
+/// - all the receipts of a batch must be signed under the same version, a
+///   receipt signed under another version fails the signature check;
+/// - the previous RAV may be signed under any known version, so that a RAV
+///   can be carried over to the new version (clean cut-over);
+/// - the new RAV is signed under the version of the receipts.
+pub fn check_and_aggregate_receipts_multi_domain(
+    domains: &HashMap<String, Eip712Domain>,
+    version: &str,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<(&str, Eip712SignedMessage<ReceiptAggregateVoucher>)>,
+    wallet: &PrivateKeySigner,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<Eip712SignedMessage<ReceiptAggregateVoucher>> {
+    let domain_separator = domain_of_version(domains, version)?;
+    let previous_rav = match previous_rav {
+        Some((version, rav)) => Some((domain_of_version(domains, version)?, rav)),
+        None => None,
+    };
+
+    let (rav, _) = check_and_aggregate(
+        domain_separator,
+        receipts,
+        previous_rav,
+        accepted_addresses,
+        options,
+    )?;
+    Ok(Eip712SignedMessage::new(domain_separator, rav, wallet)?)
+}
+
+/// Checks and aggregates receipts signed under `domain_separator`, onto a
+/// previous RAV signed under its own domain. Returns the unsigned RAV along
+/// with the total value of the receipts of each signer.
+fn check_and_aggregate(
+    domain_separator: &Eip712Domain,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<(&Eip712Domain, Eip712SignedMessage<ReceiptAggregateVoucher>)>,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<(ReceiptAggregateVoucher, HashMap<Address, u128>)> {
+    // so that the checks report the same receipt whatever the order of the
+    // receipts, see `in_canonical_order`. The aggregation itself does not
+    // depend on the order, and is done on `receipts`. Each receipt is hashed
+    // once, for both the ordering and the recovery of its signer.
+    let hashes = ComputedHashes::batch(
+        domain_separator,
+        receipts.iter().map(|receipt| &receipt.message),
+    );
+    let ordered = in_canonical_order(receipts, &hashes);
+    check_signatures_unique(ordered.receipts())?;
+
+    if options.check_nonces_unique {
+        check_nonces_unique(ordered.receipts())?;
+    }
 
     // Check that the receipts are signed by an accepted signer address
-    receipts.par_iter().try_for_each(|receipt| {
-        check_signature_is_from_one_of_addresses(receipt, domain_separator, accepted_addresses)
-    })?;
+    let signers = ordered
+        .par_iter()
+        .map(|(receipt, hashes)| {
+            let signer = check_hashed_signature_is_from_one_of_addresses(
+                receipt,
+                hashes,
+                accepted_addresses,
+                options.accept_any_signer_insecure,
+            )?;
+            check_receipt_signer_not_excluded(signer, options.excluded_receipt_signer)
+        })
+        .collect::<Result<Vec<_>>>()?;
 
     // Check that the previous rav is signed by an accepted signer address
-    if let Some(previous_rav) = &previous_rav {
-        check_signature_is_from_one_of_addresses(
-            previous_rav,
-            domain_separator,
-            accepted_addresses,
-        )?;
-    }
+    let previous_rav = match previous_rav {
+        Some((previous_rav_domain, previous_rav)) => {
+            check_signature_is_from_one_of_addresses(
+                &previous_rav,
+                previous_rav_domain,
+                accepted_addresses,
+                options.accept_any_signer_insecure,
+            )?;
+            Some(previous_rav)
+        }
+        None => None,
+    };
+
+    // Refuse to chain onto a previous rav with an implausible value
+    check_previous_rav_value(previous_rav.as_ref(), options.max_previous_rav_value)?;
 
     // Check that the receipts timestamp is greater than the previous rav
-    check_receipt_timestamps(receipts, previous_rav.as_ref())?;
+    check_receipt_timestamps(ordered.receipts(), previous_rav.as_ref())?;
 
     // Get the allocation id from the first receipt, return error if there are no receipts
-    let allocation_id = match receipts.first() {
-        Some(receipt) => receipt.message.allocation_id,
+    let allocation_id = match ordered.receipts().next() {
+        Some(receipt) => receipt.message.key(),
         None => return Err(tap_core::Error::NoValidReceiptsForRavRequest.into()),
     };
 
     // Check that the receipts all have the same allocation id
-    check_allocation_id(receipts, allocation_id)?;
+    check_allocation_id(ordered.receipts(), allocation_id)?;
 
     // Check that the rav has the correct allocation id
     if let Some(previous_rav) = &previous_rav {
@@ -60,84 +243,35 @@ pub fn check_and_aggregate_receipts(
     }
 
     // Aggregate the receipts
-    let rav = ReceiptAggregateVoucher::aggregate_receipts(allocation_id, receipts, previous_rav)?;
-
-    // Sign the rav and return
-    Ok(Eip712SignedMessage::new(domain_separator, rav, wallet)?)
-}
-
-fn check_signature_is_from_one_of_addresses<M: SolStruct>(
-    message: &Eip712SignedMessage<M>,
-    domain_separator: &Eip712Domain,
-    accepted_addresses: &HashSet<Address>,
-) -> Result<()> {
-    let recovered_address = message.recover_signer(domain_separator)?;
-    if !accepted_addresses.contains(&recovered_address) {
-        bail!(tap_core::Error::InvalidRecoveredSigner {
-            address: recovered_address,
-        });
+    let mut rav =
+        ReceiptAggregateVoucher::aggregate_receipts(allocation_id, receipts, previous_rav)?;
+    rav.timestampNs = options
+        .rav_timestamp_policy
+        .rav_timestamp_ns(rav.timestampNs)?;
+
+    // The receipts values don't overflow, their aggregate was computed above
+    let mut subtotals = HashMap::new();
+    for (signer, receipt) in signers.into_iter().zip(ordered.receipts()) {
+        *subtotals.entry(signer).or_insert(0) += receipt.message.value;
     }
-    Ok(())
-}
 
-fn check_allocation_id(
-    receipts: &[Eip712SignedMessage<Receipt>],
-    allocation_id: Address,
-) -> Result<()> {
-    for receipt in receipts.iter() {
-        let receipt = &receipt.message;
-        if receipt.allocation_id != allocation_id {
-            return Err(tap_core::Error::RavAllocationIdNotUniform.into());
-        }
-    }
-    Ok(())
-}
-
-fn check_signatures_unique(receipts: &[Eip712SignedMessage<Receipt>]) -> Result<()> {
-    let mut receipt_signatures: hash_set::HashSet<SignatureBytes> = hash_set::HashSet::new();
-    for receipt in receipts.iter() {
-        let signature = receipt.signature.get_signature_bytes();
-        if !receipt_signatures.insert(signature) {
-            return Err(tap_core::Error::DuplicateReceiptSignature(format!(
-                "{:?}",
-                receipt.signature
-            ))
-            .into());
-        }
-    }
-    Ok(())
-}
-
-fn check_receipt_timestamps(
-    receipts: &[Eip712SignedMessage<Receipt>],
-    previous_rav: Option<&Eip712SignedMessage<ReceiptAggregateVoucher>>,
-) -> Result<()> {
-    if let Some(previous_rav) = &previous_rav {
-        for receipt in receipts.iter() {
-            let receipt = &receipt.message;
-            if previous_rav.message.timestampNs >= receipt.timestamp_ns {
-                return Err(tap_core::Error::ReceiptTimestampLowerThanRav {
-                    rav_ts: previous_rav.message.timestampNs,
-                    receipt_ts: receipt.timestamp_ns,
-                }
-                .into());
-            }
-        }
-    }
-
-    Ok(())
+    Ok((rav, subtotals))
 }
 
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+    use alloy::{
+        dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner,
+        sol_types::eip712_domain,
+    };
     use rstest::*;
     use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
     use tap_graph::{Receipt, ReceiptAggregateVoucher};
 
     use super::*;
+    use crate::aggregator::{tests::RemoteSigner, RavTimestampPolicy};
 
     #[fixture]
     fn keys() -> (PrivateKeySigner, Address) {
@@ -347,4 +481,454 @@ mod tests {
 
         assert!(res.is_ok());
     }
+
+    #[rstest]
+    #[test]
+    /// Test that a previous rav is accepted up to the maximum value, and refused above it
+    fn check_max_previous_rav_value(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let accepted_addresses = HashSet::from([keys.1]);
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys.0,
+        )
+        .unwrap()];
+        let previous_rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_ids[0],
+                timestampNs: 0,
+                valueAggregate: 1000,
+            },
+            &keys.0,
+        )
+        .unwrap();
+
+        let rav = check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            Some(previous_rav.clone()),
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions {
+                max_previous_rav_value: Some(1000),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 1042);
+
+        let err = check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            Some(previous_rav),
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions {
+                max_previous_rav_value: Some(999),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::PreviousRavValueTooHigh {
+                value: 1000,
+                max_value: 999
+            })
+        ));
+    }
+
+    #[rstest]
+    #[test]
+    fn check_nonces_unique_fail(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        // Same nonce reused in two otherwise different receipts
+        let receipts: Vec<_> = (10..12)
+            .map(|timestamp_ns| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt {
+                        allocation_id: allocation_ids[0],
+                        timestamp_ns,
+                        nonce: 7,
+                        value: 42,
+                    },
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+        assert!(check_signatures_unique(&receipts).is_ok());
+
+        let res = check_nonces_unique(&receipts);
+        assert!(matches!(
+            res.unwrap_err().downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::DuplicateReceiptNonce { nonce: 7, .. })
+        ));
+
+        // the same nonce may be used for another allocation
+        let receipts = vec![
+            receipts[0].clone(),
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt {
+                    allocation_id: allocation_ids[1],
+                    timestamp_ns: 11,
+                    nonce: 7,
+                    value: 42,
+                },
+                &keys.0,
+            )
+            .unwrap(),
+        ];
+        assert!(check_nonces_unique(&receipts).is_ok());
+    }
+
+    #[rstest]
+    #[test]
+    fn check_nonces_unique_ok(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        // Receipt::new draws a random nonce
+        let receipts = vec![
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], 42).unwrap(),
+                &keys.0,
+            )
+            .unwrap(),
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], 42).unwrap(),
+                &keys.0,
+            )
+            .unwrap(),
+        ];
+
+        let res = check_nonces_unique(&receipts);
+        assert!(res.is_ok());
+    }
+
+    #[rstest]
+    #[test]
+    fn accept_any_signer_insecure(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys.0,
+        )
+        .unwrap()];
+        // the signer is not accepted
+        let accepted_addresses = HashSet::from([Address::from([0x11u8; 20])]);
+
+        let err = check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            None,
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::InvalidRecoveredSigner { address }) if *address == keys.1
+        ));
+
+        let rav = check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            None,
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions {
+                accept_any_signer_insecure: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 42);
+    }
+
+    #[rstest]
+    #[test]
+    fn subtotals_per_signer(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let other_keys = self::keys();
+        let previous_rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_ids[0],
+                timestampNs: 0,
+                valueAggregate: 1000,
+            },
+            &keys.0,
+        )
+        .unwrap();
+        let receipts: Vec<_> = [(&keys.0, 10), (&other_keys.0, 20), (&keys.0, 30)]
+            .into_iter()
+            .map(|(wallet, value)| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_ids[0], value).unwrap(),
+                    wallet,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let (rav, subtotals) = check_and_aggregate_receipts_with_subtotals(
+            &domain_separator,
+            &receipts,
+            Some(previous_rav),
+            &keys.0,
+            &HashSet::from([keys.1, other_keys.1]),
+            AggregationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 1060);
+        assert_eq!(subtotals, HashMap::from([(keys.1, 40), (other_keys.1, 20)]));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn async_signer(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipts: Vec<_> = (0..3)
+            .map(|_| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(allocation_ids[0], 42).unwrap(),
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+        let accepted_addresses = HashSet::from([keys.1]);
+        let remote_signer = RemoteSigner(keys.0.clone());
+
+        let rav = check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            None,
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 126);
+
+        // the signatures are deterministic, so that every signer gives the same RAV
+        let signers: [&(dyn Signer + Sync); 2] = [&keys.0, &remote_signer];
+        for signer in signers {
+            let async_rav = check_and_aggregate_receipts_async(
+                &domain_separator,
+                &receipts,
+                None,
+                signer,
+                &accepted_addresses,
+                AggregationOptions::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(async_rav, rav);
+        }
+
+        // the receipts are checked as in the synchronous variant
+        let res = check_and_aggregate_receipts_async(
+            &domain_separator,
+            &[receipts[0].clone(), receipts[0].clone()],
+            None,
+            &remote_signer,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[rstest]
+    #[test]
+    fn rav_timestamp_policy(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipt = |timestamp_ns| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt {
+                    allocation_id: allocation_ids[0],
+                    timestamp_ns,
+                    nonce: timestamp_ns,
+                    value: 42,
+                },
+                &keys.0,
+            )
+            .unwrap()
+        };
+        let aggregate = |receipts: &[_], rav_timestamp_policy| {
+            check_and_aggregate_receipts(
+                &domain_separator,
+                receipts,
+                None,
+                &keys.0,
+                &HashSet::from([keys.1]),
+                AggregationOptions {
+                    rav_timestamp_policy,
+                    ..Default::default()
+                },
+            )
+        };
+        let receipts = [receipt(10), receipt(20)];
+
+        let rav = aggregate(&receipts, RavTimestampPolicy::MaxReceipt).unwrap();
+        assert_eq!(rav.message.timestampNs, 20);
+
+        let rav = aggregate(&receipts, RavTimestampPolicy::AggregationTime).unwrap();
+        assert!(rav.message.timestampNs > 20);
+        assert_eq!(rav.message.valueAggregate, 84);
+
+        // the latest receipt is later than the aggregation time
+        let receipts = [receipt(10), receipt(u64::MAX)];
+        let err = aggregate(&receipts, RavTimestampPolicy::AggregationTime).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::AggregationTimeBeforeReceipt { .. })
+        ));
+    }
+
+    #[fixture]
+    fn domains() -> HashMap<String, Eip712Domain> {
+        HashMap::from([
+            (
+                "1".to_string(),
+                tap_eip712_domain(1, Address::from([0x11u8; 20])),
+            ),
+            (
+                "2".to_string(),
+                eip712_domain! {
+                    name: "TAP",
+                    version: "2",
+                    chain_id: 1,
+                    verifying_contract: Address::from([0x11u8; 20]),
+                },
+            ),
+        ])
+    }
+
+    #[rstest]
+    #[test]
+    /// Test that receipts signed under different domain versions are not aggregated together
+    fn multi_domain_rejects_mixed_batch(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domains: HashMap<String, Eip712Domain>,
+    ) {
+        let receipts: Vec<_> = ["1", "2"]
+            .into_iter()
+            .map(|version| {
+                Eip712SignedMessage::new(
+                    &domains[version],
+                    Receipt::new(allocation_ids[0], 42).unwrap(),
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let err = check_and_aggregate_receipts_multi_domain(
+            &domains,
+            "2",
+            &receipts,
+            None,
+            &keys.0,
+            &HashSet::from([keys.1]),
+            AggregationOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::InvalidRecoveredSigner { .. })
+        ));
+
+        let err = check_and_aggregate_receipts_multi_domain(
+            &domains,
+            "3",
+            &receipts[..1],
+            None,
+            &keys.0,
+            &HashSet::from([keys.1]),
+            AggregationOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::UnknownDomainVersion(version)) if version == "3"
+        ));
+    }
+
+    #[rstest]
+    #[test]
+    /// Test that a uniform batch is aggregated onto a previous rav signed under the old domain
+    fn multi_domain_aggregates_uniform_batch(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domains: HashMap<String, Eip712Domain>,
+    ) {
+        let previous_rav = Eip712SignedMessage::new(
+            &domains["1"],
+            ReceiptAggregateVoucher {
+                allocationId: allocation_ids[0],
+                timestampNs: 0,
+                valueAggregate: 100,
+            },
+            &keys.0,
+        )
+        .unwrap();
+        let receipts: Vec<_> = (0..3)
+            .map(|_| {
+                Eip712SignedMessage::new(
+                    &domains["2"],
+                    Receipt::new(allocation_ids[0], 42).unwrap(),
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let rav = check_and_aggregate_receipts_multi_domain(
+            &domains,
+            "2",
+            &receipts,
+            Some(("1", previous_rav)),
+            &keys.0,
+            &HashSet::from([keys.1]),
+            AggregationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 226);
+        // the new rav is signed under the domain of the receipts
+        assert_eq!(rav.recover_signer(&domains["2"]).unwrap(), keys.1);
+    }
 }
@@ -1,92 +1,205 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::{hash_set, HashSet};
+use std::collections::{HashMap, HashSet};
 
 use alloy::{
-    dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner,
-    sol_types::SolStruct,
+    dyn_abi::Eip712Domain,
+    primitives::Address,
+    signers::{local::PrivateKeySigner, Signer},
 };
-use anyhow::{bail, Ok, Result};
+use anyhow::{Ok, Result};
 use rayon::prelude::*;
-use tap_core::signed_message::{Eip712SignedMessage, SignatureBytes, SignatureBytesExt};
+use tap_core::signed_message::{ComputedHashes, Eip712SignedMessage};
 use tap_graph::v2::{Receipt, ReceiptAggregateVoucher};
 
+use super::{
+    check_allocation_id, check_hashed_signature_is_from_one_of_addresses, check_nonces_unique,
+    check_previous_rav_value, check_receipt_signer_not_excluded, check_receipt_timestamps,
+    check_signature_is_from_one_of_addresses, check_signatures_unique, domain_of_version,
+    in_canonical_order, AggregationOptions, ReceiptFields,
+};
+
+/// Checks `receipts` and aggregates them, with `previous_rav` if any, into a
+/// RAV signed with `wallet`.
+///
+/// The receipts are checked in their canonical order: by timestamp, then by
+/// nonce and message hash among receipts with identical timestamps. The same
+/// receipts give the same RAV, or fail with the same error, whatever the
+/// order they are sent in.
+///
+/// See [`check_and_aggregate_receipts_async`] to sign with a remote signer.
 pub fn check_and_aggregate_receipts(
     domain_separator: &Eip712Domain,
     receipts: &[Eip712SignedMessage<Receipt>],
     previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
     wallet: &PrivateKeySigner,
     accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<Eip712SignedMessage<ReceiptAggregateVoucher>> {
+    let rav = check_and_aggregate_receipts_unsigned(
+        domain_separator,
+        receipts,
+        previous_rav,
+        accepted_addresses,
+        options,
+    )?;
+    Ok(Eip712SignedMessage::new(domain_separator, rav, wallet)?)
+}
+
+/// Same as [`check_and_aggregate_receipts`], signing the RAV with an
+/// asynchronous `signer` instead of an in-process wallet.
+///
+/// Use [`check_and_aggregate_receipts`] when the key is held by a
+/// [`PrivateKeySigner`], so that callers don't need an async runtime. Use
+/// this variant when the RAVs are signed by a remote signer, e.g. a KMS or a
+/// hardware wallet. The future does not depend on a specific async runtime.
+///
+/// The receipts are checked before the first `.await`, on the calling thread
+/// and the rayon thread pool, exactly as in [`check_and_aggregate_receipts`].
+pub async fn check_and_aggregate_receipts_async<S: Signer + ?Sized>(
+    domain_separator: &Eip712Domain,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+    signer: &S,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<Eip712SignedMessage<ReceiptAggregateVoucher>> {
+    let rav = check_and_aggregate_receipts_unsigned(
+        domain_separator,
+        receipts,
+        previous_rav,
+        accepted_addresses,
+        options,
+    )?;
+    Ok(Eip712SignedMessage::new_async(domain_separator, rav, signer).await?)
+}
+
+/// Same as [`check_and_aggregate_receipts`], returning the RAV without
+/// signing it, e.g. to validate receipts without issuing a RAV.
+pub fn check_and_aggregate_receipts_unsigned(
+    domain_separator: &Eip712Domain,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<ReceiptAggregateVoucher> {
+    check_and_aggregate(
+        domain_separator,
+        receipts,
+        previous_rav.map(|rav| (domain_separator, rav)),
+        accepted_addresses,
+        options,
+    )
+}
+
+/// Same as [`check_and_aggregate_receipts`], for receipts signed under
+/// several versions of the EIP-712 domain, see
+/// [`super::v1::check_and_aggregate_receipts_multi_domain`] for the policy.
+pub fn check_and_aggregate_receipts_multi_domain(
+    domains: &HashMap<String, Eip712Domain>,
+    version: &str,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<(&str, Eip712SignedMessage<ReceiptAggregateVoucher>)>,
+    wallet: &PrivateKeySigner,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
 ) -> Result<Eip712SignedMessage<ReceiptAggregateVoucher>> {
-    check_signatures_unique(receipts)?;
+    let domain_separator = domain_of_version(domains, version)?;
+    let previous_rav = match previous_rav {
+        Some((version, rav)) => Some((domain_of_version(domains, version)?, rav)),
+        None => None,
+    };
+
+    let rav = check_and_aggregate(
+        domain_separator,
+        receipts,
+        previous_rav,
+        accepted_addresses,
+        options,
+    )?;
+    Ok(Eip712SignedMessage::new(domain_separator, rav, wallet)?)
+}
+
+/// Checks and aggregates receipts signed under `domain_separator`, onto a
+/// previous RAV signed under its own domain, into an unsigned RAV.
+fn check_and_aggregate(
+    domain_separator: &Eip712Domain,
+    receipts: &[Eip712SignedMessage<Receipt>],
+    previous_rav: Option<(&Eip712Domain, Eip712SignedMessage<ReceiptAggregateVoucher>)>,
+    accepted_addresses: &HashSet<Address>,
+    options: AggregationOptions,
+) -> Result<ReceiptAggregateVoucher> {
+    // so that the checks report the same receipt whatever the order of the
+    // receipts, see `in_canonical_order`. The aggregation itself does not
+    // depend on the order, and is done on `receipts`. Each receipt is hashed
+    // once, for both the ordering and the recovery of its signer.
+    let hashes = ComputedHashes::batch(
+        domain_separator,
+        receipts.iter().map(|receipt| &receipt.message),
+    );
+    let ordered = in_canonical_order(receipts, &hashes);
+    check_signatures_unique(ordered.receipts())?;
+
+    if options.check_nonces_unique {
+        check_nonces_unique(ordered.receipts())?;
+    }
 
     // Check that the receipts are signed by an accepted signer address
-    receipts.par_iter().try_for_each(|receipt| {
-        check_signature_is_from_one_of_addresses(receipt, domain_separator, accepted_addresses)
+    ordered.par_iter().try_for_each(|(receipt, hashes)| {
+        let signer = check_hashed_signature_is_from_one_of_addresses(
+            receipt,
+            hashes,
+            accepted_addresses,
+            options.accept_any_signer_insecure,
+        )?;
+        check_receipt_signer_not_excluded(signer, options.excluded_receipt_signer)?;
+        Ok(())
     })?;
 
     // Check that the previous rav is signed by an accepted signer address
-    if let Some(previous_rav) = &previous_rav {
-        check_signature_is_from_one_of_addresses(
-            previous_rav,
-            domain_separator,
-            accepted_addresses,
-        )?;
-    }
+    let previous_rav = match previous_rav {
+        Some((previous_rav_domain, previous_rav)) => {
+            check_signature_is_from_one_of_addresses(
+                &previous_rav,
+                previous_rav_domain,
+                accepted_addresses,
+                options.accept_any_signer_insecure,
+            )?;
+            Some(previous_rav)
+        }
+        None => None,
+    };
+
+    // Refuse to chain onto a previous rav with an implausible value
+    check_previous_rav_value(previous_rav.as_ref(), options.max_previous_rav_value)?;
 
     // Check that the receipts timestamp is greater than the previous rav
-    check_receipt_timestamps(receipts, previous_rav.as_ref())?;
-
-    // Get the allocation id from the first receipt, return error if there are no receipts
-    let (allocation_id, payer, data_service, service_provider) = match receipts.first() {
-        Some(receipt) => (
-            receipt.message.allocation_id,
-            receipt.message.payer,
-            receipt.message.data_service,
-            receipt.message.service_provider,
-        ),
+    check_receipt_timestamps(ordered.receipts(), previous_rav.as_ref())?;
+
+    // Get the key from the first receipt, return error if there are no receipts
+    let key = match ordered.receipts().next() {
+        Some(receipt) => receipt.message.key(),
         None => return Err(tap_core::Error::NoValidReceiptsForRavRequest.into()),
     };
+    let (allocation_id, payer, data_service, service_provider) = key;
 
-    // Check that the receipts all have the same allocation id
-    check_allocation_id(
-        receipts,
-        allocation_id,
-        payer,
-        data_service,
-        service_provider,
-    )?;
+    // Check that the receipts all have the same allocation id, payer, data
+    // service and service provider
+    check_allocation_id(ordered.receipts(), key)?;
 
-    // Check that the rav has the correct allocation id
+    // Check that the rav has the same allocation id, payer, data service and
+    // service provider
     if let Some(previous_rav) = &previous_rav {
-        let prev_id = previous_rav.message.allocationId;
-        let prev_payer = previous_rav.message.payer;
-        let prev_data_service = previous_rav.message.dataService;
-        let prev_service_provider = previous_rav.message.serviceProvider;
-        if prev_id != allocation_id {
-            return Err(tap_core::Error::RavAllocationIdMismatch {
-                prev_id: format!("{prev_id:#X}"),
-                new_id: format!("{allocation_id:#X}"),
-            }
-            .into());
-        }
-        if prev_payer != payer {
-            return Err(tap_core::Error::RavAllocationIdMismatch {
-                prev_id: format!("{prev_id:#X}"),
-                new_id: format!("{allocation_id:#X}"),
-            }
-            .into());
-        }
-
-        if prev_data_service != data_service {
-            return Err(tap_core::Error::RavAllocationIdMismatch {
-                prev_id: format!("{prev_id:#X}"),
-                new_id: format!("{allocation_id:#X}"),
-            }
-            .into());
-        }
-        if prev_service_provider != service_provider {
+        let rav = &previous_rav.message;
+        let prev_key = (
+            rav.allocationId,
+            rav.payer,
+            rav.dataService,
+            rav.serviceProvider,
+        );
+        if prev_key != key {
+            let prev_id = rav.allocationId;
             return Err(tap_core::Error::RavAllocationIdMismatch {
                 prev_id: format!("{prev_id:#X}"),
                 new_id: format!("{allocation_id:#X}"),
@@ -96,7 +209,7 @@ pub fn check_and_aggregate_receipts(
     }
 
     // Aggregate the receipts
-    let rav = ReceiptAggregateVoucher::aggregate_receipts(
+    let mut rav = ReceiptAggregateVoucher::aggregate_receipts(
         allocation_id,
         payer,
         data_service,
@@ -104,95 +217,27 @@ pub fn check_and_aggregate_receipts(
         receipts,
         previous_rav,
     )?;
+    rav.timestampNs = options
+        .rav_timestamp_policy
+        .rav_timestamp_ns(rav.timestampNs)?;
 
-    // Sign the rav and return
-    Ok(Eip712SignedMessage::new(domain_separator, rav, wallet)?)
-}
-
-fn check_signature_is_from_one_of_addresses<M: SolStruct>(
-    message: &Eip712SignedMessage<M>,
-    domain_separator: &Eip712Domain,
-    accepted_addresses: &HashSet<Address>,
-) -> Result<()> {
-    let recovered_address = message.recover_signer(domain_separator)?;
-    if !accepted_addresses.contains(&recovered_address) {
-        bail!(tap_core::Error::InvalidRecoveredSigner {
-            address: recovered_address,
-        });
-    }
-    Ok(())
-}
-
-fn check_allocation_id(
-    receipts: &[Eip712SignedMessage<Receipt>],
-    allocation_id: Address,
-    payer: Address,
-    data_service: Address,
-    service_provider: Address,
-) -> Result<()> {
-    for receipt in receipts.iter() {
-        let receipt = &receipt.message;
-        if receipt.allocation_id != allocation_id {
-            return Err(tap_core::Error::RavAllocationIdNotUniform.into());
-        }
-        if receipt.payer != payer {
-            return Err(tap_core::Error::RavAllocationIdNotUniform.into());
-        }
-        if receipt.data_service != data_service {
-            return Err(tap_core::Error::RavAllocationIdNotUniform.into());
-        }
-        if receipt.service_provider != service_provider {
-            return Err(tap_core::Error::RavAllocationIdNotUniform.into());
-        }
-    }
-    Ok(())
-}
-
-fn check_signatures_unique(receipts: &[Eip712SignedMessage<Receipt>]) -> Result<()> {
-    let mut receipt_signatures: hash_set::HashSet<SignatureBytes> = hash_set::HashSet::new();
-    for receipt in receipts.iter() {
-        let signature = receipt.signature.get_signature_bytes();
-        if !receipt_signatures.insert(signature) {
-            return Err(tap_core::Error::DuplicateReceiptSignature(format!(
-                "{:?}",
-                receipt.signature
-            ))
-            .into());
-        }
-    }
-    Ok(())
-}
-
-fn check_receipt_timestamps(
-    receipts: &[Eip712SignedMessage<Receipt>],
-    previous_rav: Option<&Eip712SignedMessage<ReceiptAggregateVoucher>>,
-) -> Result<()> {
-    if let Some(previous_rav) = &previous_rav {
-        for receipt in receipts.iter() {
-            let receipt = &receipt.message;
-            if previous_rav.message.timestampNs >= receipt.timestamp_ns {
-                return Err(tap_core::Error::ReceiptTimestampLowerThanRav {
-                    rav_ts: previous_rav.message.timestampNs,
-                    receipt_ts: receipt.timestamp_ns,
-                }
-                .into());
-            }
-        }
-    }
-
-    Ok(())
+    Ok(rav)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::{HashMap, HashSet};
+
     use alloy::{
         dyn_abi::Eip712Domain,
         primitives::{address, Address, Bytes},
-        signers::local::PrivateKeySigner,
+        signers::{local::PrivateKeySigner, Signer},
     };
     use rstest::*;
     use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
-    use tap_graph::v2::{Receipt, ReceiptAggregateVoucher};
+    use tap_graph::v2::{DataService, Payer, Receipt, ReceiptAggregateVoucher, ServiceProvider};
+
+    use crate::aggregator::{tests::RemoteSigner, AggregationOptions};
 
     #[fixture]
     fn keys() -> (PrivateKeySigner, Address) {
@@ -244,7 +289,14 @@ mod tests {
         let mut receipts = Vec::new();
         let receipt = Eip712SignedMessage::new(
             &domain_separator,
-            Receipt::new(allocation_id, payer, data_service, service_provider, 42).unwrap(),
+            Receipt::new(
+                allocation_id,
+                Payer(payer),
+                DataService(data_service),
+                ServiceProvider(service_provider),
+                42,
+            )
+            .unwrap(),
             &keys.0,
         )
         .unwrap();
@@ -269,13 +321,27 @@ mod tests {
         let receipts = vec![
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(allocation_id, payer, data_service, service_provider, 42).unwrap(),
+                Receipt::new(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    42,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(allocation_id, payer, data_service, service_provider, 42).unwrap(),
+                Receipt::new(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    42,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
@@ -388,19 +454,40 @@ mod tests {
         let receipts = vec![
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(allocation_id, payer, data_service, service_provider, 42).unwrap(),
+                Receipt::new(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    42,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(allocation_id, payer, data_service, service_provider, 43).unwrap(),
+                Receipt::new(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    43,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(other_address, payer, data_service, service_provider, 44).unwrap(),
+                Receipt::new(
+                    other_address,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    44,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
@@ -408,10 +495,7 @@ mod tests {
 
         let res = super::check_allocation_id(
             &receipts,
-            allocation_id,
-            payer,
-            data_service,
-            service_provider,
+            (allocation_id, payer, data_service, service_provider),
         );
 
         assert!(res.is_err());
@@ -431,19 +515,40 @@ mod tests {
         let receipts = vec![
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(allocation_id, payer, data_service, service_provider, 42).unwrap(),
+                Receipt::new(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    42,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(allocation_id, payer, data_service, service_provider, 43).unwrap(),
+                Receipt::new(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    43,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
             Eip712SignedMessage::new(
                 &domain_separator,
-                Receipt::new(allocation_id, payer, data_service, service_provider, 44).unwrap(),
+                Receipt::new(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    44,
+                )
+                .unwrap(),
                 &keys.0,
             )
             .unwrap(),
@@ -451,12 +556,298 @@ mod tests {
 
         let res = super::check_allocation_id(
             &receipts,
-            allocation_id,
-            payer,
-            data_service,
-            service_provider,
+            (allocation_id, payer, data_service, service_provider),
         );
 
         assert!(res.is_ok());
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn async_signer(
+        keys: (PrivateKeySigner, Address),
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipts: Vec<_> = (0..3)
+            .map(|_| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt::new(
+                        allocation_id,
+                        Payer(payer),
+                        DataService(data_service),
+                        ServiceProvider(service_provider),
+                        42,
+                    )
+                    .unwrap(),
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+        let accepted_addresses = HashSet::from([keys.1]);
+        let remote_signer = RemoteSigner(keys.0.clone());
+
+        let rav = super::check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            None,
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 126);
+
+        // the signatures are deterministic, so that every signer gives the same RAV
+        let signers: [&(dyn Signer + Sync); 2] = [&keys.0, &remote_signer];
+        for signer in signers {
+            let async_rav = super::check_and_aggregate_receipts_async(
+                &domain_separator,
+                &receipts,
+                None,
+                signer,
+                &accepted_addresses,
+                AggregationOptions::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(async_rav, rav);
+        }
+
+        // the receipts are checked as in the synchronous variant
+        let res = super::check_and_aggregate_receipts_async(
+            &domain_separator,
+            &[receipts[0].clone(), receipts[0].clone()],
+            None,
+            &remote_signer,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[rstest]
+    #[test]
+    /// Test that a previous rav is accepted up to the maximum value, and refused above it
+    fn check_max_previous_rav_value(
+        keys: (PrivateKeySigner, Address),
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        domain_separator: Eip712Domain,
+    ) {
+        let accepted_addresses = HashSet::from([keys.1]);
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(
+                allocation_id,
+                Payer(payer),
+                DataService(data_service),
+                ServiceProvider(service_provider),
+                42,
+            )
+            .unwrap(),
+            &keys.0,
+        )
+        .unwrap()];
+        let previous_rav = Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                dataService: data_service,
+                payer,
+                serviceProvider: service_provider,
+                timestampNs: 0,
+                valueAggregate: 1000,
+                metadata: Bytes::new(),
+            },
+            &keys.0,
+        )
+        .unwrap();
+
+        let rav = super::check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            Some(previous_rav.clone()),
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions {
+                max_previous_rav_value: Some(1000),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 1042);
+
+        let err = super::check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            Some(previous_rav),
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions {
+                max_previous_rav_value: Some(999),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::PreviousRavValueTooHigh {
+                value: 1000,
+                max_value: 999
+            })
+        ));
+    }
+
+    #[rstest]
+    #[test]
+    fn check_nonces_unique(
+        keys: (PrivateKeySigner, Address),
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipt = |timestamp_ns, nonce| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt {
+                    allocation_id,
+                    payer,
+                    data_service,
+                    service_provider,
+                    timestamp_ns,
+                    nonce,
+                    value: 42,
+                },
+                &keys.0,
+            )
+            .unwrap()
+        };
+
+        // distinct nonces
+        let receipts = vec![receipt(10, 1), receipt(11, 2)];
+        assert!(super::check_nonces_unique(&receipts).is_ok());
+
+        // same nonce, different signatures
+        let receipts = vec![receipt(10, 1), receipt(11, 1)];
+        assert!(super::check_signatures_unique(&receipts).is_ok());
+        assert!(super::check_nonces_unique(&receipts).is_err());
+
+        // only rejected when enabled
+        let accepted_addresses = HashSet::from([keys.1]);
+        assert!(super::check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            None,
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions::default(),
+        )
+        .is_ok());
+        let err = super::check_and_aggregate_receipts(
+            &domain_separator,
+            &receipts,
+            None,
+            &keys.0,
+            &accepted_addresses,
+            AggregationOptions {
+                check_nonces_unique: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::DuplicateReceiptNonce { nonce: 1, .. })
+        ));
+    }
+
+    #[rstest]
+    #[test]
+    /// Test that receipts are aggregated onto a previous rav signed under the old domain
+    fn multi_domain_carries_previous_rav_over(
+        keys: (PrivateKeySigner, Address),
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+    ) {
+        let domains = HashMap::from([
+            (
+                "old".to_string(),
+                tap_eip712_domain(1, Address::from([0x11u8; 20])),
+            ),
+            (
+                "new".to_string(),
+                tap_eip712_domain(2, Address::from([0x11u8; 20])),
+            ),
+        ]);
+        let previous_rav = Eip712SignedMessage::new(
+            &domains["old"],
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                dataService: data_service,
+                payer,
+                serviceProvider: service_provider,
+                timestampNs: 0,
+                valueAggregate: 100,
+                metadata: Bytes::new(),
+            },
+            &keys.0,
+        )
+        .unwrap();
+        let receipts = vec![Eip712SignedMessage::new(
+            &domains["new"],
+            Receipt::new(
+                allocation_id,
+                Payer(payer),
+                DataService(data_service),
+                ServiceProvider(service_provider),
+                42,
+            )
+            .unwrap(),
+            &keys.0,
+        )
+        .unwrap()];
+
+        let rav = super::check_and_aggregate_receipts_multi_domain(
+            &domains,
+            "new",
+            &receipts,
+            Some(("old", previous_rav.clone())),
+            &keys.0,
+            &HashSet::from([keys.1]),
+            AggregationOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(rav.message.valueAggregate, 142);
+        // the new rav is signed under the domain of the receipts
+        assert_eq!(rav.recover_signer(&domains["new"]).unwrap(), keys.1);
+
+        // the previous rav is not accepted under the domain of the receipts
+        let err = super::check_and_aggregate_receipts_multi_domain(
+            &domains,
+            "new",
+            &receipts,
+            Some(("new", previous_rav)),
+            &keys.0,
+            &HashSet::from([keys.1]),
+            AggregationOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<tap_core::Error>(),
+            Some(tap_core::Error::InvalidRecoveredSigner { .. })
+        ));
+    }
 }
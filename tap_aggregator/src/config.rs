@@ -0,0 +1,199 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Configuration of the aggregator that does not depend on the CLI, so that
+//! the aggregator can be embedded as a library.
+
+use std::{env, str::FromStr};
+
+use alloy::{dyn_abi::Eip712Domain, primitives::Address};
+use anyhow::{anyhow, Result};
+use tap_core::tap_eip712_domain;
+
+/// Chain ID used when none is configured.
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+
+/// Verifying contract used when none is configured.
+///
+/// This is a placeholder, the zero address, and not a deployed TAP verifier:
+/// no on-chain verifier accepts the RAVs signed for it. Deployments must
+/// configure the address of the verifier of their chain. It is only allowed
+/// with the [`DEFAULT_CHAIN_ID`], see [`DomainConfig::validate`].
+pub const DEFAULT_VERIFYING_CONTRACT: Address = Address::ZERO;
+
+/// Settings used to build the EIP-712 domain separator.
+///
+/// The domain name and version are fixed by [`tap_eip712_domain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainConfig {
+    /// Chain ID of the chain where the verifying contract is deployed.
+    pub chain_id: u64,
+    /// Address of the contract verifying the signatures.
+    pub verifying_contract: Address,
+}
+
+/// The [`DEFAULT_CHAIN_ID`] chain (mainnet) with the placeholder
+/// [`DEFAULT_VERIFYING_CONTRACT`], e.g. for tests. The verifying contract must
+/// be set to a deployed verifier for the RAVs to be redeemable.
+impl Default for DomainConfig {
+    fn default() -> Self {
+        Self {
+            chain_id: DEFAULT_CHAIN_ID,
+            verifying_contract: DEFAULT_VERIFYING_CONTRACT,
+        }
+    }
+}
+
+impl DomainConfig {
+    /// Reads the configuration from the `TAP_DOMAIN_CHAIN_ID` and
+    /// `TAP_DOMAIN_VERIFYING_CONTRACT` environment variables, the same ones
+    /// used by the `tap_aggregator` CLI.
+    ///
+    /// Missing variables resolve to the same defaults as the CLI.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a variable is set but cannot be parsed, or if the
+    /// configuration is rejected by [`DomainConfig::validate`].
+    pub fn from_env() -> Result<Self> {
+        let config = Self {
+            chain_id: parse_env_var("TAP_DOMAIN_CHAIN_ID")?.unwrap_or(DEFAULT_CHAIN_ID),
+            verifying_contract: parse_env_var("TAP_DOMAIN_VERIFYING_CONTRACT")?
+                .unwrap_or(DEFAULT_VERIFYING_CONTRACT),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the placeholder [`DEFAULT_VERIFYING_CONTRACT`] is only
+    /// used with the [`DEFAULT_CHAIN_ID`], so that configuring a chain
+    /// without its verifying contract is an error rather than silently
+    /// signing RAVs for the zero address.
+    ///
+    /// Custom verifying contracts are not checked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the default verifying contract is used with a
+    /// chain ID other than [`DEFAULT_CHAIN_ID`].
+    pub fn validate(&self) -> Result<()> {
+        if self.verifying_contract == DEFAULT_VERIFYING_CONTRACT
+            && self.chain_id != DEFAULT_CHAIN_ID
+        {
+            return Err(anyhow!(
+                "Chain ID {} requires a verifying contract, the default one is a placeholder \
+                 only allowed with chain ID {}. Set the verifying contract deployed on chain {}.",
+                self.chain_id,
+                DEFAULT_CHAIN_ID,
+                self.chain_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Builds the EIP-712 domain separator for this configuration.
+    pub fn eip712_domain(&self) -> Eip712Domain {
+        tap_eip712_domain(self.chain_id, self.verifying_contract)
+    }
+}
+
+fn parse_env_var<T>(name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid value for {name}: {e}")),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(anyhow!("Invalid value for {name}: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use alloy::primitives::Address;
+
+    use super::*;
+
+    #[test]
+    fn default_verifying_contract_requires_default_chain_id() {
+        let config = DomainConfig {
+            chain_id: DEFAULT_CHAIN_ID,
+            verifying_contract: DEFAULT_VERIFYING_CONTRACT,
+        };
+        assert!(config.validate().is_ok());
+
+        let config = DomainConfig {
+            chain_id: 42161,
+            verifying_contract: DEFAULT_VERIFYING_CONTRACT,
+        };
+        assert!(config.validate().is_err());
+
+        // custom contracts are not checked
+        let config = DomainConfig {
+            chain_id: 42161,
+            verifying_contract: Address::from([0x11u8; 20]),
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn default_domain_config_is_mainnet() {
+        let config = DomainConfig::default();
+        assert_eq!(
+            config,
+            DomainConfig {
+                chain_id: 1,
+                verifying_contract: DEFAULT_VERIFYING_CONTRACT,
+            }
+        );
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.eip712_domain(),
+            tap_eip712_domain(1, DEFAULT_VERIFYING_CONTRACT)
+        );
+    }
+
+    // Environment variables are process-wide, so all cases run in a single test.
+    #[test]
+    fn domain_config_from_env() {
+        env::remove_var("TAP_DOMAIN_CHAIN_ID");
+        env::remove_var("TAP_DOMAIN_VERIFYING_CONTRACT");
+        assert_eq!(
+            DomainConfig::from_env().unwrap(),
+            DomainConfig {
+                chain_id: DEFAULT_CHAIN_ID,
+                verifying_contract: Address::ZERO,
+            }
+        );
+
+        env::set_var("TAP_DOMAIN_CHAIN_ID", "42161");
+        env::set_var(
+            "TAP_DOMAIN_VERIFYING_CONTRACT",
+            "0x1111111111111111111111111111111111111111",
+        );
+        let config = DomainConfig::from_env().unwrap();
+        assert_eq!(
+            config,
+            DomainConfig {
+                chain_id: 42161,
+                verifying_contract: Address::from([0x11u8; 20]),
+            }
+        );
+        assert_eq!(
+            config.eip712_domain(),
+            tap_eip712_domain(42161, Address::from([0x11u8; 20]))
+        );
+
+        env::set_var("TAP_DOMAIN_CHAIN_ID", "not a number");
+        assert!(DomainConfig::from_env().is_err());
+
+        env::remove_var("TAP_DOMAIN_CHAIN_ID");
+        env::remove_var("TAP_DOMAIN_VERIFYING_CONTRACT");
+    }
+}
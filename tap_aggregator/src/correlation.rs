@@ -0,0 +1,119 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Correlation IDs of the aggregation requests.
+//!
+//! Each request is identified by the ID sent by the client in the
+//! [`CORRELATION_ID_HEADER`] HTTP header or gRPC metadata, or else by a
+//! random UUID. The ID prefixes the logs of the request and is written along
+//! with its RAV in the RAV log, so that both can be joined with the records
+//! of the client.
+
+use std::fmt;
+
+use hyper::HeaderMap;
+use rand::Rng;
+use tonic::metadata::MetadataMap;
+
+/// HTTP header, or gRPC metadata key, holding the correlation ID of a request.
+pub const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+/// Maximum length of the correlation IDs sent by the clients.
+pub const MAX_CORRELATION_ID_LEN: usize = 128;
+
+/// Correlation ID of an aggregation request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorrelationId(String);
+
+impl CorrelationId {
+    /// Returns a random UUID (version 4).
+    pub fn generate() -> Self {
+        let mut bytes: [u8; 16] = rand::thread_rng().gen();
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex = alloy::primitives::hex::encode(bytes);
+        Self(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        ))
+    }
+
+    /// Returns the correlation ID `value`, or `None` if it is empty, longer
+    /// than [`MAX_CORRELATION_ID_LEN`] or holds other characters than visible
+    /// ASCII ones, so that it can be logged as is.
+    pub fn parse(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= MAX_CORRELATION_ID_LEN
+            && value.bytes().all(|b| b.is_ascii_graphic());
+        valid.then(|| Self(value.to_owned()))
+    }
+
+    /// Returns the correlation ID of the HTTP request with `headers`,
+    /// generating one if it has none or it is invalid.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self::parse_or_generate(
+            headers
+                .get(CORRELATION_ID_HEADER)
+                .and_then(|value| value.to_str().ok()),
+        )
+    }
+
+    /// Same as [`CorrelationId::from_headers`], for a gRPC request.
+    pub fn from_metadata(metadata: &MetadataMap) -> Self {
+        Self::parse_or_generate(
+            metadata
+                .get(CORRELATION_ID_HEADER)
+                .and_then(|value| value.to_str().ok()),
+        )
+    }
+
+    fn parse_or_generate(value: Option<&str>) -> Self {
+        value.and_then(Self::parse).unwrap_or_else(Self::generate)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::HeaderMap;
+
+    use super::{CorrelationId, CORRELATION_ID_HEADER};
+
+    #[test]
+    fn correlation_id_is_taken_from_the_headers_or_generated() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CORRELATION_ID_HEADER, "request-42".parse().unwrap());
+        assert_eq!(CorrelationId::from_headers(&headers).as_str(), "request-42");
+
+        // invalid IDs are replaced, as missing ones
+        headers.insert(CORRELATION_ID_HEADER, "a b".parse().unwrap());
+        let generated = CorrelationId::from_headers(&headers);
+        assert_ne!(generated.as_str(), "a b");
+        assert_ne!(
+            CorrelationId::from_headers(&HeaderMap::new()),
+            CorrelationId::from_headers(&HeaderMap::new())
+        );
+
+        // formatted as a version 4 UUID
+        let uuid = generated.as_str();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(
+            uuid.split('-').map(str::len).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+        assert_eq!(&uuid[14..15], "4");
+    }
+}
@@ -9,17 +9,8 @@
 //! - Errors: `[-32000, -32049]`, where `-32000` is reserved for all errors without a specific code.
 //! - Warnings: `[-32050, -32099]`, where `-32050` is reserved for all warnings without a specific code.
 
-/// JSON-RPC error codes specific to the TAP aggregator.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum JsonRpcErrorCode {
-    /// -32000 -- Generic error.
-    #[allow(dead_code)]
-    Generic = -32000,
-    /// -32001 -- Invalid API version.
-    InvalidVersion = -32001,
-    /// -32002 -- Error during receipt aggregation.
-    Aggregation = -32002,
-}
+/// JSON-RPC error codes, shared with the conversion of the [`tap_core::Error`]s.
+pub use tap_core::jsonrpc::JsonRpcErrorCode;
 
 /// JSON-RPC warning codes
 /// These are not part of the JSON-RPC spec, but are used to provide additional information to the
@@ -1,6 +1,52 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+/// Conversions between an [`alloy::dyn_abi::Eip712Domain`] and the
+/// `GetEip712DomainResponse` of the module it is invoked in, which is the same
+/// message in every API version. The salt is sent as empty bytes if there is
+/// none.
+macro_rules! eip712_domain_conversions {
+    () => {
+        impl TryFrom<&Eip712Domain> for self::GetEip712DomainResponse {
+            type Error = anyhow::Error;
+            fn try_from(domain: &Eip712Domain) -> Result<Self, Self::Error> {
+                let chain_id = match domain.chain_id {
+                    Some(chain_id) => u64::try_from(chain_id)
+                        .map_err(|_| anyhow!("Chain ID {chain_id} does not fit in 64 bits"))?,
+                    None => 0,
+                };
+                Ok(Self {
+                    name: domain.name.as_deref().unwrap_or_default().to_string(),
+                    version: domain.version.as_deref().unwrap_or_default().to_string(),
+                    chain_id,
+                    verifying_contract: domain
+                        .verifying_contract
+                        .map(|address| address.to_vec())
+                        .unwrap_or_default(),
+                    salt: domain.salt.map(|salt| salt.to_vec()).unwrap_or_default(),
+                })
+            }
+        }
+
+        impl TryFrom<self::GetEip712DomainResponse> for Eip712Domain {
+            type Error = anyhow::Error;
+            fn try_from(domain: self::GetEip712DomainResponse) -> Result<Self, Self::Error> {
+                let salt = match domain.salt.as_slice() {
+                    [] => None,
+                    salt => Some(salt.try_into()?),
+                };
+                Ok(Self::new(
+                    Some(domain.name.into()),
+                    Some(domain.version.into()),
+                    Some(U256::from(domain.chain_id)),
+                    Some(domain.verifying_contract.as_slice().try_into()?),
+                    salt,
+                ))
+            }
+        }
+    };
+}
+
 pub mod uint128 {
     tonic::include_proto!("grpc.uint128");
 
@@ -20,8 +66,11 @@ pub mod uint128 {
 }
 
 pub mod v1 {
+    use alloy::{dyn_abi::Eip712Domain, primitives::U256};
     use anyhow::anyhow;
-    use tap_core::signed_message::Eip712SignedMessage;
+    use tap_core::signed_message::{Eip712SignedMessage, SignatureScheme};
+
+    use crate::api_versioning::TapRpcApiVersionsInfo;
 
     tonic::include_proto!("tap_aggregator.v1");
 
@@ -42,6 +91,7 @@ pub mod v1 {
         fn try_from(receipt: self::SignedReceipt) -> Result<Self, Self::Error> {
             Ok(Self {
                 signature: receipt.signature.as_slice().try_into()?,
+                signature_scheme: SignatureScheme::EcdsaSecp256k1,
                 message: receipt
                     .message
                     .ok_or(anyhow!("Missing message"))?
@@ -75,6 +125,7 @@ pub mod v1 {
         fn try_from(voucher: self::SignedRav) -> Result<Self, Self::Error> {
             Ok(Self {
                 signature: voucher.signature.as_slice().try_into()?,
+                signature_scheme: SignatureScheme::EcdsaSecp256k1,
                 message: voucher
                     .message
                     .ok_or(anyhow!("Missing message"))?
@@ -116,6 +167,25 @@ pub mod v1 {
         }
     }
 
+    eip712_domain_conversions!();
+
+    impl From<TapRpcApiVersionsInfo> for self::GetApiVersionsResponse {
+        fn from(info: TapRpcApiVersionsInfo) -> Self {
+            Self {
+                versions_supported: info
+                    .versions_supported
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                versions_deprecated: info
+                    .versions_deprecated
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            }
+        }
+    }
+
     impl self::RavRequest {
         pub fn new(
             receipts: Vec<tap_graph::SignedReceipt>,
@@ -126,6 +196,17 @@ pub mod v1 {
                 previous_rav: previous_rav.map(Into::into),
             }
         }
+
+        /// Takes the receipts out of the request and converts them. The
+        /// vector is allocated once, instead of growing with each receipt.
+        pub fn take_receipts(&mut self) -> anyhow::Result<Vec<tap_graph::SignedReceipt>> {
+            let receipts = std::mem::take(&mut self.receipts);
+            let mut signed_receipts = Vec::with_capacity(receipts.len());
+            for receipt in receipts {
+                signed_receipts.push(receipt.try_into()?);
+            }
+            Ok(signed_receipts)
+        }
     }
 
     impl self::RavResponse {
@@ -137,13 +218,27 @@ pub mod v1 {
                 .try_into()?;
             Ok(signed_rav)
         }
+
+        /// Returns the RAV of a response from an aggregator running in
+        /// verify-only mode
+        pub fn unsigned_rav(mut self) -> anyhow::Result<tap_graph::ReceiptAggregateVoucher> {
+            self.unsigned_rav
+                .take()
+                .ok_or(anyhow!("Couldn't find unsigned rav"))?
+                .try_into()
+        }
     }
 }
 
 pub mod v2 {
-    use alloy::primitives::Bytes;
+    use alloy::{
+        dyn_abi::Eip712Domain,
+        primitives::{Bytes, U256},
+    };
     use anyhow::anyhow;
-    use tap_core::signed_message::Eip712SignedMessage;
+    use tap_core::signed_message::{Eip712SignedMessage, SignatureScheme};
+
+    use crate::api_versioning::TapRpcApiVersionsInfo;
 
     tonic::include_proto!("tap_aggregator.v2");
 
@@ -167,6 +262,7 @@ pub mod v2 {
         fn try_from(receipt: self::SignedReceipt) -> Result<Self, Self::Error> {
             Ok(Self {
                 signature: receipt.signature.as_slice().try_into()?,
+                signature_scheme: SignatureScheme::EcdsaSecp256k1,
                 message: receipt
                     .message
                     .ok_or(anyhow!("Missing message"))?
@@ -203,6 +299,7 @@ pub mod v2 {
         fn try_from(voucher: self::SignedRav) -> Result<Self, Self::Error> {
             Ok(Self {
                 signature: voucher.signature.as_slice().try_into()?,
+                signature_scheme: SignatureScheme::EcdsaSecp256k1,
                 message: voucher
                     .message
                     .ok_or(anyhow!("Missing message"))?
@@ -252,6 +349,25 @@ pub mod v2 {
         }
     }
 
+    eip712_domain_conversions!();
+
+    impl From<TapRpcApiVersionsInfo> for self::GetApiVersionsResponse {
+        fn from(info: TapRpcApiVersionsInfo) -> Self {
+            Self {
+                versions_supported: info
+                    .versions_supported
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                versions_deprecated: info
+                    .versions_deprecated
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            }
+        }
+    }
+
     impl self::RavRequest {
         pub fn new(
             receipts: Vec<tap_graph::v2::SignedReceipt>,
@@ -262,6 +378,17 @@ pub mod v2 {
                 previous_rav: previous_rav.map(Into::into),
             }
         }
+
+        /// Takes the receipts out of the request and converts them. The
+        /// vector is allocated once, instead of growing with each receipt.
+        pub fn take_receipts(&mut self) -> anyhow::Result<Vec<tap_graph::v2::SignedReceipt>> {
+            let receipts = std::mem::take(&mut self.receipts);
+            let mut signed_receipts = Vec::with_capacity(receipts.len());
+            for receipt in receipts {
+                signed_receipts.push(receipt.try_into()?);
+            }
+            Ok(signed_receipts)
+        }
     }
 
     impl self::RavResponse {
@@ -273,5 +400,14 @@ pub mod v2 {
                 .try_into()?;
             Ok(signed_rav)
         }
+
+        /// Returns the RAV of a response from an aggregator running in
+        /// verify-only mode
+        pub fn unsigned_rav(mut self) -> anyhow::Result<tap_graph::v2::ReceiptAggregateVoucher> {
+            self.unsigned_rav
+                .take()
+                .ok_or(anyhow!("Couldn't find unsigned rav"))?
+                .try_into()
+        }
     }
 }
@@ -19,6 +19,10 @@ pub struct JsonRpcResponse<T: Serialize> {
     pub data: T,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub warnings: Option<Vec<JsonRpcWarning>>,
+    /// Reference of the RAV in `data`, to send back as `previous_rav_ref`
+    /// instead of the RAV. Only set when the server caches the RAVs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rav_ref: Option<String>,
 }
 
 pub type JsonRpcError = jsonrpsee::types::ErrorObjectOwned;
@@ -30,6 +34,7 @@ impl<T: Serialize> JsonRpcResponse<T> {
         JsonRpcResponse {
             data,
             warnings: None,
+            rav_ref: None,
         }
     }
 
@@ -43,6 +48,7 @@ impl<T: Serialize> JsonRpcResponse<T> {
             } else {
                 Some(warnings)
             },
+            rav_ref: None,
         }
     }
 }
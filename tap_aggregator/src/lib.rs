@@ -3,8 +3,13 @@
 
 pub mod aggregator;
 pub mod api_versioning;
+pub mod config;
+pub mod correlation;
 pub mod error_codes;
 pub mod grpc;
 pub mod jsonrpsee_helpers;
 pub mod metrics;
+pub mod rav_cache;
+pub mod rav_log;
+pub mod readiness;
 pub mod server;
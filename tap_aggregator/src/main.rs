@@ -3,23 +3,44 @@
 
 #![doc = include_str!("../README.md")]
 
-use std::{collections::HashSet, str::FromStr};
+use std::{
+    collections::HashSet, convert::Infallible, ffi::OsString, net::IpAddr, path::PathBuf,
+    str::FromStr, time::Duration,
+};
 
 use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
-use anyhow::Result;
-use clap::Parser;
-use log::{debug, info};
-use tap_aggregator::{metrics, server};
-use tap_core::tap_eip712_domain;
+use anyhow::{bail, Context, Result};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, Parser};
+use log::{debug, info, warn};
+use tap_aggregator::{
+    aggregator::{AggregationOptions, RavTimestampPolicy},
+    config::{DomainConfig, DEFAULT_CHAIN_ID, DEFAULT_VERIFYING_CONTRACT},
+    metrics,
+    rav_log::{RavLog, DEFAULT_QUEUE_SIZE},
+    readiness::ReadinessGate,
+    server,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// TOML file holding settings, keyed by their flag name without the leading dashes, e.g.
+    /// `max-connections = 64`. Settings set with a flag or an environment variable override the
+    /// ones in the file.
+    #[arg(long, env = "TAP_CONFIG_FILE")]
+    config_file: Option<PathBuf>,
+
     /// Port to listen on for JSON-RPC requests.
     /// Defaults to 8080.
     #[arg(long, default_value_t = 8080, env = "TAP_PORT")]
     port: u16,
 
+    /// Address of the interface the JSON-RPC and metrics servers listen on, e.g. 127.0.0.1 to
+    /// only accept local connections.
+    /// Defaults to all the interfaces (0.0.0.0).
+    #[arg(long, default_value = "0.0.0.0", env = "TAP_BIND_ADDRESS")]
+    bind_address: IpAddr,
+
     /// Signer private key for signing Receipt Aggregate Vouchers, as a hex string.
     #[arg(long, env = "TAP_PRIVATE_KEY")]
     private_key: String,
@@ -47,6 +68,89 @@ struct Args {
     #[arg(long, default_value_t = 32, env = "TAP_MAX_CONNECTIONS")]
     max_connections: u32,
 
+    /// Maximum number of aggregation requests processed concurrently, across all connections.
+    /// Requests above the limit are rejected with a "server busy" error.
+    /// Defaults to no limit.
+    #[arg(long, env = "TAP_MAX_IN_FLIGHT")]
+    max_in_flight: Option<u32>,
+
+    /// Interval in seconds between the HTTP/2 pings keeping the gRPC connections alive.
+    /// Defaults to no pings.
+    #[arg(long, env = "TAP_HTTP2_KEEPALIVE_INTERVAL_SECS")]
+    http2_keepalive_interval_secs: Option<u64>,
+
+    /// Time in seconds to wait for an HTTP/2 ping acknowledgement before closing the connection.
+    /// Defaults to 20 seconds.
+    #[arg(long, env = "TAP_HTTP2_KEEPALIVE_TIMEOUT_SECS")]
+    http2_keepalive_timeout_secs: Option<u64>,
+
+    /// Maximum number of concurrent HTTP/2 streams on each connection.
+    /// Defaults to 200.
+    #[arg(long, env = "TAP_HTTP2_MAX_CONCURRENT_STREAMS")]
+    http2_max_concurrent_streams: Option<u32>,
+
+    /// Appends every signed RAV to this file, as one JSON object per line.
+    /// Defaults to no file.
+    #[arg(long, env = "TAP_RAV_LOG_FILE")]
+    rav_log_file: Option<PathBuf>,
+
+    /// Maximum number of RAVs waiting to be written to the RAV log file.
+    /// Defaults to 1024.
+    #[arg(long, default_value_t = DEFAULT_QUEUE_SIZE, env = "TAP_RAV_LOG_QUEUE_SIZE")]
+    rav_log_queue_size: usize,
+
+    /// Fails the aggregation requests whose RAV cannot be written to the RAV
+    /// log file, e.g. because the queue is full. Otherwise such RAVs are only
+    /// counted in the `rav_log_queue_full_count` metric.
+    #[arg(long, env = "TAP_RAV_LOG_STRICT")]
+    rav_log_strict: bool,
+
+    /// Time in seconds the RAVs signed through JSON-RPC are cached, so that
+    /// clients can send the reference returned with a RAV as
+    /// `previous_rav_ref` instead of the RAV itself.
+    /// Defaults to no cache.
+    #[arg(long, env = "TAP_RAV_CACHE_TTL_SECS")]
+    rav_cache_ttl_secs: Option<u64>,
+
+    /// Checks and aggregates the receipts, but returns the RAVs unsigned
+    /// instead of signing them, e.g. for a staging aggregator.
+    #[arg(long, env = "TAP_VERIFY_ONLY")]
+    verify_only: bool,
+
+    /// Refuses aggregation requests whose previous RAV has a value above this
+    /// maximum, in GRT wei.
+    /// Defaults to no limit.
+    #[arg(long, env = "TAP_MAX_PREVIOUS_RAV_VALUE")]
+    max_previous_rav_value: Option<u128>,
+
+    /// Refuses aggregation requests holding two receipts with the same
+    /// allocation ID and nonce.
+    #[arg(long, env = "TAP_CHECK_NONCES_UNIQUE")]
+    check_nonces_unique: bool,
+
+    /// Refuses the receipts signed by the key of the aggregator, which is
+    /// only accepted for the previous RAVs, so that the aggregator is never
+    /// one of the payers.
+    #[arg(long, env = "TAP_REJECT_OWN_RECEIPTS")]
+    reject_own_receipts: bool,
+
+    /// INSECURE, for development only. Aggregates receipts and RAVs signed by
+    /// any signer, instead of only the signer and the public keys. Signatures
+    /// are still verified. Requires --i-know-this-is-insecure.
+    #[arg(long, env = "TAP_ACCEPT_ANY_SIGNER_INSECURE")]
+    accept_any_signer_insecure: bool,
+
+    /// Timestamp given to the RAVs: the timestamp of their latest receipt
+    /// (max-receipt), or the time of the aggregation (aggregation-time), in
+    /// which case receipts later than the aggregation time are refused.
+    /// Defaults to max-receipt.
+    #[arg(long, value_enum, default_value_t, env = "TAP_RAV_TIMESTAMP_POLICY")]
+    rav_timestamp_policy: RavTimestampPolicy,
+
+    /// Confirms that the insecure settings are wanted.
+    #[arg(long, env = "TAP_I_KNOW_THIS_IS_INSECURE")]
+    i_know_this_is_insecure: bool,
+
     /// Metrics server port.
     /// Defaults to 5000.
     #[arg(long, default_value_t = 5000, env = "TAP_METRICS_PORT")]
@@ -61,10 +165,12 @@ struct Args {
     domain_version: Option<String>,
 
     /// Domain chain ID to be used for the EIP-712 domain separator.
+    /// Must be left to the default (mainnet) unless a verifying contract is also set.
     #[arg(long, env = "TAP_DOMAIN_CHAIN_ID")]
     domain_chain_id: Option<String>,
 
     /// Domain verifying contract to be used for the EIP-712 domain separator.
+    /// Defaults to a placeholder, the zero address, which no deployed verifier accepts.
     #[arg(long, env = "TAP_DOMAIN_VERIFYING_CONTRACT")]
     domain_verifying_contract: Option<Address>,
 
@@ -73,6 +179,78 @@ struct Args {
     domain_salt: Option<String>,
 }
 
+impl Args {
+    /// Parses the settings from the flags and the environment variables, then
+    /// from the `--config-file`, if any, for the settings not set otherwise.
+    fn load() -> Result<Self> {
+        Self::load_from(std::env::args_os().collect())
+    }
+
+    fn load_from(cli: Vec<OsString>) -> Result<Self> {
+        // the private key may only be in the config file
+        let matches = Self::command()
+            .mut_arg("private_key", |arg| arg.required(false))
+            .get_matches_from(cli.clone());
+        let Some(path) = matches.get_one::<PathBuf>("config_file") else {
+            return Ok(Self::parse_from(cli));
+        };
+        let config = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the config file {}", path.display()))?;
+        let config = toml::from_str(&config)
+            .with_context(|| format!("Failed to parse the config file {}", path.display()))?;
+
+        // the values of the file are parsed like flags, so that they are
+        // validated the same way
+        let mut args = vec![cli[0].clone()];
+        args.extend(config_file_flags(&config, &matches)?);
+        args.extend(cli.into_iter().skip(1));
+        Ok(Self::parse_from(args))
+    }
+}
+
+/// Returns the flags setting the values of `config`, skipping the ones
+/// already set with a flag or an environment variable in `matches`.
+fn config_file_flags(config: &toml::Table, matches: &ArgMatches) -> Result<Vec<OsString>> {
+    let command = Args::command();
+    let mut flags = Vec::new();
+    for (key, value) in config {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()) && key != "config-file")
+        else {
+            bail!("Unknown setting `{key}` in the config file");
+        };
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                // e.g. `verify-only = true`
+                toml::Value::Boolean(enabled) if !arg.get_action().takes_values() => {
+                    if *enabled {
+                        flags.push(format!("--{key}").into());
+                    }
+                    continue;
+                }
+                toml::Value::String(value) => value.clone(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => bail!("Unsupported value for `{key}` in the config file"),
+            };
+            flags.push(format!("--{key}={value}").into());
+        }
+    }
+    Ok(flags)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize the logger.
@@ -82,17 +260,16 @@ async fn main() -> Result<()> {
     // See https://github.com/paritytech/jsonrpsee/pull/922 for more info.
     tracing_subscriber::fmt::init();
 
-    let args = Args::parse();
+    let args = Args::load()?;
     debug!("Settings: {:?}", args);
 
     // Start the metrics server.
     // We just let it gracelessly get killed at the end of main()
-    tokio::spawn(metrics::run_server(args.metrics_port));
+    tokio::spawn(metrics::run_server(args.bind_address, args.metrics_port));
 
     // Create a wallet from the mnemonic.
     let wallet = PrivateKeySigner::from_str(&args.private_key)?;
-
-    info!("Wallet address: {:#40x}", wallet.address());
+    let wallet_address = wallet.address();
 
     // Create the EIP-712 domain separator.
     let domain_separator = create_eip712_domain(&args)?;
@@ -104,9 +281,39 @@ async fn main() -> Result<()> {
         accepted_addresses.extend(public_keys.iter().cloned());
     }
 
+    let aggregation_options = AggregationOptions {
+        max_previous_rav_value: args.max_previous_rav_value,
+        check_nonces_unique: args.check_nonces_unique,
+        accept_any_signer_insecure: args.accept_any_signer_insecure,
+        rav_timestamp_policy: args.rav_timestamp_policy,
+        ..Default::default()
+    };
+    aggregation_options.check_insecure(args.i_know_this_is_insecure)?;
+    if let Some(max_value) = aggregation_options.max_previous_rav_value {
+        info!(
+            "Refusing previous RAVs above {}",
+            tap_core::units::format_grt(max_value)
+        );
+    }
+
+    // Open the RAV log file, if any.
+    let (rav_log, rav_log_writer) = match &args.rav_log_file {
+        Some(path) => {
+            info!("Logging RAVs to {}", path.display());
+            let (rav_log, writer) =
+                RavLog::open_with_queue_size(path, args.rav_log_queue_size).await?;
+            (Some(rav_log.with_strict(args.rav_log_strict)), Some(writer))
+        }
+        None => (None, None),
+    };
+
+    // Requests are answered with 503 until the address of the signer is
+    // resolved, see below.
+    let readiness = ReadinessGate::pending();
+
     // Start the JSON-RPC server.
     // This await is non-blocking
-    let (handle, _) = server::run_server(
+    let (handle, _) = server::run_server_with_options(
         args.port,
         wallet,
         accepted_addresses,
@@ -114,14 +321,48 @@ async fn main() -> Result<()> {
         args.max_request_body_size,
         args.max_response_body_size,
         args.max_connections,
+        server::ServerOptions {
+            max_in_flight_requests: args.max_in_flight,
+            readiness: readiness.clone(),
+            rav_log,
+            aggregation: aggregation_options,
+            rav_cache_ttl: args.rav_cache_ttl_secs.map(Duration::from_secs),
+            verify_only: args.verify_only,
+            reject_own_receipts: args.reject_own_receipts,
+            bind_address: Some(args.bind_address),
+            http2: server::Http2Options {
+                keepalive_interval: args.http2_keepalive_interval_secs.map(Duration::from_secs),
+                keepalive_timeout: args.http2_keepalive_timeout_secs.map(Duration::from_secs),
+                max_concurrent_streams: args.http2_max_concurrent_streams,
+            },
+            ..Default::default()
+        },
     )
     .await?;
-    info!("Server started. Listening on port {}.", args.port);
+    info!(
+        "Server started. Listening on {}:{}.",
+        args.bind_address, args.port
+    );
+
+    // The address of a local private key is known right away, a remote
+    // signer would resolve it here.
+    readiness.ready_when(async move { Ok::<_, Infallible>(wallet_address) });
 
     let _ = handle.await;
 
     // If we're here, we've received a signal to exit.
     info!("Shutting down...");
+
+    // The writer stops once the server dropped its RAV log handles, after
+    // writing the queued RAVs.
+    if let Some(writer) = rav_log_writer {
+        if tokio::time::timeout(Duration::from_secs(5), writer)
+            .await
+            .is_err()
+        {
+            warn!("Timed out writing the queued RAVs to the RAV log");
+        }
+    }
     Ok(())
 }
 
@@ -147,8 +388,113 @@ fn create_eip712_domain(args: &Args) -> Result<Eip712Domain> {
     let verifying_contract: Option<Address> = args.domain_verifying_contract;
 
     // Create the EIP-712 domain separator.
-    Ok(tap_eip712_domain(
-        chain_id.unwrap_or(1),
-        verifying_contract.unwrap_or_default(),
-    ))
+    let domain_config = DomainConfig {
+        chain_id: chain_id.unwrap_or(DEFAULT_CHAIN_ID),
+        verifying_contract: verifying_contract.unwrap_or(DEFAULT_VERIFYING_CONTRACT),
+    };
+    domain_config.validate()?;
+    if domain_config.verifying_contract == DEFAULT_VERIFYING_CONTRACT {
+        warn!(
+            "No domain verifying contract configured, using the placeholder {}. \
+             The RAVs will not be redeemable on-chain.",
+            DEFAULT_VERIFYING_CONTRACT
+        );
+    }
+    Ok(domain_config.eip712_domain())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use alloy::primitives::Address;
+    use tap_aggregator::aggregator::RavTimestampPolicy;
+
+    use super::Args;
+
+    #[test]
+    fn config_file_is_overridden_by_flags() {
+        let path =
+            std::env::temp_dir().join(format!("tap_aggregator_config_{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            r#"
+            private-key = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+            public-keys = [
+                "0x1111111111111111111111111111111111111111",
+                "0x2222222222222222222222222222222222222222",
+            ]
+            port = 8081
+            max-connections = 64
+            max-previous-rav-value = "100000000000000000000"
+            rav-timestamp-policy = "aggregation-time"
+            check-nonces-unique = true
+            verify-only = false
+            domain-chain-id = "42161"
+            domain-verifying-contract = "0x3333333333333333333333333333333333333333"
+            "#,
+        )
+        .unwrap();
+
+        let args = Args::load_from(vec![
+            "tap_aggregator".into(),
+            "--config-file".into(),
+            path.clone().into_os_string(),
+            "--port".into(),
+            "9000".into(),
+        ])
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            args.private_key,
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        );
+        assert_eq!(
+            args.public_keys,
+            Some(vec![
+                Address::from([0x11u8; 20]),
+                Address::from([0x22u8; 20])
+            ])
+        );
+        // set with a flag
+        assert_eq!(args.port, 9000);
+        assert_eq!(args.max_connections, 64);
+        assert_eq!(
+            args.max_previous_rav_value,
+            Some(100_000_000_000_000_000_000)
+        );
+        assert_eq!(
+            args.rav_timestamp_policy,
+            RavTimestampPolicy::AggregationTime
+        );
+        assert!(args.check_nonces_unique);
+        assert!(!args.verify_only);
+        assert_eq!(args.domain_chain_id.as_deref(), Some("42161"));
+        assert_eq!(
+            args.domain_verifying_contract,
+            Some(Address::from([0x33u8; 20]))
+        );
+        // not in the file
+        assert_eq!(args.max_request_body_size, 10 * 1024 * 1024);
+    }
+
+    #[test]
+    fn config_file_rejects_unknown_settings() {
+        let path = std::env::temp_dir().join(format!(
+            "tap_aggregator_unknown_config_{}.toml",
+            std::process::id()
+        ));
+        fs::write(&path, "kafka-brokers = \"localhost:9092\"\n").unwrap();
+
+        let err = Args::load_from(vec![
+            "tap_aggregator".into(),
+            "--config-file".into(),
+            path.clone().into_os_string(),
+        ])
+        .unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert!(err.to_string().contains("kafka-brokers"));
+    }
 }
@@ -1,7 +1,10 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{net::SocketAddr, panic};
+use std::{
+    net::{IpAddr, SocketAddr},
+    panic,
+};
 
 use axum::{http::StatusCode, response::IntoResponse, routing::get, serve, Router};
 use futures_util::FutureExt;
@@ -30,11 +33,11 @@ async fn handler_404() -> impl IntoResponse {
     (StatusCode::NOT_FOUND, "404 Not Found")
 }
 
-async fn _run_server(port: u16) {
+async fn _run_server(bind_address: IpAddr, port: u16) {
     let app = Router::new()
         .route("/metrics", get(handler_metrics))
         .fallback(handler_404);
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let addr = SocketAddr::new(bind_address, port);
     let listener = TcpListener::bind(addr)
         .await
         .expect("Failed to bind to indexer-service port");
@@ -52,10 +55,10 @@ async fn _run_server(port: u16) {
     };
 }
 
-pub async fn run_server(port: u16) {
+pub async fn run_server(bind_address: IpAddr, port: u16) {
     // Code here is to abort program if there is a panic in _run_server
     // Otherwise, when spawning the task, the panic will be silently ignored
-    let res = panic::AssertUnwindSafe(_run_server(port))
+    let res = panic::AssertUnwindSafe(_run_server(bind_address, port))
         .catch_unwind()
         .await;
     if res.is_err() {
@@ -0,0 +1,143 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Short-lived cache of the RAVs signed by the aggregator.
+//!
+//! Each RAV returned by `aggregate_receipts` comes with an opaque reference,
+//! that the client can send back as `previous_rav_ref` in its next request
+//! instead of the RAV itself. The RAVs expire after a TTL, after which the
+//! client must send the previous RAV inline again.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tap_graph::SignedRav;
+
+/// Number of RAVs kept by [`RavCache::new`].
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// Cache of the RAVs signed by the aggregator, by reference.
+///
+/// Holds at most `capacity` RAVs, the oldest ones being evicted first. Clones
+/// share the same RAVs.
+#[derive(Debug, Clone)]
+pub struct RavCache {
+    ttl: Duration,
+    capacity: usize,
+    ravs: Arc<Mutex<CachedRavs>>,
+}
+
+#[derive(Debug, Default)]
+struct CachedRavs {
+    by_ref: HashMap<String, (Instant, SignedRav)>,
+    /// References in insertion order, which is also the order they expire in
+    /// as they share the same TTL, along with their expiry time
+    expiries: VecDeque<(Instant, String)>,
+}
+
+impl RavCache {
+    /// Creates a cache keeping each RAV for `ttl`, holding at most
+    /// [`DEFAULT_CAPACITY`] RAVs.
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_capacity(ttl, DEFAULT_CAPACITY)
+    }
+
+    /// Same as [`RavCache::new`], holding at most `capacity` RAVs.
+    pub fn with_capacity(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            ravs: Default::default(),
+        }
+    }
+
+    /// Caches `rav` and returns its reference.
+    ///
+    /// Expired RAVs are evicted at the same time, and so is the oldest RAV
+    /// if the cache is full.
+    pub fn insert(&self, rav: SignedRav) -> String {
+        let rav_ref = alloy::primitives::hex::encode(rav.unique_hash().0);
+        let now = Instant::now();
+        let expires_at = now + self.ttl;
+        let mut ravs = self.ravs.lock().unwrap();
+        while let Some((oldest_expires_at, _)) = ravs.expiries.front() {
+            if *oldest_expires_at > now && ravs.by_ref.len() < self.capacity {
+                break;
+            }
+            let (oldest_expires_at, oldest_ref) = ravs.expiries.pop_front().unwrap();
+            // the RAV may have been inserted again since
+            if ravs
+                .by_ref
+                .get(&oldest_ref)
+                .is_some_and(|(cached_expires_at, _)| *cached_expires_at == oldest_expires_at)
+            {
+                ravs.by_ref.remove(&oldest_ref);
+            }
+        }
+        ravs.by_ref.insert(rav_ref.clone(), (expires_at, rav));
+        ravs.expiries.push_back((expires_at, rav_ref.clone()));
+        rav_ref
+    }
+
+    /// Returns the RAV of `rav_ref`, or `None` if it is unknown or expired.
+    pub fn get(&self, rav_ref: &str) -> Option<SignedRav> {
+        let ravs = self.ravs.lock().unwrap();
+        ravs.by_ref
+            .get(rav_ref)
+            .filter(|(expires_at, _)| *expires_at > Instant::now())
+            .map(|(_, rav)| rav.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+    use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+    use tap_graph::{ReceiptAggregateVoucher, SignedRav};
+
+    use super::RavCache;
+
+    fn rav(value_aggregate: u128) -> SignedRav {
+        Eip712SignedMessage::new(
+            &tap_eip712_domain(1, Address::ZERO),
+            ReceiptAggregateVoucher {
+                allocationId: Address::ZERO,
+                timestampNs: 1,
+                valueAggregate: value_aggregate,
+            },
+            &PrivateKeySigner::random(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ravs_are_returned_until_they_expire() {
+        let cache = RavCache::new(Duration::from_secs(60));
+        let rav_ref = cache.insert(rav(42));
+        assert_eq!(cache.get(&rav_ref).unwrap().message.valueAggregate, 42);
+        assert!(cache.get("unknown").is_none());
+
+        let cache = RavCache::new(Duration::ZERO);
+        let rav_ref = cache.insert(rav(42));
+        assert!(cache.get(&rav_ref).is_none());
+    }
+
+    #[test]
+    fn oldest_ravs_are_evicted_when_full() {
+        let cache = RavCache::with_capacity(Duration::from_secs(60), 2);
+        let first = cache.insert(rav(1));
+        let second = cache.insert(rav(2));
+        // inserting a RAV again refreshes it
+        assert_eq!(cache.insert(cache.get(&first).unwrap()), first);
+        let third = cache.insert(rav(3));
+
+        assert!(cache.get(&first).is_some());
+        assert!(cache.get(&second).is_none());
+        assert!(cache.get(&third).is_some());
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Local audit log of the RAVs signed by the aggregator.
+//!
+//! Each RAV is appended to the file as a single JSON line, along with the
+//! `correlation_id` of the request that produced it. Writes happen in
+//! a dedicated task fed through a bounded queue, so that aggregation requests
+//! never wait on the disk. If the disk cannot keep up and the queue is full,
+//! the RAV is not logged and `rav_log_queue_full_count` is incremented. In
+//! strict mode the aggregation request fails instead.
+
+use std::path::Path;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::error;
+use prometheus::{register_int_counter, IntCounter};
+use serde::Serialize;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+
+use crate::correlation::CorrelationId;
+
+lazy_static! {
+    static ref RAV_LOG_QUEUE_FULL_COUNT: IntCounter = register_int_counter!(
+        "rav_log_queue_full_count",
+        "Number of RAVs not logged because the RAV log queue was full."
+    )
+    .unwrap();
+}
+
+/// Number of RAVs waiting to be written used by [`RavLog::open`].
+pub const DEFAULT_QUEUE_SIZE: usize = 1024;
+
+/// Error returned by [`RavLog::log`] when the RAV could not be queued.
+#[derive(Debug, thiserror::Error)]
+pub enum RavLogError {
+    #[error("RAV log queue is full")]
+    QueueFull,
+    #[error("RAV log writer stopped")]
+    WriterStopped,
+    #[error("Failed to serialize RAV: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Handle used to append RAVs to the log file.
+///
+/// Clones write to the same file. The writer task stops once all the
+/// handles are dropped, after writing the queued RAVs and syncing the file.
+#[derive(Debug, Clone)]
+pub struct RavLog {
+    sender: mpsc::Sender<String>,
+    strict: bool,
+}
+
+impl RavLog {
+    /// Opens `path` in append mode, creating it if needed, and spawns the
+    /// task writing to it, with a queue of [`DEFAULT_QUEUE_SIZE`] RAVs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub async fn open(path: impl AsRef<Path>) -> Result<(Self, JoinHandle<()>)> {
+        Self::open_with_queue_size(path, DEFAULT_QUEUE_SIZE).await
+    }
+
+    /// Same as [`RavLog::open`], with at most `queue_size` RAVs waiting to
+    /// be written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub async fn open_with_queue_size(
+        path: impl AsRef<Path>,
+        queue_size: usize,
+    ) -> Result<(Self, JoinHandle<()>)> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (sender, mut receiver) = mpsc::channel::<String>(queue_size.max(1));
+
+        let handle = tokio::spawn(async move {
+            while let Some(line) = receiver.recv().await {
+                if let Err(e) = write_line(&mut file, &line).await {
+                    error!("Failed to write RAV to the RAV log: {e}");
+                }
+            }
+            // all the handles are dropped, make sure everything hits the disk
+            if let Err(e) = file.sync_all().await {
+                error!("Failed to sync the RAV log: {e}");
+            }
+        });
+
+        Ok((
+            Self {
+                sender,
+                strict: false,
+            },
+            handle,
+        ))
+    }
+
+    /// In strict mode, aggregation requests fail when their RAV cannot be
+    /// logged.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Queues `rav` to be appended to the log, with the correlation ID of the
+    /// request.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RavLogError::QueueFull`] if too many RAVs are waiting to be
+    /// written, or another [`RavLogError`] if the RAV cannot be queued.
+    pub fn log<T: Serialize>(
+        &self,
+        rav: &T,
+        correlation_id: &CorrelationId,
+    ) -> Result<(), RavLogError> {
+        let line = serde_json::to_string(&RavLogRecord {
+            rav,
+            correlation_id: correlation_id.as_str(),
+        })?;
+        self.sender.try_send(line).map_err(|e| match e {
+            TrySendError::Full(_) => {
+                RAV_LOG_QUEUE_FULL_COUNT.inc();
+                RavLogError::QueueFull
+            }
+            TrySendError::Closed(_) => RavLogError::WriterStopped,
+        })
+    }
+}
+
+/// Line of the RAV log: the fields of the RAV and the correlation ID.
+#[derive(Serialize)]
+struct RavLogRecord<'a, T> {
+    #[serde(flatten)]
+    rav: &'a T,
+    correlation_id: &'a str,
+}
+
+async fn write_line(file: &mut File, line: &str) -> std::io::Result<()> {
+    file.write_all(line.as_bytes()).await?;
+    file.write_all(b"\n").await?;
+    file.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn full_queue_is_reported() {
+        let path = std::env::temp_dir().join(format!(
+            "tap_aggregator_rav_log_full_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let (rav_log, writer) = RavLog::open_with_queue_size(&path, 1).await.unwrap();
+
+        // the writer task does not run before this task yields, so the
+        // second RAV does not fit in the queue
+        let queue_full_count = RAV_LOG_QUEUE_FULL_COUNT.get();
+        let correlation_id = CorrelationId::parse("request-1").unwrap();
+        rav_log
+            .log(&json!({"rav": "first"}), &correlation_id)
+            .unwrap();
+        assert!(matches!(
+            rav_log.log(&json!({"rav": "second"}), &correlation_id),
+            Err(RavLogError::QueueFull)
+        ));
+        assert_eq!(RAV_LOG_QUEUE_FULL_COUNT.get(), queue_full_count + 1);
+
+        // dropping the last handle writes the queued RAVs
+        drop(rav_log);
+        writer.await.unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            contents,
+            "{\"rav\":\"first\",\"correlation_id\":\"request-1\"}\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
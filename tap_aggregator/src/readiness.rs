@@ -0,0 +1,73 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Readiness of the aggregator server.
+//!
+//! Some signers (e.g. backed by a KMS) need to resolve their address
+//! asynchronously, leaving a window where the server is listening but cannot
+//! sign yet. While the [`ReadinessGate`] is closed, aggregation requests are
+//! answered with `503 Service Unavailable` and the `/health` endpoint
+//! reports the server as starting up.
+
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use alloy::primitives::Address;
+use log::{error, info};
+use tokio::task::JoinHandle;
+
+/// Shared flag telling whether the server is ready to sign RAVs.
+#[derive(Debug, Clone)]
+pub struct ReadinessGate(Arc<AtomicBool>);
+
+impl ReadinessGate {
+    /// Creates a gate that is already open.
+    pub fn ready() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+
+    /// Creates a closed gate, to be opened with [`ReadinessGate::set_ready`]
+    /// or [`ReadinessGate::ready_when`].
+    pub fn pending() -> Self {
+        Self(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn set_ready(&self) {
+        self.0.store(true, Ordering::Release)
+    }
+
+    /// Opens the gate once `signer_address` resolves successfully.
+    /// The gate stays closed if it resolves to an error.
+    pub fn ready_when<F, E>(&self, signer_address: F) -> JoinHandle<()>
+    where
+        F: Future<Output = Result<Address, E>> + Send + 'static,
+        E: Display,
+    {
+        let gate = self.clone();
+        tokio::spawn(async move {
+            match signer_address.await {
+                Ok(address) => {
+                    info!("Signer ready, address: {:#40x}", address);
+                    gate.set_ready();
+                }
+                Err(e) => error!("Failed to resolve signer address: {e}"),
+            }
+        })
+    }
+}
+
+impl Default for ReadinessGate {
+    fn default() -> Self {
+        Self::ready()
+    }
+}
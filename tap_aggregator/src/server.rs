@@ -1,34 +1,65 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{collections::HashSet, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
 use anyhow::Result;
-use axum::{error_handling::HandleError, routing::post_service, BoxError, Router};
-use hyper::StatusCode;
+use axum::{
+    error_handling::HandleError,
+    routing::{get, post_service},
+    BoxError, Router,
+};
+use hyper::{body::Incoming, StatusCode};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
 use jsonrpsee::{
     proc_macros::rpc,
-    server::{ServerBuilder, ServerHandle, TowerService},
+    server::{Extensions, ServerBuilder, ServerHandle, TowerService},
+    tracing::info_span,
 };
 use lazy_static::lazy_static;
-use log::info;
-use prometheus::{register_counter, register_int_counter, Counter, IntCounter};
-use tap_core::signed_message::Eip712SignedMessage;
-use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedReceipt};
-use tokio::{net::TcpListener, signal, task::JoinHandle};
+use log::{debug, error, info};
+use prometheus::{
+    register_counter, register_int_counter, register_int_gauge, Counter, IntCounter, IntGauge,
+};
+use serde::{Deserialize, Serialize};
+use tap_core::{
+    escrow::required_escrow, jsonrpc::aggregation_error, signed_message::Eip712SignedMessage,
+};
+use tap_graph::{Receipt, ReceiptAggregateVoucher};
+use tokio::{
+    net::TcpListener,
+    signal,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
 use tonic::{codec::CompressionEncoding, service::Routes, Request, Response, Status};
-use tower::{layer::util::Identity, make::Shared};
+use tower::{layer::util::Identity, ServiceExt};
 
 use crate::{
-    aggregator,
+    aggregator::{self, AggregationOptions},
     api_versioning::{
         tap_rpc_api_versions_info, TapRpcApiVersion, TapRpcApiVersionsInfo,
         TAP_RPC_API_VERSIONS_DEPRECATED,
     },
+    correlation::{CorrelationId, CORRELATION_ID_HEADER},
     error_codes::{JsonRpcErrorCode, JsonRpcWarningCode},
     grpc::{v1, v2},
     jsonrpsee_helpers::{JsonRpcError, JsonRpcResponse, JsonRpcResult, JsonRpcWarning},
+    rav_cache::RavCache,
+    rav_log::{RavLog, RavLogError},
+    readiness::ReadinessGate,
 };
 
 // Register the metrics into the global metrics registry.
@@ -64,6 +95,26 @@ lazy_static! {
         "Total successfully aggregated GRT value (wei)."
     )
     .unwrap();
+    static ref IN_FLIGHT_REQUESTS: IntGauge = register_int_gauge!(
+        "in_flight_requests",
+        "Number of aggregation requests currently being processed."
+    )
+    .unwrap();
+    static ref LAST_SUCCESSFUL_AGGREGATION_TIMESTAMP: IntGauge = register_int_gauge!(
+        "last_successful_aggregation_timestamp_seconds",
+        "Unix time of the last successful receipt aggregation request."
+    )
+    .unwrap();
+}
+
+/// Counts a successful aggregation request and records its time, so that a
+/// stalled aggregator can be alerted on.
+fn record_aggregation_success() {
+    AGGREGATION_SUCCESS_COUNTER.inc();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    LAST_SUCCESSFUL_AGGREGATION_TIMESTAMP.set(now.as_secs() as i64);
 }
 
 /// Generates the `RpcServer` trait that is used to define the JSON-RPC API.
@@ -80,13 +131,138 @@ pub trait Rpc {
 
     /// Aggregates the given receipts into a receipt aggregate voucher.
     /// Returns an error if the user expected API version is not supported.
-    #[method(name = "aggregate_receipts")]
+    ///
+    /// The previous RAV can be given by the reference returned along with it,
+    /// if the server caches the RAVs, falling back to `previous_rav`.
+    ///
+    /// The extensions hold the correlation ID of the request.
+    #[method(name = "aggregate_receipts", with_extensions)]
     fn aggregate_receipts(
         &self,
         api_version: String,
         receipts: Vec<Eip712SignedMessage<Receipt>>,
         previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
-    ) -> JsonRpcResult<Eip712SignedMessage<ReceiptAggregateVoucher>>;
+        previous_rav_ref: Option<String>,
+    ) -> JsonRpcResult<AggregatedRav>;
+}
+
+/// RAV returned by the JSON-RPC `aggregate_receipts`.
+///
+/// Serialized as the signed RAV, or as an [`UnsignedRav`] by the servers in
+/// verify-only mode, see [`ServerOptions::verify_only`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AggregatedRav {
+    Signed(Eip712SignedMessage<ReceiptAggregateVoucher>),
+    Unsigned(UnsignedRav),
+}
+
+/// RAV checked and aggregated, but not signed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnsignedRav {
+    pub message: ReceiptAggregateVoucher,
+    /// Always `true`, telling the unsigned RAVs apart from the signed ones
+    pub unsigned: bool,
+}
+
+impl AggregatedRav {
+    /// Returns the signed RAV, or `None` if the RAV is unsigned.
+    pub fn signed(self) -> Option<Eip712SignedMessage<ReceiptAggregateVoucher>> {
+        match self {
+            AggregatedRav::Signed(rav) => Some(rav),
+            AggregatedRav::Unsigned(_) => None,
+        }
+    }
+
+    /// Returns the RAV, signed or not.
+    pub fn message(&self) -> &ReceiptAggregateVoucher {
+        match self {
+            AggregatedRav::Signed(rav) => &rav.message,
+            AggregatedRav::Unsigned(rav) => &rav.message,
+        }
+    }
+}
+
+/// Optional settings of the aggregator server.
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    /// Maximum number of aggregation requests processed concurrently, across
+    /// all connections. Requests above the limit are rejected with a
+    /// "server busy" error. No limit if `None`.
+    pub max_in_flight_requests: Option<u32>,
+    /// Requests are answered with `503 Service Unavailable` until the gate is
+    /// open. Open by default.
+    pub readiness: ReadinessGate,
+    /// Every signed RAV is appended to this log, if set. See
+    /// [`RavLog::with_strict`] to fail the requests whose RAV is not logged.
+    pub rav_log: Option<RavLog>,
+    /// Optional checks applied to the aggregation requests.
+    pub aggregation: AggregationOptions,
+    /// The RAVs signed through JSON-RPC are cached for this long, so that
+    /// clients can send their reference as `previous_rav_ref` instead of the
+    /// RAV. No cache if `None`.
+    pub rav_cache_ttl: Option<Duration>,
+    /// HTTP/2 settings of the connections, used by the gRPC clients.
+    pub http2: Http2Options,
+    /// Additional chains, by chain ID, that gRPC clients select with the
+    /// [`CHAIN_ID_METADATA_KEY`] metadata. Requests without it use the
+    /// domain separator, wallet and accepted signers of the server.
+    pub chains: HashMap<u64, ChainConfig>,
+    /// Check and aggregate the receipts, but return the RAVs unsigned, e.g.
+    /// for a staging aggregator. Unsigned RAVs are neither logged nor
+    /// cached, and not counted in the aggregated value metrics.
+    pub verify_only: bool,
+    /// Address of the interface to listen on, e.g. `127.0.0.1` to only
+    /// accept local connections. All the interfaces (`0.0.0.0`) if `None`.
+    pub bind_address: Option<IpAddr>,
+    /// Refuse the receipts signed by the wallet signing the RAV, so that the
+    /// signer of the RAVs is never one of their payers. Previous RAVs signed
+    /// by the wallet are still accepted. See
+    /// [`AggregationOptions::excluded_receipt_signer`].
+    pub reject_own_receipts: bool,
+}
+
+/// gRPC metadata key holding the ID of the chain to aggregate the receipts
+/// for. See [`ServerOptions::chains`].
+pub const CHAIN_ID_METADATA_KEY: &str = "tap-chain-id";
+
+/// Domain separator, wallet and accepted signers used to verify and sign the
+/// RAVs of a chain.
+///
+/// The signers accepted by the server are not accepted for the chain, and the
+/// signers of the chain are not accepted for the other chains.
+#[derive(Clone)]
+pub struct ChainConfig {
+    pub domain_separator: Eip712Domain,
+    pub wallet: PrivateKeySigner,
+    /// Signers of the receipts and previous RAVs accepted for this chain. The
+    /// address of `wallet` is always accepted as well.
+    pub accepted_addresses: HashSet<Address>,
+}
+
+// Only the address of the wallet is printed, never the key
+impl fmt::Debug for ChainConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChainConfig")
+            .field("domain_separator", &self.domain_separator)
+            .field("wallet", &self.wallet.address())
+            .field("accepted_addresses", &self.accepted_addresses)
+            .finish()
+    }
+}
+
+/// HTTP/2 settings of the server connections. Unset settings keep the
+/// `hyper` defaults.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Http2Options {
+    /// Interval between the HTTP/2 pings sent to keep idle connections
+    /// alive. No pings if `None`.
+    pub keepalive_interval: Option<Duration>,
+    /// Time to wait for a ping acknowledgement before closing the
+    /// connection. Only used if `keepalive_interval` is set.
+    pub keepalive_timeout: Option<Duration>,
+    /// Maximum number of concurrent streams on each connection.
+    pub max_concurrent_streams: Option<u32>,
 }
 
 #[derive(Clone)]
@@ -94,8 +270,188 @@ struct RpcImpl {
     wallet: PrivateKeySigner,
     accepted_addresses: HashSet<Address>,
     domain_separator: Eip712Domain,
+    in_flight_requests: Arc<Semaphore>,
+    rav_log: Option<RavLog>,
+    aggregation_options: AggregationOptions,
+    chains: Arc<HashMap<u64, ChainConfig>>,
+    rav_cache: Option<RavCache>,
+    verify_only: bool,
+    reject_own_receipts: bool,
+}
+
+// Only the address of the wallet is printed, never the key
+impl fmt::Debug for RpcImpl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcImpl")
+            .field("wallet", &self.wallet.address())
+            .field("accepted_addresses", &self.accepted_addresses)
+            .field("domain_separator", &self.domain_separator)
+            .field("rav_log", &self.rav_log)
+            .field("aggregation_options", &self.aggregation_options)
+            .field("chains", &self.chains)
+            .field("verify_only", &self.verify_only)
+            .field("reject_own_receipts", &self.reject_own_receipts)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Permit for an aggregation request being processed, released on drop.
+struct InFlightRequest {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        IN_FLIGHT_REQUESTS.dec();
+    }
+}
+
+impl RpcImpl {
+    fn new(
+        wallet: PrivateKeySigner,
+        accepted_addresses: HashSet<Address>,
+        domain_separator: Eip712Domain,
+        options: &ServerOptions,
+    ) -> Self {
+        let max_in_flight_requests = options
+            .max_in_flight_requests
+            .map_or(Semaphore::MAX_PERMITS, |max| max as usize);
+        let chains = options
+            .chains
+            .iter()
+            .map(|(&chain_id, chain)| {
+                let mut chain = chain.clone();
+                chain.accepted_addresses.insert(chain.wallet.address());
+                (chain_id, chain)
+            })
+            .collect();
+        Self {
+            wallet,
+            accepted_addresses,
+            domain_separator,
+            in_flight_requests: Arc::new(Semaphore::new(max_in_flight_requests)),
+            rav_log: options.rav_log.clone(),
+            aggregation_options: options.aggregation,
+            chains: Arc::new(chains),
+            rav_cache: options.rav_cache_ttl.map(RavCache::new),
+            verify_only: options.verify_only,
+            reject_own_receipts: options.reject_own_receipts,
+        }
+    }
+
+    /// Returns the aggregation options of a request whose RAV is signed by
+    /// `wallet`, see [`ServerOptions::reject_own_receipts`].
+    fn aggregation_options_for(&self, wallet: &PrivateKeySigner) -> AggregationOptions {
+        let mut options = self.aggregation_options;
+        if self.reject_own_receipts {
+            options.excluded_receipt_signer = Some(wallet.address());
+        }
+        options
+    }
+
+    /// Returns the domain separator, wallet and accepted signers of the chain
+    /// requested with the [`CHAIN_ID_METADATA_KEY`] metadata, or the ones of
+    /// the server if no chain is requested.
+    #[allow(clippy::result_large_err)]
+    fn chain_for<T>(
+        &self,
+        request: &Request<T>,
+    ) -> Result<(&Eip712Domain, &PrivateKeySigner, &HashSet<Address>), Status> {
+        let Some(chain_id) = request.metadata().get(CHAIN_ID_METADATA_KEY) else {
+            return Ok((
+                &self.domain_separator,
+                &self.wallet,
+                &self.accepted_addresses,
+            ));
+        };
+        let chain_id: u64 = chain_id
+            .to_str()
+            .ok()
+            .and_then(|chain_id| chain_id.parse().ok())
+            .ok_or_else(|| Status::invalid_argument("Invalid chain ID"))?;
+        self.chains
+            .get(&chain_id)
+            .map(|chain| {
+                (
+                    &chain.domain_separator,
+                    &chain.wallet,
+                    &chain.accepted_addresses,
+                )
+            })
+            .ok_or_else(|| {
+                Status::invalid_argument(format!("Chain ID {chain_id} is not configured"))
+            })
+    }
+
+    /// Appends `rav` to the RAV log, if any. Failing to log the RAV is
+    /// only an error if the RAV log is strict.
+    fn log_rav<T: Serialize>(
+        &self,
+        rav: &T,
+        correlation_id: &CorrelationId,
+    ) -> Result<(), RavLogError> {
+        if let Some(rav_log) = &self.rav_log {
+            if let Err(e) = rav_log.log(rav, correlation_id) {
+                if rav_log.is_strict() {
+                    return Err(e);
+                }
+                error!("RAV not logged: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the cached RAV of `previous_rav_ref`, falling back to
+    /// `previous_rav` if there is no reference or the RAV is not cached.
+    /// A request sending both a cached reference and a different RAV is
+    /// refused, so that the RAV aggregated onto is never ambiguous.
+    #[allow(clippy::result_large_err)]
+    fn resolve_previous_rav(
+        &self,
+        previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+        previous_rav_ref: Option<String>,
+    ) -> Result<Option<Eip712SignedMessage<ReceiptAggregateVoucher>>, JsonRpcError> {
+        let Some(previous_rav_ref) = previous_rav_ref else {
+            return Ok(previous_rav);
+        };
+        let cached_rav = self
+            .rav_cache
+            .as_ref()
+            .and_then(|rav_cache| rav_cache.get(&previous_rav_ref));
+        match (cached_rav, previous_rav) {
+            (Some(cached_rav), Some(rav)) if cached_rav != rav => {
+                Err(jsonrpsee::types::ErrorObject::owned(
+                    JsonRpcErrorCode::Aggregation as i32,
+                    format!(
+                        "Previous RAV does not match the RAV of reference \"{previous_rav_ref}\"."
+                    ),
+                    None::<()>,
+                ))
+            }
+            (Some(rav), _) => Ok(Some(rav)),
+            (None, Some(rav)) => Ok(Some(rav)),
+            (None, None) => Err(jsonrpsee::types::ErrorObject::owned(
+                JsonRpcErrorCode::Aggregation as i32,
+                format!(
+                    "Unknown or expired previous RAV reference: \"{previous_rav_ref}\". \
+                    Please send the previous RAV instead."
+                ),
+                None::<()>,
+            )),
+        }
+    }
+
+    /// Reserves a slot for an aggregation request.
+    /// Returns `None` if the maximum number of in-flight requests is reached.
+    fn start_request(&self) -> Option<InFlightRequest> {
+        let permit = self.in_flight_requests.clone().try_acquire_owned().ok()?;
+        IN_FLIGHT_REQUESTS.inc();
+        Some(InFlightRequest { _permit: permit })
+    }
 }
 
+const SERVER_BUSY_MESSAGE: &str = "Server busy, too many requests in flight. Please retry later.";
+
 /// Helper method that checks if the given API version is supported.
 /// Returns an error if the API version is not supported.
 fn parse_api_version(api_version: &str) -> Result<TapRpcApiVersion, JsonRpcError> {
@@ -126,6 +482,7 @@ fn check_api_version_deprecation(api_version: &TapRpcApiVersion) -> Option<JsonR
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn aggregate_receipts_(
     api_version: String,
     wallet: &PrivateKeySigner,
@@ -133,7 +490,9 @@ fn aggregate_receipts_(
     domain_separator: &Eip712Domain,
     receipts: Vec<Eip712SignedMessage<Receipt>>,
     previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
-) -> JsonRpcResult<Eip712SignedMessage<ReceiptAggregateVoucher>> {
+    aggregation_options: AggregationOptions,
+    verify_only: bool,
+) -> JsonRpcResult<AggregatedRav> {
     // Return an error if the API version is not supported.
     let api_version = match parse_api_version(api_version.as_str()) {
         Ok(v) => v,
@@ -151,38 +510,99 @@ fn aggregate_receipts_(
     }
 
     let res = match api_version {
+        TapRpcApiVersion::V0_0 if verify_only => {
+            aggregator::v1::check_and_aggregate_receipts_unsigned(
+                domain_separator,
+                &receipts,
+                previous_rav,
+                accepted_addresses,
+                aggregation_options,
+            )
+            .map(|message| {
+                AggregatedRav::Unsigned(UnsignedRav {
+                    message,
+                    unsigned: true,
+                })
+            })
+        }
         TapRpcApiVersion::V0_0 => aggregator::v1::check_and_aggregate_receipts(
             domain_separator,
             &receipts,
             previous_rav,
             wallet,
             accepted_addresses,
-        ),
+            aggregation_options,
+        )
+        .map(AggregatedRav::Signed),
     };
 
     // Handle aggregation error
     match res {
         Ok(res) => Ok(JsonRpcResponse::warn(res, warnings)),
-        Err(e) => Err(jsonrpsee::types::ErrorObject::owned(
-            JsonRpcErrorCode::Aggregation as i32,
-            e.to_string(),
-            None::<()>,
-        )),
+        Err(e) => Err(aggregation_error(&e)),
     }
 }
 
+/// Returns `response` with the correlation ID of the request in its metadata.
+fn with_correlation_id<T>(
+    mut response: Response<T>,
+    correlation_id: &CorrelationId,
+) -> Response<T> {
+    // correlation IDs are visible ASCII, see `CorrelationId::parse`
+    if let Ok(value) = correlation_id.as_str().parse() {
+        response.metadata_mut().insert(CORRELATION_ID_HEADER, value);
+    }
+    response
+}
+
+/// Inserts the correlation ID of the JSON-RPC requests in their extensions,
+/// which are passed on to the methods.
+async fn insert_correlation_id(mut request: axum::extract::Request) -> axum::extract::Request {
+    let correlation_id = CorrelationId::from_headers(request.headers());
+    request.extensions_mut().insert(correlation_id);
+    request
+}
+
 #[tonic::async_trait]
 impl v1::tap_aggregator_server::TapAggregator for RpcImpl {
+    async fn get_eip712_domain(
+        &self,
+        request: Request<v1::GetEip712DomainRequest>,
+    ) -> Result<Response<v1::GetEip712DomainResponse>, Status> {
+        let (domain_separator, _, _) = self.chain_for(&request)?;
+        let domain = domain_separator
+            .try_into()
+            .map_err(|e: anyhow::Error| Status::internal(e.to_string()))?;
+        Ok(Response::new(domain))
+    }
+
+    async fn get_api_versions(
+        &self,
+        _request: Request<v1::GetApiVersionsRequest>,
+    ) -> Result<Response<v1::GetApiVersionsResponse>, Status> {
+        Ok(Response::new(tap_rpc_api_versions_info().into()))
+    }
+
     async fn aggregate_receipts(
         &self,
         request: Request<v1::RavRequest>,
     ) -> Result<Response<v1::RavResponse>, Status> {
-        let rav_request = request.into_inner();
-        let receipts: Vec<SignedReceipt> = rav_request
-            .receipts
-            .into_iter()
-            .map(TryFrom::try_from)
-            .collect::<Result<_, _>>()
+        let correlation_id = CorrelationId::from_metadata(request.metadata());
+        // no await below, so the span stays entered for the whole request
+        let _span = info_span!("aggregate_receipts", %correlation_id).entered();
+        let _in_flight = self.start_request().ok_or_else(|| {
+            AGGREGATION_FAILURE_COUNTER.inc();
+            Status::resource_exhausted(SERVER_BUSY_MESSAGE)
+        })?;
+
+        let (domain_separator, wallet, accepted_addresses) =
+            self.chain_for(&request).inspect_err(|_| {
+                AGGREGATION_FAILURE_COUNTER.inc();
+            })?;
+
+        let mut rav_request = request.into_inner();
+        let receipts = rav_request
+            .take_receipts()
             .map_err(|_| Status::invalid_argument("Error while getting list of signed_receipts"))?;
 
         let previous_rav = rav_request
@@ -191,28 +611,66 @@ impl v1::tap_aggregator_server::TapAggregator for RpcImpl {
             .transpose()
             .map_err(|_| Status::invalid_argument("Error while getting previous rav"))?;
 
-        let receipts_grt: u128 = receipts.iter().map(|r| r.message.value).sum();
+        let receipts_grt = required_escrow(&receipts).map_err(|e| {
+            AGGREGATION_FAILURE_COUNTER.inc();
+            Status::failed_precondition(e.to_string())
+        })?;
         let receipts_count: u64 = receipts.len() as u64;
 
+        if self.verify_only {
+            let rav = aggregator::v1::check_and_aggregate_receipts_unsigned(
+                domain_separator,
+                receipts.as_slice(),
+                previous_rav,
+                accepted_addresses,
+                self.aggregation_options_for(wallet),
+            )
+            .map_err(|e| {
+                AGGREGATION_FAILURE_COUNTER.inc();
+                debug!("Aggregation failed: {e}");
+                Status::failed_precondition(e.to_string())
+            })?;
+            record_aggregation_success();
+            debug!("Aggregated {receipts_count} receipts, unsigned");
+            return Ok(with_correlation_id(
+                Response::new(v1::RavResponse {
+                    rav: None,
+                    unsigned_rav: Some(rav.into()),
+                }),
+                &correlation_id,
+            ));
+        }
+
         match aggregator::v1::check_and_aggregate_receipts(
-            &self.domain_separator,
+            domain_separator,
             receipts.as_slice(),
             previous_rav,
-            &self.wallet,
-            &self.accepted_addresses,
+            wallet,
+            accepted_addresses,
+            self.aggregation_options_for(wallet),
         ) {
             Ok(res) => {
+                if let Err(e) = self.log_rav(&res, &correlation_id) {
+                    AGGREGATION_FAILURE_COUNTER.inc();
+                    return Err(rav_log_status(e));
+                }
                 TOTAL_GRT_AGGREGATED.inc_by(receipts_grt as f64);
                 TOTAL_AGGREGATED_RECEIPTS.inc_by(receipts_count);
-                AGGREGATION_SUCCESS_COUNTER.inc();
+                record_aggregation_success();
+                debug!("Aggregated {receipts_count} receipts");
 
                 let response = v1::RavResponse {
                     rav: Some(res.into()),
+                    unsigned_rav: None,
                 };
-                Ok(Response::new(response))
+                Ok(with_correlation_id(
+                    Response::new(response),
+                    &correlation_id,
+                ))
             }
             Err(e) => {
                 AGGREGATION_FAILURE_COUNTER.inc();
+                debug!("Aggregation failed: {e}");
                 Err(Status::failed_precondition(e.to_string()))
             }
         }
@@ -221,16 +679,44 @@ impl v1::tap_aggregator_server::TapAggregator for RpcImpl {
 
 #[tonic::async_trait]
 impl v2::tap_aggregator_server::TapAggregator for RpcImpl {
+    async fn get_eip712_domain(
+        &self,
+        request: Request<v2::GetEip712DomainRequest>,
+    ) -> Result<Response<v2::GetEip712DomainResponse>, Status> {
+        let (domain_separator, _, _) = self.chain_for(&request)?;
+        let domain = domain_separator
+            .try_into()
+            .map_err(|e: anyhow::Error| Status::internal(e.to_string()))?;
+        Ok(Response::new(domain))
+    }
+
+    async fn get_api_versions(
+        &self,
+        _request: Request<v2::GetApiVersionsRequest>,
+    ) -> Result<Response<v2::GetApiVersionsResponse>, Status> {
+        Ok(Response::new(tap_rpc_api_versions_info().into()))
+    }
+
     async fn aggregate_receipts(
         &self,
         request: Request<v2::RavRequest>,
     ) -> Result<Response<v2::RavResponse>, Status> {
-        let rav_request = request.into_inner();
-        let receipts: Vec<tap_graph::v2::SignedReceipt> = rav_request
-            .receipts
-            .into_iter()
-            .map(TryFrom::try_from)
-            .collect::<Result<_, _>>()
+        let correlation_id = CorrelationId::from_metadata(request.metadata());
+        // no await below, so the span stays entered for the whole request
+        let _span = info_span!("aggregate_receipts", %correlation_id).entered();
+        let _in_flight = self.start_request().ok_or_else(|| {
+            AGGREGATION_FAILURE_COUNTER.inc();
+            Status::resource_exhausted(SERVER_BUSY_MESSAGE)
+        })?;
+
+        let (domain_separator, wallet, accepted_addresses) =
+            self.chain_for(&request).inspect_err(|_| {
+                AGGREGATION_FAILURE_COUNTER.inc();
+            })?;
+
+        let mut rav_request = request.into_inner();
+        let receipts = rav_request
+            .take_receipts()
             .map_err(|_| Status::invalid_argument("Error while getting list of signed_receipts"))?;
 
         let previous_rav = rav_request
@@ -239,34 +725,94 @@ impl v2::tap_aggregator_server::TapAggregator for RpcImpl {
             .transpose()
             .map_err(|_| Status::invalid_argument("Error while getting previous rav"))?;
 
-        let receipts_grt: u128 = receipts.iter().map(|r| r.message.value).sum();
+        let receipts_grt = required_escrow(&receipts).map_err(|e| {
+            AGGREGATION_FAILURE_COUNTER.inc();
+            Status::failed_precondition(e.to_string())
+        })?;
         let receipts_count: u64 = receipts.len() as u64;
 
+        if self.verify_only {
+            let rav = aggregator::v2::check_and_aggregate_receipts_unsigned(
+                domain_separator,
+                receipts.as_slice(),
+                previous_rav,
+                accepted_addresses,
+                self.aggregation_options_for(wallet),
+            )
+            .map_err(|e| {
+                AGGREGATION_FAILURE_COUNTER.inc();
+                debug!("Aggregation failed: {e}");
+                Status::failed_precondition(e.to_string())
+            })?;
+            record_aggregation_success();
+            debug!("Aggregated {receipts_count} receipts, unsigned");
+            return Ok(with_correlation_id(
+                Response::new(v2::RavResponse {
+                    rav: None,
+                    unsigned_rav: Some(rav.into()),
+                }),
+                &correlation_id,
+            ));
+        }
+
         match aggregator::v2::check_and_aggregate_receipts(
-            &self.domain_separator,
+            domain_separator,
             receipts.as_slice(),
             previous_rav,
-            &self.wallet,
-            &self.accepted_addresses,
+            wallet,
+            accepted_addresses,
+            self.aggregation_options_for(wallet),
         ) {
             Ok(res) => {
+                if let Err(e) = self.log_rav(&res, &correlation_id) {
+                    AGGREGATION_FAILURE_COUNTER.inc();
+                    return Err(rav_log_status(e));
+                }
                 TOTAL_GRT_AGGREGATED.inc_by(receipts_grt as f64);
                 TOTAL_AGGREGATED_RECEIPTS.inc_by(receipts_count);
-                AGGREGATION_SUCCESS_COUNTER.inc();
+                record_aggregation_success();
+                debug!("Aggregated {receipts_count} receipts");
 
                 let response = v2::RavResponse {
                     rav: Some(res.into()),
+                    unsigned_rav: None,
                 };
-                Ok(Response::new(response))
+                Ok(with_correlation_id(
+                    Response::new(response),
+                    &correlation_id,
+                ))
             }
             Err(e) => {
                 AGGREGATION_FAILURE_COUNTER.inc();
+                debug!("Aggregation failed: {e}");
                 Err(Status::failed_precondition(e.to_string()))
             }
         }
     }
 }
 
+/// Returns the JSON-RPC error of a request whose RAV a strict RAV log failed
+/// to log: only a full queue is worth retrying, see [`RavLogError`].
+fn rav_log_rpc_error(e: RavLogError) -> jsonrpsee::types::ErrorObjectOwned {
+    let code = match e {
+        RavLogError::QueueFull => JsonRpcErrorCode::ServerBusy as i32,
+        RavLogError::WriterStopped | RavLogError::Serialization(_) => {
+            jsonrpsee::types::ErrorCode::InternalError.code()
+        }
+    };
+    jsonrpsee::types::ErrorObject::owned(code, e.to_string(), None::<()>)
+}
+
+/// Same as [`rav_log_rpc_error`], for gRPC.
+fn rav_log_status(e: RavLogError) -> Status {
+    match e {
+        RavLogError::QueueFull => Status::unavailable(e.to_string()),
+        RavLogError::WriterStopped | RavLogError::Serialization(_) => {
+            Status::internal(e.to_string())
+        }
+    }
+}
+
 impl RpcServer for RpcImpl {
     fn api_versions(&self) -> JsonRpcResult<TapRpcApiVersionsInfo> {
         Ok(JsonRpcResponse::ok(tap_rpc_api_versions_info()))
@@ -274,14 +820,45 @@ impl RpcServer for RpcImpl {
 
     fn aggregate_receipts(
         &self,
+        ext: &Extensions,
         api_version: String,
         receipts: Vec<Eip712SignedMessage<Receipt>>,
         previous_rav: Option<Eip712SignedMessage<ReceiptAggregateVoucher>>,
-    ) -> JsonRpcResult<Eip712SignedMessage<ReceiptAggregateVoucher>> {
+        previous_rav_ref: Option<String>,
+    ) -> JsonRpcResult<AggregatedRav> {
+        // set from the request headers, see `insert_correlation_id`
+        let correlation_id = ext
+            .get::<CorrelationId>()
+            .cloned()
+            .unwrap_or_else(CorrelationId::generate);
+        let _span = info_span!("aggregate_receipts", %correlation_id).entered();
+        let Some(_in_flight) = self.start_request() else {
+            AGGREGATION_FAILURE_COUNTER.inc();
+            return Err(jsonrpsee::types::ErrorObject::owned(
+                JsonRpcErrorCode::ServerBusy as i32,
+                SERVER_BUSY_MESSAGE,
+                None::<()>,
+            ));
+        };
+
         // Values for Prometheus metrics
-        let receipts_grt: u128 = receipts.iter().map(|r| r.message.value).sum();
+        let receipts_grt = match required_escrow(&receipts) {
+            Ok(value) => value,
+            Err(e) => {
+                AGGREGATION_FAILURE_COUNTER.inc();
+                return Err(e.into());
+            }
+        };
         let receipts_count: u64 = receipts.len() as u64;
 
+        let previous_rav = match self.resolve_previous_rav(previous_rav, previous_rav_ref) {
+            Ok(previous_rav) => previous_rav,
+            Err(e) => {
+                AGGREGATION_FAILURE_COUNTER.inc();
+                return Err(e);
+            }
+        };
+
         match aggregate_receipts_(
             api_version,
             &self.wallet,
@@ -289,15 +866,28 @@ impl RpcServer for RpcImpl {
             &self.domain_separator,
             receipts,
             previous_rav,
+            self.aggregation_options_for(&self.wallet),
+            self.verify_only,
         ) {
-            Ok(res) => {
-                TOTAL_GRT_AGGREGATED.inc_by(receipts_grt as f64);
-                TOTAL_AGGREGATED_RECEIPTS.inc_by(receipts_count);
-                AGGREGATION_SUCCESS_COUNTER.inc();
+            Ok(mut res) => {
+                if let AggregatedRav::Signed(rav) = &res.data {
+                    if let Err(e) = self.log_rav(rav, &correlation_id) {
+                        AGGREGATION_FAILURE_COUNTER.inc();
+                        return Err(rav_log_rpc_error(e));
+                    }
+                    TOTAL_GRT_AGGREGATED.inc_by(receipts_grt as f64);
+                    TOTAL_AGGREGATED_RECEIPTS.inc_by(receipts_count);
+                    if let Some(rav_cache) = &self.rav_cache {
+                        res.rav_ref = Some(rav_cache.insert(rav.clone()));
+                    }
+                }
+                record_aggregation_success();
+                debug!("Aggregated {receipts_count} receipts");
                 Ok(res)
             }
             Err(e) => {
                 AGGREGATION_FAILURE_COUNTER.inc();
+                debug!("Aggregation failed: {}", e.message());
                 Err(e)
             }
         }
@@ -313,12 +903,42 @@ pub async fn run_server(
     max_response_body_size: u32,
     max_concurrent_connections: u32,
 ) -> Result<(JoinHandle<()>, std::net::SocketAddr)> {
-    // Setting up the JSON RPC server
-    let rpc_impl = RpcImpl {
+    run_server_with_options(
+        port,
         wallet,
         accepted_addresses,
         domain_separator,
-    };
+        max_request_body_size,
+        max_response_body_size,
+        max_concurrent_connections,
+        ServerOptions::default(),
+    )
+    .await
+}
+
+/// Same as [`run_server`], with additional [`ServerOptions`].
+#[allow(clippy::too_many_arguments)]
+pub async fn run_server_with_options(
+    port: u16,
+    wallet: PrivateKeySigner,
+    accepted_addresses: HashSet<Address>,
+    domain_separator: Eip712Domain,
+    max_request_body_size: u32,
+    max_response_body_size: u32,
+    max_concurrent_connections: u32,
+    options: ServerOptions,
+) -> Result<(JoinHandle<()>, std::net::SocketAddr)> {
+    // Every receipt would be rejected with an invalid signer error
+    if accepted_addresses.is_empty() && !options.aggregation.accept_any_signer_insecure {
+        anyhow::bail!("No accepted signer addresses, every receipt would be rejected");
+    }
+    info!(
+        "Accepting receipts from {} signers",
+        accepted_addresses.len()
+    );
+
+    // Setting up the JSON RPC server
+    let rpc_impl = RpcImpl::new(wallet, accepted_addresses, domain_separator, &options);
     let (json_rpc_service, _) = create_json_rpc_service(
         rpc_impl.clone(),
         max_request_body_size,
@@ -332,17 +952,50 @@ pub async fn run_server(
             format!("Something went wrong: {err}"),
         )
     }
-    let json_rpc_router = Router::new().route_service(
-        "/",
-        HandleError::new(post_service(json_rpc_service), handle_anyhow_error),
-    );
+    let readiness = options.readiness.clone();
+    let json_rpc_router = Router::new()
+        .route_service(
+            "/",
+            HandleError::new(post_service(json_rpc_service), handle_anyhow_error),
+        )
+        .route(
+            "/health",
+            get(move || {
+                let readiness = readiness.clone();
+                async move {
+                    if readiness.is_ready() {
+                        (StatusCode::OK, "OK")
+                    } else {
+                        (StatusCode::SERVICE_UNAVAILABLE, "Starting up")
+                    }
+                }
+            }),
+        )
+        .layer(axum::middleware::map_request(insert_correlation_id));
 
     let grpc_service = create_grpc_service(rpc_impl)?;
 
+    // Answers every request while the signer is not ready yet
+    let starting_up_router = Router::new().fallback(|| async {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Starting up, please retry later",
+        )
+    });
+
+    let readiness = options.readiness;
     let service = tower::steer::Steer::new(
-        [json_rpc_router, grpc_service.into_axum_router()],
-        |req: &hyper::Request<_>, _services: &[_]| {
-            if req
+        [
+            json_rpc_router,
+            grpc_service.into_axum_router(),
+            starting_up_router,
+        ],
+        move |req: &hyper::Request<_>, _services: &[_]| {
+            if !readiness.is_ready() && req.uri().path() != "/health" {
+                // route to the starting up service (third service element)
+                // until the signer is ready
+                2
+            } else if req
                 .headers()
                 .get(hyper::header::CONTENT_TYPE)
                 .map(|content_type| content_type.as_bytes())
@@ -360,23 +1013,72 @@ pub async fn run_server(
     );
 
     // Create a `TcpListener` using tokio.
-    let listener = TcpListener::bind(&format!("0.0.0.0:{}", port))
+    let bind_address = options
+        .bind_address
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let listener = TcpListener::bind(SocketAddr::new(bind_address, port))
         .await
         .expect("Failed to bind to tap-aggregator port");
 
     let addr = listener.local_addr()?;
+    let builder = http_builder(&options.http2);
     let handle = tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, Shared::new(service))
-            .with_graceful_shutdown(shutdown_handler())
-            .await
-        {
-            log::error!("Tap Aggregator error: {e}");
+        let graceful = GracefulShutdown::new();
+        let shutdown = shutdown_handler();
+        tokio::pin!(shutdown);
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        // e.g. too many open files, wait for connections to close
+                        error!("Failed to accept connection: {e}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+            let service = TowerToHyperService::new(
+                service
+                    .clone()
+                    .map_request(|req: hyper::Request<Incoming>| req.map(axum::body::Body::new)),
+            );
+            let connection = builder
+                .serve_connection(TokioIo::new(stream), service)
+                .into_owned();
+            let connection = graceful.watch(connection);
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    log::debug!("Connection error: {e}");
+                }
+            });
         }
+        drop(listener);
+        graceful.shutdown().await;
     });
 
     Ok((handle, addr))
 }
 
+/// Builds the HTTP/1 and HTTP/2 connection builder with the given settings.
+fn http_builder(options: &Http2Options) -> auto::Builder<TokioExecutor> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    let mut http2 = builder.http2();
+    // needed by the keepalive pings
+    http2.timer(TokioTimer::new());
+    if let Some(interval) = options.keepalive_interval {
+        http2.keep_alive_interval(interval);
+    }
+    if let Some(timeout) = options.keepalive_timeout {
+        http2.keep_alive_timeout(timeout);
+    }
+    if let Some(max) = options.max_concurrent_streams {
+        http2.max_concurrent_streams(max);
+    }
+    builder
+}
+
 /// Graceful shutdown handler
 async fn shutdown_handler() {
     let ctrl_c = async {
@@ -403,11 +1105,13 @@ async fn shutdown_handler() {
 fn create_grpc_service(rpc_impl: RpcImpl) -> Result<Routes> {
     let grpc_service = Routes::new(
         v1::tap_aggregator_server::TapAggregatorServer::new(rpc_impl.clone())
-            .accept_compressed(CompressionEncoding::Zstd),
+            .accept_compressed(CompressionEncoding::Zstd)
+            .send_compressed(CompressionEncoding::Zstd),
     )
     .add_service(
         v2::tap_aggregator_server::TapAggregatorServer::new(rpc_impl)
-            .accept_compressed(CompressionEncoding::Zstd),
+            .accept_compressed(CompressionEncoding::Zstd)
+            .send_compressed(CompressionEncoding::Zstd),
     )
     .prepare();
 
@@ -435,16 +1139,32 @@ fn create_json_rpc_service(
 #[cfg(test)]
 #[allow(clippy::too_many_arguments)]
 mod tests {
-    use std::{collections::HashSet, str::FromStr};
+    use std::{
+        collections::{HashMap, HashSet},
+        io,
+        net::{IpAddr, Ipv4Addr},
+        str::FromStr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
 
     use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
-    use jsonrpsee::{core::client::ClientT, http_client::HttpClientBuilder, rpc_params};
+    use jsonrpsee::{
+        core::client::ClientT, http_client::HttpClientBuilder, rpc_params, server::Extensions,
+    };
     use rand::{prelude::*, seq::SliceRandom};
     use rstest::*;
     use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
     use tap_graph::{Receipt, ReceiptAggregateVoucher};
-
-    use crate::server;
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    use crate::{
+        correlation::{CorrelationId, CORRELATION_ID_HEADER},
+        grpc::v1::{tap_aggregator_client::TapAggregatorClient, RavRequest},
+        rav_log::RavLog,
+        readiness::ReadinessGate,
+        server,
+    };
 
     #[derive(Clone)]
     struct Keys {
@@ -767,46 +1487,1081 @@ mod tests {
         handle.abort();
     }
 
-    /// Test that the server returns an error when the request size exceeds the limit.
-    /// The server should return HTTP 413 (Request Entity Too Large).
-    /// In this test, the request size limit is set to 100 kB, and we are expecting
-    /// that to fit about 250 receipts. We also test with 300 receipts, which should
-    /// exceed the limit.
-    /// We conclude that a limit of 10MB should fit about 25k receipts, and thus
-    /// the TAP spec will require that the aggregator supports up to 15k receipts
-    /// per aggregation request as a safe limit.
+    #[rstest]
+    #[test]
+    fn max_in_flight_requests(domain_separator: Eip712Domain, allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+
+        let rpc_impl = server::RpcImpl::new(
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            &server::ServerOptions {
+                max_in_flight_requests: Some(1),
+                ..Default::default()
+            },
+        );
+
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        // Saturate the semaphore
+        let in_flight = rpc_impl.start_request().unwrap();
+        assert!(rpc_impl.start_request().is_none());
+
+        let res = server::RpcServer::aggregate_receipts(
+            &rpc_impl,
+            &Extensions::default(),
+            "0.0".to_string(),
+            receipts.clone(),
+            None,
+            None,
+        );
+        assert_eq!(
+            res.unwrap_err().code(),
+            crate::error_codes::JsonRpcErrorCode::ServerBusy as i32
+        );
+
+        // Once the request completes, new requests are accepted again
+        drop(in_flight);
+        assert!(server::RpcServer::aggregate_receipts(
+            &rpc_impl,
+            &Extensions::default(),
+            "0.0".to_string(),
+            receipts,
+            None,
+            None
+        )
+        .is_ok());
+    }
+
+    /// Collects the output of a `tracing` subscriber
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[rstest]
+    #[test]
+    fn aggregation_span_has_correlation_id(domain_separator: Eip712Domain) {
+        let keys_main = keys();
+        let rpc_impl = server::RpcImpl::new(
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator,
+            &server::ServerOptions::default(),
+        );
+
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_span_events(FmtSpan::NEW)
+            .with_ansi(false)
+            .finish();
+        let mut ext = Extensions::default();
+        ext.insert(CorrelationId::parse("span-request").unwrap());
+        jsonrpsee::tracing::subscriber::with_default(subscriber, || {
+            let _ = server::RpcServer::aggregate_receipts(
+                &rpc_impl,
+                &ext,
+                "0.0".to_string(),
+                Vec::new(),
+                None,
+                None,
+            );
+        });
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.contains("aggregate_receipts{correlation_id=span-request}"),
+            "{output}"
+        );
+    }
+
     #[rstest]
     #[tokio::test]
-    async fn request_size_limit(
+    async fn rav_log_has_one_line_per_aggregation(
         domain_separator: Eip712Domain,
-        http_response_size_limit: u32,
-        http_max_concurrent_connections: u32,
         allocation_ids: Vec<Address>,
-        #[values("0.0")] api_version: &str,
     ) {
-        // The keys that will be used to sign the new RAVs
         let keys_main = keys();
+        let path = std::env::temp_dir().join(format!(
+            "tap_aggregator_rav_log_{}.jsonl",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let (rav_log, writer) = RavLog::open(&path).await.unwrap();
 
-        // Set the request byte size limit to a value that easily triggers the HTTP 413
-        // error.
-        let http_request_size_limit = 100 * 1024;
+        let rpc_impl = server::RpcImpl::new(
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            &server::ServerOptions {
+                rav_log: Some(rav_log),
+                ..Default::default()
+            },
+        );
+
+        let mut previous_rav = None;
+        for value in [10, 20, 30] {
+            let receipts = vec![Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], value).unwrap(),
+                &keys_main.wallet,
+            )
+            .unwrap()];
+            let rav = server::RpcServer::aggregate_receipts(
+                &rpc_impl,
+                &Extensions::default(),
+                "0.0".to_string(),
+                receipts,
+                previous_rav,
+                None,
+            )
+            .unwrap()
+            .data
+            .signed();
+            previous_rav = rav;
+        }
 
-        // Number of receipts that is just above the number that would fit within the
-        // request size limit. This value is hard-coded here because it supports the
-        // maximum number of receipts per aggregate value we wrote in the spec / docs.
-        let number_of_receipts_to_exceed_limit = 300;
+        // the writer stops, after writing everything, once the log is dropped
+        drop(rpc_impl);
+        writer.await.unwrap();
+
+        let ravs: Vec<Eip712SignedMessage<ReceiptAggregateVoucher>> =
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ravs.len(), 3);
+        assert_eq!(ravs[2], previous_rav.unwrap());
+        assert_eq!(ravs[2].message.valueAggregate, 60);
+    }
 
-        // Start the JSON-RPC server.
-        let (handle, local_addr) = server::run_server(
-            0,
+    #[rstest]
+    #[tokio::test]
+    async fn strict_rav_log_errors(domain_separator: Eip712Domain, allocation_ids: Vec<Address>) {
+        let keys_main = keys();
+        let path = std::env::temp_dir().join(format!(
+            "tap_aggregator_strict_rav_log_{}.jsonl",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let (rav_log, writer) = RavLog::open_with_queue_size(&path, 1).await.unwrap();
+        let rpc_impl = server::RpcImpl::new(
             keys_main.wallet.clone(),
             HashSet::from([keys_main.address]),
             domain_separator.clone(),
-            http_request_size_limit,
-            http_response_size_limit,
-            http_max_concurrent_connections,
-        )
-        .await
+            &server::ServerOptions {
+                rav_log: Some(rav_log.with_strict(true)),
+                ..Default::default()
+            },
+        );
+        let aggregate = |value| {
+            let receipts = vec![Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], value).unwrap(),
+                &keys_main.wallet,
+            )
+            .unwrap()];
+            server::RpcServer::aggregate_receipts(
+                &rpc_impl,
+                &Extensions::default(),
+                "0.0".to_string(),
+                receipts,
+                None,
+                None,
+            )
+        };
+
+        // the writer task does not run before this task yields, so the
+        // second RAV does not fit in the queue: retry later
+        assert!(aggregate(10).is_ok());
+        assert_eq!(
+            aggregate(20).unwrap_err().code(),
+            crate::error_codes::JsonRpcErrorCode::ServerBusy as i32
+        );
+
+        // retrying does not help once the writer stopped
+        writer.abort();
+        let _ = writer.await;
+        assert_eq!(
+            aggregate(30).unwrap_err().code(),
+            jsonrpsee::types::ErrorCode::InternalError.code()
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn correlation_id_is_written_to_rav_log(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let path = std::env::temp_dir().join(format!(
+            "tap_aggregator_correlation_id_{}.jsonl",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let (rav_log, _writer) = RavLog::open(&path).await.unwrap();
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions {
+                rav_log: Some(rav_log),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        // JSON-RPC, from the HTTP header
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(CORRELATION_ID_HEADER, "json-rpc-request".parse().unwrap());
+        let client = HttpClientBuilder::default()
+            .set_headers(headers)
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+        let _: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", &receipts, None::<()>),
+            )
+            .await
+            .unwrap();
+
+        // gRPC, from the metadata, which is sent back
+        let mut grpc_client =
+            TapAggregatorClient::connect(format!("http://127.0.0.1:{}", local_addr.port()))
+                .await
+                .unwrap();
+        let mut request = tonic::Request::new(RavRequest::new(receipts, None));
+        request
+            .metadata_mut()
+            .insert(CORRELATION_ID_HEADER, "grpc-request".parse().unwrap());
+        let response = grpc_client.aggregate_receipts(request).await.unwrap();
+        assert_eq!(
+            response.metadata().get(CORRELATION_ID_HEADER).unwrap(),
+            "grpc-request"
+        );
+
+        // the RAVs are written in the background
+        let mut lines = Vec::new();
+        for _ in 0..50 {
+            lines = std::fs::read_to_string(&path)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect();
+            if lines.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        handle.abort();
+        std::fs::remove_file(&path).unwrap();
+
+        let correlation_ids: Vec<_> = lines
+            .iter()
+            .map(|line| line["correlation_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(correlation_ids, ["json-rpc-request", "grpc-request"]);
+        // the RAVs are still readable as is
+        for line in lines {
+            serde_json::from_value::<Eip712SignedMessage<ReceiptAggregateVoucher>>(line).unwrap();
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn previous_rav_by_reference(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions {
+                rav_cache_ttl: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+        let receipt = |value| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], value).unwrap(),
+                &keys_main.wallet,
+            )
+            .unwrap()
+        };
+
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(10)], None::<()>),
+            )
+            .await
+            .unwrap();
+        let first_rav = res.data;
+        let rav_ref = res.rav_ref.unwrap();
+
+        // the previous RAV is looked up from its reference
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(20)], None::<()>, &rav_ref),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.data.message.valueAggregate, 30);
+        let second_ref = res.rav_ref.unwrap();
+
+        // sending the referenced RAV as well is accepted, but not another RAV
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(5)], &first_rav, &rav_ref),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.data.message.valueAggregate, 15);
+        let res: Result<
+            server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+            jsonrpsee::core::ClientError,
+        > = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(30)], &first_rav, &second_ref),
+            )
+            .await;
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("does not match the RAV of reference"));
+
+        // an unknown reference without the previous RAV is refused
+        let res: Result<
+            server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+            jsonrpsee::core::ClientError,
+        > = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(30)], None::<()>, "unknown"),
+            )
+            .await;
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown or expired previous RAV reference"));
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn verify_only_never_signs(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions {
+                verify_only: true,
+                rav_cache_ttl: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        // JSON-RPC
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+        let res: server::JsonRpcResponse<serde_json::Value> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", &receipts, None::<()>),
+            )
+            .await
+            .unwrap();
+        assert!(res.data.get("signature").is_none());
+        assert!(res.rav_ref.is_none());
+        let rav: server::AggregatedRav = serde_json::from_value(res.data).unwrap();
+        assert_eq!(
+            rav,
+            server::AggregatedRav::Unsigned(server::UnsignedRav {
+                message: ReceiptAggregateVoucher {
+                    allocationId: allocation_ids[0],
+                    timestampNs: receipts[0].message.timestamp_ns,
+                    valueAggregate: 42,
+                },
+                unsigned: true,
+            })
+        );
+
+        // the receipts are still checked
+        let res: Result<server::JsonRpcResponse<serde_json::Value>, _> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", [&receipts[0], &receipts[0]], None::<()>),
+            )
+            .await;
+        assert!(res.is_err());
+
+        // gRPC
+        let mut grpc_client =
+            TapAggregatorClient::connect(format!("http://127.0.0.1:{}", local_addr.port()))
+                .await
+                .unwrap();
+        let response = grpc_client
+            .aggregate_receipts(RavRequest::new(receipts.clone(), None))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.rav.is_none());
+        assert_eq!(response.unsigned_rav().unwrap(), rav.message().clone());
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reject_own_receipts(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+        #[values(false, true)] reject_own_receipts: bool,
+    ) {
+        let keys_main = keys();
+        let keys_sender = keys();
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address, keys_sender.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions {
+                reject_own_receipts,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+        let receipt = |wallet: &PrivateKeySigner| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], 42).unwrap(),
+                wallet,
+            )
+            .unwrap()
+        };
+
+        // a receipt signed by the key of the aggregator
+        let res: Result<
+            server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+            jsonrpsee::core::ClientError,
+        > = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(&keys_main.wallet)], None::<()>),
+            )
+            .await;
+        assert_eq!(res.is_err(), reject_own_receipts);
+
+        // the RAVs signed by the aggregator are still accepted as previous RAVs
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(&keys_sender.wallet)], None::<()>),
+            )
+            .await
+            .unwrap();
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", vec![receipt(&keys_sender.wallet)], Some(res.data)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.data.message.valueAggregate, 84);
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn last_successful_aggregation_timestamp_is_updated(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let (handle, local_addr) = server::run_server(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        )
+        .await
+        .unwrap();
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        let before = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let _: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", &receipts, None::<()>),
+            )
+            .await
+            .unwrap();
+
+        assert!(server::LAST_SUCCESSFUL_AGGREGATION_TIMESTAMP.get() >= before);
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn server_listens_on_bind_address(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+    ) {
+        let keys_main = keys();
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions {
+                bind_address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(local_addr.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+        let _: server::JsonRpcResponse<server::TapRpcApiVersionsInfo> = client
+            .request("api_versions", rpc_params!(None::<()>))
+            .await
+            .unwrap();
+        handle.abort();
+
+        // all the interfaces by default
+        let (handle, local_addr) = server::run_server(
+            0,
+            keys_main.wallet,
+            HashSet::from([keys_main.address]),
+            domain_separator,
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        )
+        .await
+        .unwrap();
+        assert!(local_addr.ip().is_unspecified());
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn grpc_responses_are_compressed(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions::default(),
+        )
+        .await
+        .unwrap();
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        let mut grpc_client =
+            TapAggregatorClient::connect(format!("http://127.0.0.1:{}", local_addr.port()))
+                .await
+                .unwrap()
+                .accept_compressed(tonic::codec::CompressionEncoding::Zstd);
+        let response = grpc_client
+            .aggregate_receipts(RavRequest::new(receipts, None))
+            .await
+            .unwrap();
+        assert_eq!(response.metadata().get("grpc-encoding").unwrap(), "zstd");
+
+        let signed_rav = response.into_inner().signed_rav().unwrap();
+        assert_eq!(
+            signed_rav.recover_signer(&domain_separator).unwrap(),
+            keys_main.address
+        );
+        assert_eq!(signed_rav.message.valueAggregate, 42);
+
+        handle.abort();
+    }
+
+    /// Returns the status code of `GET /health` on a new connection
+    async fn health_status(port: u16) -> u16 {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        // e.g. "HTTP/1.1 200 OK"
+        response.split(' ').nth(1).unwrap().parse().unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn unavailable_until_signer_ready(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let readiness = ReadinessGate::pending();
+
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            // the health checks use their own connections
+            4,
+            server::ServerOptions {
+                readiness: readiness.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        // Signer whose address resolves later (e.g. a remote KMS), once
+        // `resolve_signer` is sent
+        let (resolve_signer, signer_resolved) = tokio::sync::oneshot::channel();
+        let address = keys_main.address;
+        let signer_ready = readiness.ready_when(async move {
+            signer_resolved.await?;
+            Ok::<_, anyhow::Error>(address)
+        });
+
+        assert_eq!(health_status(local_addr.port()).await, 503);
+
+        let res: Result<
+            server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>>,
+            jsonrpsee::core::ClientError,
+        > = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", &receipts, None::<()>),
+            )
+            .await;
+        assert!(res.unwrap_err().to_string().contains("503"));
+
+        resolve_signer.send(()).unwrap();
+        signer_ready.await.unwrap();
+        assert!(readiness.is_ready());
+        assert_eq!(health_status(local_addr.port()).await, 200);
+
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", &receipts, None::<()>),
+            )
+            .await
+            .unwrap();
+        assert!(res.data.recover_signer(&domain_separator).unwrap() == keys_main.address);
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn refuses_to_start_without_accepted_addresses(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+    ) {
+        let res = server::run_server(
+            0,
+            keys().wallet,
+            HashSet::new(),
+            domain_separator,
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        )
+        .await;
+
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("No accepted signer addresses"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn grpc_chain_selected_by_metadata(
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+        let default_domain = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+        let chains = HashMap::from([
+            (
+                10,
+                server::ChainConfig {
+                    domain_separator: tap_eip712_domain(10, Address::from([0x22u8; 20])),
+                    wallet: PrivateKeySigner::random(),
+                    accepted_addresses: HashSet::from([keys_main.address]),
+                },
+            ),
+            (
+                42161,
+                server::ChainConfig {
+                    domain_separator: tap_eip712_domain(42161, Address::from([0x33u8; 20])),
+                    wallet: PrivateKeySigner::random(),
+                    accepted_addresses: HashSet::from([keys_main.address]),
+                },
+            ),
+        ]);
+
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            default_domain.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions {
+                chains: chains.clone(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut client =
+            TapAggregatorClient::connect(format!("http://127.0.0.1:{}", local_addr.port()))
+                .await
+                .unwrap();
+
+        for (chain_id, chain) in &chains {
+            let receipts = vec![Eip712SignedMessage::new(
+                &chain.domain_separator,
+                Receipt::new(allocation_ids[0], 42).unwrap(),
+                &keys_main.wallet,
+            )
+            .unwrap()];
+            let mut request = tonic::Request::new(RavRequest::new(receipts, None));
+            request.metadata_mut().insert(
+                server::CHAIN_ID_METADATA_KEY,
+                chain_id.to_string().parse().unwrap(),
+            );
+
+            let signed_rav = client
+                .aggregate_receipts(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .signed_rav()
+                .unwrap();
+            // signed by the wallet of the chain, for its domain
+            assert_eq!(
+                signed_rav.recover_signer(&chain.domain_separator).unwrap(),
+                chain.wallet.address()
+            );
+        }
+
+        // the wallet of a chain is only accepted for that chain
+        let (chain_10, chain_42161) = (&chains[&10], &chains[&42161]);
+        let receipts = vec![Eip712SignedMessage::new(
+            &chain_10.domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &chain_42161.wallet,
+        )
+        .unwrap()];
+        let mut request = tonic::Request::new(RavRequest::new(receipts, None));
+        request
+            .metadata_mut()
+            .insert(server::CHAIN_ID_METADATA_KEY, "10".parse().unwrap());
+        let status = client.aggregate_receipts(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let receipts = vec![Eip712SignedMessage::new(
+            &default_domain,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &chain_10.wallet,
+        )
+        .unwrap()];
+        let request = tonic::Request::new(RavRequest::new(receipts, None));
+        let status = client.aggregate_receipts(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        // unconfigured chain
+        let receipts = vec![Eip712SignedMessage::new(
+            &tap_eip712_domain(137, Address::ZERO),
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+        let mut request = tonic::Request::new(RavRequest::new(receipts, None));
+        request
+            .metadata_mut()
+            .insert(server::CHAIN_ID_METADATA_KEY, "137".parse().unwrap());
+        let status = client.aggregate_receipts(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        handle.abort();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn serves_with_http2_options(
+        domain_separator: Eip712Domain,
+        http_request_size_limit: u32,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+    ) {
+        let keys_main = keys();
+
+        let (handle, local_addr) = server::run_server_with_options(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+            server::ServerOptions {
+                http2: server::Http2Options {
+                    keepalive_interval: Some(Duration::from_millis(100)),
+                    keepalive_timeout: Some(Duration::from_secs(1)),
+                    max_concurrent_streams: Some(1),
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let receipts = vec![Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 42).unwrap(),
+            &keys_main.wallet,
+        )
+        .unwrap()];
+
+        // gRPC over HTTP/2
+        let mut client =
+            TapAggregatorClient::connect(format!("http://127.0.0.1:{}", local_addr.port()))
+                .await
+                .unwrap();
+        let res = client
+            .aggregate_receipts(RavRequest::new(receipts.clone(), None))
+            .await
+            .unwrap();
+        let signed_rav = res.into_inner().signed_rav().unwrap();
+        assert_eq!(signed_rav.message.valueAggregate, 42);
+
+        // the connection outlives several keepalive pings, sent as the clock
+        // is advanced, well within the keepalive timeout
+        tokio::time::pause();
+        for _ in 0..5 {
+            tokio::time::advance(Duration::from_millis(100)).await;
+        }
+        tokio::time::resume();
+        let res = client
+            .aggregate_receipts(RavRequest::new(receipts.clone(), None))
+            .await
+            .unwrap();
+        assert_eq!(res.into_inner().signed_rav().unwrap(), signed_rav);
+
+        // JSON-RPC over HTTP/1 is not affected
+        let client = HttpClientBuilder::default()
+            .build(format!("http://127.0.0.1:{}", local_addr.port()))
+            .unwrap();
+        let res: server::JsonRpcResponse<Eip712SignedMessage<ReceiptAggregateVoucher>> = client
+            .request(
+                "aggregate_receipts",
+                rpc_params!("0.0", &receipts, None::<()>),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.data, signed_rav);
+
+        handle.abort();
+    }
+
+    /// Test that the server returns an error when the request size exceeds the limit.
+    /// The server should return HTTP 413 (Request Entity Too Large).
+    /// In this test, the request size limit is set to 100 kB, and we are expecting
+    /// that to fit about 250 receipts. We also test with 300 receipts, which should
+    /// exceed the limit.
+    /// We conclude that a limit of 10MB should fit about 25k receipts, and thus
+    /// the TAP spec will require that the aggregator supports up to 15k receipts
+    /// per aggregation request as a safe limit.
+    #[rstest]
+    #[tokio::test]
+    async fn request_size_limit(
+        domain_separator: Eip712Domain,
+        http_response_size_limit: u32,
+        http_max_concurrent_connections: u32,
+        allocation_ids: Vec<Address>,
+        #[values("0.0")] api_version: &str,
+    ) {
+        // The keys that will be used to sign the new RAVs
+        let keys_main = keys();
+
+        // Set the request byte size limit to a value that easily triggers the HTTP 413
+        // error.
+        let http_request_size_limit = 100 * 1024;
+
+        // Number of receipts that is just above the number that would fit within the
+        // request size limit. This value is hard-coded here because it supports the
+        // maximum number of receipts per aggregate value we wrote in the spec / docs.
+        let number_of_receipts_to_exceed_limit = 300;
+
+        // Start the JSON-RPC server.
+        let (handle, local_addr) = server::run_server(
+            0,
+            keys_main.wallet.clone(),
+            HashSet::from([keys_main.address]),
+            domain_separator.clone(),
+            http_request_size_limit,
+            http_response_size_limit,
+            http_max_concurrent_connections,
+        )
+        .await
         .unwrap();
 
         // Start the JSON-RPC client.
@@ -863,4 +2618,36 @@ mod tests {
 
         handle.abort();
     }
+
+    #[rstest]
+    fn debug_does_not_leak_private_keys(domain_separator: Eip712Domain) {
+        let server_keys = keys();
+        let chain_keys = keys();
+        let options = server::ServerOptions {
+            chains: [(
+                2,
+                server::ChainConfig {
+                    domain_separator: domain_separator.clone(),
+                    wallet: chain_keys.wallet.clone(),
+                    accepted_addresses: HashSet::new(),
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let rpc = server::RpcImpl::new(
+            server_keys.wallet.clone(),
+            HashSet::new(),
+            domain_separator,
+            &options,
+        );
+
+        let debug = format!("{rpc:?}");
+        for keys in [server_keys, chain_keys] {
+            assert!(debug.contains(&keys.address.to_string()));
+            let private_key = keys.wallet.to_bytes().to_string();
+            assert!(!debug.contains(&private_key));
+            assert!(!debug.contains(private_key.trim_start_matches("0x")));
+        }
+    }
 }
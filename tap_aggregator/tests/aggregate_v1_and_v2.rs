@@ -4,13 +4,17 @@
 use std::collections::HashSet;
 
 use alloy::{
-    primitives::{address, Address},
+    dyn_abi::Eip712Domain,
+    primitives::{address, Address, B256, U256},
     signers::local::PrivateKeySigner,
 };
 use tap_aggregator::{
     grpc::{
-        v1::{tap_aggregator_client::TapAggregatorClient as ClientV1, RavRequest as ReqV1},
-        v2::{tap_aggregator_client::TapAggregatorClient as ClientV2, RavRequest as ReqV2},
+        v1::{
+            self, tap_aggregator_client::TapAggregatorClient as ClientV1, GetApiVersionsRequest,
+            GetEip712DomainRequest, RavRequest as ReqV1,
+        },
+        v2::{self, tap_aggregator_client::TapAggregatorClient as ClientV2, RavRequest as ReqV2},
     },
     server,
 };
@@ -97,3 +101,77 @@ async fn aggregation_test() {
 
     assert!(res.is_ok());
 }
+
+#[tokio::test]
+async fn domain_and_api_versions_test() {
+    let domain_separator =
+        tap_eip712_domain(1, address!("1111111111111111111111111111111111111111"));
+    let wallet = PrivateKeySigner::random();
+
+    let (_, local_addr) = server::run_server(
+        0,
+        wallet.clone(),
+        HashSet::from([wallet.address()]),
+        domain_separator.clone(),
+        1024 * 100,
+        1024 * 100,
+        1,
+    )
+    .await
+    .unwrap();
+    let endpoint = format!("http://127.0.0.1:{}", local_addr.port());
+
+    let mut client = ClientV1::connect(endpoint.clone()).await.unwrap();
+    let domain = client
+        .get_eip712_domain(GetEip712DomainRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(Eip712Domain::try_from(domain).unwrap(), domain_separator);
+    let versions = client
+        .get_api_versions(GetApiVersionsRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(versions.versions_supported, ["0.0"]);
+    assert!(versions.versions_deprecated.is_empty());
+
+    let mut client = ClientV2::connect(endpoint).await.unwrap();
+    let domain = client
+        .get_eip712_domain(v2::GetEip712DomainRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(Eip712Domain::try_from(domain).unwrap(), domain_separator);
+    let versions = client
+        .get_api_versions(v2::GetApiVersionsRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(versions.versions_supported, ["0.0"]);
+    assert!(versions.versions_deprecated.is_empty());
+}
+
+#[test]
+fn domain_response_round_trip() {
+    let domain_separator = Eip712Domain::new(
+        Some("TAP".into()),
+        Some("1".into()),
+        Some(U256::from(u64::MAX)),
+        Some(address!("1111111111111111111111111111111111111111")),
+        Some(B256::repeat_byte(0x42)),
+    );
+
+    let domain = v1::GetEip712DomainResponse::try_from(&domain_separator).unwrap();
+    assert_eq!(Eip712Domain::try_from(domain).unwrap(), domain_separator);
+    let domain = v2::GetEip712DomainResponse::try_from(&domain_separator).unwrap();
+    assert_eq!(Eip712Domain::try_from(domain).unwrap(), domain_separator);
+
+    // the chain ID is not clamped to the protocol field
+    let domain_separator = Eip712Domain {
+        chain_id: Some(U256::from(u64::MAX) + U256::from(1)),
+        ..domain_separator
+    };
+    assert!(v1::GetEip712DomainResponse::try_from(&domain_separator).is_err());
+    assert!(v2::GetEip712DomainResponse::try_from(&domain_separator).is_err());
+}
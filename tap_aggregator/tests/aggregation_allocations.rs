@@ -0,0 +1,135 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Heap allocations made to aggregate a batch of 10k receipts received over
+//! gRPC: the conversion of the request, then
+//! `check_and_aggregate_receipts`, which must not allocate for each receipt.
+//!
+//! The test binary only holds this test, so that no other test allocates
+//! while the allocations are counted.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashSet,
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, FixedBytes},
+    signers::local::PrivateKeySigner,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use tap_aggregator::{
+    aggregator::{self, AggregationOptions},
+    grpc::v1::RavRequest,
+};
+use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+use tap_graph::{Receipt, SignedReceipt};
+
+const RECEIPT_COUNT: usize = 10_000;
+const SEED: u64 = 42;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the allocations and reallocations of the system allocator.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Runs `f` and returns the number of allocations it made.
+fn count_allocations<T>(f: impl FnOnce() -> T) -> usize {
+    ALLOCATIONS.store(0, Ordering::Relaxed);
+    let result = black_box(f());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(result);
+    allocations
+}
+
+/// Generates `RECEIPT_COUNT` signed receipts in a random order, always the
+/// same for a given seed.
+fn shuffled_receipts(
+    domain_separator: &Eip712Domain,
+    wallet: &PrivateKeySigner,
+    rng: &mut StdRng,
+) -> Vec<SignedReceipt> {
+    let allocation_id = Address::from(rng.gen::<[u8; 20]>());
+    let mut receipts: Vec<_> = (0..RECEIPT_COUNT)
+        .map(|i| {
+            let receipt = Receipt {
+                allocation_id,
+                timestamp_ns: i as u64 + 1,
+                nonce: rng.gen(),
+                value: rng.gen_range(1..1_000_000),
+            };
+            Eip712SignedMessage::new(domain_separator, receipt, wallet).unwrap()
+        })
+        .collect();
+    receipts.shuffle(rng);
+    receipts
+}
+
+#[test]
+fn aggregation_allocations() {
+    let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let wallet = PrivateKeySigner::from_bytes(&FixedBytes::from(rng.gen::<[u8; 32]>())).unwrap();
+    let accepted_addresses = HashSet::from([wallet.address()]);
+    let receipts = shuffled_receipts(&domain_separator, &wallet, &mut rng);
+    let request = RavRequest::new(receipts.clone(), None);
+
+    // the conversion the gRPC server did before, growing the vector
+    let collected = request.clone();
+    let collected = count_allocations(|| {
+        collected
+            .receipts
+            .into_iter()
+            .map(SignedReceipt::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    });
+    let mut taken = request.clone();
+    let taken = count_allocations(|| taken.take_receipts().unwrap());
+    assert!(
+        taken < collected,
+        "taking the receipts made {taken} allocations, collecting them {collected}"
+    );
+
+    let mut sorted_receipts = receipts.clone();
+    sorted_receipts.sort_by_key(|receipt| receipt.message.timestamp_ns);
+    for receipts in [receipts, sorted_receipts] {
+        let allocations = count_allocations(|| {
+            aggregator::v1::check_and_aggregate_receipts(
+                &domain_separator,
+                &receipts,
+                None,
+                &wallet,
+                &accepted_addresses,
+                AggregationOptions::default(),
+            )
+            .unwrap()
+        });
+        assert!(
+            allocations < RECEIPT_COUNT,
+            "aggregating {RECEIPT_COUNT} receipts made {allocations} allocations"
+        );
+    }
+}
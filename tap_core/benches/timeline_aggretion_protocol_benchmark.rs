@@ -8,11 +8,26 @@
 //! The payment receiver would verify the received receipt and store it to be
 //! accumulated with other received receipts in the future.
 
-use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
 
 use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use tap_core::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+use tap_core::{
+    manager::{
+        context::memory::{checks::get_full_list_of_checks, InMemoryContext},
+        Manager,
+    },
+    receipt::{
+        checks::{CheckList, StatefulTimestampCheck},
+        Context,
+    },
+    signed_message::Eip712SignedMessage,
+    tap_eip712_domain,
+};
 use tap_graph::{Receipt, ReceiptAggregateVoucher};
 
 pub fn create_and_sign_receipt(
@@ -60,6 +75,27 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         })
     });
 
+    c.bench_function("Recover signer and unique hash", |b| {
+        b.iter(|| {
+            let receipt = black_box(&receipt);
+            (
+                receipt.recover_signer(&domain_seperator).unwrap(),
+                receipt.unique_hash(),
+            )
+        })
+    });
+
+    c.bench_function("Recover signer and unique hash w/ computed hashes", |b| {
+        b.iter(|| {
+            let receipt = black_box(&receipt);
+            let hashes = receipt.computed_hashes(&domain_seperator);
+            (
+                receipt.recover_signer_with_hashes(&hashes).unwrap(),
+                hashes.message_id(),
+            )
+        })
+    });
+
     let mut rav_group = c.benchmark_group("Create RAV with varying input sizes");
 
     for log_number_of_receipts in 10..30 {
@@ -94,5 +130,98 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, criterion_benchmark);
+pub fn signing_benchmark(c: &mut Criterion) {
+    let domain_seperator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let receipts: Vec<_> = (0..10_000)
+        .map(|value| Receipt::new(allocation_id, value).unwrap())
+        .collect();
+
+    let mut signing_group = c.benchmark_group("Sign 10k receipts and compute their unique hash");
+    signing_group.sample_size(10);
+
+    signing_group.bench_function("Sign then hash", |b| {
+        b.iter(|| {
+            black_box(&receipts)
+                .iter()
+                .map(|receipt| {
+                    let signed_receipt =
+                        Eip712SignedMessage::new(&domain_seperator, receipt.clone(), &wallet)
+                            .unwrap();
+                    let unique_hash = signed_receipt.unique_hash();
+                    (signed_receipt, unique_hash)
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+
+    signing_group.bench_function("Sign w/ computed hashes", |b| {
+        b.iter(|| {
+            Eip712SignedMessage::new_batch(&domain_seperator, black_box(&receipts).clone(), &wallet)
+                .unwrap()
+                .into_iter()
+                .map(|(signed_receipt, hashes)| (signed_receipt, hashes.message_id()))
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+pub fn rav_request_benchmark(c: &mut Criterion) {
+    let domain_seperator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let value = 12345u128;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    let context = InMemoryContext::new(
+        Arc::new(RwLock::new(Vec::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(StatefulTimestampCheck::new(0)),
+    );
+    let checks = get_full_list_of_checks(
+        domain_seperator.clone(),
+        [wallet.address()].into_iter().collect(),
+        Arc::new(RwLock::new([allocation_id].into_iter().collect())),
+        Arc::new(RwLock::new(HashMap::new())),
+    );
+    let manager = Manager::new(domain_seperator.clone(), context, CheckList::new(checks)).unwrap();
+
+    runtime.block_on(async {
+        for _ in 0..5000 {
+            let receipt = create_and_sign_receipt(&domain_seperator, allocation_id, value, &wallet);
+            manager
+                .verify_and_store_receipt(&Context::new(), receipt)
+                .await
+                .unwrap();
+        }
+    });
+
+    c.bench_function("Create RAV request w/ 5000 receipts", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(manager.create_rav_request::<ReceiptAggregateVoucher>(
+                    &Context::new(),
+                    black_box(0),
+                    black_box(None),
+                    black_box(None),
+                ))
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    criterion_benchmark,
+    signing_benchmark,
+    rav_request_benchmark
+);
 criterion_main!(benches);
@@ -0,0 +1,111 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Aggregation of receipts spanning several allocations
+
+use std::collections::BTreeMap;
+
+use alloy::{dyn_abi::Eip712Domain, signers::local::PrivateKeySigner};
+use tap_receipt::{rav::Aggregate, state::Checked, ReceiptWithState, WithAllocationId};
+
+use crate::{signed_message::Eip712SignedMessage, Error};
+
+/// Aggregates `receipts` into one signed RAV per allocation.
+///
+/// The receipts are partitioned by allocation id, and each group is
+/// aggregated on its own, without a previous RAV. The results are ordered by
+/// allocation id, and a failing group does not prevent the others from being
+/// aggregated.
+///
+/// To carry on from the previous RAV of an allocation, aggregate its receipts
+/// with [`Aggregate::aggregate_receipts`] instead.
+pub fn group_and_aggregate<Rcpt, Rav>(
+    receipts: Vec<ReceiptWithState<Checked, Rcpt>>,
+    domain_separator: &Eip712Domain,
+    signer: &PrivateKeySigner,
+) -> Vec<Result<Eip712SignedMessage<Rav>, Error>>
+where
+    Rcpt: WithAllocationId,
+    Rav: Aggregate<Rcpt>,
+{
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    for receipt in receipts {
+        groups
+            .entry(receipt.signed_receipt().allocation_id())
+            .or_default()
+            .push(receipt);
+    }
+
+    groups
+        .into_values()
+        .map(|group| {
+            let rav = Rav::aggregate_receipts(&group, None)?;
+            Ok(Eip712SignedMessage::new(domain_separator, rav, signer)?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+    use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedReceipt};
+    use tap_receipt::{state::Checked, Context, ReceiptWithState};
+
+    use super::group_and_aggregate;
+    use crate::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+
+    async fn checked(receipt: SignedReceipt) -> ReceiptWithState<Checked, SignedReceipt> {
+        ReceiptWithState::new(receipt)
+            .finalize_receipt_checks(&Context::new(), &[])
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn receipts_are_aggregated_per_allocation() {
+        let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+        let signer = PrivateKeySigner::random();
+        let allocation_ids = [
+            Address::from([0xccu8; 20]),
+            Address::from([0xaau8; 20]),
+            Address::from([0xbbu8; 20]),
+        ];
+
+        // interleaved receipts of the three allocations
+        let mut receipts = Vec::new();
+        for value in 1..=9 {
+            let allocation_id = allocation_ids[value as usize % 3];
+            let receipt = Receipt::new(allocation_id, value).unwrap();
+            let signed_receipt =
+                Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+            receipts.push(checked(signed_receipt).await);
+        }
+
+        let ravs: Vec<ReceiptAggregateVoucher> =
+            group_and_aggregate(receipts, &domain_separator, &signer)
+                .into_iter()
+                .map(|signed_rav| {
+                    let signed_rav = signed_rav.unwrap();
+                    assert_eq!(
+                        signed_rav.recover_signer(&domain_separator).unwrap(),
+                        signer.address()
+                    );
+                    signed_rav.message
+                })
+                .collect();
+
+        let allocations_and_values: Vec<_> = ravs
+            .iter()
+            .map(|rav| (rav.allocationId, rav.valueAggregate))
+            .collect();
+        assert_eq!(
+            allocations_and_values,
+            vec![
+                (allocation_ids[1], 1 + 4 + 7),
+                (allocation_ids[2], 2 + 5 + 8),
+                (allocation_ids[0], 3 + 6 + 9),
+            ]
+        );
+    }
+}
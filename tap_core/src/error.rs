@@ -9,7 +9,7 @@ use std::result::Result as StdResult;
 use alloy::primitives::Address;
 use thiserror::Error as ThisError;
 
-use crate::receipt::ReceiptError;
+use crate::receipt::{rav::AggregationError, ReceiptError};
 
 /// Error type for the TAP protocol
 #[derive(ThisError, Debug)]
@@ -17,6 +17,10 @@ pub enum Error {
     /// Error when trying to aggregate receipts and the result overflows
     #[error("Aggregating receipt results in overflow")]
     AggregateOverflow,
+    /// Error when aggregating receipts into a RAV fails, other than an
+    /// overflow, which is reported as [`Error::AggregateOverflow`]
+    #[error(transparent)]
+    Aggregation(AggregationError),
     /// Error when Rust fails to get the current system time
     #[error("Failed to get current system time: {source_error_message} ")]
     InvalidSystemTime { source_error_message: String },
@@ -62,6 +66,27 @@ pub enum Error {
     /// Used in tap_aggregator
     #[error("Duplicate receipt signature: {0}")]
     DuplicateReceiptSignature(String),
+    /// Error when two receipts share the same allocation id and nonce.
+    ///
+    /// Used in tap_aggregator
+    #[error("Duplicate receipt nonce {nonce} for allocation {allocation_id}")]
+    DuplicateReceiptNonce { allocation_id: Address, nonce: u64 },
+    /// Error when the previous RAV value is above the configured maximum.
+    ///
+    /// Used in tap_aggregator
+    #[error("Previous RAV value ({value}) is above the accepted maximum ({max_value})")]
+    PreviousRavValueTooHigh { value: u128, max_value: u128 },
+    /// Error when a message is signed under an unknown EIP-712 domain version.
+    ///
+    /// Used in tap_aggregator
+    #[error("Unknown EIP-712 domain version: {0}")]
+    UnknownDomainVersion(String),
+    /// Error when a RAV stamped with the aggregation time would be older than
+    /// its latest receipt.
+    ///
+    /// Used in tap_aggregator
+    #[error("Aggregation time ({now_ns}) is before the latest receipt timestamp ({receipt_ts})")]
+    AggregationTimeBeforeReceipt { now_ns: u64, receipt_ts: u64 },
     #[error(
         "Receipt timestamp ({receipt_ts}) is less or equal than previous rav timestamp ({rav_ts})"
     )]
@@ -75,6 +100,16 @@ pub enum Error {
         max_timestamp_ns: u64,
     },
 
+    /// Error when receipts are received while their acceptance is paused
+    /// Used by [`crate::manager::Manager::verify_and_store_receipt()`]
+    #[error("Not accepting receipts at the moment")]
+    NotAcceptingReceipts,
+
+    /// Error when a receipt is held until the escrow of its sender is topped up
+    /// Used by [`crate::manager::Manager::verify_and_store_receipt()`]
+    #[error("Receipt held until the sender escrow is topped up: {0}")]
+    ReceiptPendingEscrow(ReceiptError),
+
     /// Error on the receipt side
     #[error("Receipt error: {0}")]
     ReceiptError(#[from] ReceiptError),
@@ -90,4 +125,13 @@ pub enum Error {
     FailedToVerifySigner(String),
 }
 
+impl From<AggregationError> for Error {
+    fn from(err: AggregationError) -> Self {
+        match err {
+            AggregationError::AggregateOverflow => Error::AggregateOverflow,
+            err => Error::Aggregation(err),
+        }
+    }
+}
+
 pub type Result<T> = StdResult<T, Error>;
@@ -0,0 +1,58 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Escrow needed to pay for receipts
+
+use alloy::sol_types::SolStruct;
+use tap_receipt::{rav::CheckedSum, WithValueAndTimestamp};
+
+use crate::{signed_message::Eip712SignedMessage, Error};
+
+/// Returns the escrow needed to pay for `receipts`, i.e. the sum of their
+/// values, e.g. for an indexer to check the escrow of the sender before
+/// accepting a batch of receipts.
+///
+/// Returns [`Error::AggregateOverflow`] if the sum does not fit in a `u128`,
+/// in which case the receipts could not be aggregated either.
+pub fn required_escrow<R: SolStruct + WithValueAndTimestamp>(
+    receipts: &[Eip712SignedMessage<R>],
+) -> Result<u128, Error> {
+    receipts
+        .iter()
+        .map(|receipt| receipt.message.value())
+        .checked_sum()
+        .map_err(|_| Error::AggregateOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+    use tap_graph::{Receipt, SignedReceipt};
+
+    use super::required_escrow;
+    use crate::{signed_message::Eip712SignedMessage, tap_eip712_domain, Error};
+
+    #[test]
+    fn required_escrow_sums_values() {
+        let domain_separator = tap_eip712_domain(1, Address::from([0x11u8; 20]));
+        let signer = PrivateKeySigner::random();
+        let receipt = |value| -> SignedReceipt {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(Address::from([0xaau8; 20]), value).unwrap(),
+                &signer,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(required_escrow::<Receipt>(&[]).unwrap(), 0);
+        assert_eq!(
+            required_escrow(&[receipt(1), receipt(2), receipt(3)]).unwrap(),
+            6
+        );
+        assert!(matches!(
+            required_escrow(&[receipt(u128::MAX), receipt(1)]),
+            Err(Error::AggregateOverflow)
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversion of the TAP errors into JSON-RPC errors, so that servers built
+//! on `jsonrpsee` report them consistently.
+//!
+//! The JSON-RPC spec allocates error codes in the range `[-32000, -32099]` for
+//! application errors. TAP errors use `[-32000, -32049]`, where `-32000` is
+//! reserved for all errors without a specific code.
+//!
+//! Receipt errors carry the serialized [`ReceiptError`] as data, so that
+//! clients can branch on [`ReceiptError::code`]. Aggregation errors carry the
+//! [`AggregationStage`] that failed, see [`AggregationErrorData`].
+
+use jsonrpsee_types::{ErrorObject, ErrorObjectOwned};
+use serde::{Deserialize, Serialize};
+use tap_eip712_message::Eip712Error;
+
+use crate::{
+    receipt::{rav::AggregationError, ReceiptError},
+    Error,
+};
+
+/// JSON-RPC error codes of the TAP errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JsonRpcErrorCode {
+    /// -32000 -- Generic error.
+    Generic = -32000,
+    /// -32001 -- Invalid API version.
+    InvalidVersion = -32001,
+    /// -32002 -- Error during receipt aggregation.
+    Aggregation = -32002,
+    /// -32003 -- Too many aggregation requests in flight, retry later.
+    ServerBusy = -32003,
+    /// -32004 -- Receipt rejected by a check.
+    InvalidReceipt = -32004,
+    /// -32005 -- Signature invalid or from a signer that is not accepted.
+    InvalidSignature = -32005,
+}
+
+/// Stage of the aggregation that failed, so that clients can tell e.g. a
+/// signature failure from an overflow without parsing the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AggregationStage {
+    /// Invalid signature, or signer that is not accepted
+    Signature,
+    /// Receipt or RAV timestamps out of order
+    Timestamp,
+    /// Receipts or RAV of different allocations
+    Allocation,
+    /// Aggregate value overflow
+    Overflow,
+    /// Any other failure
+    Other,
+}
+
+impl AggregationStage {
+    /// Returns the stage that failed with `err`.
+    pub fn of_error(err: &Error) -> Self {
+        match err {
+            Error::SignatureError(_)
+            | Error::VerificationFailed { .. }
+            | Error::InvalidRecoveredSigner { .. }
+            | Error::FailedToVerifySigner(_) => Self::Signature,
+            Error::AggregationTimeBeforeReceipt { .. }
+            | Error::ReceiptTimestampLowerThanRav { .. }
+            | Error::TimestampRangeError { .. } => Self::Timestamp,
+            Error::RavAllocationIdMismatch { .. } | Error::RavAllocationIdNotUniform => {
+                Self::Allocation
+            }
+            Error::AggregateOverflow => Self::Overflow,
+            Error::Aggregation(err) => Self::of_aggregation_error(err),
+            _ => Self::Other,
+        }
+    }
+
+    fn of_aggregation_error(err: &AggregationError) -> Self {
+        match err {
+            AggregationError::AggregateOverflow => Self::Overflow,
+            AggregationError::ReceiptTimestampNotAfterBase { .. } => Self::Timestamp,
+            _ => Self::Other,
+        }
+    }
+
+    /// Same as [`AggregationStage::of_error`], for an error of an aggregator,
+    /// which may also be an [`AggregationError`] or an [`Eip712Error`].
+    pub fn of(err: &anyhow::Error) -> Self {
+        if let Some(err) = err.downcast_ref::<Error>() {
+            return Self::of_error(err);
+        }
+        match err.downcast_ref::<AggregationError>() {
+            Some(err) => Self::of_aggregation_error(err),
+            None if err.is::<Eip712Error>() => Self::Signature,
+            None => Self::Other,
+        }
+    }
+}
+
+/// Data of the [`JsonRpcErrorCode::Aggregation`] errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregationErrorData {
+    pub stage: AggregationStage,
+}
+
+/// Returns the [`JsonRpcErrorCode::Aggregation`] error of an aggregator
+/// failing with `err`.
+pub fn aggregation_error(err: &anyhow::Error) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        JsonRpcErrorCode::Aggregation as i32,
+        err.to_string(),
+        Some(AggregationErrorData {
+            stage: AggregationStage::of(err),
+        }),
+    )
+}
+
+impl From<ReceiptError> for ErrorObjectOwned {
+    fn from(err: ReceiptError) -> Self {
+        ErrorObject::owned(
+            JsonRpcErrorCode::InvalidReceipt as i32,
+            err.to_string(),
+            Some(err),
+        )
+    }
+}
+
+impl From<Error> for ErrorObjectOwned {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::ReceiptError(err) => err.into(),
+            err => match error_code(&err) {
+                JsonRpcErrorCode::Aggregation => ErrorObject::owned(
+                    JsonRpcErrorCode::Aggregation as i32,
+                    err.to_string(),
+                    Some(AggregationErrorData {
+                        stage: AggregationStage::of_error(&err),
+                    }),
+                ),
+                code => ErrorObject::owned(code as i32, err.to_string(), None::<()>),
+            },
+        }
+    }
+}
+
+fn error_code(err: &Error) -> JsonRpcErrorCode {
+    match err {
+        Error::ReceiptError(_) | Error::ReceiptPendingEscrow(_) => JsonRpcErrorCode::InvalidReceipt,
+        Error::SignatureError(_)
+        | Error::VerificationFailed { .. }
+        | Error::InvalidRecoveredSigner { .. }
+        | Error::FailedToVerifySigner(_) => JsonRpcErrorCode::InvalidSignature,
+        Error::AggregateOverflow
+        | Error::Aggregation(_)
+        | Error::InvalidReceivedRav { .. }
+        | Error::NoValidReceiptsForRavRequest
+        | Error::RavAllocationIdMismatch { .. }
+        | Error::RavAllocationIdNotUniform
+        | Error::DuplicateReceiptSignature(_)
+        | Error::DuplicateReceiptNonce { .. }
+        | Error::PreviousRavValueTooHigh { .. }
+        | Error::UnknownDomainVersion(_)
+        | Error::AggregationTimeBeforeReceipt { .. }
+        | Error::ReceiptTimestampLowerThanRav { .. }
+        | Error::TimestampRangeError { .. } => JsonRpcErrorCode::Aggregation,
+        Error::NotAcceptingReceipts => JsonRpcErrorCode::ServerBusy,
+        Error::InvalidSystemTime { .. } | Error::WalletError(_) | Error::AdapterError { .. } => {
+            JsonRpcErrorCode::Generic
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Address;
+    use jsonrpsee_types::ErrorObjectOwned;
+
+    use super::{aggregation_error, AggregationStage, JsonRpcErrorCode};
+    use crate::{
+        receipt::{rav::AggregationError, ReceiptError},
+        Error,
+    };
+
+    #[test]
+    fn errors_map_to_json_rpc_codes() {
+        let err: ErrorObjectOwned = Error::InvalidRecoveredSigner {
+            address: Address::ZERO,
+        }
+        .into();
+        assert_eq!(err.code(), JsonRpcErrorCode::InvalidSignature as i32);
+
+        let err: ErrorObjectOwned = Error::NoValidReceiptsForRavRequest.into();
+        assert_eq!(err.code(), JsonRpcErrorCode::Aggregation as i32);
+        assert_eq!(
+            err.message(),
+            Error::NoValidReceiptsForRavRequest.to_string()
+        );
+
+        let err: ErrorObjectOwned = Error::AdapterError {
+            source_error: anyhow::anyhow!("storage down"),
+        }
+        .into();
+        assert_eq!(err.code(), JsonRpcErrorCode::Generic as i32);
+    }
+
+    #[test]
+    fn receipt_errors_carry_their_code() {
+        let receipt_error = ReceiptError::InvalidValue { received_value: 42 };
+        let err: ErrorObjectOwned = Error::ReceiptError(receipt_error.clone()).into();
+        assert_eq!(err.code(), JsonRpcErrorCode::InvalidReceipt as i32);
+        assert_eq!(err.message(), receipt_error.to_string());
+        assert_eq!(
+            err.data().unwrap().get(),
+            r#"{"code":"INVALID_VALUE","details":{"received_value":42}}"#
+        );
+    }
+
+    #[test]
+    fn aggregation_errors_carry_their_stage() {
+        let errors = [
+            (
+                anyhow::Error::new(Error::InvalidRecoveredSigner {
+                    address: Address::ZERO,
+                }),
+                "SIGNATURE",
+            ),
+            (
+                anyhow::Error::new(tap_eip712_message::Eip712Error::SignatureError(
+                    alloy::primitives::SignatureError::InvalidParity(42),
+                )),
+                "SIGNATURE",
+            ),
+            (
+                anyhow::Error::new(Error::ReceiptTimestampLowerThanRav {
+                    rav_ts: 2,
+                    receipt_ts: 1,
+                }),
+                "TIMESTAMP",
+            ),
+            (
+                anyhow::Error::new(AggregationError::ReceiptTimestampNotAfterBase {
+                    base_ts: 2,
+                    receipt_ts: 1,
+                }),
+                "TIMESTAMP",
+            ),
+            (
+                anyhow::Error::new(Error::from(
+                    AggregationError::ReceiptTimestampNotAfterBase {
+                        base_ts: 2,
+                        receipt_ts: 1,
+                    },
+                )),
+                "TIMESTAMP",
+            ),
+            (
+                anyhow::Error::new(Error::RavAllocationIdNotUniform),
+                "ALLOCATION",
+            ),
+            (anyhow::Error::new(Error::AggregateOverflow), "OVERFLOW"),
+            (
+                anyhow::Error::new(AggregationError::AggregateOverflow),
+                "OVERFLOW",
+            ),
+            (
+                anyhow::Error::new(Error::NoValidReceiptsForRavRequest),
+                "OTHER",
+            ),
+            (anyhow::anyhow!("unexpected"), "OTHER"),
+        ];
+
+        for (err, stage) in errors {
+            let err_object = aggregation_error(&err);
+            assert_eq!(err_object.code(), JsonRpcErrorCode::Aggregation as i32);
+            assert_eq!(err_object.message(), err.to_string());
+            assert_eq!(
+                err_object.data().unwrap().get(),
+                format!(r#"{{"stage":"{stage}"}}"#)
+            );
+        }
+
+        // also when converting the TAP errors
+        let err: ErrorObjectOwned = Error::AggregateOverflow.into();
+        assert_eq!(err.data().unwrap().get(), r#"{"stage":"OVERFLOW"}"#);
+        assert_eq!(
+            AggregationStage::of_error(&Error::RavAllocationIdMismatch {
+                prev_id: "a".into(),
+                new_id: "b".into(),
+            }),
+            AggregationStage::Allocation
+        );
+    }
+}
@@ -5,17 +5,25 @@
 //!
 //! To get started with the TAP protocol, take a look on the [`manager`] module
 //! to see how to manage the state channel and implement the needed adapters.
+//! The commonly used types and traits can be imported at once from the
+//! [`prelude`].
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use alloy::{dyn_abi::Eip712Domain, sol_types::eip712_domain};
 use thiserror::Error;
 
+pub mod aggregation;
 mod error;
+pub mod escrow;
+#[cfg(feature = "jsonrpsee")]
+pub mod jsonrpc;
 pub mod manager;
+pub mod prelude;
 pub mod rav_request;
 pub mod receipt;
 pub mod signed_message;
+pub mod units;
 
 pub use error::Error;
 use error::Result;
@@ -64,7 +72,9 @@ mod tap_tests {
     use rstest::*;
     use tap_graph::{Receipt, ReceiptAggregateVoucher};
 
-    use crate::{signed_message::Eip712SignedMessage, tap_eip712_domain};
+    use crate::{
+        receipt::rav::AggregationError, signed_message::Eip712SignedMessage, tap_eip712_domain,
+    };
 
     #[fixture]
     fn keys() -> (PrivateKeySigner, Address) {
@@ -165,6 +175,80 @@ mod tap_tests {
         assert!(signed_rav.recover_signer(&domain_separator).unwrap() == keys.1);
     }
 
+    #[rstest]
+    #[test]
+    fn rav_from_base_matches_rav_from_previous_rav(
+        keys: (PrivateKeySigner, Address),
+        allocation_ids: Vec<Address>,
+        domain_separator: Eip712Domain,
+    ) {
+        let receipts: Vec<_> = (1..=4u64)
+            .map(|i| {
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt {
+                        allocation_id: allocation_ids[0],
+                        timestamp_ns: 100 + i,
+                        nonce: i,
+                        value: 10 * i as u128,
+                    },
+                    &keys.0,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let prev_rav =
+            ReceiptAggregateVoucher::aggregate_receipts(allocation_ids[0], &receipts[0..2], None)
+                .unwrap();
+        let signed_prev_rav =
+            Eip712SignedMessage::new(&domain_separator, prev_rav.clone(), &keys.0).unwrap();
+
+        let rav = ReceiptAggregateVoucher::aggregate_receipts(
+            allocation_ids[0],
+            &receipts[2..],
+            Some(signed_prev_rav),
+        )
+        .unwrap();
+
+        // Rebuild the RAV knowing only the values of the previous RAV
+        let rav_from_base = ReceiptAggregateVoucher::aggregate_receipts_from_base(
+            allocation_ids[0],
+            &receipts[2..],
+            prev_rav.valueAggregate,
+            prev_rav.timestampNs,
+        )
+        .unwrap();
+        assert_eq!(rav, rav_from_base);
+        assert_eq!(rav_from_base.valueAggregate, 100);
+        assert_eq!(rav_from_base.timestampNs, 104);
+
+        // Receipts must post-date the base timestamp
+        assert!(matches!(
+            ReceiptAggregateVoucher::aggregate_receipts_from_base(
+                allocation_ids[0],
+                &receipts,
+                prev_rav.valueAggregate,
+                prev_rav.timestampNs,
+            ),
+            Err(AggregationError::ReceiptTimestampNotAfterBase {
+                base_ts: 102,
+                receipt_ts: 101,
+            })
+        ));
+
+        // Overflow is still detected
+        assert!(matches!(
+            ReceiptAggregateVoucher::aggregate_receipts_from_base(
+                allocation_ids[0],
+                &receipts[2..],
+                u128::MAX,
+                prev_rav.timestampNs,
+            ),
+            Err(AggregationError::AggregateOverflow)
+        ));
+    }
+
     #[rstest]
     #[test]
     fn verify_signature(
@@ -0,0 +1,27 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use async_trait::async_trait;
+
+/// Reports whether the storage backing the context can be reached.
+///
+/// Contexts backed by a database should issue a cheap query (e.g. `SELECT 1`)
+/// with a short timeout, so that a dead connection pool can be surfaced as a
+/// readiness failure instead of failing the next receipt.
+///
+/// # Example
+///
+/// For example code see [crate::manager::context::memory::InMemoryContext]
+
+#[async_trait]
+pub trait HealthCheck {
+    /// Defines the user-specified error type.
+    ///
+    /// This error type should implement the `Error` and `Debug` traits from
+    /// the standard library.
+    /// Errors of this type are returned to the user when an operation fails.
+    type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Checks that the storage is reachable and usable.
+    async fn ping(&self) -> Result<(), Self::AdapterError>;
+}
@@ -1,12 +1,14 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::ops::RangeBounds;
+
 use alloy::sol_types::SolStruct;
 use async_trait::async_trait;
 
 use crate::signed_message::Eip712SignedMessage;
 
-/// Stores the latest RAV in the storage.
+/// Stores the RAVs in the storage.
 ///
 /// # Example
 ///
@@ -24,9 +26,47 @@ pub trait RavStore<T: SolStruct> {
     /// Updates the storage with the latest validated `SignedRAV`.
     ///
     /// This method should be implemented to store the most recent validated
-    /// `SignedRAV` into your chosen storage system. Any errors that occur
-    /// during this process should be captured and returned as an `AdapterError`.
+    /// `SignedRAV` into your chosen storage system. Previous RAVs should be
+    /// kept if [`RavRead::list_ravs`] is expected to return them.
+    /// Any errors that occur during this process should be captured and
+    /// returned as an `AdapterError`.
     async fn update_last_rav(&self, rav: Eip712SignedMessage<T>) -> Result<(), Self::AdapterError>;
+
+    /// Stores `rav` only if its timestamp is after the one of the last stored
+    /// RAV, returning whether it was stored.
+    ///
+    /// Several managers sharing the same storage may store RAVs concurrently.
+    /// The timestamps must be compared and the RAV written as a single atomic
+    /// operation, e.g. with a conditional `UPDATE` in SQL backends, so that a
+    /// stale RAV never replaces a newer one.
+    async fn update_last_rav_if_newer(
+        &self,
+        rav: Eip712SignedMessage<T>,
+    ) -> Result<bool, Self::AdapterError>;
+
+    /// Stores `rav` and deletes the receipts aggregated into it, identified
+    /// by `receipt_ids`, as a single atomic operation.
+    ///
+    /// Either both happen or neither does, so that a crash in between never
+    /// leaves receipts that are already part of the stored RAV. SQL backends
+    /// should do both in one transaction.
+    ///
+    /// The default implementation is not atomic: it only calls
+    /// [`RavStore::update_last_rav`], leaving the receipts to be removed by
+    /// [`crate::manager::Manager::remove_obsolete_receipts`]. Contexts that
+    /// also store the receipts should override it, as the in-memory context
+    /// does.
+    async fn commit_rav_and_delete_receipts(
+        &self,
+        rav: Eip712SignedMessage<T>,
+        receipt_ids: &[u64],
+    ) -> Result<(), Self::AdapterError>
+    where
+        T: Send + 'static,
+    {
+        let _ = receipt_ids;
+        self.update_last_rav(rav).await
+    }
 }
 
 /// Reads the RAV from storage
@@ -48,4 +88,13 @@ pub trait RavRead<T: SolStruct> {
     ///
     /// If no `SignedRAV` is available, this method should return `None`.
     async fn last_rav(&self) -> Result<Option<Eip712SignedMessage<T>>, Self::AdapterError>;
+
+    /// Retrieves the stored `SignedRAV`s whose timestamp is within the
+    /// `timestamp_range_ns`, ordered by timestamp.
+    ///
+    /// This allows auditing the history of RAVs, not only the last one.
+    async fn list_ravs<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<Eip712SignedMessage<T>>, Self::AdapterError>;
 }
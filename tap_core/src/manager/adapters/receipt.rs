@@ -27,6 +27,10 @@ pub trait ReceiptStore<Rcpt> {
     ///
     /// It returns a unique receipt_id associated with the stored receipt. Any errors that occur during
     /// this process should be captured and returned as an `AdapterError`.
+    ///
+    /// Implementations backed by bounded storage should return an `AdapterError`
+    /// when they are full instead of growing without limit, so the caller can
+    /// request a RAV (and remove the aggregated receipts) or shed load.
     async fn store_receipt(
         &self,
         receipt: ReceiptWithState<Checking, Rcpt>,
@@ -70,7 +74,8 @@ pub trait ReceiptRead<Rcpt> {
     /// Errors of this type are returned to the user when an operation fails.
     type AdapterError: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
 
-    /// Retrieves all [`ReceiptWithState<Checking>`] within a specific timestamp range.
+    /// Retrieves all [`ReceiptWithState<Checking>`] within a specific timestamp range,
+    /// along with the receipt_id returned by [`ReceiptStore::store_receipt`].
     ///
     /// If a limit is specified, the adapter should return at most that many receipts,
     /// while making sure that no receipts are left behind for any timestamp that
@@ -92,7 +97,7 @@ pub trait ReceiptRead<Rcpt> {
         &self,
         timestamp_range_ns: R,
         limit: Option<u64>,
-    ) -> Result<Vec<ReceiptWithState<Checking, Rcpt>>, Self::AdapterError>;
+    ) -> Result<Vec<(u64, ReceiptWithState<Checking, Rcpt>)>, Self::AdapterError>;
 }
 
 /// See [`ReceiptRead::retrieve_receipts_in_timestamp_range()`] for details.
@@ -18,14 +18,18 @@ use tap_graph::{ReceiptAggregateVoucher, SignedRav, SignedReceipt};
 
 use crate::{
     manager::adapters::*,
-    receipt::{checks::StatefulTimestampCheck, state::Checking, ReceiptWithState},
+    receipt::{
+        checks::{EscrowHeadroom, StatefulTimestampCheck},
+        state::Checking,
+        ReceiptWithState,
+    },
     signed_message::MessageId,
 };
 
 pub type EscrowStorage = Arc<RwLock<HashMap<Address, u128>>>;
 pub type QueryAppraisals = Arc<RwLock<HashMap<MessageId, u128>>>;
 pub type ReceiptStorage = Arc<RwLock<HashMap<u64, ReceiptWithState<Checking, SignedReceipt>>>>;
-pub type RAVStorage = Arc<RwLock<Option<SignedRav>>>;
+pub type RAVStorage = Arc<RwLock<Vec<SignedRav>>>;
 
 use thiserror::Error;
 
@@ -33,17 +37,22 @@ use thiserror::Error;
 pub enum InMemoryError {
     #[error("something went wrong: {error}")]
     AdapterError { error: String },
+    #[error("receipt storage is full ({max_stored_receipts} receipts)")]
+    StorageFull { max_stored_receipts: usize },
 }
 
 #[derive(Clone)]
 pub struct InMemoryContext {
-    /// local RAV store with rwlocks to allow sharing with other compenents as needed
+    /// local RAV store with rwlocks to allow sharing with other compenents as needed,
+    /// holding every RAV received, the last one being the latest
     rav_storage: RAVStorage,
     receipt_storage: ReceiptStorage,
     unique_id: Arc<RwLock<u64>>,
     sender_escrow_storage: EscrowStorage,
     timestamp_check: Arc<StatefulTimestampCheck>,
     sender_address: Option<Address>,
+    /// maximum number of receipts held in `receipt_storage`, unbounded if `None`
+    max_stored_receipts: Option<usize>,
 }
 
 impl InMemoryContext {
@@ -60,6 +69,7 @@ impl InMemoryContext {
             sender_escrow_storage,
             timestamp_check,
             sender_address: None,
+            max_stored_receipts: None,
         }
     }
 
@@ -68,6 +78,16 @@ impl InMemoryContext {
         self
     }
 
+    /// Caps the number of receipts that can be stored at once.
+    ///
+    /// Once the cap is reached, `store_receipt` returns
+    /// [`InMemoryError::StorageFull`] until receipts are removed, which
+    /// signals the caller to request a RAV or shed load.
+    pub fn with_max_stored_receipts(mut self, max_stored_receipts: usize) -> Self {
+        self.max_stored_receipts = Some(max_stored_receipts);
+        self
+    }
+
     pub async fn retrieve_receipt_by_id(
         &self,
         receipt_id: u64,
@@ -100,8 +120,12 @@ impl InMemoryContext {
         &self,
         timestamp_ns: u64,
     ) -> Result<Vec<ReceiptWithState<Checking, SignedReceipt>>, InMemoryError> {
-        self.retrieve_receipts_in_timestamp_range(..=timestamp_ns, None)
-            .await
+        Ok(self
+            .retrieve_receipts_in_timestamp_range(..=timestamp_ns, None)
+            .await?
+            .into_iter()
+            .map(|(_, rx_receipt)| rx_receipt)
+            .collect())
     }
 
     pub async fn remove_receipt_by_id(&mut self, receipt_id: u64) -> Result<(), InMemoryError> {
@@ -131,7 +155,48 @@ impl RavStore<ReceiptAggregateVoucher> for InMemoryContext {
     async fn update_last_rav(&self, rav: SignedRav) -> Result<(), Self::AdapterError> {
         let mut rav_storage = self.rav_storage.write().unwrap();
         let timestamp = rav.message.timestampNs;
-        *rav_storage = Some(rav);
+        rav_storage.push(rav);
+        self.timestamp_check.update_min_timestamp_ns(timestamp);
+        Ok(())
+    }
+
+    async fn update_last_rav_if_newer(&self, rav: SignedRav) -> Result<bool, Self::AdapterError> {
+        // the lock is held from the comparison to the write
+        let mut rav_storage = self.rav_storage.write().unwrap();
+        let timestamp = rav.message.timestampNs;
+        if rav_storage
+            .last()
+            .is_some_and(|last_rav| last_rav.message.timestampNs >= timestamp)
+        {
+            return Ok(false);
+        }
+        rav_storage.push(rav);
+        self.timestamp_check.update_min_timestamp_ns(timestamp);
+        Ok(true)
+    }
+
+    async fn commit_rav_and_delete_receipts(
+        &self,
+        rav: SignedRav,
+        receipt_ids: &[u64],
+    ) -> Result<(), Self::AdapterError> {
+        // both locks are held until the end, so that no one sees one change
+        // without the other
+        let mut receipt_storage = self.receipt_storage.write().unwrap();
+        let mut rav_storage = self.rav_storage.write().unwrap();
+        if let Some(receipt_id) = receipt_ids
+            .iter()
+            .find(|receipt_id| !receipt_storage.contains_key(receipt_id))
+        {
+            return Err(InMemoryError::AdapterError {
+                error: format!("No receipt found with ID {receipt_id}"),
+            });
+        }
+        for receipt_id in receipt_ids {
+            receipt_storage.remove(receipt_id);
+        }
+        let timestamp = rav.message.timestampNs;
+        rav_storage.push(rav);
         self.timestamp_check.update_min_timestamp_ns(timestamp);
         Ok(())
     }
@@ -142,7 +207,21 @@ impl RavRead<ReceiptAggregateVoucher> for InMemoryContext {
     type AdapterError = InMemoryError;
 
     async fn last_rav(&self) -> Result<Option<SignedRav>, Self::AdapterError> {
-        Ok(self.rav_storage.read().unwrap().clone())
+        Ok(self.rav_storage.read().unwrap().last().cloned())
+    }
+
+    async fn list_ravs<R: RangeBounds<u64> + std::marker::Send>(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<SignedRav>, Self::AdapterError> {
+        let rav_storage = self.rav_storage.read().unwrap();
+        let mut ravs: Vec<_> = rav_storage
+            .iter()
+            .filter(|rav| timestamp_range_ns.contains(&rav.message.timestampNs))
+            .cloned()
+            .collect();
+        ravs.sort_by_key(|rav| rav.message.timestampNs);
+        Ok(ravs)
     }
 }
 
@@ -157,6 +236,13 @@ impl ReceiptStore<SignedReceipt> for InMemoryContext {
         let mut id_pointer = self.unique_id.write().unwrap();
         let id_previous = *id_pointer;
         let mut receipt_storage = self.receipt_storage.write().unwrap();
+        if let Some(max_stored_receipts) = self.max_stored_receipts {
+            if receipt_storage.len() >= max_stored_receipts {
+                return Err(InMemoryError::StorageFull {
+                    max_stored_receipts,
+                });
+            }
+        }
         receipt_storage.insert(*id_pointer, receipt);
         *id_pointer += 1;
         Ok(id_previous)
@@ -185,20 +271,32 @@ impl ReceiptRead<SignedReceipt> for InMemoryContext {
         &self,
         timestamp_range_ns: R,
         limit: Option<u64>,
-    ) -> Result<Vec<ReceiptWithState<Checking, SignedReceipt>>, Self::AdapterError> {
+    ) -> Result<Vec<(u64, ReceiptWithState<Checking, SignedReceipt>)>, Self::AdapterError> {
         let receipt_storage = self.receipt_storage.read().unwrap();
-        let mut receipts_in_range: Vec<ReceiptWithState<Checking, SignedReceipt>> = receipt_storage
-            .iter()
-            .filter(|(_, rx_receipt)| {
-                timestamp_range_ns.contains(&rx_receipt.signed_receipt().message.timestamp_ns)
-            })
-            .map(|(&_id, rx_receipt)| rx_receipt.clone())
-            .collect();
-
-        if limit.is_some_and(|limit| receipts_in_range.len() > limit as usize) {
-            safe_truncate_receipts(&mut receipts_in_range, limit.unwrap());
+        let timestamp_ns = |rx_receipt: &ReceiptWithState<Checking, SignedReceipt>| {
+            rx_receipt.signed_receipt().message.timestamp_ns
+        };
+        let mut receipts_in_range: Vec<(u64, &ReceiptWithState<Checking, SignedReceipt>)> =
+            receipt_storage
+                .iter()
+                .filter(|(_, rx_receipt)| timestamp_range_ns.contains(&timestamp_ns(rx_receipt)))
+                .map(|(&id, rx_receipt)| (id, rx_receipt))
+                .collect();
+
+        if let Some(limit) = limit.filter(|&limit| receipts_in_range.len() > limit as usize) {
+            receipts_in_range
+                .sort_unstable_by_key(|&(id, rx_receipt)| (timestamp_ns(rx_receipt), id));
+            // keep the receipts before the first timestamp left out, so that the receipts of a
+            // timestamp are not split, as in `safe_truncate_receipts`
+            let first_left_out = timestamp_ns(receipts_in_range[limit as usize].1);
+            let end = receipts_in_range
+                .partition_point(|&(_, rx_receipt)| timestamp_ns(rx_receipt) < first_left_out);
+            receipts_in_range.truncate(end);
         }
-        Ok(receipts_in_range.into_iter().collect())
+        Ok(receipts_in_range
+            .into_iter()
+            .map(|(id, rx_receipt)| (id, rx_receipt.clone()))
+            .collect())
     }
 }
 
@@ -252,6 +350,54 @@ impl SignatureChecker for InMemoryContext {
     }
 }
 
+#[async_trait]
+impl HealthCheck for InMemoryContext {
+    type AdapterError = InMemoryError;
+
+    /// Fails if a writer panicked while holding one of the storage locks,
+    /// leaving the storage unusable.
+    async fn ping(&self) -> Result<(), Self::AdapterError> {
+        if self.rav_storage.is_poisoned()
+            || self.receipt_storage.is_poisoned()
+            || self.sender_escrow_storage.is_poisoned()
+        {
+            return Err(InMemoryError::AdapterError {
+                error: "storage lock poisoned".to_owned(),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EscrowHeadroom<SignedReceipt> for InMemoryContext {
+    async fn available_escrow(&self, _: &SignedReceipt) -> anyhow::Result<u128> {
+        let sender_address = self
+            .sender_address
+            .ok_or_else(|| anyhow::anyhow!("No sender address configured"))?;
+        Ok(self.escrow(sender_address)?)
+    }
+
+    async fn pending_value(&self, _: &SignedReceipt) -> anyhow::Result<u128> {
+        let last_rav = self.rav_storage.read().unwrap().last().cloned();
+        let rav_value = last_rav
+            .as_ref()
+            .map(|rav| rav.message.valueAggregate)
+            .unwrap_or(0);
+        let rav_timestamp_ns = last_rav.map(|rav| rav.message.timestampNs);
+
+        // receipts already aggregated into the last RAV are counted in its value
+        self.receipt_storage
+            .read()
+            .unwrap()
+            .values()
+            .map(|rx_receipt| &rx_receipt.signed_receipt().message)
+            .filter(|receipt| rav_timestamp_ns.map_or(true, |ts| receipt.timestamp_ns > ts))
+            .try_fold(rav_value, |total, receipt| total.checked_add(receipt.value))
+            .ok_or_else(|| anyhow::anyhow!("Pending value overflows"))
+    }
+}
+
 pub mod checks {
     use std::{
         collections::{HashMap, HashSet},
@@ -262,11 +408,7 @@ pub mod checks {
     use tap_graph::SignedReceipt;
 
     use crate::{
-        receipt::{
-            checks::{Check, CheckError, CheckResult, ReceiptCheck},
-            state::Checking,
-            Context, ReceiptError, ReceiptWithState,
-        },
+        receipt::checks::{AllocationIdCheck, ReceiptCheck, SignerCheck},
         signed_message::MessageId,
     };
 
@@ -279,80 +421,10 @@ pub mod checks {
         vec![
             // Arc::new(UniqueCheck ),
             // Arc::new(ValueCheck { query_appraisals }),
-            Arc::new(AllocationIdCheck { allocation_ids }),
-            Arc::new(SignatureCheck {
-                domain_separator,
-                valid_signers,
-            }),
+            Arc::new(AllocationIdCheck::new(allocation_ids)),
+            Arc::new(SignerCheck::new(domain_separator, valid_signers)),
         ]
     }
-
-    struct AllocationIdCheck {
-        allocation_ids: Arc<RwLock<HashSet<Address>>>,
-    }
-
-    #[async_trait::async_trait]
-    impl Check<SignedReceipt> for AllocationIdCheck {
-        async fn check(
-            &self,
-            _: &Context,
-            receipt: &ReceiptWithState<Checking, SignedReceipt>,
-        ) -> CheckResult {
-            let received_allocation_id = receipt.signed_receipt().message.allocation_id;
-            if self
-                .allocation_ids
-                .read()
-                .unwrap()
-                .contains(&received_allocation_id)
-            {
-                Ok(())
-            } else {
-                Err(CheckError::Failed(
-                    ReceiptError::InvalidAllocationID {
-                        received_allocation_id,
-                    }
-                    .into(),
-                ))
-            }
-        }
-    }
-
-    struct SignatureCheck {
-        domain_separator: Eip712Domain,
-        valid_signers: HashSet<Address>,
-    }
-
-    #[async_trait::async_trait]
-    impl Check<SignedReceipt> for SignatureCheck {
-        async fn check(
-            &self,
-            _: &Context,
-            receipt: &ReceiptWithState<Checking, SignedReceipt>,
-        ) -> CheckResult {
-            let recovered_address = receipt
-                .signed_receipt()
-                .recover_signer(&self.domain_separator)
-                .map_err(|e| {
-                    CheckError::Failed(
-                        ReceiptError::InvalidSignature {
-                            source_error_message: e.to_string(),
-                        }
-                        .into(),
-                    )
-                })?;
-
-            if !self.valid_signers.contains(&recovered_address) {
-                Err(CheckError::Failed(
-                    ReceiptError::InvalidSignature {
-                        source_error_message: "Invalid signer".to_string(),
-                    }
-                    .into(),
-                ))
-            } else {
-                Ok(())
-            }
-        }
-    }
 }
 
 #[cfg(test)]
@@ -9,4 +9,10 @@
 //! Currently, there's only one context implementation available, which is
 //! the `MemoryContext`. This context is used to store data in memory and
 //! is useful for testing and development purposes.
+//!
+//! The `onchain` feature adds an `OnChainSignatureChecker`, verifying the
+//! signers against the authorizations of an on-chain contract.
+#[cfg(feature = "in_memory")]
 pub mod memory;
+#[cfg(feature = "onchain")]
+pub mod onchain;
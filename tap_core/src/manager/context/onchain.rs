@@ -0,0 +1,199 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! On-chain signature checker for the TAP manager.
+//!
+//! This module provides a [`SignatureChecker`] that accepts the signers
+//! authorized by a payer in an on-chain contract, e.g. a gateway delegating
+//! the signing of its receipts.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    network::Network, primitives::Address, providers::Provider, sol, transports::Transport,
+};
+use async_trait::async_trait;
+
+use crate::manager::adapters::SignatureChecker;
+
+sol! {
+    /// View of the contract holding the signers authorized by each payer.
+    #[sol(rpc)]
+    interface IAuthorizable {
+        function isAuthorized(address authorizer, address signer) external view returns (bool);
+    }
+}
+
+/// Source of the signers authorized by the payers.
+///
+/// It is implemented by [`IAuthorizable::IAuthorizableInstance`], which
+/// calls the contract through an alloy provider.
+#[async_trait]
+pub trait SignerAuthorization: Send + Sync {
+    /// Error returned when the authorization can not be looked up
+    type Error: std::error::Error + std::fmt::Debug + Send + Sync + 'static;
+
+    /// Returns whether `signer` is authorized to sign for `payer`
+    async fn is_authorized(&self, payer: Address, signer: Address) -> Result<bool, Self::Error>;
+}
+
+#[async_trait]
+impl<T, P, N> SignerAuthorization for IAuthorizable::IAuthorizableInstance<T, P, N>
+where
+    T: Transport + Clone,
+    P: Provider<T, N>,
+    N: Network,
+{
+    type Error = alloy::contract::Error;
+
+    async fn is_authorized(&self, payer: Address, signer: Address) -> Result<bool, Self::Error> {
+        Ok(self.isAuthorized(payer, signer).call().await?._0)
+    }
+}
+
+/// [`SignatureChecker`] accepting the signers authorized by `payer` on-chain.
+///
+/// The answers are cached for `cache_ttl`, so that receipts do not each cost
+/// a contract call. A signer authorized or revoked on-chain is therefore seen
+/// as such after at most `cache_ttl`. Clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct OnChainSignatureChecker<A> {
+    authorization: A,
+    payer: Address,
+    cache_ttl: Duration,
+    cache: Arc<Mutex<HashMap<Address, (Instant, bool)>>>,
+}
+
+impl<A> OnChainSignatureChecker<A> {
+    pub fn new(authorization: A, payer: Address, cache_ttl: Duration) -> Self {
+        Self {
+            authorization,
+            payer,
+            cache_ttl,
+            cache: Default::default(),
+        }
+    }
+
+    fn cached(&self, signer_address: Address) -> Option<bool> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(&signer_address)
+            .filter(|(expires_at, _)| *expires_at > Instant::now())
+            .map(|(_, authorized)| *authorized)
+    }
+}
+
+#[async_trait]
+impl<A> SignatureChecker for OnChainSignatureChecker<A>
+where
+    A: SignerAuthorization,
+{
+    type AdapterError = A::Error;
+
+    async fn verify_signer(&self, signer_address: Address) -> Result<bool, Self::AdapterError> {
+        if let Some(authorized) = self.cached(signer_address) {
+            return Ok(authorized);
+        }
+        // errors are not cached, the next receipt tries again
+        let authorized = self
+            .authorization
+            .is_authorized(self.payer, signer_address)
+            .await?;
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (expires_at, _)| *expires_at > now);
+        cache.insert(signer_address, (now + self.cache_ttl, authorized));
+        Ok(authorized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use alloy::primitives::{address, Address};
+    use async_trait::async_trait;
+    use thiserror::Error;
+
+    use super::{OnChainSignatureChecker, SignerAuthorization};
+    use crate::manager::adapters::SignatureChecker;
+
+    const PAYER: Address = address!("fafafafafafafafafafafafafafafafafafafafa");
+    const AUTHORIZED: Address = address!("abababababababababababababababababababab");
+    const UNAUTHORIZED: Address = address!("cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd");
+    const UNREACHABLE: Address = address!("efefefefefefefefefefefefefefefefefefefef");
+
+    #[derive(Debug, Error)]
+    #[error("provider unreachable")]
+    struct Unreachable;
+
+    /// Provider authorizing [`AUTHORIZED`] for [`PAYER`] and counting the calls
+    #[derive(Default)]
+    struct MockAuthorization {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl SignerAuthorization for MockAuthorization {
+        type Error = Unreachable;
+
+        async fn is_authorized(
+            &self,
+            payer: Address,
+            signer: Address,
+        ) -> Result<bool, Self::Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if signer == UNREACHABLE {
+                return Err(Unreachable);
+            }
+            Ok(payer == PAYER && signer == AUTHORIZED)
+        }
+    }
+
+    #[tokio::test]
+    async fn authorized_signers_are_verified() {
+        let checker = OnChainSignatureChecker::new(
+            MockAuthorization::default(),
+            PAYER,
+            Duration::from_secs(60),
+        );
+
+        assert!(checker.verify_signer(AUTHORIZED).await.unwrap());
+        assert!(!checker.verify_signer(UNAUTHORIZED).await.unwrap());
+        assert!(checker.verify_signer(UNREACHABLE).await.is_err());
+
+        // the answers are cached, the errors are not
+        assert!(checker.verify_signer(AUTHORIZED).await.unwrap());
+        assert!(!checker.verify_signer(UNAUTHORIZED).await.unwrap());
+        assert!(checker.verify_signer(UNREACHABLE).await.is_err());
+        assert_eq!(checker.authorization.calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn signers_of_other_payers_are_rejected() {
+        let checker = OnChainSignatureChecker::new(
+            MockAuthorization::default(),
+            Address::ZERO,
+            Duration::from_secs(60),
+        );
+
+        assert!(!checker.verify_signer(AUTHORIZED).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_answers_are_looked_up_again() {
+        let checker =
+            OnChainSignatureChecker::new(MockAuthorization::default(), PAYER, Duration::ZERO);
+
+        assert!(checker.verify_signer(AUTHORIZED).await.unwrap());
+        assert!(checker.verify_signer(AUTHORIZED).await.unwrap());
+        assert_eq!(checker.authorization.calls.load(Ordering::SeqCst), 2);
+    }
+}
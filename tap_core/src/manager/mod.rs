@@ -70,15 +70,18 @@
 //!
 //! let receipt = Eip712SignedMessage::new(&domain_separator, message, &wallet).unwrap();
 //!
-//! let manager = Manager::new(domain_separator, MyContext, CheckList::empty());
-//! manager.verify_and_store_receipt(&Context::new(), receipt).await.unwrap()
+//! let manager = Manager::new(domain_separator, MyContext, CheckList::empty()).unwrap();
+//! let receipt_id = manager.verify_and_store_receipt(&Context::new(), receipt).await.unwrap();
+//! # assert_eq!(receipt_id, 0);
 //! # }
 //! ```
 //!
 
 pub mod adapters;
-#[cfg(feature = "in_memory")]
+#[cfg(any(feature = "in_memory", feature = "onchain"))]
 pub mod context;
+mod rav_trigger;
 mod tap_manager;
 
-pub use tap_manager::Manager;
+pub use rav_trigger::RavTrigger;
+pub use tap_manager::{Manager, ReceiptMigration, StoredReceipt};
@@ -0,0 +1,147 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helper to decide when a new RAV should be requested.
+//!
+//! Receivers usually request a RAV after a given number of receipts or after
+//! a given amount of value has been received since the last request.
+//! [`RavTrigger`] keeps track of both and signals when either threshold is
+//! crossed.
+//!
+//! # Example
+//!
+//! ```rust
+//! use tap_core::manager::RavTrigger;
+//!
+//! let trigger = RavTrigger::new()
+//!     .with_receipt_count_threshold(3)
+//!     .with_value_threshold(1000);
+//!
+//! assert!(!trigger.record_receipt(10));
+//! assert!(!trigger.record_receipt(10));
+//! // third receipt crosses the count threshold
+//! assert!(trigger.record_receipt(10));
+//! // the trigger is reset after firing
+//! assert_eq!(trigger.receipt_count(), 0);
+//! ```
+
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+struct RavTriggerState {
+    receipt_count: u64,
+    value: u128,
+}
+
+/// Tracks receipts received since the last RAV request and signals when a
+/// new RAV should be requested.
+///
+/// A trigger without any threshold never fires.
+#[derive(Debug, Default)]
+pub struct RavTrigger {
+    receipt_count_threshold: Option<u64>,
+    value_threshold: Option<u128>,
+    state: Mutex<RavTriggerState>,
+}
+
+impl RavTrigger {
+    /// Creates a new trigger without any threshold.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fires once at least `threshold` receipts have been recorded.
+    pub fn with_receipt_count_threshold(mut self, threshold: u64) -> Self {
+        self.receipt_count_threshold = Some(threshold);
+        self
+    }
+
+    /// Fires once the accumulated value of the recorded receipts reaches `threshold`.
+    pub fn with_value_threshold(mut self, threshold: u128) -> Self {
+        self.value_threshold = Some(threshold);
+        self
+    }
+
+    /// Records a receipt of `value` and returns `true` if a RAV should be
+    /// requested.
+    ///
+    /// When it returns `true`, the counters are reset so that only one of
+    /// the concurrent callers is signaled.
+    pub fn record_receipt(&self, value: u128) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.receipt_count = state.receipt_count.saturating_add(1);
+        state.value = state.value.saturating_add(value);
+
+        let count_reached = self
+            .receipt_count_threshold
+            .is_some_and(|threshold| state.receipt_count >= threshold);
+        let value_reached = self
+            .value_threshold
+            .is_some_and(|threshold| state.value >= threshold);
+
+        if count_reached || value_reached {
+            *state = RavTriggerState::default();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Resets the counters, e.g. after requesting a RAV for another reason.
+    pub fn reset(&self) {
+        *self.state.lock().unwrap() = RavTriggerState::default();
+    }
+
+    /// Number of receipts recorded since the last reset.
+    pub fn receipt_count(&self) -> u64 {
+        self.state.lock().unwrap().receipt_count
+    }
+
+    /// Accumulated value recorded since the last reset.
+    pub fn value(&self) -> u128 {
+        self.state.lock().unwrap().value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_on_receipt_count() {
+        let trigger = RavTrigger::new().with_receipt_count_threshold(3);
+
+        assert!(!trigger.record_receipt(u128::MAX));
+        assert!(!trigger.record_receipt(u128::MAX));
+        assert!(trigger.record_receipt(u128::MAX));
+        assert_eq!(trigger.receipt_count(), 0);
+        assert_eq!(trigger.value(), 0);
+
+        // starts counting again after firing
+        assert!(!trigger.record_receipt(1));
+        assert_eq!(trigger.receipt_count(), 1);
+    }
+
+    #[test]
+    fn fires_on_value() {
+        let trigger = RavTrigger::new()
+            .with_receipt_count_threshold(100)
+            .with_value_threshold(50);
+
+        assert!(!trigger.record_receipt(20));
+        assert!(!trigger.record_receipt(20));
+        assert_eq!(trigger.value(), 40);
+        assert!(trigger.record_receipt(20));
+        assert_eq!(trigger.value(), 0);
+    }
+
+    #[test]
+    fn never_fires_without_thresholds() {
+        let trigger = RavTrigger::new();
+        for _ in 0..10 {
+            assert!(!trigger.record_receipt(u128::MAX));
+        }
+        trigger.reset();
+        assert_eq!(trigger.receipt_count(), 0);
+    }
+}
@@ -1,18 +1,36 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use alloy::{dyn_abi::Eip712Domain, sol_types::SolStruct};
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    ops::RangeBounds,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, sol_types::SolStruct};
+use futures_util::future::join_all;
+use rayon::prelude::*;
 use tap_receipt::rav::Aggregate;
+use tokio::sync::{oneshot, Mutex as AsyncMutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
 
 use super::adapters::{
-    RavRead, RavStore, ReceiptDelete, ReceiptRead, ReceiptStore, SignatureChecker,
+    HealthCheck, RavRead, RavStore, ReceiptDelete, ReceiptRead, ReceiptStore, SignatureChecker,
 };
 use crate::{
     rav_request::RavRequest,
     receipt::{
-        checks::{CheckBatch, CheckList, TimestampCheck, UniqueCheck},
-        state::{Checked, Failed},
-        Context, ReceiptError, ReceiptWithState, WithUniqueId, WithValueAndTimestamp,
+        checks::{
+            CheckBatch, CheckConfigError, CheckList, CheckWarning, ClosedAllocationCheck,
+            ReceiptCheck, TimestampCheck, UniqueCheck,
+        },
+        state::{Checked, Checking, Failed, PendingEscrow},
+        Context, ReceiptError, ReceiptWithState, WithAllocationId, WithUniqueId,
+        WithValueAndTimestamp,
     },
     signed_message::Eip712SignedMessage,
     Error,
@@ -28,6 +46,83 @@ pub struct Manager<E, Rcpt> {
     /// Struct responsible for doing checks for receipt. Ownership stays with manager allowing manager
     /// to update configuration ( like minimum timestamp ).
     domain_separator: Eip712Domain,
+
+    /// Allocations finalized with [`Manager::finalize_allocation`], whose
+    /// receipts are rejected
+    closed_allocations: Arc<ClosedAllocationCheck>,
+
+    /// Whether new receipts are accepted, see [`Manager::set_accepting`]
+    accepting: AtomicBool,
+
+    /// Lock of the RAV rounds of each allocation, see [`Manager::request_rav`]
+    rav_rounds: Mutex<HashMap<Address, Arc<AsyncMutex<()>>>>,
+
+    /// Limit of the RAV rounds running at once across allocations, see
+    /// [`Manager::with_max_concurrent_rav_requests`]
+    rav_round_permits: Option<Arc<Semaphore>>,
+
+    /// Called with each RAV stored, see [`Manager::with_on_rav_stored`]
+    on_rav_stored: Option<RavStoredHook>,
+
+    /// Receipts held until the escrow of their sender is topped up, see
+    /// [`Manager::with_max_pending_escrow_receipts`]
+    pending_escrow: Mutex<Vec<ReceiptWithState<PendingEscrow, Rcpt>>>,
+
+    /// Maximum number of receipts in `pending_escrow`, none are held if 0
+    max_pending_escrow_receipts: usize,
+}
+
+/// Hook called with the stored RAVs, downcast to the RAV type it was
+/// registered for.
+type RavStoredHook = Arc<dyn Fn(&dyn Any) + Send + Sync>;
+
+/// Outcome of [`Manager::migrate_allocation`].
+#[derive(Debug)]
+pub struct ReceiptMigration<Rav: SolStruct, TargetRcpt> {
+    /// Final RAV of the allocation, `None` if there were no receipts
+    pub final_rav: Option<Eip712SignedMessage<Rav>>,
+    /// Receipts aggregated into the final RAV, mapped to the target type.
+    ///
+    /// They are already paid by the final RAV, so they must not be stored
+    /// with the receipts to aggregate, e.g. only kept as an audit trail.
+    pub migrated_receipts: Vec<TargetRcpt>,
+}
+
+/// Receipt stored by [`Manager::verify_and_store_receipt_with_warnings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredReceipt {
+    /// Id assigned to the receipt by [`ReceiptStore::store_receipt`]
+    pub receipt_id: u64,
+    /// Warnings raised by the checks the receipt passed
+    pub warnings: Vec<CheckWarning>,
+}
+
+/// Held while a RAV round of an allocation is running.
+///
+/// The lock of the allocation is removed from the RAV rounds once the last
+/// round using it is done, so that the rounds of allocations that are no
+/// longer served do not accumulate.
+struct RavRoundGuard<'a> {
+    rav_rounds: &'a Mutex<HashMap<Address, Arc<AsyncMutex<()>>>>,
+    allocation_id: Address,
+    allocation: Option<OwnedMutexGuard<()>>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for RavRoundGuard<'_> {
+    fn drop(&mut self) {
+        let mut rav_rounds = self.rav_rounds.lock().unwrap();
+        // the guard holds a reference to the lock, so the map holds the only
+        // one left if no other round is waiting for it. Rounds only take a
+        // reference while holding the map, so none can be taken meanwhile.
+        drop(self.allocation.take());
+        if rav_rounds
+            .get(&self.allocation_id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            rav_rounds.remove(&self.allocation_id);
+        }
+    }
 }
 
 impl<E, Rcpt> Manager<E, Rcpt> {
@@ -35,18 +130,131 @@ impl<E, Rcpt> Manager<E, Rcpt> {
     /// will complete all `required_checks` before being accepted or declined from RAV.
     /// `starting_min_timestamp` will be used as min timestamp until the first RAV request is created.
     ///
+    /// # Errors
+    ///
+    /// Returns [`CheckConfigError`] if `checks` is rejected by
+    /// [`CheckList::validate`]
+    ///
     pub fn new(
         domain_separator: Eip712Domain,
         context: E,
         checks: impl Into<CheckList<Rcpt>>,
-    ) -> Self {
-        Self {
+    ) -> Result<Self, CheckConfigError> {
+        let checks = checks.into();
+        checks.validate()?;
+        Ok(Self {
             context,
             domain_separator,
-            checks: checks.into(),
+            checks,
+            closed_allocations: Default::default(),
+            accepting: AtomicBool::new(true),
+            rav_rounds: Default::default(),
+            rav_round_permits: None,
+            on_rav_stored: None,
+            pending_escrow: Default::default(),
+            max_pending_escrow_receipts: 0,
+        })
+    }
+
+    /// Limits the RAV rounds of [`Manager::request_rav`] and
+    /// [`Manager::finalize_allocation`] running at once to `max`, across
+    /// all allocations. Rounds of the same allocation never run at once.
+    /// The rounds are not limited by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    ///
+    pub fn with_max_concurrent_rav_requests(mut self, max: usize) -> Self {
+        assert!(max > 0, "at least one RAV request must be allowed");
+        self.rav_round_permits = Some(Arc::new(Semaphore::new(max)));
+        self
+    }
+
+    /// Holds up to `max` receipts failing with
+    /// [`ReceiptError::InsufficientEscrow`] in
+    /// [`Manager::verify_and_store_receipt`], instead of rejecting them, until
+    /// they are stored by [`Manager::retry_pending_escrow_receipts`] once the
+    /// escrow of their sender is topped up. Receipts above `max` are rejected.
+    /// No receipts are held by default.
+    ///
+    /// Held receipts are kept in memory only, and lost on restart.
+    pub fn with_max_pending_escrow_receipts(mut self, max: usize) -> Self {
+        self.max_pending_escrow_receipts = max;
+        self
+    }
+
+    /// Calls `on_rav_stored` with each RAV of type `Rav` stored by
+    /// [`Manager::verify_and_store_rav`], once it is stored and its receipts
+    /// deleted, e.g. to start redeeming it. A RAV sent again that was
+    /// already stored is not passed again.
+    ///
+    /// RAVs of other types are not passed to `on_rav_stored`, a warning is
+    /// logged instead. No hook is called by default, and calling this again
+    /// replaces the previous hook.
+    pub fn with_on_rav_stored<Rav>(
+        mut self,
+        on_rav_stored: impl Fn(&Eip712SignedMessage<Rav>) + Send + Sync + 'static,
+    ) -> Self
+    where
+        Rav: SolStruct + 'static,
+    {
+        self.on_rav_stored = Some(Arc::new(move |rav: &dyn Any| {
+            match rav.downcast_ref::<Eip712SignedMessage<Rav>>() {
+                Some(rav) => on_rav_stored(rav),
+                None => log::warn!(
+                    "Stored RAV is not a {} RAV, not passing it to the on_rav_stored hook",
+                    std::any::type_name::<Rav>()
+                ),
+            }
+        }));
+        self
+    }
+
+    /// Waits for the RAV rounds of `allocation_id` running, then for a
+    /// permit if the rounds are limited.
+    async fn start_rav_round(&self, allocation_id: Address) -> RavRoundGuard<'_> {
+        let allocation_lock = self
+            .rav_rounds
+            .lock()
+            .unwrap()
+            .entry(allocation_id)
+            .or_default()
+            .clone();
+        let allocation = allocation_lock.lock_owned().await;
+        let permit = match &self.rav_round_permits {
+            Some(permits) => Some(
+                permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        RavRoundGuard {
+            rav_rounds: &self.rav_rounds,
+            allocation_id,
+            allocation: Some(allocation),
+            _permit: permit,
         }
     }
 
+    /// Pauses (`false`) or resumes (`true`) the acceptance of receipts,
+    /// e.g. during maintenance.
+    ///
+    /// While paused, [`Manager::verify_and_store_receipt`] returns
+    /// [`Error::NotAcceptingReceipts`]. RAVs can still be requested and
+    /// stored. Receipts are accepted by default.
+    pub fn set_accepting(&self, accepting: bool) {
+        self.accepting.store(accepting, Ordering::Relaxed);
+    }
+
+    /// Returns `false` if the acceptance of receipts is paused.
+    pub fn is_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Relaxed)
+    }
+
     async fn get_previous_rav<Rav: SolStruct>(
         &self,
     ) -> Result<Option<Eip712SignedMessage<Rav>>, Error>
@@ -63,21 +271,158 @@ impl<E, Rcpt> Manager<E, Rcpt> {
         Ok(previous_rav)
     }
 
+    /// Checks that the storage behind the context is reachable, e.g. to
+    /// report the receiver as not ready while its database is down.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if [`HealthCheck::ping`] fails
+    ///
+    pub async fn health(&self) -> Result<(), Error>
+    where
+        E: HealthCheck,
+    {
+        self.context
+            .ping()
+            .await
+            .map_err(|err| Error::AdapterError {
+                source_error: anyhow::Error::new(err),
+            })
+    }
+
+    /// Lists the stored RAVs whose timestamp is within `timestamp_range_ns`,
+    /// ordered by timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while retrieving RAVs
+    ///
+    pub async fn list_ravs<Rav, R>(
+        &self,
+        timestamp_range_ns: R,
+    ) -> Result<Vec<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct,
+        R: RangeBounds<u64> + Send,
+    {
+        self.context
+            .list_ravs(timestamp_range_ns)
+            .await
+            .map_err(|err| Error::AdapterError {
+                source_error: anyhow::Error::new(err),
+            })
+    }
+
+    /// Verifies a batch of RAVs, e.g. received by an auditor, returning the
+    /// signer of each RAV or its error, in the same order as `ravs`.
+    ///
+    /// The signers are recovered in parallel on the rayon thread pool, without
+    /// blocking the async runtime, with the domain separator of the manager,
+    /// then checked with [`SignatureChecker::verify_signer`]. Nothing is
+    /// stored.
+    ///
+    /// # Errors
+    ///
+    /// Each RAV fails with [`Error::SignatureError`] if its signer cannot be
+    /// recovered
+    ///
+    /// Each RAV fails with [`Error::InvalidRecoveredSigner`] if its signer is
+    /// not accepted, and [`Error::FailedToVerifySigner`] if it could not be
+    /// verified
+    ///
+    pub async fn verify_ravs_batch<Rav>(
+        &self,
+        ravs: &[Eip712SignedMessage<Rav>],
+    ) -> Vec<Result<Address, Error>>
+    where
+        E: SignatureChecker,
+        Rav: SolStruct + Clone + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let ravs = ravs.to_vec();
+        let domain_separator = self.domain_separator.clone();
+        rayon::spawn(move || {
+            let signers: Vec<_> = ravs
+                .into_par_iter()
+                .map(|rav| rav.recover_signer(&domain_separator))
+                .collect();
+            // the receiver is gone if the verification was cancelled
+            let _ = sender.send(signers);
+        });
+        let signers = receiver.await.expect("signer recovery stopped");
+        join_all(signers.into_iter().map(|signer| async move {
+            let signer = signer?;
+            if self
+                .context
+                .verify_signer(signer)
+                .await
+                .map_err(|e| Error::FailedToVerifySigner(e.to_string()))?
+            {
+                Ok(signer)
+            } else {
+                Err(Error::InvalidRecoveredSigner { address: signer })
+            }
+        }))
+        .await
+    }
+
     /// Verify `signed_rav` matches all values on `expected_rav`, and that `signed_rav` has a valid signer.
+    /// Then stores it and deletes the receipts aggregated into it, identified
+    /// by `receipt_ids`, in one step with
+    /// [`RavStore::commit_rav_and_delete_receipts`].
+    ///
+    /// `receipt_ids` are usually the [`RavRequest::included_receipt_ids`] of
+    /// the request `expected_rav` comes from. Both changes are atomic only if
+    /// the context overrides [`RavStore::commit_rav_and_delete_receipts`], as
+    /// the in-memory context does. Otherwise the receipts are left to
+    /// [`Manager::remove_obsolete_receipts`].
+    ///
+    /// The signer recovered from `signed_rav` must be accepted by
+    /// [`SignatureChecker::verify_signer`], so that a RAV signed with the wrong
+    /// key is never stored.
+    ///
+    /// A `signed_rav` identical to the last stored RAV, e.g. sent again by a
+    /// client retrying, is accepted without being stored again.
     ///
     /// # Errors
     ///
-    /// Returns [`Error::AdapterError`] if there are any errors while storing RAV
+    /// Returns [`Error::AdapterError`] if there are any errors while reading
+    /// the last RAV or storing RAV
+    ///
+    /// Returns [`Error::InvalidRecoveredSigner`] if the signer of `signed_rav`
+    /// is not accepted, and [`Error::FailedToVerifySigner`] if it could not be
+    /// verified
+    ///
+    /// Returns [`Error::InvalidReceivedRav`] if `signed_rav` doesn't match
+    /// `expected_rav`
+    ///
+    /// Nothing is stored nor deleted on error.
     ///
     pub async fn verify_and_store_rav<Rav>(
         &self,
         expected_rav: Rav,
         signed_rav: Eip712SignedMessage<Rav>,
+        receipt_ids: &[u64],
     ) -> std::result::Result<(), Error>
     where
-        E: RavStore<Rav> + SignatureChecker,
-        Rav: SolStruct + PartialEq<Rav> + Sync + std::fmt::Debug,
+        E: RavStore<Rav> + RavRead<Rav> + SignatureChecker,
+        Rav: SolStruct + Clone + PartialEq<Rav> + Send + Sync + std::fmt::Debug + 'static,
     {
+        // already verified and stored, storing it again would settle it twice
+        let last_rav = self
+            .context
+            .last_rav()
+            .await
+            .map_err(|err| Error::AdapterError {
+                source_error: anyhow::Error::new(err),
+            })?;
+        if last_rav.as_ref() == Some(&signed_rav) {
+            return Ok(());
+        }
+
+        // reject RAVs signed by a key that is not accepted before looking at
+        // their content
         self.context
             .check_signature(&signed_rav, &self.domain_separator)
             .await?;
@@ -89,13 +434,19 @@ impl<E, Rcpt> Manager<E, Rcpt> {
             });
         }
 
+        // keep a copy for the hook only if there is one
+        let stored_rav = self.on_rav_stored.as_ref().map(|_| signed_rav.clone());
         self.context
-            .update_last_rav(signed_rav)
+            .commit_rav_and_delete_receipts(signed_rav, receipt_ids)
             .await
             .map_err(|err| Error::AdapterError {
                 source_error: anyhow::Error::new(err),
             })?;
 
+        if let (Some(on_rav_stored), Some(stored_rav)) = (&self.on_rav_stored, &stored_rav) {
+            on_rav_stored(stored_rav);
+        }
+
         Ok(())
     }
 }
@@ -103,22 +454,30 @@ impl<E, Rcpt> Manager<E, Rcpt> {
 impl<E, Rcpt> Manager<E, Rcpt>
 where
     E: ReceiptRead<Rcpt>,
-    Rcpt: WithUniqueId + WithValueAndTimestamp,
+    Rcpt: WithUniqueId + WithValueAndTimestamp + Sync,
 {
     async fn collect_receipts(
         &self,
         ctx: &Context,
         timestamp_buffer_ns: u64,
         min_timestamp_ns: u64,
+        upto_timestamp_ns: Option<u64>,
         limit: Option<u64>,
+        run_checks_with_side_effects: bool,
+        select: impl Fn(&Rcpt) -> bool,
     ) -> Result<
         (
             Vec<ReceiptWithState<Checked, Rcpt>>,
             Vec<ReceiptWithState<Failed, Rcpt>>,
+            Vec<u64>,
         ),
         Error,
     > {
-        let max_timestamp_ns = crate::get_current_timestamp_u64_ns()? - timestamp_buffer_ns;
+        let mut max_timestamp_ns = crate::get_current_timestamp_u64_ns()? - timestamp_buffer_ns;
+        if let Some(upto_timestamp_ns) = upto_timestamp_ns {
+            // the range end is exclusive
+            max_timestamp_ns = max_timestamp_ns.min(upto_timestamp_ns.saturating_add(1));
+        }
 
         if min_timestamp_ns > max_timestamp_ns {
             return Err(Error::TimestampRangeError {
@@ -126,7 +485,7 @@ where
                 max_timestamp_ns,
             });
         }
-        let checking_receipts = self
+        let receipts_with_ids = self
             .context
             .retrieve_receipts_in_timestamp_range(min_timestamp_ns..max_timestamp_ns, limit)
             .await
@@ -134,6 +493,20 @@ where
                 source_error: anyhow::Error::new(err),
             })?;
 
+        // duplicated receipts are rejected by `UniqueCheck`, except the
+        // first one, so only its id is kept
+        let mut receipt_ids = HashMap::new();
+        let checking_receipts = receipts_with_ids
+            .into_iter()
+            .filter(|(_, receipt)| select(receipt.signed_receipt()))
+            .map(|(id, receipt)| {
+                receipt_ids
+                    .entry(receipt.signed_receipt().unique_id())
+                    .or_insert(id);
+                receipt
+            })
+            .collect();
+
         let mut checked_receipts = vec![];
         let mut failed_receipts = vec![];
 
@@ -146,11 +519,38 @@ where
         let (checking_receipts, already_failed) = UniqueCheck.check_batch(checking_receipts);
         failed_receipts.extend(already_failed);
 
-        for receipt in checking_receipts.into_iter() {
-            let receipt = receipt
-                .finalize_receipt_checks(ctx, &self.checks)
-                .await
-                .map_err(|e| Error::ReceiptError(ReceiptError::RetryableCheck(e)))?;
+        // Checks only run when the receipts were received are skipped, as
+        // well as the checks with side effects if
+        // `run_checks_with_side_effects` is false. Checks updating shared
+        // state run after the others, on one receipt at a time.
+        let (parallel_checks, sequential_checks): (Vec<_>, Vec<_>) = self
+            .checks
+            .iter()
+            .filter(|check| !check.is_ingest_only())
+            .filter(|check| run_checks_with_side_effects || !check.has_side_effects())
+            .cloned()
+            .partition(|check| check.is_parallel_safe());
+
+        // Each check runs on all the receipts at once, e.g. the signers are
+        // recovered in parallel by `SignerCheck`. The results keep the order
+        // of the receipts, so the split between valid and invalid receipts
+        // doesn't depend on thread scheduling.
+        let partially_checked_receipts = ReceiptWithState::perform_checks_or_fail_batch(
+            ctx,
+            checking_receipts,
+            &parallel_checks,
+        )
+        .await
+        .map_err(|e| Error::ReceiptError(ReceiptError::RetryableCheck(e)))?;
+
+        for receipt in partially_checked_receipts {
+            let receipt = match receipt {
+                Ok(checking) => checking
+                    .finalize_receipt_checks(ctx, &sequential_checks)
+                    .await
+                    .map_err(|e| Error::ReceiptError(ReceiptError::RetryableCheck(e)))?,
+                Err(failed) => Err(failed),
+            };
 
             match receipt {
                 Ok(checked) => checked_receipts.push(checked),
@@ -158,7 +558,16 @@ where
             }
         }
 
-        Ok((checked_receipts, failed_receipts))
+        let included_receipt_ids = checked_receipts
+            .iter()
+            .filter_map(|receipt| {
+                receipt_ids
+                    .get(&receipt.signed_receipt().unique_id())
+                    .copied()
+            })
+            .collect();
+
+        Ok((checked_receipts, failed_receipts, included_receipt_ids))
     }
 
     /// Completes remaining checks on all receipts up to
@@ -176,12 +585,52 @@ where
     /// previous RAV is greater than the min timestamp. Caused by timestamp
     /// buffer being too large, or requests coming too soon.
     ///
+    /// Returns `None` if there are no valid receipts to aggregate, whether or
+    /// not there is a previous RAV, so that there is no request to send to
+    /// `tap_aggregator`. Errors are reserved for actual failures.
+    ///
+    /// If `upto_timestamp_ns` is set, only the receipts with a timestamp up
+    /// to it (inclusive) are aggregated, e.g. to cut a RAV at the end of a
+    /// billing period. Later receipts are left for the next RAV. The
+    /// timestamp of the RAV is the one of the latest receipt included.
+    ///
+    /// Building the request does not modify the storage, so it can be
+    /// retried if the aggregator fails. The receipts are only aggregated
+    /// once [`Manager::verify_and_store_rav`] stores the RAV, and only
+    /// removed with it, given their [`RavRequest::included_receipt_ids`], or
+    /// by [`Manager::remove_obsolete_receipts`] after that.
+    ///
     pub async fn create_rav_request<Rav>(
         &self,
         ctx: &Context,
         timestamp_buffer_ns: u64,
         receipts_limit: Option<u64>,
-    ) -> Result<RavRequest<Rcpt, Rav>, Error>
+        upto_timestamp_ns: Option<u64>,
+    ) -> Result<Option<RavRequest<Rcpt, Rav>>, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp + Clone + Aggregate<Rcpt>,
+    {
+        self.build_rav_request(
+            ctx,
+            timestamp_buffer_ns,
+            receipts_limit,
+            upto_timestamp_ns,
+            true,
+            |_| true,
+        )
+        .await
+    }
+
+    async fn build_rav_request<Rav>(
+        &self,
+        ctx: &Context,
+        timestamp_buffer_ns: u64,
+        receipts_limit: Option<u64>,
+        upto_timestamp_ns: Option<u64>,
+        run_checks_with_side_effects: bool,
+        select: impl Fn(&Rcpt) -> bool,
+    ) -> Result<Option<RavRequest<Rcpt, Rav>>, Error>
     where
         E: RavRead<Rav>,
         Rav: SolStruct + WithValueAndTimestamp + Clone + Aggregate<Rcpt>,
@@ -192,17 +641,297 @@ where
             .map(|rav| rav.message.timestamp_ns() + 1)
             .unwrap_or(0);
 
-        let (valid_receipts, invalid_receipts) = self
-            .collect_receipts(ctx, timestamp_buffer_ns, min_timestamp_ns, receipts_limit)
+        let (valid_receipts, invalid_receipts, included_receipt_ids) = self
+            .collect_receipts(
+                ctx,
+                timestamp_buffer_ns,
+                min_timestamp_ns,
+                upto_timestamp_ns,
+                receipts_limit,
+                run_checks_with_side_effects,
+                select,
+            )
             .await?;
 
+        if valid_receipts.is_empty() {
+            return Ok(None);
+        }
         let expected_rav = Rav::aggregate_receipts(&valid_receipts, previous_rav.clone());
 
-        Ok(RavRequest {
+        Ok(Some(RavRequest {
             valid_receipts,
             previous_rav,
+            included_receipt_ids,
             invalid_receipts,
             expected_rav,
+        }))
+    }
+
+    /// Returns the RAV that the next call to [`Manager::create_rav_request`]
+    /// would expect, without signing it, e.g. for an indexer to check the
+    /// pending value before deciding to request a RAV.
+    ///
+    /// The receipts are selected and aggregated as in
+    /// [`Manager::create_rav_request`], without a receipts limit nor
+    /// `upto_timestamp_ns`, and the storage is not modified. The checks with
+    /// side effects, see [`crate::receipt::checks::Check::has_side_effects`],
+    /// are skipped so that a preview does not count against the next RAV
+    /// request.
+    ///
+    /// Returns `None` if there are no valid receipts to aggregate.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Manager::create_rav_request`]. Aggregation errors are
+    /// returned as [`Error::AggregateOverflow`] or [`Error::Aggregation`],
+    /// as in [`Manager::request_rav`].
+    ///
+    pub async fn preview_next_rav<Rav>(
+        &self,
+        ctx: &Context,
+        timestamp_buffer_ns: u64,
+    ) -> Result<Option<Rav>, Error>
+    where
+        E: RavRead<Rav>,
+        Rav: SolStruct + WithValueAndTimestamp + Clone + Aggregate<Rcpt>,
+    {
+        let Some(rav_request) = self
+            .build_rav_request::<Rav>(ctx, timestamp_buffer_ns, None, None, false, |_| true)
+            .await?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(rav_request.expected_rav?))
+    }
+}
+
+impl<E, Rcpt> Manager<E, Rcpt>
+where
+    E: ReceiptRead<Rcpt>,
+    Rcpt: WithUniqueId + WithValueAndTimestamp + Sync,
+{
+    /// Runs a RAV round for `allocation_id`: creates the RAV request with
+    /// [`Manager::create_rav_request`], has it signed by `aggregate`
+    /// (usually by calling the aggregator), then verifies and stores the RAV,
+    /// deleting the aggregated receipts at the same time with
+    /// [`Manager::verify_and_store_rav`].
+    ///
+    /// Rounds of the same allocation are serialized, so that a round always
+    /// starts from the RAV stored by the previous one, while rounds of
+    /// different allocations run concurrently, see
+    /// [`Manager::with_max_concurrent_rav_requests`]. Rounds run by calling
+    /// [`Manager::create_rav_request`] and [`Manager::verify_and_store_rav`]
+    /// directly are not serialized.
+    ///
+    /// The receipts in the context are expected to belong to `allocation_id`,
+    /// as for [`Manager::create_rav_request`].
+    ///
+    /// Returns `None` if there are no receipts to aggregate.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Manager::finalize_allocation`]
+    ///
+    pub async fn request_rav<Rav, F, Fut>(
+        &self,
+        ctx: &Context,
+        allocation_id: Address,
+        timestamp_buffer_ns: u64,
+        receipts_limit: Option<u64>,
+        aggregate: F,
+    ) -> Result<Option<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavRead<Rav> + RavStore<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + WithValueAndTimestamp
+            + Aggregate<Rcpt>
+            + Clone
+            + PartialEq<Rav>
+            + Send
+            + Sync
+            + std::fmt::Debug
+            + 'static,
+        F: FnOnce(RavRequest<Rcpt, Rav>) -> Fut,
+        Fut: Future<Output = anyhow::Result<Eip712SignedMessage<Rav>>>,
+    {
+        self.run_rav_round(
+            ctx,
+            allocation_id,
+            timestamp_buffer_ns,
+            receipts_limit,
+            |_| true,
+            aggregate,
+        )
+        .await
+    }
+
+    /// Runs a round of [`Manager::request_rav`], aggregating only the
+    /// receipts for which `select` returns `true`.
+    async fn run_rav_round<Rav, F, Fut>(
+        &self,
+        ctx: &Context,
+        allocation_id: Address,
+        timestamp_buffer_ns: u64,
+        receipts_limit: Option<u64>,
+        select: impl Fn(&Rcpt) -> bool,
+        aggregate: F,
+    ) -> Result<Option<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavRead<Rav> + RavStore<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + WithValueAndTimestamp
+            + Aggregate<Rcpt>
+            + Clone
+            + PartialEq<Rav>
+            + Send
+            + Sync
+            + std::fmt::Debug
+            + 'static,
+        F: FnOnce(RavRequest<Rcpt, Rav>) -> Fut,
+        Fut: Future<Output = anyhow::Result<Eip712SignedMessage<Rav>>>,
+    {
+        let _round = self.start_rav_round(allocation_id).await;
+
+        let Some(rav_request) = self
+            .build_rav_request(ctx, timestamp_buffer_ns, receipts_limit, None, true, select)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let expected_rav = match rav_request.expected_rav {
+            Ok(ref expected_rav) => expected_rav.clone(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let included_receipt_ids = rav_request.included_receipt_ids.clone();
+        let signed_rav = aggregate(rav_request)
+            .await
+            .map_err(|source_error| Error::AdapterError { source_error })?;
+        self.verify_and_store_rav(expected_rav, signed_rav.clone(), &included_receipt_ids)
+            .await?;
+        Ok(Some(signed_rav))
+    }
+
+    /// Finalizes `allocation_id` once it is closed on-chain.
+    ///
+    /// All the remaining receipts for `allocation_id` are aggregated into a
+    /// final RAV by a last round of [`Manager::request_rav`]. Receipts for
+    /// other allocations are left in the context. Once the final RAV is
+    /// stored, the allocation is marked as closed, so that any receipt for it
+    /// received afterwards is rejected by [`Manager::verify_and_store_receipt`].
+    ///
+    /// Receipts stored while the final RAV is requested are not part of it
+    /// and are left in the context.
+    ///
+    /// Returns `None` if there are no remaining receipts to aggregate.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while
+    /// retrieving receipts or storing the RAV, or if `aggregate` fails
+    ///
+    /// Returns [`Error::AggregateOverflow`] if the final RAV value overflows,
+    /// or [`Error::Aggregation`] if the receipts cannot be aggregated otherwise
+    ///
+    /// Returns [`Error::InvalidReceivedRav`] if the RAV signed by `aggregate`
+    /// does not match the expected RAV
+    ///
+    /// The allocation stays open on error, so that finalizing it can be
+    /// retried.
+    ///
+    pub async fn finalize_allocation<Rav, F, Fut>(
+        &self,
+        ctx: &Context,
+        allocation_id: Address,
+        aggregate: F,
+    ) -> Result<Option<Eip712SignedMessage<Rav>>, Error>
+    where
+        E: RavRead<Rav> + RavStore<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + WithValueAndTimestamp
+            + Aggregate<Rcpt>
+            + Clone
+            + PartialEq<Rav>
+            + Send
+            + Sync
+            + std::fmt::Debug
+            + 'static,
+        F: FnOnce(RavRequest<Rcpt, Rav>) -> Fut,
+        Fut: Future<Output = anyhow::Result<Eip712SignedMessage<Rav>>>,
+        Rcpt: WithAllocationId,
+    {
+        // every remaining receipt is aggregated, hence no timestamp buffer
+        let final_rav = self
+            .run_rav_round(
+                ctx,
+                allocation_id,
+                0,
+                None,
+                |receipt| receipt.allocation_id() == allocation_id,
+                aggregate,
+            )
+            .await?;
+        self.closed_allocations.close(allocation_id);
+        Ok(final_rav)
+    }
+
+    /// Migrates `allocation_id` to another receipt type, e.g. when upgrading
+    /// from the v1 to the v2 protocol, whatever the storage behind the
+    /// contexts.
+    ///
+    /// The allocation is finalized with [`Manager::finalize_allocation`], so
+    /// that all its receipts are aggregated into a final RAV, then each
+    /// receipt aggregated is mapped with `map_receipt`, e.g. with
+    /// `tap_graph::v2::Receipt::from_v1` and signed again, and returned with
+    /// the final RAV.
+    ///
+    /// The migration is one way: the allocation is closed, so that receipts
+    /// of the old type are rejected afterwards, and the migrated receipts are
+    /// deleted from the context once aggregated. The migrated receipts are
+    /// not stored, as they are already paid by the final RAV: storing them
+    /// with a [`ReceiptStore`] would aggregate them again. Use
+    /// [`Manager::finalize_allocation`] alone to only get the final RAV.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Manager::finalize_allocation`]
+    ///
+    pub async fn migrate_allocation<Rav, F, Fut, TargetRcpt>(
+        &self,
+        ctx: &Context,
+        allocation_id: Address,
+        aggregate: F,
+        map_receipt: impl Fn(&Rcpt) -> TargetRcpt,
+    ) -> Result<ReceiptMigration<Rav, TargetRcpt>, Error>
+    where
+        E: RavRead<Rav> + RavStore<Rav> + SignatureChecker,
+        Rav: SolStruct
+            + WithValueAndTimestamp
+            + Aggregate<Rcpt>
+            + Clone
+            + PartialEq<Rav>
+            + Send
+            + Sync
+            + std::fmt::Debug
+            + 'static,
+        F: FnOnce(RavRequest<Rcpt, Rav>) -> Fut,
+        Fut: Future<Output = anyhow::Result<Eip712SignedMessage<Rav>>>,
+        Rcpt: WithAllocationId,
+    {
+        let mut migrated_receipts = Vec::new();
+        let final_rav = self
+            .finalize_allocation(ctx, allocation_id, |rav_request| {
+                migrated_receipts = rav_request
+                    .valid_receipts
+                    .iter()
+                    .map(|receipt| map_receipt(receipt.signed_receipt()))
+                    .collect();
+                aggregate(rav_request)
+            })
+            .await?;
+        Ok(ReceiptMigration {
+            final_rav,
+            migrated_receipts,
         })
     }
 }
@@ -244,32 +973,250 @@ where
 impl<E, Rcpt> Manager<E, Rcpt>
 where
     E: ReceiptStore<Rcpt>,
+    Rcpt: WithAllocationId + Sync,
 {
     /// Runs `initial_checks` on `signed_receipt` for initial verification,
     /// then stores received receipt.
     /// The provided `query_id` will be used as a key when chaecking query appraisal.
     ///
+    /// Returns the id assigned to the receipt by [`ReceiptStore::store_receipt`].
+    ///
+    /// Receipts for allocations finalized with [`Manager::finalize_allocation`]
+    /// are rejected.
+    ///
+    /// If the caller already recovered the signer of `signed_receipt`, it can
+    /// insert it in `ctx` as a [`crate::receipt::RecoveredSigner`] so that
+    /// signature checks do not recover it again.
+    ///
+    /// The check rejecting a receipt, if any, is recorded with the
+    /// [`crate::receipt::checks::CheckMetrics`] of the check list.
+    ///
+    /// The warnings raised by the checks are dropped, use
+    /// [`Manager::verify_and_store_receipt_with_warnings`] to get them.
+    ///
     /// # Errors
     ///
+    /// Returns [`Error::NotAcceptingReceipts`] if the acceptance of receipts
+    /// is paused with [`Manager::set_accepting`]
+    ///
+    /// Returns [`Error::ReceiptPendingEscrow`] if the receipt is held until
+    /// the escrow of its sender is topped up, see
+    /// [`Manager::with_max_pending_escrow_receipts`]
+    ///
     /// Returns [`Error::AdapterError`] if there are any errors while storing receipts
     ///
     pub async fn verify_and_store_receipt(
         &self,
         ctx: &Context,
         signed_receipt: Rcpt,
-    ) -> std::result::Result<(), Error> {
-        let mut received_receipt = ReceiptWithState::new(signed_receipt);
+    ) -> std::result::Result<u64, Error> {
+        self.verify_and_store_receipt_with_warnings(ctx, signed_receipt)
+            .await
+            .map(|stored| stored.receipt_id)
+    }
 
-        // perform checks
-        received_receipt.perform_checks(ctx, &self.checks).await?;
+    /// Same as [`Manager::verify_and_store_receipt`], also returning the
+    /// warnings raised by the checks on the stored receipt, see
+    /// [`crate::receipt::checks::CheckOutcome::warnings`]. They are recorded
+    /// with the [`crate::receipt::checks::CheckMetrics`] of the check list as
+    /// well.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`Manager::verify_and_store_receipt`]
+    ///
+    pub async fn verify_and_store_receipt_with_warnings(
+        &self,
+        ctx: &Context,
+        signed_receipt: Rcpt,
+    ) -> std::result::Result<StoredReceipt, Error> {
+        if !self.is_accepting() {
+            return Err(Error::NotAcceptingReceipts);
+        }
+        let (received_receipt, warnings) = match self
+            .check_or_hold(ctx, ReceiptWithState::new(signed_receipt))
+            .await?
+        {
+            Ok(checked) => checked,
+            Err(pending_receipt) => {
+                let error = pending_receipt.error().clone();
+                let mut pending_escrow = self.pending_escrow.lock().unwrap();
+                if pending_escrow.len() >= self.max_pending_escrow_receipts {
+                    // rejected as if no receipts were held
+                    return Err(ReceiptError::CheckFailure(error.to_string()).into());
+                }
+                pending_escrow.push(pending_receipt);
+                return Err(Error::ReceiptPendingEscrow(error));
+            }
+        };
 
         // store the receipt
-        self.context
+        let receipt_id = self
+            .context
             .store_receipt(received_receipt)
             .await
             .map_err(|err| Error::AdapterError {
                 source_error: anyhow::Error::new(err),
             })?;
-        Ok(())
+        Ok(StoredReceipt {
+            receipt_id,
+            warnings,
+        })
+    }
+
+    /// Checks again the receipts held by
+    /// [`Manager::verify_and_store_receipt`] because the escrow of their
+    /// sender was insufficient, e.g. after a top-up, and stores the ones that
+    /// pass the checks now. The receipts still lacking escrow stay held,
+    /// while the ones failing another check are dropped.
+    ///
+    /// The checks are resumed from the one that held a receipt: the checks
+    /// it passed before are not performed again, so that the ones recording
+    /// the receipts they accept do not count it twice.
+    ///
+    /// Returns the stored receipts, with the warnings raised by the checks
+    /// as in [`Manager::verify_and_store_receipt_with_warnings`], including
+    /// the ones raised before a receipt was held.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AdapterError`] if there are any errors while storing
+    /// receipts. The receipts not stored yet stay held.
+    ///
+    pub async fn retry_pending_escrow_receipts(
+        &self,
+        ctx: &Context,
+    ) -> Result<Vec<StoredReceipt>, Error>
+    where
+        Rcpt: Clone,
+    {
+        let pending_receipts = std::mem::take(&mut *self.pending_escrow.lock().unwrap());
+        let mut still_pending = Vec::new();
+        let mut stored_receipts = Vec::new();
+        let mut result = Ok(());
+        for pending_receipt in pending_receipts {
+            if result.is_err() {
+                still_pending.push(pending_receipt);
+                continue;
+            }
+            match self.resume_or_hold(ctx, pending_receipt.clone()).await {
+                Ok(Ok((received_receipt, warnings))) => {
+                    match self.context.store_receipt(received_receipt).await {
+                        Ok(receipt_id) => stored_receipts.push(StoredReceipt {
+                            receipt_id,
+                            warnings,
+                        }),
+                        Err(err) => {
+                            still_pending.push(pending_receipt);
+                            result = Err(Error::AdapterError {
+                                source_error: anyhow::Error::new(err),
+                            });
+                        }
+                    }
+                }
+                Ok(Err(pending_receipt)) => still_pending.push(pending_receipt),
+                Err(Error::ReceiptError(ReceiptError::RetryableCheck(_))) => {
+                    still_pending.push(pending_receipt)
+                }
+                // e.g. its allocation was closed in the meantime
+                Err(_) => {}
+            }
+        }
+        self.pending_escrow.lock().unwrap().extend(still_pending);
+        result.map(|()| stored_receipts)
+    }
+
+    /// Runs the checks of [`Manager::verify_and_store_receipt`] on `receipt`,
+    /// returning it in the [`PendingEscrow`] state if receipts lacking escrow
+    /// are held. A passing receipt is returned with the warnings raised by
+    /// the checks.
+    async fn check_or_hold(
+        &self,
+        ctx: &Context,
+        mut receipt: ReceiptWithState<Checking, Rcpt>,
+    ) -> Result<
+        Result<
+            (ReceiptWithState<Checking, Rcpt>, Vec<CheckWarning>),
+            ReceiptWithState<PendingEscrow, Rcpt>,
+        >,
+        Error,
+    > {
+        let closed_allocation_check: ReceiptCheck<Rcpt> = self.closed_allocations.clone();
+        receipt
+            .perform_checks(ctx, &[closed_allocation_check])
+            .await?;
+        if self.max_pending_escrow_receipts == 0 {
+            let warnings = self
+                .checks
+                .perform_checks_with_warnings(ctx, &receipt)
+                .await?;
+            return Ok(Ok((receipt, warnings)));
+        }
+        Ok(self.checks.perform_checks_or_hold(ctx, receipt).await?)
+    }
+
+    /// Same as [`Manager::check_or_hold`] on a held receipt, resuming the
+    /// checks from the one that held it, see
+    /// [`CheckList::resume_checks_or_hold`].
+    async fn resume_or_hold(
+        &self,
+        ctx: &Context,
+        receipt: ReceiptWithState<PendingEscrow, Rcpt>,
+    ) -> Result<
+        Result<
+            (ReceiptWithState<Checking, Rcpt>, Vec<CheckWarning>),
+            ReceiptWithState<PendingEscrow, Rcpt>,
+        >,
+        Error,
+    > {
+        let held_by = receipt.check();
+        let warnings = receipt.warnings().to_vec();
+        let mut receipt = receipt.retry();
+        let closed_allocation_check: ReceiptCheck<Rcpt> = self.closed_allocations.clone();
+        receipt
+            .perform_checks(ctx, &[closed_allocation_check])
+            .await?;
+        Ok(self
+            .checks
+            .resume_checks_or_hold(ctx, receipt, held_by, warnings)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use alloy::primitives::Address;
+    use futures_util::FutureExt;
+    use tap_graph::SignedReceipt;
+
+    use super::Manager;
+    use crate::{receipt::checks::CheckList, tap_eip712_domain};
+
+    #[tokio::test]
+    async fn rav_rounds_are_removed_once_done() {
+        let manager = Manager::<(), SignedReceipt>::new(
+            tap_eip712_domain(1, Address::ZERO),
+            (),
+            CheckList::empty(),
+        )
+        .unwrap();
+        let allocation_id = Address::ZERO;
+
+        let round = manager.start_rav_round(allocation_id).await;
+        let mut waiting = pin!(manager.start_rav_round(allocation_id));
+        assert!(waiting.as_mut().now_or_never().is_none());
+
+        // the waiting round still needs the lock of the allocation
+        drop(round);
+        assert!(manager
+            .rav_rounds
+            .lock()
+            .unwrap()
+            .contains_key(&allocation_id));
+
+        drop(waiting.await);
+        assert!(manager.rav_rounds.lock().unwrap().is_empty());
     }
 }
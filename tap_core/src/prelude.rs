@@ -0,0 +1,45 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! # Prelude
+//!
+//! Re-exports the types and traits used by most integrations of TAP, so that
+//! they can be imported at once:
+//!
+//! ```
+//! use tap_core::prelude::*;
+//! ```
+//!
+//! It includes:
+//! - the [`Manager`], the [`RavRequest`] it creates and the [`RavTrigger`];
+//! - the adapter traits the context of the manager implements;
+//! - the receipt checks commonly configured, with [`Check`] and [`CheckList`];
+//! - the traits receipts and RAVs implement to be checked and aggregated;
+//! - [`Eip712SignedMessage`] and [`tap_eip712_domain`], to sign and verify
+//!   messages;
+//! - the [`Error`] of this crate.
+//!
+//! Less common items, e.g. the in-memory context or the receipt states, are
+//! left out and must be imported from their own module.
+
+pub use crate::{
+    manager::{
+        adapters::{
+            HealthCheck, RavRead, RavStore, ReceiptDelete, ReceiptRead, ReceiptStore,
+            SignatureChecker,
+        },
+        Manager, RavTrigger,
+    },
+    rav_request::RavRequest,
+    receipt::{
+        checks::{
+            Check, CheckError, CheckList, CheckOutcome, CheckResult, EscrowHeadroom,
+            EscrowHeadroomCheck, StatefulTimestampCheck, TimestampCheck,
+        },
+        rav::{Aggregate, AggregationError},
+        Context, ReceiptError, ReceiptWithState, WithAllocationId, WithUniqueId,
+        WithValueAndTimestamp,
+    },
+    signed_message::{Eip712SignedMessage, MessageId},
+    tap_eip712_domain, Error,
+};
@@ -21,6 +21,10 @@ pub struct RavRequest<Rcpt, Rav: SolStruct> {
     pub valid_receipts: Vec<ReceiptWithState<Checked, Rcpt>>,
     /// Optional previous RAV to aggregate with
     pub previous_rav: Option<Eip712SignedMessage<Rav>>,
+    /// IDs of the valid receipts in the storage, as returned by
+    /// [`crate::manager::adapters::ReceiptStore::store_receipt`], to remove
+    /// exactly the aggregated receipts once the RAV is stored
+    pub included_receipt_ids: Vec<u64>,
     /// List of failed receipt used to log invalid receipts
     pub invalid_receipts: Vec<ReceiptWithState<Failed, Rcpt>>,
     /// Expected RAV to be created
@@ -0,0 +1,83 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Formatting of receipt and RAV values
+//!
+//! Values are amounts of GRT wei (10^-18 GRT). These helpers render them in
+//! GRT for logs, e.g. `1.5 GRT` instead of `1500000000000000000`.
+
+/// Number of decimals of GRT: 1 GRT is 10^18 wei.
+pub const GRT_DECIMALS: u32 = 18;
+
+const WEI_PER_GRT: u128 = 10u128.pow(GRT_DECIMALS);
+
+/// Renders `wei` in GRT, without trailing zeros, e.g. `1.5 GRT`.
+///
+/// The value is exact, down to the wei.
+pub fn format_grt(wei: u128) -> String {
+    let fraction = format!("{:018}", wei % WEI_PER_GRT);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        format!("{} GRT", wei / WEI_PER_GRT)
+    } else {
+        format!("{}.{fraction} GRT", wei / WEI_PER_GRT)
+    }
+}
+
+/// Renders `wei` in GRT with exactly `decimals` decimals, e.g. `1.50 GRT`
+/// for 2 decimals.
+///
+/// Decimals beyond the precision are truncated, so a value is never
+/// rendered higher than it is. `decimals` above [`GRT_DECIMALS`] are
+/// capped.
+pub fn format_grt_with_decimals(wei: u128, decimals: u32) -> String {
+    let decimals = decimals.min(GRT_DECIMALS);
+    if decimals == 0 {
+        return format!("{} GRT", wei / WEI_PER_GRT);
+    }
+    let fraction = (wei % WEI_PER_GRT) / 10u128.pow(GRT_DECIMALS - decimals);
+    format!(
+        "{}.{fraction:0width$} GRT",
+        wei / WEI_PER_GRT,
+        width = decimals as usize
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero() {
+        assert_eq!(format_grt(0), "0 GRT");
+        assert_eq!(format_grt_with_decimals(0, 2), "0.00 GRT");
+        assert_eq!(format_grt_with_decimals(0, 0), "0 GRT");
+    }
+
+    #[test]
+    fn sub_unit() {
+        assert_eq!(format_grt(1), "0.000000000000000001 GRT");
+        assert_eq!(format_grt(500_000_000_000_000_000), "0.5 GRT");
+        assert_eq!(format_grt_with_decimals(1, 2), "0.00 GRT");
+        assert_eq!(format_grt_with_decimals(1, 30), "0.000000000000000001 GRT");
+        // truncated, not rounded
+        assert_eq!(
+            format_grt_with_decimals(999_999_999_999_999_999, 3),
+            "0.999 GRT"
+        );
+    }
+
+    #[test]
+    fn large() {
+        assert_eq!(format_grt(1_500_000_000_000_000_000), "1.5 GRT");
+        assert_eq!(format_grt(42 * WEI_PER_GRT), "42 GRT");
+        assert_eq!(
+            format_grt(u128::MAX),
+            "340282366920938463463.374607431768211455 GRT"
+        );
+        assert_eq!(
+            format_grt_with_decimals(u128::MAX, 4),
+            "340282366920938463463.3746 GRT"
+        );
+    }
+}
@@ -1,13 +1,20 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     str::FromStr,
-    sync::{atomic::AtomicBool, Arc, RwLock},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, PrimitiveSignature, U256},
+    signers::local::PrivateKeySigner,
+};
 use anyhow::anyhow;
 use rstest::*;
 
@@ -17,21 +24,29 @@ fn get_current_timestamp_u64_ns() -> anyhow::Result<u64> {
 
 use tap_core::{
     manager::{
-        adapters::ReceiptRead,
+        adapters::{RavRead, ReceiptRead},
         context::memory::{
             checks::get_full_list_of_checks, EscrowStorage, InMemoryContext, QueryAppraisals,
         },
         Manager,
     },
+    rav_request::RavRequest,
     receipt::{
-        checks::{Check, CheckError, CheckList, StatefulTimestampCheck},
+        checks::{
+            AggregatorCheckConfig, Check, CheckConfigError, CheckError, CheckList, CheckMetrics,
+            CheckOutcome, CheckResult, EscrowHeadroomCheck, IndexerCheckConfig, SignerCheck,
+            StatefulTimestampCheck, ValueRateLimitCheck,
+        },
         state::Checking,
-        Context, ReceiptWithState,
+        Context, ReceiptError, ReceiptWithState,
     },
     signed_message::Eip712SignedMessage,
     tap_eip712_domain,
 };
-use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedReceipt};
+use tap_graph::{
+    v2::{DataService, Payer, ServiceProvider},
+    Receipt, ReceiptAggregateVoucher, SignedRav, SignedReceipt,
+};
 
 #[fixture]
 fn signer() -> PrivateKeySigner {
@@ -83,7 +98,7 @@ fn context(
 ) -> ContextFixture {
     let (signer, sender_ids) = sender_ids;
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(Vec::new()));
     let query_appraisals = Arc::new(RwLock::new(HashMap::new()));
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
@@ -128,7 +143,7 @@ async fn manager_verify_and_store_varying_initial_checks(
         signer,
         ..
     } = context;
-    let manager = Manager::new(domain_separator.clone(), context, checks);
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
 
     let value = 20u128;
     let signed_receipt = Eip712SignedMessage::new(
@@ -152,7 +167,46 @@ async fn manager_verify_and_store_varying_initial_checks(
 
 #[rstest]
 #[tokio::test]
-async fn manager_create_rav_request_all_valid_receipts(
+async fn manager_verify_and_store_returns_receipt_ids(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+
+    for expected_id in 0..5 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20u128).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        let receipt_id = manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt.clone())
+            .await
+            .unwrap();
+        // ids are assigned sequentially by the in-memory storage
+        assert_eq!(receipt_id, expected_id);
+        assert_eq!(
+            context
+                .retrieve_receipt_by_id(receipt_id)
+                .await
+                .unwrap()
+                .signed_receipt(),
+            &signed_receipt
+        );
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rejects_receipt_exceeding_escrow(
     allocation_ids: Vec<Address>,
     domain_separator: Eip712Domain,
     context: ContextFixture,
@@ -160,91 +214,168 @@ async fn manager_create_rav_request_all_valid_receipts(
     let ContextFixture {
         context,
         checks,
-        query_appraisals,
         escrow_storage,
         signer,
         ..
     } = context;
-    let manager = Manager::new(domain_separator.clone(), context, checks);
+
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(EscrowHeadroomCheck(context.clone())));
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    )
+    .unwrap();
+
     escrow_storage
         .write()
         .unwrap()
-        .insert(signer.address(), 999999);
+        .insert(signer.address(), 100);
 
-    let mut stored_signed_receipts = Vec::new();
-    for _ in 0..10 {
-        let value = 20u128;
+    // 3 receipts of 30 fit in an escrow of 100
+    for _ in 0..3 {
         let signed_receipt = Eip712SignedMessage::new(
             &domain_separator,
-            Receipt::new(allocation_ids[0], value).unwrap(),
+            Receipt::new(allocation_ids[0], 30).unwrap(),
             &signer,
         )
         .unwrap();
-        let query_id = signed_receipt.unique_hash();
-        stored_signed_receipts.push(signed_receipt.clone());
-        query_appraisals.write().unwrap().insert(query_id, value);
-        assert!(manager
+        manager
             .verify_and_store_receipt(&Context::new(), signed_receipt)
             .await
-            .is_ok());
+            .unwrap();
     }
-    let rav_request_result = manager.create_rav_request(&Context::new(), 0, None).await;
-    assert!(rav_request_result.is_ok());
 
-    let rav_request = rav_request_result.unwrap();
-    // all passing
+    // The 4th one would exceed it
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 30).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    let err = manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap_err();
     assert_eq!(
-        rav_request.valid_receipts.len(),
-        stored_signed_receipts.len()
+        err.to_string(),
+        tap_core::Error::ReceiptError(ReceiptError::CheckFailure(
+            ReceiptError::InsufficientEscrow {
+                required_escrow: 120,
+                available_escrow: 100,
+            }
+            .to_string()
+        ))
+        .to_string()
     );
-    // no failing
-    assert_eq!(rav_request.invalid_receipts.len(), 0);
-
-    let expected_rav = rav_request.expected_rav.unwrap();
-
-    let signed_rav =
-        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
-    assert!(manager
-        .verify_and_store_rav(expected_rav, signed_rav)
-        .await
-        .is_ok());
 }
 
 #[rstest]
 #[tokio::test]
-async fn deny_rav_due_to_wrong_value(domain_separator: Eip712Domain, context: ContextFixture) {
+async fn manager_holds_receipts_pending_escrow(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
     let ContextFixture {
         context,
         checks,
+        escrow_storage,
         signer,
         ..
     } = context;
-    let manager = Manager::new(domain_separator.clone(), context, checks);
 
-    let rav = ReceiptAggregateVoucher {
-        allocationId: Address::from_str("0xabababababababababababababababababababab").unwrap(),
-        timestampNs: 1232442,
-        valueAggregate: 20u128,
-    };
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    // would reject the held receipt if it counted it again on retry
+    checks.push(Arc::new(ValueRateLimitCheck::new(
+        domain_separator.clone(),
+        Duration::from_secs(60),
+        120,
+    )));
+    checks.push(Arc::new(EscrowHeadroomCheck(context.clone())));
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    )
+    .unwrap()
+    .with_max_pending_escrow_receipts(10);
 
-    let rav_wrong_value = ReceiptAggregateVoucher {
-        allocationId: Address::from_str("0xabababababababababababababababababababab").unwrap(),
-        timestampNs: 1232442,
-        valueAggregate: 10u128,
-    };
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 100);
 
-    let signed_rav_with_wrong_aggregate =
-        Eip712SignedMessage::new(&domain_separator, rav_wrong_value, &signer).unwrap();
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 60).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
 
-    assert!(manager
-        .verify_and_store_rav(rav, signed_rav_with_wrong_aggregate)
+    // The 2nd one would exceed the escrow, it is held
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 60).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    let err = manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt.clone())
         .await
-        .is_err());
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        tap_core::Error::ReceiptPendingEscrow(ReceiptError::InsufficientEscrow {
+            required_escrow: 120,
+            available_escrow: 100,
+        })
+    ));
+
+    // still held without a top-up
+    let stored_receipts = manager
+        .retry_pending_escrow_receipts(&Context::new())
+        .await
+        .unwrap();
+    assert!(stored_receipts.is_empty());
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 200);
+    let stored_receipts = manager
+        .retry_pending_escrow_receipts(&Context::new())
+        .await
+        .unwrap();
+    assert_eq!(stored_receipts.len(), 1);
+    assert!(stored_receipts[0].warnings.is_empty());
+    let receipts = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert!(receipts
+        .iter()
+        .any(|(id, receipt)| *id == stored_receipts[0].receipt_id
+            && *receipt.signed_receipt() == signed_receipt));
+
+    // nothing left to retry
+    let stored_receipts = manager
+        .retry_pending_escrow_receipts(&Context::new())
+        .await
+        .unwrap();
+    assert!(stored_receipts.is_empty());
 }
 
 #[rstest]
 #[tokio::test]
-async fn manager_create_multiple_rav_requests_all_valid_receipts(
+async fn manager_create_rav_request_all_valid_receipts(
     allocation_ids: Vec<Address>,
     domain_separator: Eip712Domain,
     context: ContextFixture,
@@ -257,16 +388,13 @@ async fn manager_create_multiple_rav_requests_all_valid_receipts(
         signer,
         ..
     } = context;
-
-    let manager = Manager::new(domain_separator.clone(), context, checks);
-
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
     escrow_storage
         .write()
         .unwrap()
         .insert(signer.address(), 999999);
 
     let mut stored_signed_receipts = Vec::new();
-    let mut expected_accumulated_value = 0;
     for _ in 0..10 {
         let value = 20u128;
         let signed_receipt = Eip712SignedMessage::new(
@@ -282,211 +410,1898 @@ async fn manager_create_multiple_rav_requests_all_valid_receipts(
             .verify_and_store_receipt(&Context::new(), signed_receipt)
             .await
             .is_ok());
-        expected_accumulated_value += value;
     }
-    let rav_request_result = manager.create_rav_request(&Context::new(), 0, None).await;
+    let rav_request_result = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await;
     assert!(rav_request_result.is_ok());
 
-    let rav_request = rav_request_result.unwrap();
-    // all receipts passing
+    let rav_request = rav_request_result.unwrap().unwrap();
+    // all passing
     assert_eq!(
         rav_request.valid_receipts.len(),
         stored_signed_receipts.len()
     );
-    // no receipts failing
+    // no failing
     assert_eq!(rav_request.invalid_receipts.len(), 0);
 
     let expected_rav = rav_request.expected_rav.unwrap();
-    // accumulated value is correct
-    assert_eq!(expected_rav.valueAggregate, expected_accumulated_value);
-    // no previous rav
-    assert!(rav_request.previous_rav.is_none());
 
     let signed_rav =
         Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
     assert!(manager
-        .verify_and_store_rav(expected_rav, signed_rav)
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
         .await
         .is_ok());
+}
 
-    stored_signed_receipts.clear();
-    for _ in 10..20 {
-        let value = 20u128;
+/// Accepts every receipt, standing in for the checks provided by the lib user
+struct AcceptCheck;
+
+#[async_trait::async_trait]
+impl Check<SignedReceipt> for AcceptCheck {
+    async fn check(
+        &self,
+        _: &Context,
+        _: &ReceiptWithState<Checking, SignedReceipt>,
+    ) -> CheckResult {
+        Ok(CheckOutcome::passed())
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_create_rav_request_with_preset_checks(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+    #[values(false, true)] indexer: bool,
+) {
+    let ContextFixture {
+        context,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let config = AggregatorCheckConfig {
+        domain_separator: domain_separator.clone(),
+        accepted_signers: HashSet::from([signer.address()]),
+        stored_receipt_check: Arc::new(AcceptCheck),
+        expected_receipts: 1_000,
+        timestamp_check: Arc::new(StatefulTimestampCheck::new(0)),
+        max_timestamp_distance_years: 1,
+    };
+    let checks = if indexer {
+        CheckList::indexer_defaults(
+            config,
+            IndexerCheckConfig {
+                allocation_ids: Arc::new(RwLock::new(allocation_ids.iter().cloned().collect())),
+                value_check: Arc::new(AcceptCheck),
+                escrow: context.clone(),
+            },
+        )
+    } else {
+        CheckList::aggregator_defaults(config)
+    };
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+
+    // just enough escrow for the receipts, checking them again would exceed it
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 100);
+
+    for _ in 0..5 {
         let signed_receipt = Eip712SignedMessage::new(
             &domain_separator,
-            Receipt::new(allocation_ids[0], value).unwrap(),
+            Receipt::new(allocation_ids[0], 20).unwrap(),
             &signer,
         )
         .unwrap();
-
-        let query_id = signed_receipt.unique_hash();
-        stored_signed_receipts.push(signed_receipt.clone());
-        query_appraisals.write().unwrap().insert(query_id, value);
-        assert!(manager
+        manager
             .verify_and_store_receipt(&Context::new(), signed_receipt)
             .await
-            .is_ok());
-        expected_accumulated_value += value;
+            .unwrap();
     }
-    let rav_request_result = manager.create_rav_request(&Context::new(), 0, None).await;
-    assert!(rav_request_result.is_ok());
 
-    let rav_request = rav_request_result.unwrap();
-    // all receipts passing
-    assert_eq!(
-        rav_request.valid_receipts.len(),
-        stored_signed_receipts.len()
-    );
-    // no receipts failing
+    // the nonce filter and the escrow headroom are not checked again
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 5);
     assert_eq!(rav_request.invalid_receipts.len(), 0);
 
     let expected_rav = rav_request.expected_rav.unwrap();
-    // accumulated value is correct
-    assert_eq!(expected_rav.valueAggregate, expected_accumulated_value);
-    // Verify there is a previous rav
-    assert!(rav_request.previous_rav.is_some());
-
+    assert_eq!(expected_rav.valueAggregate, 100);
     let signed_rav =
         Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
-    assert!(manager
-        .verify_and_store_rav(expected_rav, signed_rav)
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
         .await
-        .is_ok());
+        .unwrap();
 }
 
 #[rstest]
 #[tokio::test]
-async fn manager_create_multiple_rav_requests_all_valid_receipts_consecutive_timestamps(
+async fn manager_create_rav_request_with_value_rate_limit(
     allocation_ids: Vec<Address>,
     domain_separator: Eip712Domain,
-    #[values(true, false)] remove_old_receipts: bool,
     context: ContextFixture,
 ) {
     let ContextFixture {
         context,
         checks,
-        query_appraisals,
         escrow_storage,
         signer,
         ..
     } = context;
-    let starting_min_timestamp = get_current_timestamp_u64_ns().unwrap() - 500000000;
-
-    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
-
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(ValueRateLimitCheck::new(
+        domain_separator.clone(),
+        Duration::from_secs(60),
+        100,
+    )));
+    let manager = Manager::new(domain_separator.clone(), context, CheckList::new(checks)).unwrap();
     escrow_storage
         .write()
         .unwrap()
         .insert(signer.address(), 999999);
 
-    let mut stored_signed_receipts = Vec::new();
-    let mut expected_accumulated_value = 0;
-    for query_id in 0..10 {
-        let value = 20u128;
-        let mut receipt = Receipt::new(allocation_ids[0], value).unwrap();
-        receipt.timestamp_ns = starting_min_timestamp + query_id + 1;
-        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
-
-        let query_id = signed_receipt.unique_hash();
-        stored_signed_receipts.push(signed_receipt.clone());
-        query_appraisals.write().unwrap().insert(query_id, value);
-        assert!(manager
-            .verify_and_store_receipt(&Context::new(), signed_receipt)
+    let receipt = || {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap()
+    };
+    for _ in 0..5 {
+        manager
+            .verify_and_store_receipt(&Context::new(), receipt())
             .await
-            .is_ok());
-        expected_accumulated_value += value;
+            .unwrap();
     }
+    let err = manager
+        .verify_and_store_receipt(&Context::new(), receipt())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("rate limit"));
 
-    // Remove old receipts if requested
-    // This shouldn't do anything since there has been no rav created yet
-    if remove_old_receipts {
-        manager.remove_obsolete_receipts().await.unwrap();
+    // the stored receipts are not charged to the sender again
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 5);
+    assert_eq!(rav_request.invalid_receipts.len(), 0);
+    assert_eq!(rav_request.expected_rav.unwrap().valueAggregate, 100);
+}
+
+/// Counts the receipts it checks, standing in for a check with side effects,
+/// e.g. a quota
+struct CountingCheck(Arc<AtomicUsize>);
+
+#[async_trait::async_trait]
+impl Check<SignedReceipt> for CountingCheck {
+    async fn check(
+        &self,
+        _: &Context,
+        _: &ReceiptWithState<Checking, SignedReceipt>,
+    ) -> CheckResult {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Ok(CheckOutcome::passed())
     }
 
-    let rav_request_1_result = manager.create_rav_request(&Context::new(), 0, None).await;
-    assert!(rav_request_1_result.is_ok());
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
 
-    let rav_request_1 = rav_request_1_result.unwrap();
-    // all receipts passing
-    assert_eq!(
-        rav_request_1.valid_receipts.len(),
-        stored_signed_receipts.len()
+#[rstest]
+#[tokio::test]
+async fn manager_preview_next_rav(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let checked = Arc::new(AtomicUsize::new(0));
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(CountingCheck(checked.clone())));
+    let manager = Manager::new(domain_separator.clone(), context, CheckList::new(checks)).unwrap();
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    assert!(manager
+        .preview_next_rav::<ReceiptAggregateVoucher>(&Context::new(), 0)
+        .await
+        .unwrap()
+        .is_none());
+
+    for value in 1..=5u128 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        let query_id = signed_receipt.unique_hash();
+        query_appraisals.write().unwrap().insert(query_id, value);
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let preview = manager
+        .preview_next_rav::<ReceiptAggregateVoucher>(&Context::new(), 0)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(preview.valueAggregate, 15);
+    // the check with side effects only ran when the receipts were stored
+    assert_eq!(checked.load(Ordering::SeqCst), 5);
+
+    // previewing leaves the receipts to the real RAV request
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 5);
+    assert_eq!(checked.load(Ordering::SeqCst), 10);
+    let expected_rav = rav_request.expected_rav.unwrap();
+    assert_eq!(expected_rav.valueAggregate, preview.valueAggregate);
+    assert_eq!(expected_rav.timestampNs, preview.timestampNs);
+
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
+        .await
+        .unwrap();
+
+    assert!(manager
+        .preview_next_rav::<ReceiptAggregateVoucher>(&Context::new(), 0)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_stores_duplicate_rav_once(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for _ in 0..10 {
+        let value = 20u128;
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), value);
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+
+    manager
+        .verify_and_store_rav(
+            expected_rav.clone(),
+            signed_rav.clone(),
+            &rav_request.included_receipt_ids,
+        )
+        .await
+        .unwrap();
+    // e.g. the client retried after a timeout
+    manager
+        .verify_and_store_rav(
+            expected_rav,
+            signed_rav.clone(),
+            &rav_request.included_receipt_ids,
+        )
+        .await
+        .unwrap();
+
+    let ravs: Vec<SignedRav> = manager.list_ravs(..).await.unwrap();
+    assert_eq!(ravs, vec![signed_rav]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_calls_on_rav_stored(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let stored_ravs = Arc::new(RwLock::new(Vec::new()));
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .unwrap()
+        .with_on_rav_stored({
+            let stored_ravs = stored_ravs.clone();
+            move |rav: &SignedRav| stored_ravs.write().unwrap().push(rav.clone())
+        });
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let value = 20u128;
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], value).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    query_appraisals
+        .write()
+        .unwrap()
+        .insert(signed_receipt.unique_hash(), value);
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+
+    // not called before the RAV is stored
+    assert!(stored_ravs.read().unwrap().is_empty());
+    manager
+        .verify_and_store_rav(
+            expected_rav.clone(),
+            signed_rav.clone(),
+            &rav_request.included_receipt_ids,
+        )
+        .await
+        .unwrap();
+    assert_eq!(*stored_ravs.read().unwrap(), vec![signed_rav.clone()]);
+
+    // a RAV sent again is not stored again
+    manager
+        .verify_and_store_rav(
+            expected_rav,
+            signed_rav.clone(),
+            &rav_request.included_receipt_ids,
+        )
+        .await
+        .unwrap();
+    assert_eq!(*stored_ravs.read().unwrap(), vec![signed_rav]);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_failed_rav_round_keeps_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for _ in 0..10 {
+        let value = 20u128;
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), value);
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+
+    // the aggregator answers with a wrong RAV
+    let wrong_rav = ReceiptAggregateVoucher {
+        valueAggregate: expected_rav.valueAggregate - 1,
+        ..expected_rav.clone()
+    };
+    let signed_wrong_rav = Eip712SignedMessage::new(&domain_separator, wrong_rav, &signer).unwrap();
+    assert!(manager
+        .verify_and_store_rav(
+            expected_rav.clone(),
+            signed_wrong_rav,
+            &rav_request.included_receipt_ids
+        )
+        .await
+        .is_err());
+    manager
+        .remove_obsolete_receipts::<ReceiptAggregateVoucher>()
+        .await
+        .unwrap();
+
+    // nothing was stored or removed, the request can be built again
+    let ravs: Vec<SignedRav> = manager.list_ravs(..).await.unwrap();
+    assert!(ravs.is_empty());
+    assert_eq!(
+        context
+            .retrieve_receipts_in_timestamp_range(.., None)
+            .await
+            .unwrap()
+            .len(),
+        10
+    );
+    let retried_rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(retried_rav_request.valid_receipts.len(), 10);
+    assert_eq!(retried_rav_request.expected_rav.unwrap(), expected_rav);
+}
+
+#[rstest]
+#[tokio::test]
+async fn deny_rav_due_to_wrong_value(domain_separator: Eip712Domain, context: ContextFixture) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+
+    let rav = ReceiptAggregateVoucher {
+        allocationId: Address::from_str("0xabababababababababababababababababababab").unwrap(),
+        timestampNs: 1232442,
+        valueAggregate: 20u128,
+    };
+
+    let rav_wrong_value = ReceiptAggregateVoucher {
+        allocationId: Address::from_str("0xabababababababababababababababababababab").unwrap(),
+        timestampNs: 1232442,
+        valueAggregate: 10u128,
+    };
+
+    let signed_rav_with_wrong_aggregate =
+        Eip712SignedMessage::new(&domain_separator, rav_wrong_value, &signer).unwrap();
+
+    assert!(manager
+        .verify_and_store_rav(rav, signed_rav_with_wrong_aggregate, &[])
+        .await
+        .is_err());
+}
+
+#[rstest]
+#[tokio::test]
+async fn deny_rav_signed_by_foreign_key(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20u128).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+
+    // the RAV content is right, but it's signed with another key
+    let foreign_signer = PrivateKeySigner::random();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &foreign_signer).unwrap();
+    let err = manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        tap_core::Error::InvalidRecoveredSigner { address } if address == foreign_signer.address()
+    ));
+
+    // nothing was stored
+    let ravs: Vec<SignedRav> = manager.list_ravs(..).await.unwrap();
+    assert!(ravs.is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_create_multiple_rav_requests_all_valid_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut stored_signed_receipts = Vec::new();
+    let mut expected_accumulated_value = 0;
+    for _ in 0..10 {
+        let value = 20u128;
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        let query_id = signed_receipt.unique_hash();
+        stored_signed_receipts.push(signed_receipt.clone());
+        query_appraisals.write().unwrap().insert(query_id, value);
+        assert!(manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .is_ok());
+        expected_accumulated_value += value;
+    }
+    let rav_request_result = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await;
+    assert!(rav_request_result.is_ok());
+
+    let rav_request = rav_request_result.unwrap().unwrap();
+    // all receipts passing
+    assert_eq!(
+        rav_request.valid_receipts.len(),
+        stored_signed_receipts.len()
+    );
+    // no receipts failing
+    assert_eq!(rav_request.invalid_receipts.len(), 0);
+
+    let expected_rav = rav_request.expected_rav.unwrap();
+    // accumulated value is correct
+    assert_eq!(expected_rav.valueAggregate, expected_accumulated_value);
+    // no previous rav
+    assert!(rav_request.previous_rav.is_none());
+
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    assert!(manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
+        .await
+        .is_ok());
+
+    stored_signed_receipts.clear();
+    for _ in 10..20 {
+        let value = 20u128;
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+
+        let query_id = signed_receipt.unique_hash();
+        stored_signed_receipts.push(signed_receipt.clone());
+        query_appraisals.write().unwrap().insert(query_id, value);
+        assert!(manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .is_ok());
+        expected_accumulated_value += value;
+    }
+    let rav_request_result = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await;
+    assert!(rav_request_result.is_ok());
+
+    let rav_request = rav_request_result.unwrap().unwrap();
+    // all receipts passing
+    assert_eq!(
+        rav_request.valid_receipts.len(),
+        stored_signed_receipts.len()
+    );
+    // no receipts failing
+    assert_eq!(rav_request.invalid_receipts.len(), 0);
+
+    let expected_rav = rav_request.expected_rav.unwrap();
+    // accumulated value is correct
+    assert_eq!(expected_rav.valueAggregate, expected_accumulated_value);
+    // Verify there is a previous rav
+    assert!(rav_request.previous_rav.is_some());
+
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    assert!(manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
+        .await
+        .is_ok());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_list_ravs(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut signed_ravs = Vec::new();
+    for _ in 0..3 {
+        for _ in 0..5 {
+            let signed_receipt = Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], 20u128).unwrap(),
+                &signer,
+            )
+            .unwrap();
+            manager
+                .verify_and_store_receipt(&Context::new(), signed_receipt)
+                .await
+                .unwrap();
+        }
+        let rav_request = manager
+            .create_rav_request(&Context::new(), 0, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let expected_rav = rav_request.expected_rav.unwrap();
+        let signed_rav =
+            Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+        manager
+            .verify_and_store_rav(
+                expected_rav,
+                signed_rav.clone(),
+                &rav_request.included_receipt_ids,
+            )
+            .await
+            .unwrap();
+        signed_ravs.push(signed_rav);
+    }
+
+    // every RAV is kept, oldest first
+    let ravs: Vec<SignedRav> = manager.list_ravs(..).await.unwrap();
+    assert_eq!(ravs, signed_ravs);
+    assert_eq!(ravs[2].message.valueAggregate, 300);
+
+    let ravs: Vec<SignedRav> = manager
+        .list_ravs(signed_ravs[1].message.timestampNs..)
+        .await
+        .unwrap();
+    assert_eq!(ravs, signed_ravs[1..]);
+
+    let ravs: Vec<SignedRav> = manager
+        .list_ravs(..signed_ravs[0].message.timestampNs)
+        .await
+        .unwrap();
+    assert!(ravs.is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_finalize_allocation(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for _ in 0..5 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20u128).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    // left out of the final RAV of allocation_ids[0]
+    let other_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[1], 7u128).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), other_receipt)
+        .await
+        .unwrap();
+
+    // the allocation stays open if the final RAV is not stored
+    let err = manager
+        .finalize_allocation(&Context::new(), allocation_ids[0], |_| async {
+            Err::<SignedRav, _>(anyhow!("aggregator unavailable"))
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(err, tap_core::Error::AdapterError { .. }));
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20u128).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+
+    let aggregate = |rav_request: RavRequest<SignedReceipt, ReceiptAggregateVoucher>| {
+        let domain_separator = domain_separator.clone();
+        let signer = signer.clone();
+        let allocation_id = allocation_ids[0];
+        async move {
+            for receipt in &rav_request.valid_receipts {
+                assert_eq!(
+                    receipt.signed_receipt().message.allocation_id,
+                    allocation_id
+                );
+            }
+            Ok::<_, anyhow::Error>(Eip712SignedMessage::new(
+                &domain_separator,
+                rav_request.expected_rav?,
+                &signer,
+            )?)
+        }
+    };
+
+    let final_rav = manager
+        .finalize_allocation(&Context::new(), allocation_ids[0], aggregate)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(final_rav.message.valueAggregate, 120);
+    let ravs: Vec<SignedRav> = manager.list_ravs(..).await.unwrap();
+    assert_eq!(ravs, vec![final_rav.clone()]);
+
+    // receipts for the closed allocation are rejected
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20u128).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    let err = manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        tap_core::Error::ReceiptError(ReceiptError::CheckFailure(
+            ReceiptError::AllocationClosed {
+                allocation_id: allocation_ids[0]
+            }
+            .to_string()
+        ))
+        .to_string()
+    );
+
+    // nothing left to aggregate
+    assert!(manager
+        .finalize_allocation(&Context::new(), allocation_ids[0], aggregate)
+        .await
+        .unwrap()
+        .is_none());
+
+    // other allocations are not affected
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[1], 20u128).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    assert!(manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .is_ok());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_migrates_allocation(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut v1_receipts = Vec::new();
+    for value in 1..=5u128 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], value).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        v1_receipts.push(signed_receipt.message.clone());
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let aggregate = |rav_request: RavRequest<SignedReceipt, ReceiptAggregateVoucher>| {
+        let domain_separator = domain_separator.clone();
+        let signer = signer.clone();
+        async move {
+            Ok::<_, anyhow::Error>(Eip712SignedMessage::new(
+                &domain_separator,
+                rav_request.expected_rav?,
+                &signer,
+            )?)
+        }
+    };
+    let payer = Payer(Address::from([0xbbu8; 20]));
+    let data_service = DataService(Address::from([0xccu8; 20]));
+    let service_provider = ServiceProvider(Address::from([0xddu8; 20]));
+
+    let migration = manager
+        .migrate_allocation(
+            &Context::new(),
+            allocation_ids[0],
+            aggregate,
+            |receipt: &SignedReceipt| {
+                let receipt = tap_graph::v2::Receipt::from_v1(
+                    &receipt.message,
+                    payer,
+                    data_service,
+                    service_provider,
+                );
+                Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap()
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(migration.final_rav.unwrap().message.valueAggregate, 15);
+    let v2_receipts = migration.migrated_receipts;
+    assert_eq!(v2_receipts.len(), v1_receipts.len());
+    for v1_receipt in &v1_receipts {
+        let v2_receipt = v2_receipts
+            .iter()
+            .map(|receipt| &receipt.message)
+            .find(|receipt| receipt.nonce == v1_receipt.nonce)
+            .unwrap();
+        assert_eq!(v2_receipt.allocation_id, v1_receipt.allocation_id);
+        assert_eq!(v2_receipt.timestamp_ns, v1_receipt.timestamp_ns);
+        assert_eq!(v2_receipt.value, v1_receipt.value);
+        assert_eq!(v2_receipt.payer, payer.0);
+        assert_eq!(v2_receipt.data_service, data_service.0);
+        assert_eq!(v2_receipt.service_provider, service_provider.0);
+    }
+
+    // the v1 receipts were aggregated and deleted
+    let remaining = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert!(remaining.is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_serializes_rav_rounds_per_allocation(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context, checks)
+        .unwrap()
+        .with_max_concurrent_rav_requests(2);
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    // (previous RAV, signed RAV) of each round, in the order they are signed
+    let rounds = Arc::new(std::sync::Mutex::new(
+        Vec::<(Option<SignedRav>, SignedRav)>::new(),
+    ));
+    let round = |value: u128| {
+        let manager = &manager;
+        let domain_separator = domain_separator.clone();
+        let signer = signer.clone();
+        let rounds = rounds.clone();
+        let allocation_id = allocation_ids[0];
+        async move {
+            let signed_receipt = Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, value).unwrap(),
+                &signer,
+            )
+            .unwrap();
+            manager
+                .verify_and_store_receipt(&Context::new(), signed_receipt)
+                .await
+                .unwrap();
+
+            manager
+                .request_rav(
+                    &Context::new(),
+                    allocation_id,
+                    0,
+                    None,
+                    |rav_request| async move {
+                        // let the other rounds run while the aggregator is called
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        let signed_rav = Eip712SignedMessage::new(
+                            &domain_separator,
+                            rav_request.expected_rav?,
+                            &signer,
+                        )?;
+                        rounds
+                            .lock()
+                            .unwrap()
+                            .push((rav_request.previous_rav, signed_rav.clone()));
+                        Ok::<_, anyhow::Error>(signed_rav)
+                    },
+                )
+                .await
+                .unwrap()
+        }
+    };
+
+    futures_util::future::join_all((1..=10).map(round)).await;
+
+    // each round starts from the RAV of the previous one
+    let rounds = rounds.lock().unwrap().clone();
+    assert!(!rounds.is_empty());
+    assert_eq!(rounds[0].0, None);
+    for pair in rounds.windows(2) {
+        assert_eq!(pair[1].0.as_ref(), Some(&pair[0].1));
+        assert!(pair[1].1.message.valueAggregate > pair[0].1.message.valueAggregate);
+    }
+    let last_rav = &rounds.last().unwrap().1;
+    assert_eq!(last_rav.message.valueAggregate, (1..=10).sum::<u128>());
+    let signed_ravs: Vec<SignedRav> = rounds.iter().map(|(_, rav)| rav.clone()).collect();
+    let ravs: Vec<SignedRav> = manager.list_ravs(..).await.unwrap();
+    assert_eq!(ravs, signed_ravs);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_create_multiple_rav_requests_all_valid_receipts_consecutive_timestamps(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    #[values(true, false)] remove_old_receipts: bool,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let starting_min_timestamp = get_current_timestamp_u64_ns().unwrap() - 500000000;
+
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut stored_signed_receipts = Vec::new();
+    let mut expected_accumulated_value = 0;
+    for query_id in 0..10 {
+        let value = 20u128;
+        let mut receipt = Receipt::new(allocation_ids[0], value).unwrap();
+        receipt.timestamp_ns = starting_min_timestamp + query_id + 1;
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+
+        let query_id = signed_receipt.unique_hash();
+        stored_signed_receipts.push(signed_receipt.clone());
+        query_appraisals.write().unwrap().insert(query_id, value);
+        assert!(manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .is_ok());
+        expected_accumulated_value += value;
+    }
+
+    // Remove old receipts if requested
+    // This shouldn't do anything since there has been no rav created yet
+    if remove_old_receipts {
+        manager.remove_obsolete_receipts().await.unwrap();
+    }
+
+    let rav_request_1_result = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await;
+    assert!(rav_request_1_result.is_ok());
+
+    let rav_request_1 = rav_request_1_result.unwrap().unwrap();
+    // all receipts passing
+    assert_eq!(
+        rav_request_1.valid_receipts.len(),
+        stored_signed_receipts.len()
+    );
+    // no receipts failing
+    assert_eq!(rav_request_1.invalid_receipts.len(), 0);
+
+    let expected_rav_1 = rav_request_1.expected_rav.unwrap();
+    // accumulated value is correct
+    assert_eq!(expected_rav_1.valueAggregate, expected_accumulated_value);
+    // no previous rav
+    assert!(rav_request_1.previous_rav.is_none());
+
+    let signed_rav_1 =
+        Eip712SignedMessage::new(&domain_separator, expected_rav_1.clone(), &signer).unwrap();
+    assert!(manager
+        .verify_and_store_rav(
+            expected_rav_1,
+            signed_rav_1,
+            &rav_request_1.included_receipt_ids
+        )
+        .await
+        .is_ok());
+
+    stored_signed_receipts.clear();
+    for query_id in 10..20 {
+        let value = 20u128;
+        let mut receipt = Receipt::new(allocation_ids[0], value).unwrap();
+        receipt.timestamp_ns = starting_min_timestamp + query_id + 1;
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        let query_id = signed_receipt.unique_hash();
+        stored_signed_receipts.push(signed_receipt.clone());
+        query_appraisals.write().unwrap().insert(query_id, value);
+        assert!(manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .is_ok());
+        expected_accumulated_value += value;
+    }
+
+    // Remove old receipts if requested
+    if remove_old_receipts {
+        manager.remove_obsolete_receipts().await.unwrap();
+        // We expect to have 10 receipts left in receipt storage
+        assert_eq!(
+            context
+                .retrieve_receipts_in_timestamp_range(.., None)
+                .await
+                .unwrap()
+                .len(),
+            10
+        );
+    }
+
+    let rav_request_2_result = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await;
+    assert!(rav_request_2_result.is_ok());
+
+    let rav_request_2 = rav_request_2_result.unwrap().unwrap();
+    // all receipts passing
+    assert_eq!(
+        rav_request_2.valid_receipts.len(),
+        stored_signed_receipts.len()
+    );
+    // no receipts failing
+    assert_eq!(rav_request_2.invalid_receipts.len(), 0);
+
+    let expected_rav_2 = rav_request_2.expected_rav.unwrap();
+    // accumulated value is correct
+    assert_eq!(expected_rav_2.valueAggregate, expected_accumulated_value);
+    // Verify there is a previous rav
+    assert!(rav_request_2.previous_rav.is_some());
+
+    let signed_rav_2 =
+        Eip712SignedMessage::new(&domain_separator, expected_rav_2.clone(), &signer).unwrap();
+    assert!(manager
+        .verify_and_store_rav(
+            expected_rav_2,
+            signed_rav_2,
+            &rav_request_2.included_receipt_ids
+        )
+        .await
+        .is_ok());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_create_rav_and_ignore_invalid_receipts(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut stored_signed_receipts = Vec::new();
+    //Forcing all receipts but one to be invalid by making all the same
+    for _ in 0..10 {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: 1,
+            nonce: 1,
+            value: 20u128,
+        };
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        stored_signed_receipts.push(signed_receipt.clone());
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+
+    assert_eq!(rav_request.valid_receipts.len(), 1);
+    // All receipts but one being invalid
+    assert_eq!(rav_request.invalid_receipts.len(), 9);
+    //Rav Value corresponds only to value of one receipt
+    assert_eq!(expected_rav.valueAggregate, 20);
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_retryable_checks(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    struct RetryableCheck(Arc<AtomicBool>);
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for RetryableCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            receipt: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> CheckResult {
+            // we want to fail only if nonce is 5 and if is create rav step
+            if self.0.load(std::sync::atomic::Ordering::SeqCst)
+                && receipt.signed_receipt().message.nonce == 5
+            {
+                Err(CheckError::Retryable(anyhow!("Retryable error")))
+            } else {
+                Ok(CheckOutcome::passed())
+            }
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let is_create_rav = Arc::new(AtomicBool::new(false));
+
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(RetryableCheck(is_create_rav.clone())));
+
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    )
+    .unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut stored_signed_receipts = Vec::new();
+    for i in 0..10 {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: i + 1,
+            nonce: i,
+            value: 20u128,
+        };
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        stored_signed_receipts.push(signed_receipt.clone());
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    is_create_rav.store(true, std::sync::atomic::Ordering::SeqCst);
+
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await;
+
+    assert_eq!(
+        rav_request.expect_err("Didn't fail").to_string(),
+        tap_core::Error::ReceiptError(tap_core::receipt::ReceiptError::RetryableCheck(
+            "Retryable error".to_string()
+        ))
+        .to_string()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_create_rav_request_keeps_receipt_order(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    // Checks complete in reverse order of the nonce, and fail for odd nonces
+    struct SlowCheck(Arc<AtomicBool>);
+
+    #[async_trait::async_trait]
+    impl Check<SignedReceipt> for SlowCheck {
+        async fn check(
+            &self,
+            _: &Context,
+            receipt: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> CheckResult {
+            if !self.0.load(std::sync::atomic::Ordering::SeqCst) {
+                return Ok(CheckOutcome::passed());
+            }
+            let nonce = receipt.signed_receipt().message.nonce;
+            tokio::time::sleep(Duration::from_millis(20 - nonce)).await;
+            if nonce % 2 == 1 {
+                Err(CheckError::Failed(anyhow!("Odd nonce")))
+            } else {
+                Ok(CheckOutcome::passed())
+            }
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let is_create_rav = Arc::new(AtomicBool::new(false));
+
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(Arc::new(SlowCheck(is_create_rav.clone())));
+
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    )
+    .unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for i in 0..20 {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: i + 1,
+            nonce: i,
+            value: 20u128,
+        };
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    is_create_rav.store(true, std::sync::atomic::Ordering::SeqCst);
+
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // receipts are split in the order they are retrieved from storage
+    let stored_nonces: Vec<u64> = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap()
+        .iter()
+        .map(|(_, receipt)| receipt.signed_receipt().message.nonce)
+        .collect();
+    let valid_nonces: Vec<u64> = rav_request
+        .valid_receipts
+        .iter()
+        .map(|receipt| receipt.signed_receipt().message.nonce)
+        .collect();
+    let invalid_nonces: Vec<u64> = rav_request
+        .invalid_receipts
+        .iter()
+        .map(|receipt| receipt.signed_receipt().message.nonce)
+        .collect();
+    assert_eq!(
+        valid_nonces,
+        stored_nonces
+            .iter()
+            .copied()
+            .filter(|nonce| nonce % 2 == 0)
+            .collect::<Vec<_>>()
     );
-    // no receipts failing
-    assert_eq!(rav_request_1.invalid_receipts.len(), 0);
+    assert_eq!(
+        invalid_nonces,
+        stored_nonces
+            .iter()
+            .copied()
+            .filter(|nonce| nonce % 2 == 1)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_runs_stateful_checks_sequentially(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    // Records the maximum number of receipts checked at the same time
+    #[derive(Default)]
+    struct ConcurrencyCheck<const PARALLEL_SAFE: bool> {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl<const PARALLEL_SAFE: bool> Check<SignedReceipt> for ConcurrencyCheck<PARALLEL_SAFE> {
+        async fn check(
+            &self,
+            _: &Context,
+            _: &ReceiptWithState<Checking, SignedReceipt>,
+        ) -> CheckResult {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(CheckOutcome::passed())
+        }
+
+        fn is_parallel_safe(&self) -> bool {
+            PARALLEL_SAFE
+        }
+    }
+
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let parallel_check = Arc::new(ConcurrencyCheck::<true>::default());
+    let stateful_check = Arc::new(ConcurrencyCheck::<false>::default());
+    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
+        checks.iter().cloned().collect();
+    checks.push(stateful_check.clone());
+    checks.push(parallel_check.clone());
+
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context.clone(),
+        CheckList::new(checks),
+    )
+    .unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for i in 0..10 {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: i + 1,
+            nonce: i,
+            value: 20u128,
+        };
+        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+        manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+    }
+
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(rav_request.valid_receipts.len(), 10);
+    assert!(parallel_check.max_running.load(Ordering::SeqCst) > 1);
+    assert_eq!(stateful_check.max_running.load(Ordering::SeqCst), 1);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_health(domain_separator: Eip712Domain, context: ContextFixture) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator, context, checks).unwrap();
+    manager.health().await.unwrap();
+
+    // a writer panicking while holding the lock leaves the storage unusable
+    let _ = std::thread::spawn(move || {
+        let _guard = escrow_storage.write().unwrap();
+        panic!("writer crashed");
+    })
+    .join();
+
+    let err = manager.health().await.unwrap_err();
+    assert!(matches!(err, tap_core::Error::AdapterError { .. }));
+}
+
+#[rstest]
+fn manager_rejects_duplicate_checks(domain_separator: Eip712Domain, context: ContextFixture) {
+    let ContextFixture {
+        context, checks, ..
+    } = context;
+    let mut duplicated: Vec<_> = checks.to_vec();
+    duplicated.push(checks[0].clone());
+
+    let result = Manager::<_, SignedReceipt>::new(domain_separator, context, duplicated);
+    assert!(matches!(
+        result,
+        Err(CheckConfigError::DuplicateCheck { .. })
+    ));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_rav_request_included_receipt_ids(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let mut stored_ids = Vec::new();
+    for _ in 0..5 {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20u128).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        let receipt_id = manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .unwrap();
+        stored_ids.push(receipt_id);
+    }
+
+    let rav_request: RavRequest<SignedReceipt, ReceiptAggregateVoucher> = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut included_ids = rav_request.included_receipt_ids.clone();
+    included_ids.sort();
+    assert_eq!(included_ids, stored_ids);
+
+    // a receipt arriving after the RAV request is not removed
+    let newer_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20u128).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), newer_receipt.clone())
+        .await
+        .unwrap();
+
+    context
+        .clone()
+        .remove_receipts_by_ids(&rav_request.included_receipt_ids)
+        .await
+        .unwrap();
+    let remaining = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(*remaining[0].1.signed_receipt(), newer_receipt);
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_create_empty_rav_request(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+
+    // no receipts and no previous RAV
+    let rav_request: Option<RavRequest<SignedReceipt, ReceiptAggregateVoucher>> = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap();
+    assert!(rav_request.is_none());
+
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &signer,
+    )
+    .unwrap();
+    query_appraisals
+        .write()
+        .unwrap()
+        .insert(signed_receipt.unique_hash(), 20);
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
+        .await
+        .unwrap();
+
+    // no new receipts since the previous RAV
+    let rav_request: Option<RavRequest<SignedReceipt, ReceiptAggregateVoucher>> = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap();
+    assert!(rav_request.is_none());
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_paused_rejects_receipts_but_creates_ravs(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        query_appraisals,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let new_receipt = || {
+        let signed_receipt = Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_ids[0], 20).unwrap(),
+            &signer,
+        )
+        .unwrap();
+        query_appraisals
+            .write()
+            .unwrap()
+            .insert(signed_receipt.unique_hash(), 20);
+        signed_receipt
+    };
 
-    let expected_rav_1 = rav_request_1.expected_rav.unwrap();
-    // accumulated value is correct
-    assert_eq!(expected_rav_1.valueAggregate, expected_accumulated_value);
-    // no previous rav
-    assert!(rav_request_1.previous_rav.is_none());
+    assert!(manager.is_accepting());
+    manager
+        .verify_and_store_receipt(&Context::new(), new_receipt())
+        .await
+        .unwrap();
 
-    let signed_rav_1 =
-        Eip712SignedMessage::new(&domain_separator, expected_rav_1.clone(), &signer).unwrap();
-    assert!(manager
-        .verify_and_store_rav(expected_rav_1, signed_rav_1)
+    manager.set_accepting(false);
+    assert!(!manager.is_accepting());
+    let err = manager
+        .verify_and_store_receipt(&Context::new(), new_receipt())
         .await
-        .is_ok());
+        .unwrap_err();
+    assert!(matches!(err, tap_core::Error::NotAcceptingReceipts));
 
-    stored_signed_receipts.clear();
-    for query_id in 10..20 {
-        let value = 20u128;
-        let mut receipt = Receipt::new(allocation_ids[0], value).unwrap();
-        receipt.timestamp_ns = starting_min_timestamp + query_id + 1;
+    // RAVs can still be requested and stored while paused
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 1);
+    let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
+        .await
+        .unwrap();
+
+    manager.set_accepting(true);
+    manager
+        .verify_and_store_receipt(&Context::new(), new_receipt())
+        .await
+        .unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_create_rav_request_upto_timestamp(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    for i in 0..10 {
+        let receipt = Receipt {
+            allocation_id: allocation_ids[0],
+            timestamp_ns: i + 1,
+            nonce: i,
+            value: 20u128,
+        };
         let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
-        let query_id = signed_receipt.unique_hash();
-        stored_signed_receipts.push(signed_receipt.clone());
-        query_appraisals.write().unwrap().insert(query_id, value);
-        assert!(manager
+        manager
             .verify_and_store_receipt(&Context::new(), signed_receipt)
             .await
-            .is_ok());
-        expected_accumulated_value += value;
-    }
-
-    // Remove old receipts if requested
-    if remove_old_receipts {
-        manager.remove_obsolete_receipts().await.unwrap();
-        // We expect to have 10 receipts left in receipt storage
-        assert_eq!(
-            context
-                .retrieve_receipts_in_timestamp_range(.., None)
-                .await
-                .unwrap()
-                .len(),
-            10
-        );
+            .unwrap();
     }
 
-    let rav_request_2_result = manager.create_rav_request(&Context::new(), 0, None).await;
-    assert!(rav_request_2_result.is_ok());
-
-    let rav_request_2 = rav_request_2_result.unwrap();
-    // all receipts passing
-    assert_eq!(
-        rav_request_2.valid_receipts.len(),
-        stored_signed_receipts.len()
-    );
-    // no receipts failing
-    assert_eq!(rav_request_2.invalid_receipts.len(), 0);
+    // cut at the 5th receipt
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, Some(5))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 5);
+    let expected_rav = rav_request.expected_rav.unwrap();
+    assert_eq!(expected_rav.timestampNs, 5);
+    assert_eq!(expected_rav.valueAggregate, 100);
 
-    let expected_rav_2 = rav_request_2.expected_rav.unwrap();
-    // accumulated value is correct
-    assert_eq!(expected_rav_2.valueAggregate, expected_accumulated_value);
-    // Verify there is a previous rav
-    assert!(rav_request_2.previous_rav.is_some());
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
+    manager
+        .verify_and_store_rav(expected_rav, signed_rav, &rav_request.included_receipt_ids)
+        .await
+        .unwrap();
+    manager
+        .remove_obsolete_receipts::<ReceiptAggregateVoucher>()
+        .await
+        .unwrap();
 
-    let signed_rav_2 =
-        Eip712SignedMessage::new(&domain_separator, expected_rav_2.clone(), &signer).unwrap();
-    assert!(manager
-        .verify_and_store_rav(expected_rav_2, signed_rav_2)
+    // the later receipts are left for the next RAV
+    let rav_request = manager
+        .create_rav_request(&Context::new(), 0, None, None)
         .await
-        .is_ok());
+        .unwrap()
+        .unwrap();
+    assert_eq!(rav_request.valid_receipts.len(), 5);
+    let expected_rav = rav_request.expected_rav.unwrap();
+    assert_eq!(expected_rav.timestampNs, 10);
+    assert_eq!(expected_rav.valueAggregate, 200);
 }
 
 #[rstest]
 #[tokio::test]
-async fn manager_create_rav_and_ignore_invalid_receipts(
+async fn manager_commit_rav_and_delete_receipts_is_atomic(
     allocation_ids: Vec<Address>,
     domain_separator: Eip712Domain,
     context: ContextFixture,
@@ -498,25 +2313,20 @@ async fn manager_create_rav_and_ignore_invalid_receipts(
         signer,
         ..
     } = context;
-
-    let manager = Manager::new(domain_separator.clone(), context.clone(), checks);
-
+    let manager = Manager::new(domain_separator.clone(), context.clone(), checks).unwrap();
     escrow_storage
         .write()
         .unwrap()
         .insert(signer.address(), 999999);
 
-    let mut stored_signed_receipts = Vec::new();
-    //Forcing all receipts but one to be invalid by making all the same
-    for _ in 0..10 {
+    for i in 0..5 {
         let receipt = Receipt {
             allocation_id: allocation_ids[0],
-            timestamp_ns: 1,
-            nonce: 1,
+            timestamp_ns: i + 1,
+            nonce: i,
             value: 20u128,
         };
         let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
-        stored_signed_receipts.push(signed_receipt.clone());
         manager
             .verify_and_store_receipt(&Context::new(), signed_receipt)
             .await
@@ -524,95 +2334,257 @@ async fn manager_create_rav_and_ignore_invalid_receipts(
     }
 
     let rav_request = manager
-        .create_rav_request(&Context::new(), 0, None)
+        .create_rav_request(&Context::new(), 0, None, None)
         .await
+        .unwrap()
         .unwrap();
     let expected_rav = rav_request.expected_rav.unwrap();
+    let signed_rav =
+        Eip712SignedMessage::new(&domain_separator, expected_rav.clone(), &signer).unwrap();
 
-    assert_eq!(rav_request.valid_receipts.len(), 1);
-    // All receipts but one being invalid
-    assert_eq!(rav_request.invalid_receipts.len(), 9);
-    //Rav Value corresponds only to value of one receipt
-    assert_eq!(expected_rav.valueAggregate, 20);
+    // an unknown receipt id fails the commit, nothing is stored nor deleted
+    let mut receipt_ids = rav_request.included_receipt_ids.clone();
+    receipt_ids.push(u64::MAX);
+    assert!(manager
+        .verify_and_store_rav(expected_rav.clone(), signed_rav.clone(), &receipt_ids)
+        .await
+        .is_err());
+    assert!(context.last_rav().await.unwrap().is_none());
+    let stored_receipts = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert_eq!(stored_receipts.len(), 5);
+
+    // both the RAV is stored and its receipts are deleted
+    manager
+        .verify_and_store_rav(
+            expected_rav,
+            signed_rav.clone(),
+            &rav_request.included_receipt_ids,
+        )
+        .await
+        .unwrap();
+    assert_eq!(context.last_rav().await.unwrap(), Some(signed_rav));
+    let stored_receipts = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert!(stored_receipts.is_empty());
+}
+
+/// Counts the failures of each check
+#[derive(Default)]
+struct RecordingCheckMetrics(RwLock<HashMap<&'static str, u64>>);
+
+impl CheckMetrics for RecordingCheckMetrics {
+    fn record_failure(&self, check_name: &'static str) {
+        *self.0.write().unwrap().entry(check_name).or_default() += 1;
+    }
 }
 
 #[rstest]
 #[tokio::test]
-async fn test_retryable_checks(
+async fn manager_records_rejections_per_check(
     allocation_ids: Vec<Address>,
     domain_separator: Eip712Domain,
     context: ContextFixture,
 ) {
-    struct RetryableCheck(Arc<AtomicBool>);
+    let ContextFixture {
+        context,
+        checks,
+        escrow_storage,
+        signer,
+        ..
+    } = context;
+    let metrics = Arc::new(RecordingCheckMetrics::default());
+    let manager = Manager::new(
+        domain_separator.clone(),
+        context,
+        checks.with_metrics(metrics.clone()),
+    )
+    .unwrap();
+    escrow_storage
+        .write()
+        .unwrap()
+        .insert(signer.address(), 999999);
+
+    let receipt = Receipt::new(allocation_ids[0], 20).unwrap();
+    let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+    manager
+        .verify_and_store_receipt(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    assert!(metrics.0.read().unwrap().is_empty());
+
+    // signed by an unknown key
+    for _ in 0..2 {
+        let receipt = Receipt::new(allocation_ids[0], 20).unwrap();
+        let signed_receipt =
+            Eip712SignedMessage::new(&domain_separator, receipt, &PrivateKeySigner::random())
+                .unwrap();
+        assert!(manager
+            .verify_and_store_receipt(&Context::new(), signed_receipt)
+            .await
+            .is_err());
+    }
+    assert_eq!(
+        *metrics.0.read().unwrap(),
+        HashMap::from([(std::any::type_name::<SignerCheck>(), 2)])
+    );
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_reports_check_warnings(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    struct HighValueCheck;
 
     #[async_trait::async_trait]
-    impl Check<SignedReceipt> for RetryableCheck {
+    impl Check<SignedReceipt> for HighValueCheck {
         async fn check(
             &self,
             _: &Context,
             receipt: &ReceiptWithState<Checking, SignedReceipt>,
-        ) -> Result<(), CheckError> {
-            // we want to fail only if nonce is 5 and if is create rav step
-            if self.0.load(std::sync::atomic::Ordering::SeqCst)
-                && receipt.signed_receipt().message.nonce == 5
-            {
-                Err(CheckError::Retryable(anyhow!("Retryable error")))
-            } else {
-                Ok(())
+        ) -> CheckResult {
+            let value = receipt.signed_receipt().message.value;
+            if value > 100 {
+                return Ok(CheckOutcome::warning(format!(
+                    "unusually high value {value}"
+                )));
             }
+            Ok(CheckOutcome::passed())
+        }
+    }
+
+    #[derive(Default)]
+    struct WarningMetrics(AtomicUsize);
+
+    impl CheckMetrics for WarningMetrics {
+        fn record_failure(&self, _: &'static str) {}
+
+        fn record_warning(&self, check_name: &'static str) {
+            assert_eq!(check_name, std::any::type_name::<HighValueCheck>());
+            self.0.fetch_add(1, Ordering::SeqCst);
         }
     }
 
     let ContextFixture {
         context,
-        checks,
+        mut checks,
         escrow_storage,
         signer,
         ..
     } = context;
-
-    let is_create_rav = Arc::new(AtomicBool::new(false));
-
-    let mut checks: Vec<Arc<dyn Check<SignedReceipt> + Send + Sync>> =
-        checks.iter().cloned().collect();
-    checks.push(Arc::new(RetryableCheck(is_create_rav.clone())));
-
+    checks.extend(vec![Arc::new(HighValueCheck)]);
+    let metrics = Arc::new(WarningMetrics::default());
     let manager = Manager::new(
         domain_separator.clone(),
         context.clone(),
-        CheckList::new(checks),
-    );
-
+        checks.with_metrics(metrics.clone()),
+    )
+    .unwrap();
     escrow_storage
         .write()
         .unwrap()
         .insert(signer.address(), 999999);
 
-    let mut stored_signed_receipts = Vec::new();
-    for i in 0..10 {
-        let receipt = Receipt {
-            allocation_id: allocation_ids[0],
-            timestamp_ns: i + 1,
-            nonce: i,
-            value: 20u128,
-        };
-        let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
-        stored_signed_receipts.push(signed_receipt.clone());
-        manager
-            .verify_and_store_receipt(&Context::new(), signed_receipt)
-            .await
-            .unwrap();
-    }
-
-    is_create_rav.store(true, std::sync::atomic::Ordering::SeqCst);
-
-    let rav_request = manager.create_rav_request(&Context::new(), 0, None).await;
+    let receipt = Receipt::new(allocation_ids[0], 20).unwrap();
+    let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+    let stored = manager
+        .verify_and_store_receipt_with_warnings(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    assert!(stored.warnings.is_empty());
 
+    let receipt = Receipt::new(allocation_ids[0], 500).unwrap();
+    let signed_receipt = Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap();
+    let stored = manager
+        .verify_and_store_receipt_with_warnings(&Context::new(), signed_receipt)
+        .await
+        .unwrap();
+    assert_eq!(stored.warnings.len(), 1);
     assert_eq!(
-        rav_request.expect_err("Didn't fail").to_string(),
-        tap_core::Error::ReceiptError(tap_core::receipt::ReceiptError::RetryableCheck(
-            "Retryable error".to_string()
-        ))
-        .to_string()
+        stored.warnings[0].check,
+        std::any::type_name::<HighValueCheck>()
     );
+    assert_eq!(stored.warnings[0].message, "unusually high value 500");
+    assert_eq!(metrics.0.load(Ordering::SeqCst), 1);
+
+    // the receipt raising the warning is stored
+    let received_receipts = context
+        .retrieve_receipts_in_timestamp_range(.., None)
+        .await
+        .unwrap();
+    assert_eq!(received_receipts.len(), 2);
+    assert!(received_receipts
+        .iter()
+        .any(|receipt| receipt.signed_receipt().message.value == 500));
+}
+
+#[rstest]
+#[tokio::test]
+async fn manager_verify_ravs_batch(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture {
+        context,
+        checks,
+        signer,
+        ..
+    } = context;
+    let manager = Manager::new(domain_separator.clone(), context, checks).unwrap();
+    let rav = |value_aggregate, signer: &PrivateKeySigner| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_ids[0],
+                timestampNs: 42,
+                valueAggregate: value_aggregate,
+            },
+            signer,
+        )
+        .unwrap()
+    };
+
+    let valid_rav = rav(100, &signer);
+    // value changed after signing, attributed to another signer
+    let mut tampered_rav = rav(100, &signer);
+    tampered_rav.message.valueAggregate = 1_000_000;
+    let unknown_signer_rav = rav(100, &PrivateKeySigner::random());
+    let invalid_signature_rav = SignedRav {
+        signature: PrimitiveSignature::new(U256::ZERO, U256::ZERO, false),
+        ..rav(100, &signer)
+    };
+
+    let results = manager
+        .verify_ravs_batch(&[
+            valid_rav.clone(),
+            tampered_rav,
+            unknown_signer_rav,
+            invalid_signature_rav,
+            valid_rav,
+        ])
+        .await;
+
+    assert_eq!(results.len(), 5);
+    assert_eq!(*results[0].as_ref().unwrap(), signer.address());
+    assert!(matches!(
+        results[1],
+        Err(tap_core::Error::InvalidRecoveredSigner { .. })
+    ));
+    assert!(matches!(
+        results[2],
+        Err(tap_core::Error::InvalidRecoveredSigner { .. })
+    ));
+    assert!(matches!(
+        results[3],
+        Err(tap_core::Error::SignatureError(_))
+    ));
+    assert_eq!(*results[4].as_ref().unwrap(), signer.address());
 }
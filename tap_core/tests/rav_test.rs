@@ -24,6 +24,7 @@ use tap_core::{
     tap_eip712_domain,
 };
 use tap_graph::{Receipt, ReceiptAggregateVoucher};
+use tokio::sync::Barrier;
 
 #[fixture]
 fn domain_separator() -> Eip712Domain {
@@ -33,7 +34,7 @@ fn domain_separator() -> Eip712Domain {
 #[fixture]
 fn context() -> InMemoryContext {
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(Vec::new()));
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
 
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
@@ -152,3 +153,66 @@ async fn rav_storage_adapter_test(domain_separator: Eip712Domain, context: InMem
     let retrieved_rav = context.last_rav().await;
     assert!(retrieved_rav.unwrap().unwrap() == signed_rav);
 }
+
+#[rstest]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn rav_storage_update_if_newer_test(domain_separator: Eip712Domain) {
+    let rav_storage = Arc::new(RwLock::new(Vec::new()));
+    let context = InMemoryContext::new(
+        rav_storage.clone(),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(StatefulTimestampCheck::new(0)),
+    );
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let rav = |timestamp_ns| {
+        Eip712SignedMessage::new(
+            &domain_separator,
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                timestampNs: timestamp_ns,
+                valueAggregate: timestamp_ns as u128,
+            },
+            &wallet,
+        )
+        .unwrap()
+    };
+
+    // several writers on different threads store RAVs at the same time, in
+    // no particular order
+    let timestamps = [3, 7, 1, 9, 4, 8, 2, 6, 5, 10];
+    let barrier = Arc::new(Barrier::new(timestamps.len()));
+    let handles: Vec<_> = timestamps
+        .into_iter()
+        .map(|timestamp_ns| {
+            let (context, barrier) = (context.clone(), barrier.clone());
+            let rav = rav(timestamp_ns);
+            tokio::spawn(async move {
+                barrier.wait().await;
+                context.update_last_rav_if_newer(rav).await.unwrap()
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    // a stale RAV was never stored after a newer one
+    let timestamps: Vec<_> = rav_storage
+        .read()
+        .unwrap()
+        .iter()
+        .map(|rav| rav.message.timestampNs)
+        .collect();
+    assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+    let last_rav = context.last_rav().await.unwrap().unwrap();
+    assert_eq!(last_rav.message.timestampNs, 10);
+
+    // RAVs not newer than the last one are not stored
+    assert!(!context.update_last_rav_if_newer(rav(10)).await.unwrap());
+    assert!(!context.update_last_rav_if_newer(rav(5)).await.unwrap());
+    assert!(context.update_last_rav_if_newer(rav(11)).await.unwrap());
+    let last_rav = context.last_rav().await.unwrap().unwrap();
+    assert_eq!(last_rav.message.timestampNs, 11);
+}
@@ -11,9 +11,12 @@ use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateK
 use rand::{seq::SliceRandom, thread_rng};
 use rstest::*;
 use tap_core::{
-    manager::{adapters::ReceiptStore, context::memory::InMemoryContext},
+    manager::{
+        adapters::{ReceiptRead, ReceiptStore},
+        context::memory::{InMemoryContext, InMemoryError},
+    },
     receipt::{checks::StatefulTimestampCheck, state::Checking, ReceiptWithState},
-    signed_message::Eip712SignedMessage,
+    signed_message::{Eip712Error, Eip712SignedMessage, SignatureScheme},
     tap_eip712_domain,
 };
 use tap_graph::{Receipt, SignedReceipt};
@@ -26,7 +29,7 @@ fn domain_separator() -> Eip712Domain {
 #[fixture]
 fn context() -> InMemoryContext {
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(Vec::new()));
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
 
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
@@ -144,6 +147,53 @@ async fn multi_receipt_adapter_test(domain_separator: Eip712Domain, mut context:
     }
 }
 
+#[rstest]
+#[tokio::test]
+async fn receipt_adapter_storage_full_test(
+    domain_separator: Eip712Domain,
+    context: InMemoryContext,
+) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+
+    let max_stored_receipts = 5;
+    let mut context = context.with_max_stored_receipts(max_stored_receipts);
+
+    // Fill the storage up to the cap
+    let mut receipt_ids = Vec::new();
+    for value in 0..max_stored_receipts as u128 {
+        let received_receipt = ReceiptWithState::new(
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, value).unwrap(),
+                &wallet,
+            )
+            .unwrap(),
+        );
+        receipt_ids.push(context.store_receipt(received_receipt).await.unwrap());
+    }
+
+    // One more receipt should be rejected
+    let received_receipt = ReceiptWithState::new(
+        Eip712SignedMessage::new(
+            &domain_separator,
+            Receipt::new(allocation_id, 100).unwrap(),
+            &wallet,
+        )
+        .unwrap(),
+    );
+    assert!(matches!(
+        context.store_receipt(received_receipt.clone()).await,
+        Err(InMemoryError::StorageFull {
+            max_stored_receipts: 5
+        })
+    ));
+
+    // Freeing up space allows storing again
+    context.remove_receipt_by_id(receipt_ids[0]).await.unwrap();
+    assert!(context.store_receipt(received_receipt).await.is_ok());
+}
+
 /// The test code will shuffle the input timestamps prior to calling safe_truncate_receipts.
 #[rstest]
 #[case(vec![1, 2, 3, 4, 5], 3, vec![1, 2, 3])]
@@ -196,3 +246,96 @@ fn safe_truncate_receipts_test(
         );
     }
 }
+
+/// Same cases as `safe_truncate_receipts_test`, retrieving the receipts from an
+/// [`InMemoryContext`] along with their ids.
+#[rstest]
+#[case(vec![1, 2, 3, 4, 5], 3, vec![1, 2, 3])]
+#[case(vec![1, 2, 3, 3, 4, 5], 3, vec![1, 2])]
+#[case(vec![1, 2, 3, 4, 4, 4], 3, vec![1, 2, 3])]
+#[case(vec![1, 1, 1, 1, 2, 3], 3, vec![])]
+#[case(vec![1, 1, 2, 2, 3], 4, vec![1, 1, 2, 2])]
+#[tokio::test]
+async fn in_memory_retrieve_receipts_with_limit(
+    domain_separator: Eip712Domain,
+    mut context: InMemoryContext,
+    #[case] input: Vec<u64>,
+    #[case] limit: u64,
+    #[case] expected: Vec<u64>,
+) {
+    let wallet = PrivateKeySigner::random();
+
+    // (id, timestamp) of the stored receipts
+    let mut stored = Vec::new();
+    for (nonce, timestamp) in input.iter().enumerate() {
+        let receipt_id = context
+            .store_receipt(ReceiptWithState::new(
+                Eip712SignedMessage::new(
+                    &domain_separator,
+                    Receipt {
+                        allocation_id: Address::ZERO,
+                        timestamp_ns: *timestamp,
+                        nonce: nonce as u64,
+                        value: 0,
+                    },
+                    &wallet,
+                )
+                .unwrap(),
+            ))
+            .await
+            .unwrap();
+        stored.push((receipt_id, *timestamp));
+    }
+
+    let retrieved: Vec<(u64, u64)> = context
+        .retrieve_receipts_in_timestamp_range(.., Some(limit))
+        .await
+        .unwrap()
+        .iter()
+        .map(|(id, receipt)| (*id, receipt.signed_receipt().message.timestamp_ns))
+        .collect();
+
+    stored.sort_unstable_by_key(|&(id, timestamp)| (timestamp, id));
+    stored.truncate(expected.len());
+    assert_eq!(retrieved, stored);
+    let timestamps: Vec<u64> = retrieved.iter().map(|(_, timestamp)| *timestamp).collect();
+    assert_eq!(timestamps, expected);
+}
+
+#[rstest]
+fn signature_scheme_round_trip(domain_separator: Eip712Domain) {
+    let wallet = PrivateKeySigner::random();
+    let allocation_id = Address::from_str("0xabababababababababababababababababababab").unwrap();
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_id, 100).unwrap(),
+        &wallet,
+    )
+    .unwrap();
+    assert_eq!(
+        signed_receipt.signature_scheme,
+        SignatureScheme::EcdsaSecp256k1
+    );
+
+    // the default scheme is left out, keeping the serialization unchanged
+    let json = serde_json::to_value(&signed_receipt).unwrap();
+    assert!(json.get("signature_scheme").is_none());
+    let deserialized: SignedReceipt = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(deserialized, signed_receipt);
+    assert_eq!(
+        deserialized.recover_signer(&domain_separator).unwrap(),
+        wallet.address()
+    );
+
+    // schemes unknown to this version cannot be verified
+    let mut json = json;
+    json["signature_scheme"] = "some_future_scheme".into();
+    let deserialized: SignedReceipt = serde_json::from_value(json).unwrap();
+    assert_eq!(deserialized.signature_scheme, SignatureScheme::Unknown);
+    assert!(matches!(
+        deserialized.recover_signer(&domain_separator),
+        Err(Eip712Error::UnsupportedSignatureScheme(
+            SignatureScheme::Unknown
+        ))
+    ));
+}
@@ -7,13 +7,17 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{Address, PrimitiveSignature, U256},
+    signers::local::PrivateKeySigner,
+};
 use rstest::*;
 use tap_core::{
     manager::context::memory::{checks::get_full_list_of_checks, EscrowStorage, QueryAppraisals},
     receipt::{
         checks::{ReceiptCheck, StatefulTimestampCheck},
-        Context, ReceiptWithState,
+        Context, ReceiptError, ReceiptWithState, RecoveredSigner,
     },
     signed_message::Eip712SignedMessage,
     tap_eip712_domain,
@@ -224,3 +228,143 @@ async fn standard_lifetime_valid_receipt(
     let checked_receipt = awaiting_escrow_receipt.unwrap();
     assert!(checked_receipt.is_ok());
 }
+
+#[rstest]
+#[tokio::test]
+async fn pre_recovered_signer_skips_recovery(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture { checks, signer, .. } = context;
+
+    let signed_receipts: Vec<_> = (0..2)
+        .map(|_| {
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_ids[0], 20).unwrap(),
+                &signer,
+            )
+            .unwrap()
+        })
+        .collect();
+
+    // no signer can be recovered from this signature
+    let unrecoverable_receipt = SignedReceipt {
+        signature: PrimitiveSignature::new(U256::ZERO, U256::ZERO, false),
+        ..signed_receipts[0].clone()
+    };
+    let result = ReceiptWithState::new(unrecoverable_receipt.clone())
+        .perform_checks(&Context::new(), &checks)
+        .await;
+    assert!(matches!(result, Err(ReceiptError::InvalidSignature { .. })));
+
+    // so it only passes if the pre-recovered signer is used instead
+    let mut ctx = Context::new();
+    ctx.insert(RecoveredSigner::new(
+        &unrecoverable_receipt,
+        signer.address(),
+    ));
+    let result = ReceiptWithState::new(unrecoverable_receipt)
+        .perform_checks(&ctx, &checks)
+        .await;
+    assert!(result.is_ok());
+
+    // the pre-recovered signer is trusted as is
+    let mut ctx = Context::new();
+    ctx.insert(RecoveredSigner::new(&signed_receipts[0], Address::ZERO));
+    let result = ReceiptWithState::new(signed_receipts[0].clone())
+        .perform_checks(&ctx, &checks)
+        .await;
+    assert!(matches!(result, Err(ReceiptError::InvalidSignature { .. })));
+
+    // but ignored for other receipts
+    let result = ReceiptWithState::new(signed_receipts[1].clone())
+        .perform_checks(&ctx, &checks)
+        .await;
+    assert!(result.is_ok());
+}
+
+#[rstest]
+#[tokio::test]
+async fn failed_receipt_reports_failing_check(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture { checks, .. } = context;
+
+    // signed by a sender that is not accepted
+    let signed_receipt = Eip712SignedMessage::new(
+        &domain_separator,
+        Receipt::new(allocation_ids[0], 20).unwrap(),
+        &PrivateKeySigner::random(),
+    )
+    .unwrap();
+
+    let failed_receipt = ReceiptWithState::new(signed_receipt)
+        .finalize_receipt_checks(&Context::new(), &checks)
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    let failed_checks = failed_receipt.failed_checks();
+    assert_eq!(failed_checks.len(), 1);
+    assert!(failed_checks[0].ends_with("::SignerCheck"));
+
+    let errors = failed_receipt.errors();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].to_string().contains("Invalid signer"));
+}
+
+#[rstest]
+#[tokio::test]
+async fn batch_checks_keep_receipt_order_and_failing_check(
+    allocation_ids: Vec<Address>,
+    domain_separator: Eip712Domain,
+    context: ContextFixture,
+) {
+    let ContextFixture { checks, signer, .. } = context;
+    let foreign_signer = PrivateKeySigner::random();
+    let unknown_allocation_id = Address::from([0x22u8; 20]);
+
+    let receipt = |allocation_id, signer: &PrivateKeySigner| {
+        ReceiptWithState::new(
+            Eip712SignedMessage::new(
+                &domain_separator,
+                Receipt::new(allocation_id, 20).unwrap(),
+                signer,
+            )
+            .unwrap(),
+        )
+    };
+    let receipts = vec![
+        receipt(allocation_ids[0], &signer),
+        receipt(allocation_ids[0], &foreign_signer),
+        // fails both checks, the allocation is checked first
+        receipt(unknown_allocation_id, &foreign_signer),
+        receipt(allocation_ids[1], &signer),
+    ];
+    let expected_receipts: Vec<_> = receipts
+        .iter()
+        .map(|receipt| receipt.signed_receipt().clone())
+        .collect();
+
+    let results =
+        ReceiptWithState::perform_checks_or_fail_batch(&Context::new(), receipts, &checks)
+            .await
+            .unwrap();
+
+    assert_eq!(results.len(), expected_receipts.len());
+    for (result, expected_receipt) in results.iter().zip(&expected_receipts) {
+        let signed_receipt = match result {
+            Ok(receipt) => receipt.signed_receipt(),
+            Err(receipt) => receipt.signed_receipt(),
+        };
+        assert_eq!(signed_receipt, expected_receipt);
+    }
+    assert!(results[0].is_ok());
+    assert!(results[1].as_ref().unwrap_err().failed_checks()[0].ends_with("::SignerCheck"));
+    assert!(results[2].as_ref().unwrap_err().failed_checks()[0].ends_with("::AllocationIdCheck"));
+    assert!(results[3].is_ok());
+}
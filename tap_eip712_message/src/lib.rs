@@ -22,10 +22,12 @@
 //! ```
 //!
 
+use std::collections::HashSet;
+
 use alloy::{
     dyn_abi::Eip712Domain,
-    primitives::{Address, PrimitiveSignature as Signature},
-    signers::{local::PrivateKeySigner, SignerSync},
+    primitives::{keccak256, Address, PrimitiveSignature as Signature, B256},
+    signers::{local::PrivateKeySigner, Signer, SignerSync},
     sol_types::SolStruct,
 };
 use serde::{Deserialize, Serialize};
@@ -40,6 +42,36 @@ pub enum Eip712Error {
     /// `alloy` signature error
     #[error(transparent)]
     SignatureError(#[from] alloy::primitives::SignatureError),
+
+    /// The signature uses a scheme not supported by this version
+    #[error("Unsupported signature scheme: {0:?}")]
+    UnsupportedSignatureScheme(SignatureScheme),
+
+    /// The recovered signer is not one of the accepted signers
+    #[error("Recovered signer {address} is not accepted")]
+    SignerNotAccepted { address: Address },
+}
+
+/// Scheme used to sign a [`Eip712SignedMessage`].
+///
+/// Only ECDSA over secp256k1 is supported for now. Tagging the messages lets
+/// other schemes be negotiated later without changing the message format.
+/// Messages serialized without a scheme use the default one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureScheme {
+    /// ECDSA over secp256k1, as used by Ethereum accounts
+    #[default]
+    EcdsaSecp256k1,
+    /// Scheme unknown to this version of the library
+    #[serde(other)]
+    Unknown,
+}
+
+impl SignatureScheme {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 /// EIP712 signed message
@@ -49,6 +81,9 @@ pub struct Eip712SignedMessage<M: SolStruct> {
     pub message: M,
     /// ECDSA Signature of eip712 hash of message
     pub signature: Signature,
+    /// Scheme of `signature`
+    #[serde(default, skip_serializing_if = "SignatureScheme::is_default")]
+    pub signature_scheme: SignatureScheme,
 }
 
 /// Signature that can be used in a HashSet
@@ -77,9 +112,84 @@ impl SignatureBytesExt for Signature {
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub struct MessageId(pub [u8; 32]);
 
+/// EIP712 hashes of a message, computed in a single pass.
+///
+/// The signing hash is derived from the struct hash, so computing both
+/// together hashes the message once instead of twice when calling
+/// [`Eip712SignedMessage::recover_signer`] and
+/// [`Eip712SignedMessage::unique_hash`].
+///
+/// # Example
+/// ```rust
+/// # use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+/// # let domain_separator = Eip712Domain::default();
+/// use tap_eip712_message::Eip712SignedMessage;
+/// # let wallet = PrivateKeySigner::random();
+/// # let message = msg::Receipt::new(Address::from([0x11u8; 20]), 100).unwrap();
+///
+/// let signed_message = Eip712SignedMessage::new(&domain_separator, message, &wallet).unwrap();
+/// let hashes = signed_message.computed_hashes(&domain_separator);
+///
+/// assert_eq!(hashes.message_id(), signed_message.unique_hash());
+/// assert_eq!(
+///     signed_message.recover_signer_with_hashes(&hashes).unwrap(),
+///     signed_message.recover_signer(&domain_separator).unwrap(),
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputedHashes {
+    /// EIP712 `hashStruct` of the message
+    pub struct_hash: B256,
+    /// EIP712 hash signed by the sender of the message
+    pub signing_hash: B256,
+}
+
+impl ComputedHashes {
+    pub fn new<M: SolStruct>(domain_separator: &Eip712Domain, message: &M) -> Self {
+        Self::with_domain_hash(&domain_separator.hash_struct(), message)
+    }
+
+    /// Computes the hashes of several messages signed under the same domain,
+    /// hashing the domain separator once for all of them.
+    pub fn batch<'a, M: SolStruct + 'a>(
+        domain_separator: &Eip712Domain,
+        messages: impl IntoIterator<Item = &'a M>,
+    ) -> Vec<Self> {
+        let domain_hash = domain_separator.hash_struct();
+        messages
+            .into_iter()
+            .map(|message| Self::with_domain_hash(&domain_hash, message))
+            .collect()
+    }
+
+    fn with_domain_hash<M: SolStruct>(domain_hash: &B256, message: &M) -> Self {
+        let struct_hash = message.eip712_hash_struct();
+
+        // keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct(message))
+        let mut digest_input = [0u8; 2 + 32 + 32];
+        digest_input[0] = 0x19;
+        digest_input[1] = 0x01;
+        digest_input[2..34].copy_from_slice(domain_hash.as_slice());
+        digest_input[34..66].copy_from_slice(struct_hash.as_slice());
+
+        Self {
+            struct_hash,
+            signing_hash: keccak256(digest_input),
+        }
+    }
+
+    /// Same as [`Eip712SignedMessage::unique_hash`]
+    pub fn message_id(&self) -> MessageId {
+        MessageId(self.struct_hash.into())
+    }
+}
+
 impl<M: SolStruct> Eip712SignedMessage<M> {
     /// Creates a signed message with signed EIP712 hash of `message` using `signing_wallet`
     ///
+    /// Signatures are deterministic (RFC 6979): the same message signed with
+    /// the same wallet under the same domain always has the same signature.
+    ///
     /// # Errors
     ///
     /// Returns [`crate::Error::WalletError`] if could not sign using the wallet
@@ -89,20 +199,156 @@ impl<M: SolStruct> Eip712SignedMessage<M> {
         message: M,
         signing_wallet: &PrivateKeySigner,
     ) -> Result<Self, Eip712Error> {
-        let recovery_message_hash = message.eip712_signing_hash(domain_separator);
+        let (signed_message, _) = Self::new_with_hashes(domain_separator, message, signing_wallet)?;
+        Ok(signed_message)
+    }
+
+    /// Same as [`Eip712SignedMessage::new`], signing with an asynchronous
+    /// `signer`, e.g. a remote signer or a hardware wallet. The future does
+    /// not depend on a specific async runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`crate::Error::WalletError`] if could not sign using the signer
+    ///
+    pub async fn new_async<S: Signer + ?Sized>(
+        domain_separator: &Eip712Domain,
+        message: M,
+        signer: &S,
+    ) -> Result<Self, Eip712Error> {
+        let hashes = ComputedHashes::new(domain_separator, &message);
+        let signature = signer.sign_hash(&hashes.signing_hash).await?;
+
+        Ok(Self {
+            message,
+            signature,
+            signature_scheme: SignatureScheme::EcdsaSecp256k1,
+        })
+    }
+
+    /// Same as [`Eip712SignedMessage::new`], also returning the hashes
+    /// computed to sign the message, so that e.g. its
+    /// [`Eip712SignedMessage::unique_hash`] can be read from
+    /// [`ComputedHashes::message_id`] without hashing the message again.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`crate::Error::WalletError`] if could not sign using the wallet
+    ///
+    pub fn new_with_hashes(
+        domain_separator: &Eip712Domain,
+        message: M,
+        signing_wallet: &PrivateKeySigner,
+    ) -> Result<(Self, ComputedHashes), Eip712Error> {
+        let hashes = ComputedHashes::new(domain_separator, &message);
+        Ok((Self::sign(message, &hashes, signing_wallet)?, hashes))
+    }
 
-        let signature = signing_wallet.sign_hash_sync(&recovery_message_hash)?;
+    /// Signs each of `messages` with `signing_wallet`, returning them with
+    /// their hashes. The domain separator is hashed once for all of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`crate::Error::WalletError`] if could not sign using the wallet
+    ///
+    pub fn new_batch(
+        domain_separator: &Eip712Domain,
+        messages: Vec<M>,
+        signing_wallet: &PrivateKeySigner,
+    ) -> Result<Vec<(Self, ComputedHashes)>, Eip712Error> {
+        let hashes = ComputedHashes::batch(domain_separator, &messages);
+        messages
+            .into_iter()
+            .zip(hashes)
+            .map(|(message, hashes)| Ok((Self::sign(message, &hashes, signing_wallet)?, hashes)))
+            .collect()
+    }
+
+    fn sign(
+        message: M,
+        hashes: &ComputedHashes,
+        signing_wallet: &PrivateKeySigner,
+    ) -> Result<Self, Eip712Error> {
+        let signature = signing_wallet.sign_hash_sync(&hashes.signing_hash)?;
 
-        Ok(Self { message, signature })
+        Ok(Self {
+            message,
+            signature,
+            signature_scheme: SignatureScheme::EcdsaSecp256k1,
+        })
     }
 
     /// Recovers and returns the signer of the message from the signature.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::UnsupportedSignatureScheme`] if the message is
+    /// not signed with [`SignatureScheme::EcdsaSecp256k1`].
     pub fn recover_signer(&self, domain_separator: &Eip712Domain) -> Result<Address, Eip712Error> {
         let recovery_message_hash = self.message.eip712_signing_hash(domain_separator);
-        let recovered_address = self
-            .signature
-            .recover_address_from_prehash(&recovery_message_hash)?;
-        Ok(recovered_address)
+        self.recover_signer_from_prehash(&recovery_message_hash)
+    }
+
+    /// Recovers the signer of the message, checking that it is one of
+    /// `accepted_signers`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::SignerNotAccepted`] if the recovered signer is
+    /// not in `accepted_signers`, or the errors of
+    /// [`Eip712SignedMessage::recover_signer`].
+    pub fn recover_if_accepted(
+        &self,
+        domain_separator: &Eip712Domain,
+        accepted_signers: &HashSet<Address>,
+    ) -> Result<Address, Eip712Error> {
+        self.recover_if_accepted_with_hashes(
+            &self.computed_hashes(domain_separator),
+            accepted_signers,
+        )
+    }
+
+    /// Same as [`Eip712SignedMessage::recover_if_accepted`], reusing hashes
+    /// computed with [`Eip712SignedMessage::computed_hashes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Eip712Error::SignerNotAccepted`] if the recovered signer is
+    /// not in `accepted_signers`, or the errors of
+    /// [`Eip712SignedMessage::recover_signer_with_hashes`].
+    pub fn recover_if_accepted_with_hashes(
+        &self,
+        hashes: &ComputedHashes,
+        accepted_signers: &HashSet<Address>,
+    ) -> Result<Address, Eip712Error> {
+        let address = self.recover_signer_with_hashes(hashes)?;
+        if !accepted_signers.contains(&address) {
+            return Err(Eip712Error::SignerNotAccepted { address });
+        }
+        Ok(address)
+    }
+
+    /// Computes the signing hash and the struct hash of the message at once.
+    pub fn computed_hashes(&self, domain_separator: &Eip712Domain) -> ComputedHashes {
+        ComputedHashes::new(domain_separator, &self.message)
+    }
+
+    /// Recovers and returns the signer of the message from the signature,
+    /// reusing hashes computed with [`Eip712SignedMessage::computed_hashes`].
+    pub fn recover_signer_with_hashes(
+        &self,
+        hashes: &ComputedHashes,
+    ) -> Result<Address, Eip712Error> {
+        self.recover_signer_from_prehash(&hashes.signing_hash)
+    }
+
+    fn recover_signer_from_prehash(&self, prehash: &B256) -> Result<Address, Eip712Error> {
+        match self.signature_scheme {
+            SignatureScheme::EcdsaSecp256k1 => {
+                Ok(self.signature.recover_address_from_prehash(prehash)?)
+            }
+            scheme => Err(Eip712Error::UnsupportedSignatureScheme(scheme)),
+        }
     }
 
     /// Checks that receipts signature is valid for given verifying key, returns `Ok(true)` if it is valid.
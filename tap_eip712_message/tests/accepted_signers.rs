@@ -0,0 +1,72 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashSet;
+
+use alloy::{
+    dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner,
+    sol_types::eip712_domain,
+};
+use msg::Receipt;
+use tap_eip712_message::{Eip712Error, Eip712SignedMessage};
+
+fn domain_separator() -> Eip712Domain {
+    eip712_domain! {
+        name: "TAP",
+        version: "1",
+        chain_id: 1,
+        verifying_contract: Address::from([0x11u8; 20]),
+    }
+}
+
+fn signed_receipt(wallet: &PrivateKeySigner) -> Eip712SignedMessage<Receipt> {
+    let receipt = Receipt::new(Address::from([0x22u8; 20]), 42).unwrap();
+    Eip712SignedMessage::new(&domain_separator(), receipt, wallet).unwrap()
+}
+
+#[test]
+fn accepted_signer_is_recovered() {
+    let wallet = PrivateKeySigner::random();
+    let accepted_signers = HashSet::from([Address::from([0x33u8; 20]), wallet.address()]);
+
+    let signer = signed_receipt(&wallet)
+        .recover_if_accepted(&domain_separator(), &accepted_signers)
+        .unwrap();
+
+    assert_eq!(signer, wallet.address());
+}
+
+#[test]
+fn rejected_signer_is_reported() {
+    let wallet = PrivateKeySigner::random();
+    let accepted_signers = HashSet::from([Address::from([0x33u8; 20])]);
+
+    let err = signed_receipt(&wallet)
+        .recover_if_accepted(&domain_separator(), &accepted_signers)
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        Eip712Error::SignerNotAccepted { address } if address == wallet.address()
+    ));
+}
+
+#[test]
+fn accepted_signer_is_recovered_with_hashes() {
+    let wallet = PrivateKeySigner::random();
+    let signed_receipt = signed_receipt(&wallet);
+    let hashes = signed_receipt.computed_hashes(&domain_separator());
+
+    let signer = signed_receipt
+        .recover_if_accepted_with_hashes(&hashes, &HashSet::from([wallet.address()]))
+        .unwrap();
+    assert_eq!(signer, wallet.address());
+
+    let err = signed_receipt
+        .recover_if_accepted_with_hashes(&hashes, &HashSet::new())
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Eip712Error::SignerNotAccepted { address } if address == wallet.address()
+    ));
+}
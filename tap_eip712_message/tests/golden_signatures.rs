@@ -0,0 +1,108 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signatures pinned for a known key and known messages.
+//!
+//! Signing is deterministic (RFC 6979), so these values only change if the
+//! EIP-712 encoding of the messages or the signing scheme changes, which
+//! would break the signatures verified on-chain. A dependency bump failing
+//! these tests must not be merged as is.
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{address, b256, uint, Address},
+    signers::local::PrivateKeySigner,
+};
+use msg::{Receipt, ReceiptAggregateVoucher};
+use tap_eip712_message::Eip712SignedMessage;
+
+fn domain_separator() -> Eip712Domain {
+    alloy::sol_types::eip712_domain! {
+        name: "TAP",
+        version: "1",
+        chain_id: 1,
+        verifying_contract: Address::from([0x11u8; 20]),
+    }
+}
+
+// First account of the default anvil and hardhat mnemonic
+fn signer() -> PrivateKeySigner {
+    "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn signer_address() {
+    assert_eq!(
+        signer().address(),
+        address!("f39Fd6e51aad88F6F4ce6aB8827279cffFb92266")
+    );
+}
+
+#[test]
+fn receipt_signature_is_pinned() {
+    let receipt = Receipt {
+        allocation_id: Address::from([0xabu8; 20]),
+        timestamp_ns: 1_700_000_000_000_000_000,
+        nonce: 42,
+        value: 1234,
+    };
+    let signed_receipt =
+        Eip712SignedMessage::new(&domain_separator(), receipt.clone(), &signer()).unwrap();
+
+    let hashes = signed_receipt.computed_hashes(&domain_separator());
+    assert_eq!(
+        hashes.struct_hash,
+        b256!("f883996c753ebf5001c422fe58b1a246492361770d8eb7b7553b81983fe71fa8")
+    );
+    assert_eq!(
+        hashes.signing_hash,
+        b256!("b476e211629902fe11324ee649123944ffe3aeb4e4725efae73e413713f54b08")
+    );
+    assert_eq!(
+        signed_receipt.signature.r(),
+        uint!(0x2988b2a5a14233cdbb78facc34ef64bb02f2dfbea42d609e0802e93829c0eba8_U256)
+    );
+    assert_eq!(
+        signed_receipt.signature.s(),
+        uint!(0x0bdb6301ac47b5620e92606888b4f574a61db1dee14beafd68cac90e65992f6d_U256)
+    );
+    assert!(signed_receipt.signature.v());
+
+    // signing again gives the same bytes
+    let signed_again = Eip712SignedMessage::new(&domain_separator(), receipt, &signer()).unwrap();
+    assert_eq!(signed_again, signed_receipt);
+}
+
+#[test]
+fn rav_signature_is_pinned() {
+    let rav = ReceiptAggregateVoucher {
+        allocationId: Address::from([0xabu8; 20]),
+        timestampNs: 1_700_000_000_000_000_000,
+        valueAggregate: 1234,
+    };
+    let signed_rav = Eip712SignedMessage::new(&domain_separator(), rav.clone(), &signer()).unwrap();
+
+    let hashes = signed_rav.computed_hashes(&domain_separator());
+    assert_eq!(
+        hashes.struct_hash,
+        b256!("5b228e0127d9c0555c590cf761310a602578d212e03aee603ede36bc7cf0864d")
+    );
+    assert_eq!(
+        hashes.signing_hash,
+        b256!("5ab4c359b953e13ec771c1fb35e8f9573d47fc75f899bcc666b2a28edacf7dd1")
+    );
+    assert_eq!(
+        signed_rav.signature.r(),
+        uint!(0xb650d749c9259e8a878dcb9d8143516ed143f25299174a497a1e11b4a1a3b888_U256)
+    );
+    assert_eq!(
+        signed_rav.signature.s(),
+        uint!(0x01de585525c0923a434fa6a6b6cae37d6105334902b8234f08b7e47e672977d3_U256)
+    );
+    assert!(!signed_rav.signature.v());
+
+    let signed_again = Eip712SignedMessage::new(&domain_separator(), rav, &signer()).unwrap();
+    assert_eq!(signed_again, signed_rav);
+}
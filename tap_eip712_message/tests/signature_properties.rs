@@ -0,0 +1,184 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Property tests of the serialization and signer recovery of signed
+//! messages, including malleated signatures.
+
+use alloy::{
+    dyn_abi::Eip712Domain,
+    primitives::{uint, Address, FixedBytes, PrimitiveSignature as Signature, U256},
+    signers::local::PrivateKeySigner,
+    sol_types::eip712_domain,
+};
+use msg::{Receipt, ReceiptAggregateVoucher};
+use proptest::prelude::*;
+use tap_eip712_message::{Eip712SignedMessage, SignatureBytesExt};
+
+/// Order of the secp256k1 curve
+const SECP256K1N_ORDER: U256 =
+    uint!(0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141_U256);
+
+fn domain_separator() -> Eip712Domain {
+    eip712_domain! {
+        name: "TAP",
+        version: "1",
+        chain_id: 1,
+        verifying_contract: Address::from([0x11u8; 20]),
+    }
+}
+
+/// Returns the other valid signature of the same message: `(r, n - s)` with
+/// the opposite parity.
+fn malleate(signature: &Signature) -> Signature {
+    Signature::new(
+        signature.r(),
+        SECP256K1N_ORDER - signature.s(),
+        !signature.v(),
+    )
+}
+
+// Shrinks towards the zero address
+fn address() -> impl Strategy<Value = Address> {
+    any::<[u8; 20]>().prop_map(Address::from)
+}
+
+// Keys are shrunk towards small scalars, skipping the invalid ones
+fn signer() -> impl Strategy<Value = PrivateKeySigner> {
+    any::<[u8; 32]>().prop_filter_map("invalid private key", |bytes| {
+        PrivateKeySigner::from_bytes(&FixedBytes::from(bytes)).ok()
+    })
+}
+
+fn receipt() -> impl Strategy<Value = Receipt> {
+    (address(), any::<u64>(), any::<u64>(), any::<u128>()).prop_map(
+        |(allocation_id, timestamp_ns, nonce, value)| Receipt {
+            allocation_id,
+            timestamp_ns,
+            nonce,
+            value,
+        },
+    )
+}
+
+fn rav() -> impl Strategy<Value = ReceiptAggregateVoucher> {
+    (address(), any::<u64>(), any::<u128>()).prop_map(
+        |(allocation_id, timestamp_ns, value_aggregate)| ReceiptAggregateVoucher {
+            allocationId: allocation_id,
+            timestampNs: timestamp_ns,
+            valueAggregate: value_aggregate,
+        },
+    )
+}
+
+proptest! {
+    // signing and recovering is slow, keep the number of cases low
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn receipt_serialization_round_trips(receipt in receipt(), signer in signer()) {
+        let signed_receipt =
+            Eip712SignedMessage::new(&domain_separator(), receipt, &signer).unwrap();
+
+        let json = serde_json::to_string(&signed_receipt).unwrap();
+        let deserialized: Eip712SignedMessage<Receipt> = serde_json::from_str(&json).unwrap();
+
+        prop_assert_eq!(&deserialized, &signed_receipt);
+        prop_assert_eq!(
+            deserialized.recover_signer(&domain_separator()).unwrap(),
+            signer.address()
+        );
+    }
+
+    #[test]
+    fn rav_serialization_round_trips(rav in rav(), signer in signer()) {
+        let signed_rav = Eip712SignedMessage::new(&domain_separator(), rav, &signer).unwrap();
+
+        let json = serde_json::to_string(&signed_rav).unwrap();
+        let deserialized: Eip712SignedMessage<ReceiptAggregateVoucher> =
+            serde_json::from_str(&json).unwrap();
+
+        prop_assert_eq!(&deserialized, &signed_rav);
+        prop_assert_eq!(
+            deserialized.recover_signer(&domain_separator()).unwrap(),
+            signer.address()
+        );
+    }
+
+    #[test]
+    fn recovery_is_stable_under_low_s_normalization(
+        receipt in receipt(),
+        signer in signer(),
+    ) {
+        let signed_receipt =
+            Eip712SignedMessage::new(&domain_separator(), receipt, &signer).unwrap();
+
+        // signatures are created in the low-S form
+        prop_assert!(signed_receipt.signature.normalize_s().is_none());
+
+        let malleated = malleate(&signed_receipt.signature);
+        prop_assert_eq!(malleated.normalized_s(), signed_receipt.signature);
+    }
+
+    #[test]
+    fn malleated_signatures_recover_the_same_signer(
+        receipt in receipt(),
+        signer in signer(),
+    ) {
+        let signed_receipt =
+            Eip712SignedMessage::new(&domain_separator(), receipt, &signer).unwrap();
+        let malleated_receipt = Eip712SignedMessage {
+            signature: malleate(&signed_receipt.signature),
+            ..signed_receipt.clone()
+        };
+
+        // The signature bytes differ, so a uniqueness check on the signature
+        // alone lets the copy through...
+        prop_assert_ne!(
+            malleated_receipt.signature.get_signature_bytes(),
+            signed_receipt.signature.get_signature_bytes()
+        );
+        // ...but it is attributed to the same signer and has the same id,
+        // so it is caught by a uniqueness check on the message.
+        prop_assert_eq!(
+            malleated_receipt.recover_signer(&domain_separator()).unwrap(),
+            signer.address()
+        );
+        prop_assert_eq!(malleated_receipt.unique_hash(), signed_receipt.unique_hash());
+    }
+
+    #[test]
+    fn hashes_returned_when_signing_match_the_message(
+        receipts in proptest::collection::vec(receipt(), 1..4),
+        signer in signer(),
+    ) {
+        let signed_receipts =
+            Eip712SignedMessage::new_batch(&domain_separator(), receipts, &signer).unwrap();
+
+        for (signed_receipt, hashes) in signed_receipts {
+            prop_assert_eq!(hashes, signed_receipt.computed_hashes(&domain_separator()));
+            prop_assert_eq!(hashes.message_id(), signed_receipt.unique_hash());
+            prop_assert_eq!(
+                signed_receipt.recover_signer_with_hashes(&hashes).unwrap(),
+                signer.address()
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_messages_do_not_recover_the_signer(
+        receipt in receipt(),
+        signer in signer(),
+        value in any::<u128>(),
+    ) {
+        prop_assume!(value != receipt.value);
+        let signed_receipt =
+            Eip712SignedMessage::new(&domain_separator(), receipt, &signer).unwrap();
+        let mut tampered_receipt = signed_receipt.clone();
+        tampered_receipt.message.value = value;
+
+        // recovery either fails or yields an unrelated address
+        if let Ok(address) = tampered_receipt.recover_signer(&domain_separator()) {
+            prop_assert_ne!(address, signer.address());
+        }
+    }
+}
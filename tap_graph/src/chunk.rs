@@ -0,0 +1,81 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::SignedReceipt;
+
+/// Splits `receipts` into chunks whose serialized JSON array is at most
+/// `max_bytes` long, e.g. to stay under the request size limit of
+/// `tap_aggregator` when aggregating a large set of receipts.
+///
+/// Receipts are grouped greedily, keeping their order. The size of a chunk
+/// accounts for the brackets of the array and the comma separating each
+/// receipt. A receipt that does not fit in `max_bytes` on its own gets a
+/// chunk of its own, which is over the budget.
+pub fn chunk_by_byte_budget(
+    receipts: Vec<SignedReceipt>,
+    max_bytes: usize,
+) -> Vec<Vec<SignedReceipt>> {
+    const BRACKETS_LEN: usize = 2;
+    const SEPARATOR_LEN: usize = 1;
+
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_len = BRACKETS_LEN;
+    for receipt in receipts {
+        let receipt_len = serde_json::to_vec(&receipt)
+            .expect("receipts are serializable")
+            .len();
+        if !chunk.is_empty() {
+            if chunk_len + SEPARATOR_LEN + receipt_len > max_bytes {
+                chunks.push(std::mem::take(&mut chunk));
+                chunk_len = BRACKETS_LEN;
+            } else {
+                chunk_len += SEPARATOR_LEN;
+            }
+        }
+        chunk_len += receipt_len;
+        chunk.push(receipt);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+    use tap_eip712_message::Eip712SignedMessage;
+
+    use super::chunk_by_byte_budget;
+    use crate::Receipt;
+
+    #[test]
+    fn chunks_are_under_budget() {
+        let domain_separator = Eip712Domain::default();
+        let signer = PrivateKeySigner::random();
+        let receipts: Vec<_> = (1..=50u128)
+            .map(|value| {
+                let receipt = Receipt::new(Address::from([0x11u8; 20]), value).unwrap();
+                Eip712SignedMessage::new(&domain_separator, receipt, &signer).unwrap()
+            })
+            .collect();
+        let max_bytes = 2_000;
+
+        let chunks = chunk_by_byte_budget(receipts.clone(), max_bytes);
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(!chunk.is_empty());
+            assert!(serde_json::to_vec(chunk).unwrap().len() <= max_bytes);
+        }
+        // every receipt is kept once, in order
+        assert_eq!(chunks.concat(), receipts);
+
+        // a receipt over the budget gets its own chunk
+        let chunks = chunk_by_byte_budget(receipts[..3].to_vec(), 1);
+        assert_eq!(chunks.len(), 3);
+
+        assert!(chunk_by_byte_budget(Vec::new(), max_bytes).is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use alloy::primitives::Address;
+
+/// Inconsistency found by the `validate` method of the receipts, before
+/// they are signed.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ReceiptValidationError {
+    #[error("receipt timestamp is zero")]
+    ZeroTimestamp,
+    #[error("receipt {field} is the zero address")]
+    ZeroAddress { field: &'static str },
+}
+
+/// First violation found by [`crate::verify_rav_chain`], at `index` in the
+/// chain.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    #[error("RAV {index}: could not recover the signer: {message}")]
+    InvalidSignature { index: usize, message: String },
+    #[error("RAV {index} is signed by {signer}, expected {expected}")]
+    SignerMismatch {
+        index: usize,
+        signer: Address,
+        expected: Address,
+    },
+    #[error("RAV {index} is for allocation {allocation_id}, expected {expected}")]
+    AllocationMismatch {
+        index: usize,
+        allocation_id: Address,
+        expected: Address,
+    },
+    #[error("RAV {index} value {value} is below the previous value {previous}")]
+    DecreasingValue {
+        index: usize,
+        value: u128,
+        previous: u128,
+    },
+    #[error("RAV {index} timestamp {timestamp_ns} is not after the previous timestamp {previous}")]
+    NonIncreasingTimestamp {
+        index: usize,
+        timestamp_ns: u64,
+        previous: u64,
+    },
+}
@@ -6,9 +6,20 @@
 //! These structs are used for communication between The Graph systems.
 //!
 
+mod chunk;
+mod error;
+mod nonce;
+mod rav_chain;
+pub mod redemption;
+mod struct_hash;
+mod typed_data;
 mod v1;
 
 #[cfg(any(test, feature = "v2"))]
 pub mod v2;
 
+pub use chunk::chunk_by_byte_budget;
+pub use error::{ChainError, ReceiptValidationError};
+pub use nonce::NonceStrategy;
+pub use rav_chain::verify_rav_chain;
 pub use v1::{Receipt, ReceiptAggregateVoucher, SignedRav, SignedReceipt};
@@ -0,0 +1,44 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use rand::{thread_rng, Rng};
+
+/// How the nonce of a new receipt is chosen, see
+/// [`crate::Receipt::new_with_strategy`].
+///
+/// A sender configures it once and uses it for all its receipts.
+#[derive(Debug, Clone, Default)]
+pub enum NonceStrategy {
+    /// A random nonce, as chosen by [`crate::Receipt::new`].
+    #[default]
+    Random,
+    /// Consecutive nonces taken from a counter shared by the clones of the
+    /// strategy, which never collide until the counter wraps around.
+    ///
+    /// The counter is not persisted: a sender restarting must not start it
+    /// again from a nonce it already used for the same allocation.
+    Sequential(Arc<AtomicU64>),
+    /// The same nonce for every receipt, e.g. for tests.
+    Fixed(u64),
+}
+
+impl NonceStrategy {
+    /// Returns a [`NonceStrategy::Sequential`] whose first nonce is `first`.
+    pub fn sequential(first: u64) -> Self {
+        Self::Sequential(Arc::new(AtomicU64::new(first)))
+    }
+
+    /// Returns the nonce of the next receipt.
+    pub fn next_nonce(&self) -> u64 {
+        match self {
+            NonceStrategy::Random => thread_rng().gen(),
+            NonceStrategy::Sequential(counter) => counter.fetch_add(1, Ordering::Relaxed),
+            NonceStrategy::Fixed(nonce) => *nonce,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use alloy::dyn_abi::Eip712Domain;
+
+use crate::{ChainError, SignedRav};
+
+/// Verifies that `ravs`, ordered from the oldest, form a valid chain for one
+/// allocation, e.g. to audit the RAVs stored for it.
+///
+/// All the RAVs must be signed by the signer of the first one under
+/// `domain_separator`, and be for the same allocation. Each RAV must have a
+/// value at least the one of the previous RAV, and a later timestamp. An
+/// empty chain is valid.
+///
+/// # Errors
+///
+/// Returns the first [`ChainError`] found, with the index of the RAV
+///
+pub fn verify_rav_chain(
+    ravs: &[SignedRav],
+    domain_separator: &Eip712Domain,
+) -> Result<(), ChainError> {
+    let Some(first) = ravs.first() else {
+        return Ok(());
+    };
+    let recover_signer = |index: usize, rav: &SignedRav| {
+        rav.recover_signer(domain_separator)
+            .map_err(|e| ChainError::InvalidSignature {
+                index,
+                message: e.to_string(),
+            })
+    };
+    let expected_signer = recover_signer(0, first)?;
+    let expected_allocation_id = first.message.allocationId;
+
+    for (index, rav) in ravs.iter().enumerate().skip(1) {
+        let signer = recover_signer(index, rav)?;
+        if signer != expected_signer {
+            return Err(ChainError::SignerMismatch {
+                index,
+                signer,
+                expected: expected_signer,
+            });
+        }
+        if rav.message.allocationId != expected_allocation_id {
+            return Err(ChainError::AllocationMismatch {
+                index,
+                allocation_id: rav.message.allocationId,
+                expected: expected_allocation_id,
+            });
+        }
+        let previous = &ravs[index - 1].message;
+        if rav.message.valueAggregate < previous.valueAggregate {
+            return Err(ChainError::DecreasingValue {
+                index,
+                value: rav.message.valueAggregate,
+                previous: previous.valueAggregate,
+            });
+        }
+        if rav.message.timestampNs <= previous.timestampNs {
+            return Err(ChainError::NonIncreasingTimestamp {
+                index,
+                timestamp_ns: rav.message.timestampNs,
+                previous: previous.timestampNs,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner};
+    use tap_eip712_message::Eip712SignedMessage;
+
+    use super::verify_rav_chain;
+    use crate::{ChainError, ReceiptAggregateVoucher, SignedRav};
+
+    const ALLOCATION_ID: Address = Address::new([0x11; 20]);
+
+    fn rav(
+        signer: &PrivateKeySigner,
+        allocation_id: Address,
+        timestamp_ns: u64,
+        value_aggregate: u128,
+    ) -> SignedRav {
+        Eip712SignedMessage::new(
+            &Eip712Domain::default(),
+            ReceiptAggregateVoucher {
+                allocationId: allocation_id,
+                timestampNs: timestamp_ns,
+                valueAggregate: value_aggregate,
+            },
+            signer,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_chain() {
+        let domain_separator = Eip712Domain::default();
+        let signer = PrivateKeySigner::random();
+        let ravs = [
+            rav(&signer, ALLOCATION_ID, 10, 100),
+            rav(&signer, ALLOCATION_ID, 20, 100),
+            rav(&signer, ALLOCATION_ID, 30, 250),
+        ];
+
+        assert_eq!(verify_rav_chain(&ravs, &domain_separator), Ok(()));
+        assert_eq!(verify_rav_chain(&ravs[..1], &domain_separator), Ok(()));
+        assert_eq!(verify_rav_chain(&[], &domain_separator), Ok(()));
+    }
+
+    #[test]
+    fn broken_chains() {
+        let domain_separator = Eip712Domain::default();
+        let signer = PrivateKeySigner::random();
+        let other_signer = PrivateKeySigner::random();
+        let first = rav(&signer, ALLOCATION_ID, 10, 100);
+
+        let ravs = [
+            first.clone(),
+            rav(&signer, ALLOCATION_ID, 20, 150),
+            rav(&other_signer, ALLOCATION_ID, 30, 200),
+        ];
+        assert_eq!(
+            verify_rav_chain(&ravs, &domain_separator),
+            Err(ChainError::SignerMismatch {
+                index: 2,
+                signer: other_signer.address(),
+                expected: signer.address(),
+            })
+        );
+
+        let other_allocation_id = Address::new([0x22; 20]);
+        let ravs = [first.clone(), rav(&signer, other_allocation_id, 20, 150)];
+        assert_eq!(
+            verify_rav_chain(&ravs, &domain_separator),
+            Err(ChainError::AllocationMismatch {
+                index: 1,
+                allocation_id: other_allocation_id,
+                expected: ALLOCATION_ID,
+            })
+        );
+
+        let ravs = [first.clone(), rav(&signer, ALLOCATION_ID, 20, 99)];
+        assert_eq!(
+            verify_rav_chain(&ravs, &domain_separator),
+            Err(ChainError::DecreasingValue {
+                index: 1,
+                value: 99,
+                previous: 100,
+            })
+        );
+
+        let ravs = [first, rav(&signer, ALLOCATION_ID, 10, 150)];
+        assert_eq!(
+            verify_rav_chain(&ravs, &domain_separator),
+            Err(ChainError::NonIncreasingTimestamp {
+                index: 1,
+                timestamp_ns: 10,
+                previous: 10,
+            })
+        );
+    }
+}
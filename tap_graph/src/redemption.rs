@@ -0,0 +1,89 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! # Redemption cost
+//!
+//! Redeeming a RAV on-chain costs gas, so RAVs worth less than the gas
+//! ("dust") are not worth redeeming. These helpers estimate the cost of a
+//! redemption in GRT and compare it to the value of the RAV.
+
+/// Estimated gas used to redeem a RAV on-chain.
+///
+/// Override it with [`RedemptionCost::with_gas`] if the redemption contract
+/// of the network uses a different amount.
+pub const DEFAULT_REDEMPTION_GAS: u64 = 200_000;
+
+/// Estimates the cost of redeeming a RAV on-chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedemptionCost {
+    gas: u64,
+}
+
+impl Default for RedemptionCost {
+    fn default() -> Self {
+        Self::with_gas(DEFAULT_REDEMPTION_GAS)
+    }
+}
+
+impl RedemptionCost {
+    /// Estimates the cost of a redemption using `gas` units of gas.
+    pub fn with_gas(gas: u64) -> Self {
+        Self { gas }
+    }
+
+    pub fn gas(&self) -> u64 {
+        self.gas
+    }
+
+    /// Returns the cost of a redemption in GRT wei, rounded up.
+    ///
+    /// `gas_price_wei` is the price of a unit of gas, in wei of the native
+    /// token, and `grt_per_gas` the GRT wei worth one wei of the native token.
+    pub fn cost_grt(&self, gas_price_wei: u128, grt_per_gas: f64) -> u128 {
+        // saturates on overflow, so that a huge cost is never economical
+        (self.gas as f64 * gas_price_wei as f64 * grt_per_gas).ceil() as u128
+    }
+
+    /// Returns `true` if a RAV worth `value_aggregate` GRT wei is worth more
+    /// than the cost of its redemption. A RAV worth exactly the cost is not.
+    pub fn is_economical(
+        &self,
+        value_aggregate: u128,
+        gas_price_wei: u128,
+        grt_per_gas: f64,
+    ) -> bool {
+        value_aggregate > self.cost_grt(gas_price_wei, grt_per_gas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn economical_above_break_even() {
+        let cost = RedemptionCost::with_gas(100_000);
+        // 100_000 gas * 10 wei * 2 GRT wei per wei
+        assert_eq!(cost.cost_grt(10, 2.0), 2_000_000);
+
+        assert!(!cost.is_economical(1_999_999, 10, 2.0));
+        assert!(!cost.is_economical(2_000_000, 10, 2.0));
+        assert!(cost.is_economical(2_000_001, 10, 2.0));
+
+        // fractional costs are rounded up
+        assert_eq!(cost.cost_grt(1, 0.000_015), 2);
+        assert!(!cost.is_economical(2, 1, 0.000_015));
+        assert!(cost.is_economical(3, 1, 0.000_015));
+    }
+
+    #[test]
+    fn default_gas() {
+        let cost = RedemptionCost::default();
+        assert_eq!(cost.gas(), DEFAULT_REDEMPTION_GAS);
+        assert_eq!(cost.cost_grt(1, 1.0), DEFAULT_REDEMPTION_GAS as u128);
+
+        // the cost saturates instead of wrapping
+        assert_eq!(cost.cost_grt(u128::MAX, 2.0), u128::MAX);
+        assert!(!cost.is_economical(u128::MAX, u128::MAX, 2.0));
+    }
+}
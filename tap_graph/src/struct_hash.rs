@@ -0,0 +1,94 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `Hash` of the TAP messages
+
+/// Implements `Hash` for the messages by hashing their EIP-712 struct hash,
+/// so that they can be used as `HashMap` keys.
+///
+/// The struct hash does not depend on the domain separator, unlike the
+/// signing hash, so equal messages hash equally under any domain.
+macro_rules! hash_by_struct_hash {
+    ($($message:ty),+ $(,)?) => {
+        $(
+            impl std::hash::Hash for $message {
+                fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                    alloy::sol_types::SolStruct::eip712_hash_struct(self).hash(state);
+                }
+            }
+        )+
+    };
+}
+
+hash_by_struct_hash!(crate::v1::Receipt, crate::v1::ReceiptAggregateVoucher);
+
+#[cfg(any(test, feature = "v2"))]
+hash_by_struct_hash!(crate::v2::Receipt, crate::v2::ReceiptAggregateVoucher);
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{BuildHasher, Hash, RandomState};
+
+    use alloy::primitives::{Address, Bytes};
+    use rstest::*;
+
+    use crate::{v1, v2};
+
+    /// Hashes of `message`, of a clone of it and of `other`
+    fn hashes<M: Hash + Clone>(message: M, other: M) -> [u64; 3] {
+        let state = RandomState::new();
+        [
+            state.hash_one(&message),
+            state.hash_one(message.clone()),
+            state.hash_one(&other),
+        ]
+    }
+
+    fn v2_receipt(payer: Address) -> v2::Receipt {
+        v2::Receipt::new(
+            Address::from([0x11u8; 20]),
+            v2::Payer(payer),
+            v2::DataService(Address::from([0x22u8; 20])),
+            v2::ServiceProvider(Address::from([0x33u8; 20])),
+            1234,
+        )
+        .unwrap()
+    }
+
+    fn v2_rav(metadata: Bytes) -> v2::ReceiptAggregateVoucher {
+        v2::ReceiptAggregateVoucher {
+            allocationId: Address::ZERO,
+            payer: Address::ZERO,
+            dataService: Address::ZERO,
+            serviceProvider: Address::ZERO,
+            timestampNs: 30,
+            valueAggregate: 42,
+            metadata,
+        }
+    }
+
+    #[rstest]
+    #[case::v1_receipt(hashes(
+        v1::Receipt::new(Address::ZERO, 1234).unwrap(),
+        v1::Receipt::new(Address::from([0x11u8; 20]), 1234).unwrap(),
+    ))]
+    #[case::v1_rav(hashes(
+        v1::ReceiptAggregateVoucher {
+            allocationId: Address::ZERO,
+            timestampNs: 30,
+            valueAggregate: 42,
+        },
+        v1::ReceiptAggregateVoucher {
+            allocationId: Address::ZERO,
+            timestampNs: 30,
+            valueAggregate: 43,
+        },
+    ))]
+    #[case::v2_receipt(hashes(v2_receipt(Address::from([0x44u8; 20])), v2_receipt(Address::ZERO)))]
+    #[case::v2_rav(hashes(v2_rav(Bytes::new()), v2_rav(Bytes::from(vec![0x11u8; 32]))))]
+    fn hash_struct_hash(#[case] message_hashes: [u64; 3]) {
+        let [hash, clone_hash, other_hash] = message_hashes;
+        assert_eq!(hash, clone_hash);
+        assert_ne!(hash, other_hash);
+    }
+}
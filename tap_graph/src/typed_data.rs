@@ -0,0 +1,31 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! EIP-712 typed data of the TAP messages, for the browser wallets to sign them
+
+use alloy::{
+    dyn_abi::{Eip712Domain, Resolver, TypedData},
+    sol_types::SolStruct,
+};
+
+/// Returns the EIP-712 typed data of the `S` message, with its types and
+/// `domain`, in the JSON structure expected by `eth_signTypedData_v4`.
+///
+/// `message` holds the fields of the struct. The integers are given as
+/// decimal strings, as JSON numbers lose precision above 2^53 in browsers.
+pub(crate) fn typed_data_json<S: SolStruct>(
+    domain: &Eip712Domain,
+    message: serde_json::Value,
+) -> serde_json::Value {
+    let mut resolver = Resolver::from_struct::<S>();
+    resolver
+        .ingest_string(domain.encode_type())
+        .expect("domain type is always valid");
+    let typed_data = TypedData {
+        domain: domain.clone(),
+        resolver,
+        primary_type: S::NAME.into(),
+        message,
+    };
+    serde_json::to_value(typed_data).expect("typed data is always serializable")
+}
@@ -43,12 +43,13 @@ use alloy::{primitives::Address, sol};
 use serde::{Deserialize, Serialize};
 use tap_eip712_message::Eip712SignedMessage;
 use tap_receipt::{
-    rav::{Aggregate, AggregationError},
+    rav::{Aggregate, AggregationError, CheckedSum},
     state::Checked,
     ReceiptWithState, WithValueAndTimestamp,
 };
 
 use super::{Receipt, SignedReceipt};
+use crate::redemption::RedemptionCost;
 
 /// A Rav wrapped in an Eip712SignedMessage
 pub type SignedRav = Eip712SignedMessage<ReceiptAggregateVoucher>;
@@ -82,6 +83,30 @@ impl ReceiptAggregateVoucher {
         allocation_id: Address,
         receipts: &[Eip712SignedMessage<Receipt>],
         previous_rav: Option<Eip712SignedMessage<Self>>,
+    ) -> Result<Self, AggregationError> {
+        Self::aggregate_receipts_cumulative(allocation_id, receipts, previous_rav, true)
+    }
+
+    /// Same as [`ReceiptAggregateVoucher::aggregate_receipts`] if `cumulative`
+    /// is `true`. If it is `false`, the value of the RAV is only the sum of
+    /// `receipts`, the delta since `previous_rav`, whose timestamp is still
+    /// the minimum timestamp of the new RAV.
+    ///
+    /// The TAP escrow contracts expect cumulative RAVs: the v1 `Escrow` redeems
+    /// a single RAV per allocation, the latest one, and the v2
+    /// `GraphTallyCollector` pays the value of a RAV minus what was already
+    /// collected for it. Delta RAVs only fit verifiers paying each RAV in
+    /// full, which must then refuse to pay the same RAV twice.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AggregateOverflow`] if any receipt value causes aggregate
+    /// value to overflow
+    pub fn aggregate_receipts_cumulative(
+        allocation_id: Address,
+        receipts: &[Eip712SignedMessage<Receipt>],
+        previous_rav: Option<Eip712SignedMessage<Self>>,
+        cumulative: bool,
     ) -> Result<Self, AggregationError> {
         //TODO(#29): When receipts in flight struct in created check that the state
         // of every receipt is OK with all checks complete (relies on #28)
@@ -91,17 +116,82 @@ impl ReceiptAggregateVoucher {
 
         if let Some(prev_rav) = previous_rav {
             timestamp_max = prev_rav.message.timestampNs;
-            value_aggregate = prev_rav.message.valueAggregate;
+            if cumulative {
+                value_aggregate = prev_rav.message.valueAggregate;
+            }
         }
 
-        for receipt in receipts {
-            value_aggregate = value_aggregate
-                .checked_add(receipt.message.value)
-                .ok_or(AggregationError::AggregateOverflow)?;
+        Self::fold_receipts(allocation_id, receipts, value_aggregate, timestamp_max)
+    }
 
-            timestamp_max = cmp::max(timestamp_max, receipt.message.timestamp_ns)
+    /// Aggregates a batch of validated receipts on top of a known aggregate
+    /// value and timestamp, instead of a signed previous RAV.
+    ///
+    /// This is useful to reconstruct a RAV when the signed previous RAV was
+    /// lost but its values were retained.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AggregationError::ReceiptTimestampNotAfterBase`] if any
+    /// receipt timestamp is not strictly greater than `base_timestamp_ns`.
+    ///
+    /// Returns [`AggregationError::AggregateOverflow`] if any receipt value
+    /// causes aggregate value to overflow
+    pub fn aggregate_receipts_from_base(
+        allocation_id: Address,
+        receipts: &[Eip712SignedMessage<Receipt>],
+        base_value: u128,
+        base_timestamp_ns: u64,
+    ) -> Result<Self, AggregationError> {
+        if let Some(receipt) = receipts
+            .iter()
+            .find(|receipt| receipt.message.timestamp_ns <= base_timestamp_ns)
+        {
+            return Err(AggregationError::ReceiptTimestampNotAfterBase {
+                base_ts: base_timestamp_ns,
+                receipt_ts: receipt.message.timestamp_ns,
+            });
         }
 
+        Self::fold_receipts(allocation_id, receipts, base_value, base_timestamp_ns)
+    }
+
+    /// Returns the timestamp of the latest receipt aggregated into this RAV.
+    ///
+    /// Aggregation sets `timestampNs` to the maximum timestamp of the
+    /// aggregated receipts and of the previous RAV, if any. As the previous
+    /// RAV timestamp is itself the latest of its receipts, this is the
+    /// maximum timestamp of all the receipts covered by this RAV, and it is
+    /// never lower than the previous RAV timestamp.
+    pub fn latest_receipt_timestamp(&self) -> u64 {
+        self.timestampNs
+    }
+
+    /// Returns `true` if the value of this RAV exceeds the cost of redeeming
+    /// it on-chain, estimated with
+    /// [`crate::redemption::DEFAULT_REDEMPTION_GAS`].
+    ///
+    /// See [`RedemptionCost`] for the parameters, and to estimate the cost
+    /// with another amount of gas.
+    pub fn is_economical_to_redeem(&self, gas_price_wei: u128, grt_per_gas: f64) -> bool {
+        RedemptionCost::default().is_economical(self.valueAggregate, gas_price_wei, grt_per_gas)
+    }
+
+    fn fold_receipts(
+        allocation_id: Address,
+        receipts: &[Eip712SignedMessage<Receipt>],
+        mut value_aggregate: u128,
+        mut timestamp_max: u64,
+    ) -> Result<Self, AggregationError> {
+        value_aggregate = receipts
+            .iter()
+            .map(|receipt| receipt.message.value)
+            .checked_sum_from(value_aggregate)?;
+        timestamp_max = receipts
+            .iter()
+            .map(|receipt| receipt.message.timestamp_ns)
+            .fold(timestamp_max, cmp::max);
+
         Ok(Self {
             allocationId: allocation_id,
             timestampNs: timestamp_max,
@@ -140,3 +230,118 @@ impl WithValueAndTimestamp for ReceiptAggregateVoucher {
         self.timestampNs
     }
 }
+
+#[cfg(test)]
+mod rav_unit_test {
+    use alloy::{dyn_abi::Eip712Domain, signers::local::PrivateKeySigner};
+    use rstest::*;
+
+    use super::*;
+
+    #[fixture]
+    fn receipts() -> Vec<SignedReceipt> {
+        let wallet = PrivateKeySigner::random();
+        [30, 10, 20]
+            .into_iter()
+            .map(|timestamp_ns| {
+                let receipt = Receipt {
+                    allocation_id: Address::ZERO,
+                    timestamp_ns,
+                    nonce: timestamp_ns,
+                    value: 1,
+                };
+                Eip712SignedMessage::new(&Eip712Domain::default(), receipt, &wallet).unwrap()
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn latest_receipt_timestamp(receipts: Vec<SignedReceipt>) {
+        let rav = ReceiptAggregateVoucher::aggregate_receipts(Address::ZERO, &receipts[..2], None)
+            .unwrap();
+        assert_eq!(rav.latest_receipt_timestamp(), 30);
+
+        // the previous RAV covers a later receipt
+        let previous_rav =
+            Eip712SignedMessage::new(&Eip712Domain::default(), rav, &PrivateKeySigner::random())
+                .unwrap();
+        let rav = ReceiptAggregateVoucher::aggregate_receipts(
+            Address::ZERO,
+            &receipts[2..],
+            Some(previous_rav),
+        )
+        .unwrap();
+        assert_eq!(rav.latest_receipt_timestamp(), 30);
+    }
+
+    #[rstest]
+    fn cumulative_and_delta_ravs(receipts: Vec<SignedReceipt>) {
+        let previous_rav = Eip712SignedMessage::new(
+            &Eip712Domain::default(),
+            ReceiptAggregateVoucher {
+                allocationId: Address::ZERO,
+                timestampNs: 5,
+                valueAggregate: 100,
+            },
+            &PrivateKeySigner::random(),
+        )
+        .unwrap();
+
+        let rav = ReceiptAggregateVoucher::aggregate_receipts_cumulative(
+            Address::ZERO,
+            &receipts,
+            Some(previous_rav.clone()),
+            true,
+        )
+        .unwrap();
+        assert_eq!(rav.valueAggregate, 103);
+        assert_eq!(rav.timestampNs, 30);
+
+        let rav = ReceiptAggregateVoucher::aggregate_receipts_cumulative(
+            Address::ZERO,
+            &receipts,
+            Some(previous_rav.clone()),
+            false,
+        )
+        .unwrap();
+        assert_eq!(rav.valueAggregate, 3);
+        assert_eq!(rav.timestampNs, 30);
+
+        // the previous RAV still bounds the timestamp
+        let previous_rav = Eip712SignedMessage::new(
+            &Eip712Domain::default(),
+            ReceiptAggregateVoucher {
+                timestampNs: 40,
+                ..previous_rav.message
+            },
+            &PrivateKeySigner::random(),
+        )
+        .unwrap();
+        let rav = ReceiptAggregateVoucher::aggregate_receipts_cumulative(
+            Address::ZERO,
+            &receipts,
+            Some(previous_rav),
+            false,
+        )
+        .unwrap();
+        assert_eq!(rav.valueAggregate, 3);
+        assert_eq!(rav.timestampNs, 40);
+    }
+
+    #[rstest]
+    fn economical_to_redeem_above_default_gas_cost() {
+        let break_even = ReceiptAggregateVoucher {
+            allocationId: Address::ZERO,
+            timestampNs: 30,
+            valueAggregate: crate::redemption::DEFAULT_REDEMPTION_GAS as u128,
+        };
+        let above = ReceiptAggregateVoucher {
+            valueAggregate: break_even.valueAggregate + 1,
+            ..break_even.clone()
+        };
+
+        assert!(!break_even.is_economical_to_redeem(1, 1.0));
+        assert!(above.is_economical_to_redeem(1, 1.0));
+        assert!(!above.is_economical_to_redeem(2, 1.0));
+    }
+}
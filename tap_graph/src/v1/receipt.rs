@@ -10,11 +10,13 @@
 
 use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 
-use alloy::{primitives::Address, sol};
-use rand::{thread_rng, Rng};
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, sol, sol_types::SolStruct};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use tap_eip712_message::Eip712SignedMessage;
-use tap_receipt::WithValueAndTimestamp;
+use tap_receipt::{WithAllocationId, WithValueAndTimestamp};
+
+use crate::{typed_data::typed_data_json, NonceStrategy, ReceiptValidationError};
 
 /// A Receipt wrapped in an Eip712SignedMessage
 pub type SignedReceipt = Eip712SignedMessage<Receipt>;
@@ -39,10 +41,20 @@ fn get_current_timestamp_u64_ns() -> Result<u64, SystemTimeError> {
 }
 
 impl Receipt {
-    /// Returns a receipt with provided values
+    /// Returns a receipt with provided values and a random nonce
     pub fn new(allocation_id: Address, value: u128) -> Result<Self, SystemTimeError> {
+        Self::new_with_strategy(allocation_id, value, &NonceStrategy::Random)
+    }
+
+    /// Returns a receipt with provided values and a nonce chosen by
+    /// `nonce_strategy`
+    pub fn new_with_strategy(
+        allocation_id: Address,
+        value: u128,
+        nonce_strategy: &NonceStrategy,
+    ) -> Result<Self, SystemTimeError> {
         let timestamp_ns = get_current_timestamp_u64_ns()?;
-        let nonce = thread_rng().gen::<u64>();
+        let nonce = nonce_strategy.next_nonce();
         Ok(Self {
             allocation_id,
             timestamp_ns,
@@ -50,6 +62,51 @@ impl Receipt {
             value,
         })
     }
+
+    /// Checks the consistency of the fields of the receipt, to catch
+    /// construction bugs before signing it.
+    ///
+    /// The timestamp must not be zero, and the allocation id must not be the
+    /// zero address. Every nonce and value is valid.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`ReceiptValidationError`] found
+    ///
+    pub fn validate(&self) -> Result<(), ReceiptValidationError> {
+        if self.timestamp_ns == 0 {
+            return Err(ReceiptValidationError::ZeroTimestamp);
+        }
+        if self.allocation_id.is_zero() {
+            return Err(ReceiptValidationError::ZeroAddress {
+                field: "allocation_id",
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the receipt as EIP-712 typed data, with its types, `domain`
+    /// and message, in the JSON structure expected by
+    /// `eth_signTypedData_v4`, so that a browser wallet can sign it.
+    ///
+    /// The integer fields are given as decimal strings.
+    pub fn to_typed_data_json(&self, domain: &Eip712Domain) -> serde_json::Value {
+        typed_data_json::<Self>(
+            domain,
+            json!({
+                "allocation_id": self.allocation_id,
+                "timestamp_ns": self.timestamp_ns.to_string(),
+                "nonce": self.nonce.to_string(),
+                "value": self.value.to_string(),
+            }),
+        )
+    }
+}
+
+impl WithAllocationId for Receipt {
+    fn allocation_id(&self) -> Address {
+        self.allocation_id
+    }
 }
 
 impl WithValueAndTimestamp for Receipt {
@@ -69,6 +126,7 @@ mod receipt_unit_test {
         time::{SystemTime, UNIX_EPOCH},
     };
 
+    use alloy::{dyn_abi::TypedData, sol_types::eip712_domain};
     use rstest::*;
 
     use super::*;
@@ -125,4 +183,92 @@ mod receipt_unit_test {
         assert!(receipt2.timestamp_ns <= now);
         assert!(receipt2.timestamp_ns >= now - 5000000); // 5 second tolerance
     }
+
+    #[rstest]
+    fn test_nonce_strategies(allocation_ids: Vec<Address>) {
+        let new_receipt = |nonce_strategy: &NonceStrategy| {
+            Receipt::new_with_strategy(allocation_ids[0], 1234, nonce_strategy).unwrap()
+        };
+
+        let fixed = NonceStrategy::Fixed(42);
+        assert_eq!(new_receipt(&fixed).nonce, 42);
+        assert_eq!(new_receipt(&fixed).nonce, 42);
+
+        // the clones share the same counter
+        let sequential = NonceStrategy::sequential(7);
+        let other_sequential = sequential.clone();
+        assert_eq!(new_receipt(&sequential).nonce, 7);
+        assert_eq!(new_receipt(&other_sequential).nonce, 8);
+        assert_eq!(new_receipt(&sequential).nonce, 9);
+
+        // Same extremely low probability of false failure as
+        // `test_unique_nonce_and_timestamp`
+        let random = NonceStrategy::Random;
+        assert_ne!(new_receipt(&random).nonce, new_receipt(&random).nonce);
+    }
+
+    #[rstest]
+    fn test_validate(allocation_ids: Vec<Address>) {
+        let receipt = Receipt::new(allocation_ids[0], 1234).unwrap();
+        assert_eq!(receipt.validate(), Ok(()));
+
+        let zero_timestamp = Receipt {
+            timestamp_ns: 0,
+            ..receipt.clone()
+        };
+        assert_eq!(
+            zero_timestamp.validate(),
+            Err(ReceiptValidationError::ZeroTimestamp)
+        );
+
+        let zero_allocation_id = Receipt {
+            allocation_id: Address::ZERO,
+            ..receipt
+        };
+        assert_eq!(
+            zero_allocation_id.validate(),
+            Err(ReceiptValidationError::ZeroAddress {
+                field: "allocation_id"
+            })
+        );
+    }
+
+    #[rstest]
+    fn test_typed_data_json(allocation_ids: Vec<Address>) {
+        let receipt = Receipt::new(allocation_ids[0], u128::MAX).unwrap();
+        let domain = eip712_domain! {
+            name: "TAP",
+            version: "1",
+            chain_id: 1,
+            verifying_contract: allocation_ids[1],
+        };
+        let typed_data = receipt.to_typed_data_json(&domain);
+
+        assert_eq!(typed_data["primaryType"], "Receipt");
+        assert_eq!(
+            typed_data["types"]["Receipt"],
+            json!([
+                { "name": "allocation_id", "type": "address" },
+                { "name": "timestamp_ns", "type": "uint64" },
+                { "name": "nonce", "type": "uint64" },
+                { "name": "value", "type": "uint128" },
+            ])
+        );
+        assert_eq!(
+            typed_data["message"],
+            json!({
+                "allocation_id": allocation_ids[0],
+                "timestamp_ns": receipt.timestamp_ns.to_string(),
+                "nonce": receipt.nonce.to_string(),
+                "value": u128::MAX.to_string(),
+            })
+        );
+
+        // a wallet signs the same hash
+        let typed_data: TypedData = serde_json::from_value(typed_data).unwrap();
+        assert_eq!(
+            typed_data.eip712_signing_hash().unwrap(),
+            receipt.eip712_signing_hash(&domain)
+        );
+    }
 }
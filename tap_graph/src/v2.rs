@@ -4,5 +4,5 @@
 mod rav;
 mod receipt;
 
-pub use rav::{ReceiptAggregateVoucher, SignedRav};
-pub use receipt::{Receipt, SignedReceipt};
+pub use rav::{MetadataSizeCheck, ReceiptAggregateVoucher, SignedRav, DEFAULT_MAX_METADATA_SIZE};
+pub use receipt::{DataService, Payer, Receipt, ServiceProvider, SignedReceipt};
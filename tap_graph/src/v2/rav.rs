@@ -6,22 +6,30 @@
 use std::cmp;
 
 use alloy::{
-    primitives::{Address, Bytes},
+    primitives::{Address, Bytes, FixedBytes},
     sol,
 };
 use serde::{Deserialize, Serialize};
 use tap_eip712_message::Eip712SignedMessage;
 use tap_receipt::{
-    rav::{Aggregate, AggregationError},
-    state::Checked,
-    ReceiptWithState, WithValueAndTimestamp,
+    checks::{Check, CheckError, CheckOutcome, CheckResult},
+    rav::{Aggregate, AggregationError, CheckedSum},
+    state::{Checked, Checking},
+    Context, ReceiptWithState, WithValueAndTimestamp,
 };
 
 use super::{Receipt, SignedReceipt};
+use crate::redemption::RedemptionCost;
 
 /// EIP712 signed message for ReceiptAggregateVoucher
 pub type SignedRav = Eip712SignedMessage<ReceiptAggregateVoucher>;
 
+/// Default maximum size of the RAV metadata, in bytes.
+///
+/// Large enough for a single 32 bytes word, see
+/// [`ReceiptAggregateVoucher::metadata_word`].
+pub const DEFAULT_MAX_METADATA_SIZE: usize = 32;
+
 sol! {
     /// Holds information needed for promise of payment signed with ECDSA
     ///
@@ -41,7 +49,9 @@ sol! {
         // Total amount owed to the service provider since the beginning of the
         // payer-service provider relationship, including all debt that is already paid for.
         uint128 valueAggregate;
-        // Arbitrary metadata to extend functionality if a data service requires it
+        // Arbitrary metadata to extend functionality if a data service requires it.
+        // It is opaque to TAP, which always aggregates RAVs with empty metadata,
+        // and is covered by the signature.
         bytes metadata;
     }
 }
@@ -61,6 +71,38 @@ impl ReceiptAggregateVoucher {
         service_provider: Address,
         receipts: &[Eip712SignedMessage<Receipt>],
         previous_rav: Option<Eip712SignedMessage<Self>>,
+    ) -> Result<Self, AggregationError> {
+        Self::aggregate_receipts_cumulative(
+            allocation_id,
+            payer,
+            data_service,
+            service_provider,
+            receipts,
+            previous_rav,
+            true,
+        )
+    }
+
+    /// Same as [`ReceiptAggregateVoucher::aggregate_receipts`] if `cumulative`
+    /// is `true`. If it is `false`, the value of the RAV is only the sum of
+    /// `receipts`, the delta since `previous_rav`, whose timestamp is still
+    /// the minimum timestamp of the new RAV.
+    ///
+    /// See [`crate::ReceiptAggregateVoucher::aggregate_receipts_cumulative`]
+    /// for why the TAP escrow contracts expect cumulative RAVs.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AggregateOverflow`] if any receipt value causes aggregate
+    /// value to overflow
+    pub fn aggregate_receipts_cumulative(
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        receipts: &[Eip712SignedMessage<Receipt>],
+        previous_rav: Option<Eip712SignedMessage<Self>>,
+        cumulative: bool,
     ) -> Result<Self, AggregationError> {
         //TODO(#29): When receipts in flight struct in created check that the state
         // of every receipt is OK with all checks complete (relies on #28)
@@ -70,16 +112,19 @@ impl ReceiptAggregateVoucher {
 
         if let Some(prev_rav) = previous_rav {
             timestamp_max = prev_rav.message.timestampNs;
-            value_aggregate = prev_rav.message.valueAggregate;
+            if cumulative {
+                value_aggregate = prev_rav.message.valueAggregate;
+            }
         }
 
-        for receipt in receipts {
-            value_aggregate = value_aggregate
-                .checked_add(receipt.message.value)
-                .ok_or(AggregationError::AggregateOverflow)?;
-
-            timestamp_max = cmp::max(timestamp_max, receipt.message.timestamp_ns)
-        }
+        value_aggregate = receipts
+            .iter()
+            .map(|receipt| receipt.message.value)
+            .checked_sum_from(value_aggregate)?;
+        timestamp_max = receipts
+            .iter()
+            .map(|receipt| receipt.message.timestamp_ns)
+            .fold(timestamp_max, cmp::max);
 
         Ok(Self {
             allocationId: allocation_id,
@@ -93,6 +138,85 @@ impl ReceiptAggregateVoucher {
     }
 }
 
+impl ReceiptAggregateVoucher {
+    /// Returns the timestamp of the latest receipt aggregated into this RAV.
+    ///
+    /// Aggregation sets `timestampNs` to the maximum timestamp of the
+    /// aggregated receipts and of the previous RAV, if any. As the previous
+    /// RAV timestamp is itself the latest of its receipts, this is the
+    /// maximum timestamp of all the receipts covered by this RAV, and it is
+    /// never lower than the previous RAV timestamp.
+    pub fn latest_receipt_timestamp(&self) -> u64 {
+        self.timestampNs
+    }
+
+    /// Returns `true` if the value of this RAV exceeds the cost of redeeming
+    /// it on-chain, estimated with
+    /// [`crate::redemption::DEFAULT_REDEMPTION_GAS`].
+    ///
+    /// See [`RedemptionCost`] for the parameters, and to estimate the cost
+    /// with another amount of gas.
+    pub fn is_economical_to_redeem(&self, gas_price_wei: u128, grt_per_gas: f64) -> bool {
+        RedemptionCost::default().is_economical(self.valueAggregate, gas_price_wei, grt_per_gas)
+    }
+
+    /// Returns the metadata as a single 32 bytes word, the format expected by
+    /// data services that attach metadata to RAVs.
+    ///
+    /// Returns `None` if the metadata is not exactly 32 bytes long.
+    pub fn metadata_word(&self) -> Option<FixedBytes<32>> {
+        FixedBytes::try_from(self.metadata.as_ref()).ok()
+    }
+}
+
+/// Rejects RAVs whose metadata is larger than `max_size` bytes.
+///
+/// Metadata is not interpreted by TAP, so without a limit a sender could
+/// bloat the stored RAVs and the calldata used to redeem them.
+///
+/// v2 receipts carry no metadata, so the check runs on signed RAVs, e.g. in
+/// a [`tap_receipt::checks::CheckList`] of the RAVs received by an auditor.
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataSizeCheck {
+    pub max_size: usize,
+}
+
+impl MetadataSizeCheck {
+    pub fn new(max_size: usize) -> Self {
+        Self { max_size }
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`AggregationError::MetadataTooLarge`] if the metadata of
+    /// `rav` exceeds the maximum size.
+    pub fn check_rav(&self, rav: &ReceiptAggregateVoucher) -> Result<(), AggregationError> {
+        let size = rav.metadata.len();
+        if size > self.max_size {
+            return Err(AggregationError::MetadataTooLarge {
+                size,
+                max_size: self.max_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for MetadataSizeCheck {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_METADATA_SIZE)
+    }
+}
+
+#[async_trait::async_trait]
+impl Check<SignedRav> for MetadataSizeCheck {
+    async fn check(&self, _: &Context, rav: &ReceiptWithState<Checking, SignedRav>) -> CheckResult {
+        self.check_rav(&rav.signed_receipt().message)
+            .map(|()| CheckOutcome::passed())
+            .map_err(|e| CheckError::Failed(e.into()))
+    }
+}
+
 impl Aggregate<SignedReceipt> for ReceiptAggregateVoucher {
     fn aggregate_receipts(
         receipts: &[ReceiptWithState<Checked, SignedReceipt>],
@@ -129,3 +253,174 @@ impl WithValueAndTimestamp for ReceiptAggregateVoucher {
         self.timestampNs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy::{
+        dyn_abi::Eip712Domain,
+        primitives::{Address, Bytes, FixedBytes},
+        signers::local::PrivateKeySigner,
+    };
+    use rstest::*;
+    use tap_eip712_message::Eip712SignedMessage;
+
+    use tap_receipt::{
+        checks::{CheckList, ReceiptCheck},
+        Context, ReceiptWithState,
+    };
+
+    use super::{
+        MetadataSizeCheck, Receipt, ReceiptAggregateVoucher, SignedRav, SignedReceipt,
+        DEFAULT_MAX_METADATA_SIZE,
+    };
+
+    #[fixture]
+    fn rav() -> ReceiptAggregateVoucher {
+        ReceiptAggregateVoucher::aggregate_receipts(
+            Address::ZERO,
+            Address::ZERO,
+            Address::ZERO,
+            Address::ZERO,
+            &[],
+            None,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    fn metadata_at_size_limit(mut rav: ReceiptAggregateVoucher) {
+        assert!(rav.metadata.is_empty());
+        assert!(rav.metadata_word().is_none());
+
+        rav.metadata = Bytes::from(vec![0x11u8; DEFAULT_MAX_METADATA_SIZE]);
+        assert!(MetadataSizeCheck::default().check_rav(&rav).is_ok());
+        assert_eq!(rav.metadata_word(), Some(FixedBytes::from([0x11u8; 32])));
+    }
+
+    #[rstest]
+    fn metadata_over_size_limit(mut rav: ReceiptAggregateVoucher) {
+        rav.metadata = Bytes::from(vec![0x11u8; DEFAULT_MAX_METADATA_SIZE + 1]);
+        assert!(MetadataSizeCheck::default().check_rav(&rav).is_err());
+        assert!(rav.metadata_word().is_none());
+
+        // a larger limit can be configured
+        assert!(MetadataSizeCheck::new(64).check_rav(&rav).is_ok());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn metadata_size_checked_by_check_list(mut rav: ReceiptAggregateVoucher) {
+        let checks = CheckList::new(vec![
+            Arc::new(MetadataSizeCheck::default()) as ReceiptCheck<SignedRav>
+        ]);
+        let domain_separator = Eip712Domain::default();
+        let wallet = PrivateKeySigner::random();
+        let ctx = Context::new();
+
+        rav.metadata = Bytes::from(vec![0x11u8; DEFAULT_MAX_METADATA_SIZE]);
+        let signed_rav = Eip712SignedMessage::new(&domain_separator, rav.clone(), &wallet).unwrap();
+        assert!(checks
+            .perform_checks(&ctx, &ReceiptWithState::new(signed_rav))
+            .await
+            .is_ok());
+
+        rav.metadata = Bytes::from(vec![0x11u8; DEFAULT_MAX_METADATA_SIZE + 1]);
+        let signed_rav = Eip712SignedMessage::new(&domain_separator, rav, &wallet).unwrap();
+        assert!(checks
+            .perform_checks(&ctx, &ReceiptWithState::new(signed_rav))
+            .await
+            .is_err());
+    }
+
+    #[rstest]
+    fn latest_receipt_timestamp() {
+        let wallet = PrivateKeySigner::random();
+        let receipts: Vec<SignedReceipt> = [30, 10, 20]
+            .into_iter()
+            .map(|timestamp_ns| {
+                let receipt = Receipt {
+                    allocation_id: Address::ZERO,
+                    payer: Address::ZERO,
+                    data_service: Address::ZERO,
+                    service_provider: Address::ZERO,
+                    timestamp_ns,
+                    nonce: timestamp_ns,
+                    value: 1,
+                };
+                Eip712SignedMessage::new(&Eip712Domain::default(), receipt, &wallet).unwrap()
+            })
+            .collect();
+
+        let rav = ReceiptAggregateVoucher::aggregate_receipts(
+            Address::ZERO,
+            Address::ZERO,
+            Address::ZERO,
+            Address::ZERO,
+            &receipts[..2],
+            None,
+        )
+        .unwrap();
+        assert_eq!(rav.latest_receipt_timestamp(), 30);
+
+        // the previous RAV covers a later receipt
+        let previous_rav =
+            Eip712SignedMessage::new(&Eip712Domain::default(), rav, &wallet).unwrap();
+        let rav = ReceiptAggregateVoucher::aggregate_receipts(
+            Address::ZERO,
+            Address::ZERO,
+            Address::ZERO,
+            Address::ZERO,
+            &receipts[2..],
+            Some(previous_rav),
+        )
+        .unwrap();
+        assert_eq!(rav.latest_receipt_timestamp(), 30);
+    }
+
+    #[rstest]
+    fn cumulative_and_delta_ravs(rav: ReceiptAggregateVoucher) {
+        let wallet = PrivateKeySigner::random();
+        let receipts: Vec<SignedReceipt> = [10, 20]
+            .into_iter()
+            .map(|timestamp_ns| {
+                let receipt = Receipt {
+                    allocation_id: Address::ZERO,
+                    payer: Address::ZERO,
+                    data_service: Address::ZERO,
+                    service_provider: Address::ZERO,
+                    timestamp_ns,
+                    nonce: timestamp_ns,
+                    value: 1,
+                };
+                Eip712SignedMessage::new(&Eip712Domain::default(), receipt, &wallet).unwrap()
+            })
+            .collect();
+        let previous_rav = Eip712SignedMessage::new(
+            &Eip712Domain::default(),
+            ReceiptAggregateVoucher {
+                timestampNs: 5,
+                valueAggregate: 100,
+                ..rav
+            },
+            &wallet,
+        )
+        .unwrap();
+
+        for (cumulative, value_aggregate) in [(true, 102), (false, 2)] {
+            let rav = ReceiptAggregateVoucher::aggregate_receipts_cumulative(
+                Address::ZERO,
+                Address::ZERO,
+                Address::ZERO,
+                Address::ZERO,
+                &receipts,
+                Some(previous_rav.clone()),
+                cumulative,
+            )
+            .unwrap();
+            assert_eq!(rav.valueAggregate, value_aggregate);
+            assert_eq!(rav.timestampNs, 20);
+        }
+    }
+}
@@ -5,11 +5,13 @@
 
 use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 
-use alloy::{primitives::Address, sol};
-use rand::{thread_rng, Rng};
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, sol, sol_types::SolStruct};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use tap_eip712_message::Eip712SignedMessage;
-use tap_receipt::WithValueAndTimestamp;
+use tap_receipt::{WithAllocationId, WithValueAndTimestamp};
+
+use crate::{typed_data::typed_data_json, NonceStrategy, ReceiptValidationError};
 
 /// A signed receipt message
 pub type SignedReceipt = Eip712SignedMessage<Receipt>;
@@ -37,30 +39,161 @@ sol! {
     }
 }
 
+/// Address of the payer of a [`Receipt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Payer(pub Address);
+
+/// Address of the data service of a [`Receipt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DataService(pub Address);
+
+/// Address of the service provider of a [`Receipt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ServiceProvider(pub Address);
+
+impl From<Address> for Payer {
+    fn from(address: Address) -> Self {
+        Self(address)
+    }
+}
+
+impl From<Address> for DataService {
+    fn from(address: Address) -> Self {
+        Self(address)
+    }
+}
+
+impl From<Address> for ServiceProvider {
+    fn from(address: Address) -> Self {
+        Self(address)
+    }
+}
+
 fn get_current_timestamp_u64_ns() -> Result<u64, SystemTimeError> {
     Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64)
 }
 impl Receipt {
     /// Returns a receipt with provided values
+    ///
+    /// The payer, data service and service provider are given as plain
+    /// addresses, or wrapped in distinct types so that they cannot be swapped
+    /// by mistake.
     pub fn new(
         allocation_id: Address,
-        payer: Address,
-        data_service: Address,
-        service_provider: Address,
+        payer: impl Into<Payer>,
+        data_service: impl Into<DataService>,
+        service_provider: impl Into<ServiceProvider>,
         value: u128,
     ) -> Result<Self, SystemTimeError> {
-        let timestamp_ns = get_current_timestamp_u64_ns()?;
-        let nonce = thread_rng().gen::<u64>();
-        Ok(Self {
+        Self::new_with_strategy(
             allocation_id,
             payer,
             data_service,
             service_provider,
+            value,
+            &NonceStrategy::Random,
+        )
+    }
+
+    /// Returns a receipt with provided values and a nonce chosen by
+    /// `nonce_strategy`
+    pub fn new_with_strategy(
+        allocation_id: Address,
+        payer: impl Into<Payer>,
+        data_service: impl Into<DataService>,
+        service_provider: impl Into<ServiceProvider>,
+        value: u128,
+        nonce_strategy: &NonceStrategy,
+    ) -> Result<Self, SystemTimeError> {
+        let timestamp_ns = get_current_timestamp_u64_ns()?;
+        let nonce = nonce_strategy.next_nonce();
+        Ok(Self {
+            allocation_id,
+            payer: payer.into().0,
+            data_service: data_service.into().0,
+            service_provider: service_provider.into().0,
             timestamp_ns,
             nonce,
             value,
         })
     }
+
+    /// Maps a v1 `receipt` to a v2 receipt with the same allocation id,
+    /// timestamp, nonce and value, for the given payer, data service and
+    /// service provider.
+    ///
+    /// The v2 receipt must be signed again, the signature of the v1 receipt
+    /// does not cover the new fields.
+    pub fn from_v1(
+        receipt: &crate::v1::Receipt,
+        payer: impl Into<Payer>,
+        data_service: impl Into<DataService>,
+        service_provider: impl Into<ServiceProvider>,
+    ) -> Self {
+        Self {
+            allocation_id: receipt.allocation_id,
+            payer: payer.into().0,
+            data_service: data_service.into().0,
+            service_provider: service_provider.into().0,
+            timestamp_ns: receipt.timestamp_ns,
+            nonce: receipt.nonce,
+            value: receipt.value,
+        }
+    }
+
+    /// Checks the consistency of the fields of the receipt, to catch
+    /// construction bugs before signing it.
+    ///
+    /// The timestamp must not be zero, and none of the allocation id, payer,
+    /// data service and service provider may be the zero address. Every
+    /// nonce and value is valid.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`ReceiptValidationError`] found
+    ///
+    pub fn validate(&self) -> Result<(), ReceiptValidationError> {
+        if self.timestamp_ns == 0 {
+            return Err(ReceiptValidationError::ZeroTimestamp);
+        }
+        for (field, address) in [
+            ("allocation_id", self.allocation_id),
+            ("payer", self.payer),
+            ("data_service", self.data_service),
+            ("service_provider", self.service_provider),
+        ] {
+            if address.is_zero() {
+                return Err(ReceiptValidationError::ZeroAddress { field });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the receipt as EIP-712 typed data, with its types, `domain`
+    /// and message, in the JSON structure expected by
+    /// `eth_signTypedData_v4`, so that a browser wallet can sign it.
+    ///
+    /// The integer fields are given as decimal strings.
+    pub fn to_typed_data_json(&self, domain: &Eip712Domain) -> serde_json::Value {
+        typed_data_json::<Self>(
+            domain,
+            json!({
+                "allocation_id": self.allocation_id,
+                "payer": self.payer,
+                "data_service": self.data_service,
+                "service_provider": self.service_provider,
+                "timestamp_ns": self.timestamp_ns.to_string(),
+                "nonce": self.nonce.to_string(),
+                "value": self.value.to_string(),
+            }),
+        )
+    }
+}
+
+impl WithAllocationId for Receipt {
+    fn allocation_id(&self) -> Address {
+        self.allocation_id
+    }
 }
 
 impl WithValueAndTimestamp for Receipt {
@@ -77,7 +210,7 @@ impl WithValueAndTimestamp for Receipt {
 mod receipt_unit_test {
     use std::time::{SystemTime, UNIX_EPOCH};
 
-    use alloy::primitives::address;
+    use alloy::{dyn_abi::TypedData, primitives::address, sol_types::eip712_domain};
     use rstest::*;
 
     use super::*;
@@ -115,14 +248,38 @@ mod receipt_unit_test {
         service_provider: Address,
         value: u128,
     ) -> Receipt {
-        Receipt::new(allocation_id, payer, data_service, service_provider, value).unwrap()
+        Receipt::new(
+            allocation_id,
+            Payer(payer),
+            DataService(data_service),
+            ServiceProvider(service_provider),
+            value,
+        )
+        .unwrap()
     }
 
     #[rstest]
-    fn test_new_receipt(allocation_id: Address, value: u128, receipt: Receipt) {
+    fn test_new_receipt(
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        value: u128,
+        receipt: Receipt,
+    ) {
         assert_eq!(receipt.allocation_id, allocation_id);
+        assert_eq!(receipt.payer, payer);
+        assert_eq!(receipt.data_service, data_service);
+        assert_eq!(receipt.service_provider, service_provider);
         assert_eq!(receipt.value, value);
 
+        // plain addresses are accepted as well
+        let from_addresses =
+            Receipt::new(allocation_id, payer, data_service, service_provider, value).unwrap();
+        assert_eq!(from_addresses.payer, payer);
+        assert_eq!(from_addresses.data_service, data_service);
+        assert_eq!(from_addresses.service_provider, service_provider);
+
         // Check that the timestamp is within a reasonable range
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -155,4 +312,127 @@ mod receipt_unit_test {
         assert!(receipt2.timestamp_ns <= now);
         assert!(receipt2.timestamp_ns >= now - 5000000); // 5 second tolerance
     }
+
+    #[rstest]
+    fn test_sequential_nonces(
+        allocation_id: Address,
+        payer: Address,
+        data_service: Address,
+        service_provider: Address,
+        value: u128,
+    ) {
+        let nonce_strategy = NonceStrategy::sequential(u64::MAX - 1);
+        let nonces: Vec<u64> = (0..3)
+            .map(|_| {
+                Receipt::new_with_strategy(
+                    allocation_id,
+                    Payer(payer),
+                    DataService(data_service),
+                    ServiceProvider(service_provider),
+                    value,
+                    &nonce_strategy,
+                )
+                .unwrap()
+                .nonce
+            })
+            .collect();
+
+        // the counter wraps around
+        assert_eq!(nonces, vec![u64::MAX - 1, u64::MAX, 0]);
+    }
+
+    #[rstest]
+    fn test_validate(receipt: Receipt) {
+        assert_eq!(receipt.validate(), Ok(()));
+
+        let zero_timestamp = Receipt {
+            timestamp_ns: 0,
+            ..receipt.clone()
+        };
+        assert_eq!(
+            zero_timestamp.validate(),
+            Err(ReceiptValidationError::ZeroTimestamp)
+        );
+
+        let zero_addresses = [
+            (
+                "allocation_id",
+                Receipt {
+                    allocation_id: Address::ZERO,
+                    ..receipt.clone()
+                },
+            ),
+            (
+                "payer",
+                Receipt {
+                    payer: Address::ZERO,
+                    ..receipt.clone()
+                },
+            ),
+            (
+                "data_service",
+                Receipt {
+                    data_service: Address::ZERO,
+                    ..receipt.clone()
+                },
+            ),
+            (
+                "service_provider",
+                Receipt {
+                    service_provider: Address::ZERO,
+                    ..receipt
+                },
+            ),
+        ];
+        for (field, receipt) in zero_addresses {
+            assert_eq!(
+                receipt.validate(),
+                Err(ReceiptValidationError::ZeroAddress { field })
+            );
+        }
+    }
+
+    #[rstest]
+    fn test_typed_data_json(receipt: Receipt, data_service: Address) {
+        let domain = eip712_domain! {
+            name: "TAP",
+            version: "1",
+            chain_id: 1,
+            verifying_contract: data_service,
+        };
+        let typed_data = receipt.to_typed_data_json(&domain);
+
+        assert_eq!(typed_data["primaryType"], "Receipt");
+        assert_eq!(
+            typed_data["types"]["Receipt"],
+            json!([
+                { "name": "allocation_id", "type": "address" },
+                { "name": "payer", "type": "address" },
+                { "name": "data_service", "type": "address" },
+                { "name": "service_provider", "type": "address" },
+                { "name": "timestamp_ns", "type": "uint64" },
+                { "name": "nonce", "type": "uint64" },
+                { "name": "value", "type": "uint128" },
+            ])
+        );
+        assert_eq!(
+            typed_data["message"],
+            json!({
+                "allocation_id": receipt.allocation_id,
+                "payer": receipt.payer,
+                "data_service": receipt.data_service,
+                "service_provider": receipt.service_provider,
+                "timestamp_ns": receipt.timestamp_ns.to_string(),
+                "nonce": receipt.nonce.to_string(),
+                "value": receipt.value.to_string(),
+            })
+        );
+
+        // a wallet signs the same hash
+        let typed_data: TypedData = serde_json::from_value(typed_data).unwrap();
+        assert_eq!(
+            typed_data.eip712_signing_hash().unwrap(),
+            receipt.eip712_signing_hash(&domain)
+        );
+    }
 }
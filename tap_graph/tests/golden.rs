@@ -0,0 +1,106 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Wire format pinned for known receipts and RAVs.
+//!
+//! The JSON is what the aggregators and indexers exchange, and the EIP-712
+//! struct hash is what the signatures cover. Renaming, reordering or
+//! retyping a field changes them and breaks the compatibility with the
+//! deployed aggregators, indexers and contracts, so such a change must be
+//! deliberate and not just an update of these values.
+
+use alloy::{
+    primitives::{address, b256, Address, B256},
+    sol_types::SolStruct,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+const ALLOCATION_ID: Address = address!("abababababababababababababababababababab");
+
+/// Checks the JSON of `message` both ways, its EIP-712 type and struct hash
+fn assert_golden<M>(message: M, json: &str, encode_type: &str, struct_hash: B256)
+where
+    M: SolStruct + Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    assert_eq!(serde_json::to_string(&message).unwrap(), json);
+    assert_eq!(serde_json::from_str::<M>(json).unwrap(), message);
+    assert_eq!(M::eip712_encode_type(), encode_type);
+    assert_eq!(message.eip712_hash_struct(), struct_hash);
+}
+
+#[test]
+fn v1_receipt() {
+    assert_golden(
+        tap_graph::Receipt {
+            allocation_id: ALLOCATION_ID,
+            timestamp_ns: 1685670449225087255,
+            nonce: 11835827017881841442,
+            value: 34,
+        },
+        r#"{"allocation_id":"0xabababababababababababababababababababab","timestamp_ns":1685670449225087255,"nonce":11835827017881841442,"value":34}"#,
+        "Receipt(address allocation_id,uint64 timestamp_ns,uint64 nonce,uint128 value)",
+        b256!("9a9c0909356846a232a0c1c762d2c6791baec2a42d45ed56fc1de5e695b6b4c0"),
+    );
+}
+
+#[test]
+fn v1_rav() {
+    assert_golden(
+        tap_graph::ReceiptAggregateVoucher {
+            allocationId: ALLOCATION_ID,
+            timestampNs: 1685670449225830106,
+            valueAggregate: 158,
+        },
+        r#"{"allocationId":"0xabababababababababababababababababababab","timestampNs":1685670449225830106,"valueAggregate":158}"#,
+        "ReceiptAggregateVoucher(address allocationId,uint64 timestampNs,uint128 valueAggregate)",
+        b256!("28ac136fc0329b99c72d71b6b99d4d0f743761484388da4b5fb60ddd7cd01ab5"),
+    );
+}
+
+#[cfg(feature = "v2")]
+mod v2 {
+    use alloy::primitives::{address, b256, bytes, Address};
+    use tap_graph::v2::{Receipt, ReceiptAggregateVoucher};
+
+    use super::{assert_golden, ALLOCATION_ID};
+
+    const PAYER: Address = address!("fafafafafafafafafafafafafafafafafafafafa");
+    const DATA_SERVICE: Address = address!("dededededededededededededededededededede");
+    const SERVICE_PROVIDER: Address = address!("bebebebebebebebebebebebebebebebebebebebe");
+
+    #[test]
+    fn v2_receipt() {
+        assert_golden(
+            Receipt {
+                allocation_id: ALLOCATION_ID,
+                payer: PAYER,
+                data_service: DATA_SERVICE,
+                service_provider: SERVICE_PROVIDER,
+                timestamp_ns: 1685670449225087255,
+                nonce: 11835827017881841442,
+                value: 34,
+            },
+            r#"{"allocation_id":"0xabababababababababababababababababababab","payer":"0xfafafafafafafafafafafafafafafafafafafafa","data_service":"0xdededededededededededededededededededede","service_provider":"0xbebebebebebebebebebebebebebebebebebebebe","timestamp_ns":1685670449225087255,"nonce":11835827017881841442,"value":34}"#,
+            "Receipt(address allocation_id,address payer,address data_service,address service_provider,uint64 timestamp_ns,uint64 nonce,uint128 value)",
+            b256!("78f2a05d204363d22b89c08487f65753487db41f0e640247e0533894d64d0317"),
+        );
+    }
+
+    #[test]
+    fn v2_rav() {
+        assert_golden(
+            ReceiptAggregateVoucher {
+                allocationId: ALLOCATION_ID,
+                payer: PAYER,
+                dataService: DATA_SERVICE,
+                serviceProvider: SERVICE_PROVIDER,
+                timestampNs: 1685670449225830106,
+                valueAggregate: 158,
+                metadata: bytes!("deadbeef"),
+            },
+            r#"{"allocationId":"0xabababababababababababababababababababab","payer":"0xfafafafafafafafafafafafafafafafafafafafa","dataService":"0xdededededededededededededededededededede","serviceProvider":"0xbebebebebebebebebebebebebebebebebebebebe","timestampNs":1685670449225830106,"valueAggregate":158,"metadata":"0xdeadbeef"}"#,
+            "ReceiptAggregateVoucher(address allocationId,address payer,address dataService,address serviceProvider,uint64 timestampNs,uint128 valueAggregate,bytes metadata)",
+            b256!("9e4bb532545a55174d5338e5b35e1140d7a88693a4d0ef318e9c3a8f914749c4"),
+        );
+    }
+}
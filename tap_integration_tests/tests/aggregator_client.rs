@@ -0,0 +1,228 @@
+// Copyright 2023-, Semiotic AI, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// AggregatorClient sends RAV requests to a tap_aggregator server, retrying
+// with an exponential backoff on transient errors.
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use alloy::{primitives::Address, signers::local::PrivateKeySigner};
+use anyhow::Result;
+use jsonrpsee::{
+    http_client::{transport, HttpClient, HttpClientBuilder},
+    rpc_params,
+};
+use jsonrpsee_core::{client::ClientT, ClientError};
+use rstest::*;
+use tap_aggregator::jsonrpsee_helpers::JsonRpcResponse;
+use tap_core::{jsonrpc::JsonRpcErrorCode, signed_message::Eip712SignedMessage, tap_eip712_domain};
+use tap_graph::{Receipt, ReceiptAggregateVoucher, SignedRav, SignedReceipt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// RetryConfig sets how many times, and how fast, a failed RAV request is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    pub max_retries: u32,     // Number of retries after the first attempt
+    pub base_delay: Duration, // Delay before the first retry, doubled after each retry
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// AggregatorClient is a JSON-RPC client of the tap_aggregator server.
+/// Requests failing with a transient error (timeout, connection error, 5xx status or
+/// busy server) are retried. Other errors, e.g. invalid receipts or signatures, are not.
+pub struct AggregatorClient {
+    client: HttpClient,  // HTTP client for sending requests to the aggregator server
+    api_version: String, // API version of the aggregator server
+    retry: RetryConfig,  // Retry policy on transient errors
+}
+
+impl AggregatorClient {
+    pub fn new(aggregate_server_address: String, api_version: String) -> Result<Self> {
+        Ok(Self {
+            client: HttpClientBuilder::default().build(aggregate_server_address)?,
+            api_version,
+            retry: RetryConfig::default(),
+        })
+    }
+
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Calls the aggregate_receipts method of the aggregator server and returns the signed RAV.
+    pub async fn aggregate_receipts(
+        &self,
+        receipts: &[&SignedReceipt],
+        previous_rav: Option<&SignedRav>,
+    ) -> Result<SignedRav, ClientError> {
+        let mut delay = self.retry.base_delay;
+        let mut retries = 0;
+        loop {
+            let params = rpc_params!(&self.api_version, receipts, previous_rav);
+            let result: Result<JsonRpcResponse<SignedRav>, ClientError> =
+                self.client.request("aggregate_receipts", params).await;
+            match result {
+                Ok(response) => return Ok(response.data),
+                Err(e) if retries < self.retry.max_retries && is_transient(&e) => {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    retries += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn is_transient(error: &ClientError) -> bool {
+    match error {
+        ClientError::RequestTimeout => true,
+        ClientError::Transport(e) => match e.downcast_ref::<transport::Error>() {
+            Some(transport::Error::Rejected { status_code }) => *status_code >= 500,
+            Some(transport::Error::Http(_)) => true,
+            _ => false,
+        },
+        ClientError::Call(e) => e.code() == JsonRpcErrorCode::ServerBusy as i32,
+        _ => false,
+    }
+}
+
+#[fixture]
+fn keys() -> PrivateKeySigner {
+    PrivateKeySigner::random()
+}
+
+#[fixture]
+fn receipt(keys: PrivateKeySigner) -> SignedReceipt {
+    Eip712SignedMessage::new(
+        &tap_eip712_domain(1, Address::ZERO),
+        Receipt::new(Address::ZERO, 42).unwrap(),
+        &keys,
+    )
+    .unwrap()
+}
+
+#[fixture]
+fn rav(keys: PrivateKeySigner) -> SignedRav {
+    let rav = ReceiptAggregateVoucher {
+        allocationId: Address::ZERO,
+        timestampNs: 1,
+        valueAggregate: 42,
+    };
+    Eip712SignedMessage::new(&tap_eip712_domain(1, Address::ZERO), rav, &keys).unwrap()
+}
+
+// Starts a mock aggregator answering with the `failures` HTTP statuses, in order, then
+// with `rav`. Returns its address and the number of requests received.
+async fn flaky_server(failures: Vec<u16>, rav: SignedRav) -> (SocketAddr, Arc<AtomicU32>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicU32::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let body = read_request_body(&mut stream).await;
+            let attempt = counter.fetch_add(1, Ordering::SeqCst) as usize;
+            let response = match failures.get(attempt) {
+                Some(status) => {
+                    format!(
+                        "HTTP/1.1 {status} Error\r\nconnection: close\r\ncontent-length: 0\r\n\r\n"
+                    )
+                }
+                None => {
+                    let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    let body = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": JsonRpcResponse::ok(rav.clone()),
+                    })
+                    .to_string();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                        body.len()
+                    )
+                }
+            };
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    (addr, requests)
+}
+
+async fn read_request_body(stream: &mut TcpStream) -> Vec<u8> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some(headers_end) = text.find("\r\n\r\n") {
+            let content_length = text[..headers_end]
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or(0);
+            let body_start = headers_end + 4;
+            if request.len() >= body_start + content_length {
+                return request[body_start..body_start + content_length].to_vec();
+            }
+        }
+    }
+}
+
+fn client(addr: SocketAddr) -> AggregatorClient {
+    AggregatorClient::new(format!("http://{addr}"), "0.0".to_string())
+        .unwrap()
+        .with_retry(RetryConfig {
+            max_retries: 3,
+            base_delay: Duration::from_millis(1),
+        })
+}
+
+#[rstest]
+#[tokio::test]
+async fn retries_transient_errors(receipt: SignedReceipt, rav: SignedRav) {
+    let (addr, requests) = flaky_server(vec![503, 502], rav.clone()).await;
+
+    let signed_rav = client(addr)
+        .aggregate_receipts(&[&receipt], None)
+        .await
+        .unwrap();
+
+    // succeeds on the third attempt
+    assert_eq!(signed_rav, rav);
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[rstest]
+#[tokio::test]
+async fn does_not_retry_client_errors(receipt: SignedReceipt, rav: SignedRav) {
+    let (addr, requests) = flaky_server(vec![400], rav).await;
+
+    let result = client(addr).aggregate_receipts(&[&receipt], None).await;
+
+    assert!(result.is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
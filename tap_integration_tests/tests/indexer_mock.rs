@@ -1,29 +1,70 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
-use std::sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
-};
+use std::sync::Arc;
 
 use alloy::dyn_abi::Eip712Domain;
 use anyhow::{Error, Result};
 use jsonrpsee::{
     core::async_trait,
-    http_client::{HttpClient, HttpClientBuilder},
     proc_macros::rpc,
-    rpc_params,
     server::{ServerBuilder, ServerHandle},
 };
-use jsonrpsee_core::client::ClientT;
-use tap_aggregator::jsonrpsee_helpers;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge_vec, register_int_gauge_vec, Gauge, GaugeVec, IntGauge, IntGaugeVec,
+};
 use tap_core::{
+    jsonrpc::aggregation_error,
     manager::{
         adapters::{RavRead, RavStore, ReceiptRead, ReceiptStore, SignatureChecker},
-        Manager,
+        Manager, RavTrigger,
     },
     receipt::{checks::CheckList, Context},
 };
-use tap_graph::{ReceiptAggregateVoucher, SignedRav, SignedReceipt};
+use tap_graph::{ReceiptAggregateVoucher, SignedReceipt};
+
+use crate::aggregator_client::AggregatorClient;
+
+lazy_static! {
+    static ref STORED_RECEIPTS: IntGaugeVec = register_int_gauge_vec!(
+        "indexer_stored_receipts",
+        "Number of receipts stored by the indexer.",
+        &["indexer"]
+    )
+    .unwrap();
+    static ref RECEIPTS_SINCE_LAST_RAV: IntGaugeVec = register_int_gauge_vec!(
+        "indexer_receipts_since_last_rav",
+        "Number of receipts received since the last RAV request.",
+        &["indexer"]
+    )
+    .unwrap();
+    static ref LAST_RAV_VALUE: GaugeVec = register_gauge_vec!(
+        "indexer_last_rav_value",
+        "Value aggregate of the last RAV received by the indexer.",
+        &["indexer"]
+    )
+    .unwrap();
+}
+
+/// IndexerMetrics holds the Prometheus gauges of a single indexer, labelled with its address.
+/// Several indexers can run in the same process, e.g. in the tests.
+#[derive(Clone)]
+pub struct IndexerMetrics {
+    pub stored_receipts: IntGauge,
+    pub receipts_since_last_rav: IntGauge,
+    pub last_rav_value: Gauge,
+}
+
+impl IndexerMetrics {
+    pub fn new(indexer: &str) -> Self {
+        Self {
+            stored_receipts: STORED_RECEIPTS.with_label_values(&[indexer]),
+            receipts_since_last_rav: RECEIPTS_SINCE_LAST_RAV.with_label_values(&[indexer]),
+            last_rav_value: LAST_RAV_VALUE.with_label_values(&[indexer]),
+        }
+    }
+}
+
 /// Rpc trait represents a JSON-RPC server that has a single async method `request`.
 /// This method is designed to handle incoming JSON-RPC requests.
 #[rpc(server)]
@@ -37,17 +78,19 @@ pub trait Rpc {
 }
 
 /// RpcManager is a struct that implements the `Rpc` trait and it represents a JSON-RPC server manager.
-/// It includes a manager, initial_checks, receipt_count, threshold and aggregator_client.
+/// It includes a manager, initial_checks, rav_trigger, threshold and aggregator_client.
 /// Manager holds an Arc to an instance of a generic `Manager` object which is shared and can be accessed by multiple threads.
 /// initial_checks is a list of checks that needs to be performed for every incoming request.
-/// receipt_count is a thread-safe counter that increments with each receipt verified and stored.
-/// threshold is a limit to which receipt_count can increment, after reaching which RAV request is triggered.
-/// aggregator_client is an HTTP client used for making JSON-RPC requests to another server.
+/// rav_trigger is a thread-safe counter that tracks each receipt verified and stored.
+/// threshold is the receipt count after reaching which RAV request is triggered.
+/// aggregator_client is a JSON-RPC client of the aggregator server, retrying on transient errors.
+/// metrics holds the gauges updated on every request.
 pub struct RpcManager<E> {
     manager: Arc<Manager<E, SignedReceipt>>, // Manager object reference counted with an Arc
-    receipt_count: Arc<AtomicU64>,           // Thread-safe atomic counter for receipts
+    rav_trigger: RavTrigger,                 // Thread-safe receipt counter signaling RAV requests
     threshold: u64,                          // The count at which a RAV request will be triggered
-    aggregator_client: (HttpClient, String), // HTTP client for sending requests to the aggregator server
+    aggregator_client: AggregatorClient,     // Client for sending requests to the aggregator server
+    metrics: IndexerMetrics,                 // Gauges of this indexer
 }
 
 /// Implementation for `RpcManager`, includes the constructor and the `request` method.
@@ -64,19 +107,21 @@ where
         threshold: u64,
         aggregate_server_address: String,
         aggregate_server_api_version: String,
+        metrics: IndexerMetrics,
     ) -> Result<Self> {
         Ok(Self {
             manager: Arc::new(Manager::<E, SignedReceipt>::new(
                 domain_separator,
                 context,
                 required_checks,
-            )),
-            receipt_count: Arc::new(AtomicU64::new(0)),
+            )?),
+            rav_trigger: RavTrigger::new().with_receipt_count_threshold(threshold),
             threshold,
-            aggregator_client: (
-                HttpClientBuilder::default().build(aggregate_server_address)?,
+            aggregator_client: AggregatorClient::new(
+                aggregate_server_address,
                 aggregate_server_api_version,
-            ),
+            )?,
+            metrics,
         })
     }
 }
@@ -97,24 +142,25 @@ where
         &self,
         receipt: SignedReceipt,
     ) -> Result<(), jsonrpsee::types::ErrorObjectOwned> {
-        let verify_result = match self
+        let value = receipt.message.value;
+        let verify_result: Result<(), jsonrpsee::types::ErrorObjectOwned> = match self
             .manager
             .verify_and_store_receipt(&Context::new(), receipt)
             .await
         {
-            Ok(_) => Ok(()),
-            Err(e) => Err(to_rpc_error(
-                Box::new(e),
-                "Failed to verify and store receipt",
-            )),
+            Ok(_) => {
+                self.metrics.stored_receipts.inc();
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
         };
 
-        // Increment the receipt count
-        self.receipt_count.fetch_add(1, Ordering::Relaxed);
-        let rav_request_valid = if self.receipt_count.load(Ordering::SeqCst) >= self.threshold {
-            // Reset the counter after reaching the threshold
-            self.receipt_count.store(0, Ordering::SeqCst);
-
+        // Record the receipt, the trigger resets itself after reaching the threshold
+        let rav_requested = self.rav_trigger.record_receipt(value);
+        self.metrics
+            .receipts_since_last_rav
+            .set(self.rav_trigger.receipt_count() as i64);
+        let rav_request_valid = if rav_requested {
             // Create the aggregate_receipts request params
             let time_stamp_buffer = 0;
             match request_rav(
@@ -125,8 +171,15 @@ where
             )
             .await
             {
-                Ok(_) => Ok(()),
-                Err(e) => Err(to_rpc_error(e.into(), "Failed to request rav")),
+                Ok(value_aggregate) => {
+                    self.metrics.last_rav_value.set(value_aggregate as f64);
+                    Ok(())
+                }
+                Err(e) => Err(match e.downcast::<tap_core::Error>() {
+                    Ok(e) => e.into(),
+                    // e.g. the aggregator could not be reached
+                    Err(e) => aggregation_error(&e),
+                }),
             }
         } else {
             Ok(())
@@ -176,6 +229,7 @@ where
         threshold,
         aggregate_server_address,
         aggregate_server_api_version,
+        IndexerMetrics::new(&addr.to_string()),
     )?;
 
     let handle = server.start(rpc_manager.into_rpc());
@@ -183,12 +237,13 @@ where
 }
 
 // request_rav function creates a request for aggregate receipts (RAV), sends it to another server and verifies the result.
+// Returns the value aggregate of the RAV.
 async fn request_rav<E>(
     manager: &Arc<Manager<E, SignedReceipt>>,
     time_stamp_buffer: u64, // Buffer for timestamping, see tap_core for details
-    aggregator_client: &(HttpClient, String), // HttpClient for making requests to the tap_aggregator server
+    aggregator_client: &AggregatorClient, // Client for making requests to the tap_aggregator server
     threshold: usize,
-) -> Result<()>
+) -> Result<u128>
 where
     E: ReceiptRead<SignedReceipt>
         + RavRead<ReceiptAggregateVoucher>
@@ -196,28 +251,31 @@ where
         + SignatureChecker,
 {
     // Create the aggregate_receipts request params
-    let rav_request = manager
-        .create_rav_request(&Context::new(), time_stamp_buffer, None)
-        .await?;
+    let Some(rav_request) = manager
+        .create_rav_request(&Context::new(), time_stamp_buffer, None, None)
+        .await?
+    else {
+        return Err(Error::msg("No receipts to aggregate"));
+    };
 
     // To-do: Need to add previous RAV, when tap_manager supports replacing receipts
-    let params = rpc_params!(
-        &aggregator_client.1,
-        &rav_request
-            .valid_receipts
-            .iter()
-            .map(|receipt| receipt.signed_receipt())
-            .collect::<Vec<_>>(),
-        rav_request.previous_rav
-    );
+    let receipts = rav_request
+        .valid_receipts
+        .iter()
+        .map(|receipt| receipt.signed_receipt())
+        .collect::<Vec<_>>();
 
     // Call the aggregate_receipts method on the other server
-    let remote_rav_result: jsonrpsee_helpers::JsonRpcResponse<SignedRav> = aggregator_client
-        .0
-        .request("aggregate_receipts", params)
+    let remote_rav = aggregator_client
+        .aggregate_receipts(&receipts, rav_request.previous_rav.as_ref())
         .await?;
+    let value_aggregate = remote_rav.message.valueAggregate;
     manager
-        .verify_and_store_rav(rav_request.expected_rav?, remote_rav_result.data)
+        .verify_and_store_rav(
+            rav_request.expected_rav?,
+            remote_rav,
+            &rav_request.included_receipt_ids,
+        )
         .await?;
 
     // For these tests, we expect every receipt to be valid, i.e. there should be no invalid receipts, nor any missing receipts (less than the expected threshold).
@@ -227,9 +285,5 @@ where
         true => Ok(()),
         false => Err(Error::msg("Invalid receipts found")),
     }?;
-    Ok(())
-}
-
-fn to_rpc_error(e: Box<dyn std::error::Error>, msg: &str) -> jsonrpsee::types::ErrorObjectOwned {
-    jsonrpsee::types::ErrorObject::owned(-32000, format!("{} - {}", e, msg), None::<()>)
+    Ok(value_aggregate)
 }
@@ -1,5 +1,6 @@
 // Copyright 2023-, Semiotic AI, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+mod aggregator_client;
 mod indexer_mock;
 mod showcase;
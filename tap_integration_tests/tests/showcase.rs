@@ -170,7 +170,7 @@ fn context(
 ) -> ContextFixture {
     let receipt_storage = Arc::new(RwLock::new(HashMap::new()));
     let escrow_storage = Arc::new(RwLock::new(HashMap::new()));
-    let rav_storage = Arc::new(RwLock::new(None));
+    let rav_storage = Arc::new(RwLock::new(Vec::new()));
     let timestamp_check = Arc::new(StatefulTimestampCheck::new(0));
     let context = InMemoryContext::new(
         rav_storage,
@@ -497,6 +497,7 @@ async fn test_manager_one_indexer(
         single_indexer_test_server.await?;
     let indexer_1_address = "http://".to_string() + &socket_addr.to_string();
     let client_1 = HttpClientBuilder::default().build(indexer_1_address)?;
+    let num_requests = requests_1.len() as i64;
 
     for receipt_1 in requests_1 {
         let result = client_1.request("request", (receipt_1,)).await;
@@ -507,6 +508,12 @@ async fn test_manager_one_indexer(
         }
     }
 
+    // The threshold divides the number of requests, so the last receipt triggered a RAV request
+    let metrics = indexer_mock::IndexerMetrics::new(&socket_addr.to_string());
+    assert_eq!(metrics.stored_receipts.get(), num_requests);
+    assert_eq!(metrics.receipts_since_last_rav.get(), 0);
+    assert!(metrics.last_rav_value.get() > 0.0);
+
     Ok(())
 }
 
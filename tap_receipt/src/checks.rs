@@ -11,7 +11,7 @@
 //! ```rust
 //! # use std::sync::Arc;
 //! use tap_receipt::{
-//!     checks::{Check, CheckResult, ReceiptCheck},
+//!     checks::{Check, CheckOutcome, CheckResult, ReceiptCheck},
 //!     Context, ReceiptWithState, state::Checking
 //! };
 //! # use async_trait::async_trait;
@@ -23,7 +23,7 @@
 //! impl<T> Check<T> for MyCheck {
 //!    async fn check(&self, ctx: &Context, receipt: &ReceiptWithState<Checking, T>) -> CheckResult {
 //!       // Implement your check here
-//!      Ok(())
+//!      Ok(CheckOutcome::passed())
 //!   }
 //! }
 //!
@@ -31,21 +31,63 @@
 //! ```
 
 use std::{
-    collections::HashSet,
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::{BuildHasher, Hash},
     ops::Deref,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use alloy::{dyn_abi::Eip712Domain, primitives::Address, sol_types::SolStruct};
+use futures_util::future::join_all;
+use rayon::prelude::*;
+use tap_eip712_message::{Eip712Error, Eip712SignedMessage};
+use tokio::{sync::oneshot, time::Instant};
+
 use super::{
-    state::{Checking, Failed},
-    Context, ReceiptError, ReceiptWithState, WithUniqueId, WithValueAndTimestamp,
+    received_receipt::check_error_to_receipt_error,
+    state::{Checking, Failed, PendingEscrow},
+    Context, ReceiptError, ReceiptResult, ReceiptWithState, RecoveredSigner, WithAllocationId,
+    WithUniqueId, WithValueAndTimestamp,
 };
 
 /// ReceiptCheck is a type alias for an Arc of a struct that implements the `Check` trait.
 pub type ReceiptCheck<Rcpt> = Arc<dyn Check<Rcpt> + Sync + Send>;
 
 /// Result of a check operation. It uses the `anyhow` crate to handle errors.
-pub type CheckResult = Result<(), CheckError>;
+pub type CheckResult = Result<CheckOutcome, CheckError>;
+
+/// Outcome of a check on a receipt that passed it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckOutcome {
+    /// Concerns about the receipt, e.g. an unusually high value, reported
+    /// along with the stored receipt instead of rejecting it
+    pub warnings: Vec<String>,
+}
+
+impl CheckOutcome {
+    /// The receipt passed the check without warnings
+    pub fn passed() -> Self {
+        Self::default()
+    }
+
+    /// The receipt passed the check with a warning
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            warnings: vec![message.into()],
+        }
+    }
+}
+
+/// Concern raised by a check about a receipt that passed it, see
+/// [`CheckOutcome::warnings`]. Unlike a [`CheckError`], it does not reject
+/// the receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckWarning {
+    /// Name of the check raising the warning, see [`Check::typetag_name`]
+    pub check: &'static str,
+    pub message: String,
+}
 
 #[derive(thiserror::Error, Debug)]
 pub enum CheckError {
@@ -55,17 +97,384 @@ pub enum CheckError {
     Failed(anyhow::Error),
 }
 
+/// Misconfiguration of a [`CheckList`], reported by [`CheckList::validate`].
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum CheckConfigError {
+    #[error("check {name} is registered more than once")]
+    DuplicateCheck { name: &'static str },
+    #[error("check {name} is managed by the TAP manager and must not be registered")]
+    ManagedCheck { name: &'static str },
+}
+
+/// Records the receipts rejected by each check of a [`CheckList`], e.g. to
+/// see that most rejections are signature failures after a key rotation.
+pub trait CheckMetrics: Send + Sync {
+    /// Called each time a receipt fails the check named `check_name`, see
+    /// [`Check::typetag_name`].
+    fn record_failure(&self, check_name: &'static str);
+
+    /// Called for each warning raised by the check named `check_name` on a
+    /// receipt passing it, see [`CheckOutcome::warnings`]. Does nothing by
+    /// default.
+    fn record_warning(&self, _check_name: &'static str) {}
+}
+
+/// [`CheckMetrics`] recording nothing, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopCheckMetrics;
+
+impl CheckMetrics for NoopCheckMetrics {
+    fn record_failure(&self, _check_name: &'static str) {}
+}
+
+/// [`CheckMetrics`] counting the failures of each check in the
+/// `tap_receipts_rejected_total` counter, and its warnings in the
+/// `tap_receipt_warnings_total` counter, both labelled by check name.
+#[cfg(feature = "prometheus")]
+#[derive(Debug, Clone)]
+pub struct PrometheusCheckMetrics {
+    rejected: prometheus::IntCounterVec,
+    warnings: prometheus::IntCounterVec,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusCheckMetrics {
+    /// Creates the counters and registers them in `registry`.
+    pub fn new(registry: &prometheus::Registry) -> prometheus::Result<Self> {
+        let rejected = prometheus::IntCounterVec::new(
+            prometheus::Opts::new(
+                "tap_receipts_rejected_total",
+                "Number of receipts rejected by each check",
+            ),
+            &["check"],
+        )?;
+        let warnings = prometheus::IntCounterVec::new(
+            prometheus::Opts::new(
+                "tap_receipt_warnings_total",
+                "Number of warnings raised by each check on accepted receipts",
+            ),
+            &["check"],
+        )?;
+        registry.register(Box::new(rejected.clone()))?;
+        registry.register(Box::new(warnings.clone()))?;
+        Ok(Self { rejected, warnings })
+    }
+
+    /// Returns the number of receipts rejected by the check named
+    /// `check_name`.
+    pub fn rejected(&self, check_name: &str) -> u64 {
+        self.rejected.with_label_values(&[check_name]).get()
+    }
+
+    /// Returns the number of warnings raised by the check named
+    /// `check_name`.
+    pub fn warnings(&self, check_name: &str) -> u64 {
+        self.warnings.with_label_values(&[check_name]).get()
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl CheckMetrics for PrometheusCheckMetrics {
+    fn record_failure(&self, check_name: &'static str) {
+        self.rejected.with_label_values(&[check_name]).inc();
+    }
+
+    fn record_warning(&self, check_name: &'static str) {
+        self.warnings.with_label_values(&[check_name]).inc();
+    }
+}
+
 /// CheckList is a NewType pattern to store a list of checks.
-/// It is a wrapper around an Arc of ReceiptCheck[].
-pub struct CheckList<Rcpt>(Arc<[ReceiptCheck<Rcpt>]>);
+/// It is a wrapper around an Arc of ReceiptCheck[], along with the
+/// [`CheckMetrics`] recording their failures.
+pub struct CheckList<Rcpt> {
+    checks: Arc<[ReceiptCheck<Rcpt>]>,
+    metrics: Arc<dyn CheckMetrics>,
+}
 
 impl<Rcpt> CheckList<Rcpt> {
     pub fn new(checks: Vec<ReceiptCheck<Rcpt>>) -> Self {
-        Self(checks.into())
+        Self {
+            checks: checks.into(),
+            metrics: Arc::new(NoopCheckMetrics),
+        }
     }
 
     pub fn empty() -> Self {
-        Self(Arc::new([]))
+        Self::new(Vec::new())
+    }
+
+    /// Records the failures of the checks with `metrics`, instead of
+    /// [`NoopCheckMetrics`].
+    pub fn with_metrics(mut self, metrics: Arc<dyn CheckMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Performs the checks of the list on `receipt`, like
+    /// [`ReceiptWithState::perform_checks`], recording the failing check, if
+    /// any, with the [`CheckMetrics`] of the list.
+    pub async fn perform_checks(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+    ) -> ReceiptResult<()> {
+        self.perform_checks_with_warnings(ctx, receipt)
+            .await
+            .map(|_| ())
+    }
+
+    /// Same as [`CheckList::perform_checks`], returning the warnings raised
+    /// by the checks on a passing receipt, see [`CheckOutcome::warnings`].
+    /// They are recorded with the [`CheckMetrics`] of the list as well.
+    pub async fn perform_checks_with_warnings(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+    ) -> ReceiptResult<Vec<CheckWarning>> {
+        match receipt.run_checks(ctx, &self.checks).await {
+            Ok(warnings) => Ok(self.record_warnings(warnings)),
+            Err((check_name, error)) => {
+                self.metrics.record_failure(check_name);
+                Err(error)
+            }
+        }
+    }
+
+    /// Same as [`CheckList::perform_checks`], except that a receipt failing
+    /// with [`ReceiptError::InsufficientEscrow`], see [`EscrowHeadroomCheck`],
+    /// is returned in the [`PendingEscrow`] state instead, so that it can be
+    /// checked again once the escrow of its sender is topped up, see
+    /// [`CheckList::resume_checks_or_hold`]. A passing receipt is returned
+    /// with its warnings, as in [`CheckList::perform_checks_with_warnings`].
+    pub async fn perform_checks_or_hold(
+        &self,
+        ctx: &Context,
+        receipt: ReceiptWithState<Checking, Rcpt>,
+    ) -> ReceiptResult<
+        Result<
+            (ReceiptWithState<Checking, Rcpt>, Vec<CheckWarning>),
+            ReceiptWithState<PendingEscrow, Rcpt>,
+        >,
+    > {
+        self.checks_or_hold_from(ctx, receipt, 0, Vec::new()).await
+    }
+
+    /// Same as [`CheckList::perform_checks_or_hold`] on a receipt held by
+    /// the check named `check`, see [`ReceiptWithState::check`], resuming the
+    /// checks from it. The checks the receipt passed before being held are
+    /// not performed again, so that the ones recording the receipts they
+    /// accept, e.g. [`ValueRateLimitCheck`], do not count it twice.
+    ///
+    /// `warnings` are the ones raised by those checks, see
+    /// [`ReceiptWithState::warnings`], returned along with the warnings of the
+    /// remaining checks. The checks are performed from the start if the list
+    /// has no check named `check`.
+    pub async fn resume_checks_or_hold(
+        &self,
+        ctx: &Context,
+        receipt: ReceiptWithState<Checking, Rcpt>,
+        check: &'static str,
+        warnings: Vec<CheckWarning>,
+    ) -> ReceiptResult<
+        Result<
+            (ReceiptWithState<Checking, Rcpt>, Vec<CheckWarning>),
+            ReceiptWithState<PendingEscrow, Rcpt>,
+        >,
+    > {
+        let start = self
+            .checks
+            .iter()
+            .position(|held_by| held_by.typetag_name() == check);
+        match start {
+            Some(start) => {
+                self.checks_or_hold_from(ctx, receipt, start, warnings)
+                    .await
+            }
+            // the warnings are raised again from the start
+            None => self.checks_or_hold_from(ctx, receipt, 0, Vec::new()).await,
+        }
+    }
+
+    async fn checks_or_hold_from(
+        &self,
+        ctx: &Context,
+        receipt: ReceiptWithState<Checking, Rcpt>,
+        start: usize,
+        mut warnings: Vec<CheckWarning>,
+    ) -> ReceiptResult<
+        Result<
+            (ReceiptWithState<Checking, Rcpt>, Vec<CheckWarning>),
+            ReceiptWithState<PendingEscrow, Rcpt>,
+        >,
+    > {
+        let (index, error) = match receipt
+            .run_checks_raw(ctx, &self.checks[start..], &mut warnings)
+            .await
+        {
+            Ok(()) => return Ok(Ok((receipt, self.record_warnings(warnings)))),
+            Err((index, error)) => (start + index, error),
+        };
+        let check_name = self.checks[index].typetag_name();
+        if let CheckError::Failed(e) = &error {
+            if let Some(insufficient @ ReceiptError::InsufficientEscrow { .. }) = e.downcast_ref() {
+                return Ok(Err(receipt.hold_for_escrow(
+                    check_name,
+                    insufficient.clone(),
+                    warnings,
+                )));
+            }
+        }
+        receipt.revert_checks(ctx, &self.checks[..index]);
+        self.metrics.record_failure(check_name);
+        Err(check_error_to_receipt_error(error))
+    }
+
+    fn record_warnings(&self, warnings: Vec<CheckWarning>) -> Vec<CheckWarning> {
+        for warning in &warnings {
+            self.metrics.record_warning(warning.check);
+        }
+        warnings
+    }
+
+    /// Appends `checks` to the list, skipping the ones whose
+    /// [`Check::typetag_name`] is already in the list.
+    pub fn extend(&mut self, checks: Vec<ReceiptCheck<Rcpt>>) {
+        let mut names: HashSet<&'static str> =
+            self.iter().map(|check| check.typetag_name()).collect();
+        let checks: Vec<_> = self
+            .iter()
+            .cloned()
+            .chain(
+                checks
+                    .into_iter()
+                    .filter(|check| names.insert(check.typetag_name())),
+            )
+            .collect();
+        self.checks = checks.into();
+    }
+
+    /// Checks that the list can be used as is.
+    ///
+    /// Two checks with the same [`Check::typetag_name`] are rejected, e.g.
+    /// two [`StatefulTimestampCheck`]s with different floors, as only one of
+    /// them would ever be updated. [`ClosedAllocationCheck`] is rejected as
+    /// well, since the manager runs its own instance and would not see the
+    /// allocations closed through the registered one.
+    pub fn validate(&self) -> Result<(), CheckConfigError> {
+        let mut names = HashSet::new();
+        for check in self.iter() {
+            let name = check.typetag_name();
+            if name == std::any::type_name::<ClosedAllocationCheck>() {
+                return Err(CheckConfigError::ManagedCheck { name });
+            }
+            if !names.insert(name) {
+                return Err(CheckConfigError::DuplicateCheck { name });
+            }
+        }
+        Ok(())
+    }
+
+    /// Combines two lists, keeping the order of the checks and skipping
+    /// the checks of `other` already in `self`. See [`CheckList::extend`].
+    pub fn merge(mut self, other: CheckList<Rcpt>) -> Self {
+        self.extend(other.to_vec());
+        self
+    }
+}
+
+/// False-positive rate of the [`BloomNonceCheck`] of the presets
+const PRESET_FALSE_POSITIVE_RATE: f64 = 0.001;
+
+/// Configuration of the checks of [`CheckList::aggregator_defaults`], also
+/// used by [`CheckList::indexer_defaults`].
+pub struct AggregatorCheckConfig<Rcpt> {
+    /// Domain separator the receipts are signed with
+    pub domain_separator: Eip712Domain,
+    /// Signers whose receipts are accepted
+    pub accepted_signers: HashSet<Address>,
+    /// Exact check confirming the hits of the nonce filter, see [`BloomNonceCheck`]
+    pub stored_receipt_check: ReceiptCheck<Rcpt>,
+    /// Number of receipts the nonce filter is sized for
+    pub expected_receipts: usize,
+    /// Timestamp floor, shared with the code updating it when a RAV is stored
+    pub timestamp_check: Arc<StatefulTimestampCheck>,
+    /// Maximum distance, in years, of a receipt timestamp from the current time
+    pub max_timestamp_distance_years: u64,
+}
+
+/// Configuration of the checks [`CheckList::indexer_defaults`] adds to the
+/// aggregator ones.
+pub struct IndexerCheckConfig<Rcpt, E> {
+    /// Allocations receipts are accepted for
+    pub allocation_ids: Arc<RwLock<HashSet<Address>>>,
+    /// Check comparing the value of a receipt to the value of the query it
+    /// pays for. It depends on the pricing of the indexer, so it is provided
+    /// by the lib user.
+    pub value_check: ReceiptCheck<Rcpt>,
+    /// Escrow figures of the senders, see [`EscrowHeadroomCheck`]
+    pub escrow: E,
+}
+
+impl<T> CheckList<Eip712SignedMessage<T>>
+where
+    T: SolStruct + WithValueAndTimestamp + Clone + Send + Sync + 'static,
+{
+    /// Checks run by an aggregator on the receipts it is asked to aggregate,
+    /// in this order:
+    ///
+    /// 1. [`TimestampSanityCheck`], with `max_timestamp_distance_years`
+    /// 2. [`StatefulTimestampCheck`], the `timestamp_check` of the config
+    /// 3. [`SignerCheck`], with `domain_separator` and `accepted_signers`
+    /// 4. [`BloomNonceCheck`], sized for `expected_receipts` and confirming its
+    ///    hits with `stored_receipt_check`
+    ///
+    /// Receipts in the same batch are also checked for uniqueness by the
+    /// manager ([`UniqueCheck`]), which is not part of the list. The
+    /// [`BloomNonceCheck`] is only run when a receipt is received, not when
+    /// creating a RAV, see [`Check::is_ingest_only`].
+    pub fn aggregator_defaults(config: AggregatorCheckConfig<Eip712SignedMessage<T>>) -> Self {
+        Self::new(vec![
+            Arc::new(TimestampSanityCheck::new(
+                config.max_timestamp_distance_years,
+            )) as ReceiptCheck<_>,
+            config.timestamp_check,
+            Arc::new(SignerCheck::new(
+                config.domain_separator,
+                config.accepted_signers,
+            )),
+            Arc::new(BloomNonceCheck::new(
+                config.expected_receipts,
+                PRESET_FALSE_POSITIVE_RATE,
+                config.stored_receipt_check,
+            )),
+        ])
+    }
+
+    /// Checks run by an indexer on the receipts it receives: the
+    /// [`CheckList::aggregator_defaults`] followed by
+    ///
+    /// 5. [`AllocationIdCheck`], with `allocation_ids`
+    /// 6. the `value_check` of the config
+    /// 7. [`EscrowHeadroomCheck`], with `escrow`, only run when a receipt is
+    ///    received
+    ///
+    /// [`ClosedAllocationCheck`] is not included, the manager runs its own.
+    pub fn indexer_defaults<E>(
+        config: AggregatorCheckConfig<Eip712SignedMessage<T>>,
+        indexer_config: IndexerCheckConfig<Eip712SignedMessage<T>, E>,
+    ) -> Self
+    where
+        T: WithAllocationId,
+        E: EscrowHeadroom<Eip712SignedMessage<T>> + Send + Sync + 'static,
+    {
+        let mut checks = Self::aggregator_defaults(config);
+        checks.extend(vec![
+            Arc::new(AllocationIdCheck::new(indexer_config.allocation_ids)) as ReceiptCheck<_>,
+            indexer_config.value_check,
+            Arc::new(EscrowHeadroomCheck(indexer_config.escrow)),
+        ]);
+        checks
     }
 }
 
@@ -73,15 +482,82 @@ impl<Rcpt> Deref for CheckList<Rcpt> {
     type Target = [ReceiptCheck<Rcpt>];
 
     fn deref(&self) -> &Self::Target {
-        self.0.as_ref()
+        self.checks.as_ref()
     }
 }
 
 /// Check trait is implemented by the lib user to validate receipts before they are stored.
 #[async_trait::async_trait]
 pub trait Check<Rcpt> {
+    /// Rejects `receipt` with a [`CheckError`], or accepts it, possibly with
+    /// warnings, see [`CheckOutcome`].
     async fn check(&self, ctx: &Context, receipt: &ReceiptWithState<Checking, Rcpt>)
         -> CheckResult;
+
+    /// Performs the check on several receipts, returning their results in
+    /// the same order as `receipts`.
+    ///
+    /// Used by the manager to check the stored receipts when creating a RAV.
+    /// Defaults to running [`Check::check`] on the receipts concurrently.
+    /// Checks doing CPU-bound work override it to spread the receipts over
+    /// threads, e.g. [`SignerCheck`] recovers the signers with rayon.
+    async fn check_all(
+        &self,
+        ctx: &Context,
+        receipts: &[ReceiptWithState<Checking, Rcpt>],
+    ) -> Vec<CheckResult>
+    where
+        Rcpt: Sync,
+    {
+        join_all(receipts.iter().map(|receipt| self.check(ctx, receipt))).await
+    }
+
+    /// Name identifying the check, so that the same check is not added twice
+    /// to a [`CheckList`].
+    ///
+    /// Defaults to the type name. Override it to add several checks of the
+    /// same type with different configurations to a list.
+    fn typetag_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Whether the check can run on several receipts concurrently.
+    ///
+    /// Defaults to `true`. Checks updating shared state from
+    /// [`Check::check`], e.g. recording the receipts they have seen, return
+    /// `false` so that the manager runs them on one receipt at a time, after
+    /// the other checks.
+    fn is_parallel_safe(&self) -> bool {
+        true
+    }
+
+    /// Whether the check only runs when a receipt is received.
+    ///
+    /// Defaults to `false`. Checks recording the receipts they accept, e.g.
+    /// [`BloomNonceCheck`], return `true`: the manager skips them when
+    /// checking the stored receipts again to create a RAV, as they would
+    /// reject (or count twice) the receipts they recorded on receipt.
+    fn is_ingest_only(&self) -> bool {
+        false
+    }
+
+    /// Whether [`Check::check`] has side effects outside the check, e.g.
+    /// counting the receipts against a quota.
+    ///
+    /// Defaults to `false`. The manager skips the checks returning `true`
+    /// when it only previews the next RAV, so that a preview does not count
+    /// against the next RAV request.
+    fn has_side_effects(&self) -> bool {
+        false
+    }
+
+    /// Undoes what [`Check::check`] recorded about a receipt it passed, once
+    /// a later check of the list rejected the receipt.
+    ///
+    /// Defaults to nothing. Checks accumulating the receipts they accept,
+    /// e.g. [`ValueRateLimitCheck`], override it so that a rejected receipt
+    /// does not count.
+    fn revert(&self, _ctx: &Context, _receipt: &ReceiptWithState<Checking, Rcpt>) {}
 }
 
 type CheckBatchResponse<Rcpt> = (
@@ -98,22 +574,102 @@ pub trait CheckBatch<Rcpt> {
     ) -> CheckBatchResponse<Rcpt>;
 }
 
+/// Window around the current time in which receipt timestamps are accepted,
+/// shared by the timestamp checks through a [`SharedClockSkewPolicy`].
+///
+/// The window is driven by the clock, unlike the floor of a
+/// [`StatefulTimestampCheck`], which is driven by the last RAV. A receipt
+/// must be above the floor *and* within the window, so the effective lower
+/// bound is the greater of the floor and `now - max_past_ns`. A floor in the
+/// future of `now - max_past_ns` makes the past tolerance irrelevant; a floor
+/// above `now + max_future_ns` rejects every receipt until the clock catches
+/// up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSkewPolicy {
+    /// How far in the future of the current time a timestamp may be
+    pub max_future_ns: u64,
+    /// How far in the past of the current time a timestamp may be
+    pub max_past_ns: u64,
+}
+
+impl ClockSkewPolicy {
+    /// Returns `true` if `timestamp_ns` is within the window around `now_ns`.
+    pub fn contains(&self, timestamp_ns: u64, now_ns: u64) -> bool {
+        timestamp_ns <= now_ns.saturating_add(self.max_future_ns)
+            && timestamp_ns >= now_ns.saturating_sub(self.max_past_ns)
+    }
+
+    fn check(&self, timestamp_ns: u64) -> CheckResult {
+        if !self.contains(timestamp_ns, current_timestamp_ns()?) {
+            return Err(CheckError::Failed(
+                ReceiptError::ImplausibleTimestamp {
+                    received_timestamp: timestamp_ns,
+                }
+                .into(),
+            ));
+        }
+        Ok(CheckOutcome::passed())
+    }
+}
+
+/// Returns the current time in nanoseconds, saturating at `u64::MAX`.
+fn current_timestamp_ns() -> Result<u64, CheckError> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| CheckError::Retryable(e.into()))?
+        .as_nanos()
+        .try_into()
+        .unwrap_or(u64::MAX))
+}
+
+/// [`ClockSkewPolicy`] that can be adjusted at runtime.
+///
+/// Clones share the same policy, so every check built with a clone sees
+/// the updates made with [`SharedClockSkewPolicy::set`].
+#[derive(Debug, Clone)]
+pub struct SharedClockSkewPolicy(Arc<RwLock<ClockSkewPolicy>>);
+
+impl SharedClockSkewPolicy {
+    pub fn new(policy: ClockSkewPolicy) -> Self {
+        Self(Arc::new(RwLock::new(policy)))
+    }
+
+    pub fn get(&self) -> ClockSkewPolicy {
+        *self.0.read().unwrap()
+    }
+
+    /// Replaces the policy of this instance and all its clones.
+    pub fn set(&self, policy: ClockSkewPolicy) {
+        *self.0.write().unwrap() = policy;
+    }
+}
+
 /// Provides a built-in check to verify that the timestamp of a receipt
 /// is greater than a given value.
 ///
 /// This check is stateful, meaning that it can be updated with a new minimum
-/// timestamp.
+/// timestamp. With [`StatefulTimestampCheck::with_clock_skew`], it also
+/// rejects timestamps outside the window of a [`ClockSkewPolicy`].
 #[derive(Debug)]
 pub struct StatefulTimestampCheck {
     min_timestamp_ns: RwLock<u64>,
+    clock_skew: Option<SharedClockSkewPolicy>,
 }
 
 impl StatefulTimestampCheck {
     pub fn new(min_timestamp_ns: u64) -> Self {
         Self {
             min_timestamp_ns: RwLock::new(min_timestamp_ns),
+            clock_skew: None,
         }
     }
+
+    /// Also rejects receipts outside the window of `clock_skew`.
+    pub fn with_clock_skew(mut self, clock_skew: SharedClockSkewPolicy) -> Self {
+        self.clock_skew = Some(clock_skew);
+        self
+    }
+
     /// Updates the minimum timestamp that will be accepted for a receipt (exclusive).
     pub fn update_min_timestamp_ns(&self, min_timestamp_ns: u64) {
         *self.min_timestamp_ns.write().unwrap() = min_timestamp_ns;
@@ -137,7 +693,106 @@ where
                 .into(),
             ));
         }
-        Ok(())
+        if let Some(clock_skew) = &self.clock_skew {
+            clock_skew.get().check(signed_receipt.timestamp_ns())?;
+        }
+        Ok(CheckOutcome::passed())
+    }
+}
+
+/// RavWindowCheck rejects receipts stamped more than `window` past the
+/// timestamp of the last RAV.
+///
+/// It is the upper bound matching the lower bound of
+/// [`StatefulTimestampCheck`]: without it, a sender could stamp a receipt far
+/// into the future, so that the next RAV's timestamp becomes the minimum
+/// timestamp and all its later receipts are rejected.
+///
+/// Until the RAV timestamp is set with
+/// [`RavWindowCheck::update_rav_timestamp_ns`], e.g. before the first RAV,
+/// the window starts at the current time instead.
+#[derive(Debug)]
+pub struct RavWindowCheck {
+    rav_timestamp_ns: RwLock<Option<u64>>,
+    window_ns: u64,
+}
+
+impl RavWindowCheck {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            rav_timestamp_ns: RwLock::new(None),
+            window_ns: window.as_nanos().try_into().unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Updates the timestamp of the last RAV, the start of the window.
+    pub fn update_rav_timestamp_ns(&self, rav_timestamp_ns: u64) {
+        *self.rav_timestamp_ns.write().unwrap() = Some(rav_timestamp_ns);
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt> Check<Rcpt> for RavWindowCheck
+where
+    Rcpt: WithValueAndTimestamp + Sync,
+{
+    async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
+        let window_start_ns = match *self.rav_timestamp_ns.read().unwrap() {
+            Some(rav_timestamp_ns) => rav_timestamp_ns,
+            None => current_timestamp_ns()?,
+        };
+        let timestamp_max = window_start_ns.saturating_add(self.window_ns);
+        let received_timestamp = receipt.signed_receipt().timestamp_ns();
+        if received_timestamp > timestamp_max {
+            return Err(CheckError::Failed(
+                ReceiptError::TimestampBeyondRavWindow {
+                    received_timestamp,
+                    timestamp_max,
+                }
+                .into(),
+            ));
+        }
+        Ok(CheckOutcome::passed())
+    }
+}
+
+/// TimestampSanityCheck rejects receipts whose timestamp is outside the
+/// window of a [`ClockSkewPolicy`].
+///
+/// Catches senders using the wrong unit for `timestamp_ns`: a timestamp in
+/// milliseconds lands in 1970, and one multiplied by too large a factor lands
+/// centuries in the future.
+pub struct TimestampSanityCheck {
+    clock_skew: SharedClockSkewPolicy,
+}
+
+impl TimestampSanityCheck {
+    const NANOS_PER_YEAR: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+    /// Accepts timestamps within `years` years of the current time.
+    pub fn new(years: u64) -> Self {
+        let max_distance_ns = years.saturating_mul(Self::NANOS_PER_YEAR);
+        Self::with_clock_skew(SharedClockSkewPolicy::new(ClockSkewPolicy {
+            max_future_ns: max_distance_ns,
+            max_past_ns: max_distance_ns,
+        }))
+    }
+
+    /// Accepts timestamps within the window of `clock_skew`.
+    pub fn with_clock_skew(clock_skew: SharedClockSkewPolicy) -> Self {
+        Self { clock_skew }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt> Check<Rcpt> for TimestampSanityCheck
+where
+    Rcpt: WithValueAndTimestamp + Sync,
+{
+    async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
+        self.clock_skew
+            .get()
+            .check(receipt.signed_receipt().timestamp_ns())
     }
 }
 
@@ -165,10 +820,13 @@ where
             if receipt_timestamp_ns >= min_timestamp_ns {
                 checking.push(receipt);
             } else {
-                failed.push(receipt.perform_state_error(ReceiptError::InvalidTimestamp {
-                    received_timestamp: receipt_timestamp_ns,
-                    timestamp_min: min_timestamp_ns,
-                }));
+                failed.push(receipt.perform_state_error(
+                    std::any::type_name::<Self>(),
+                    ReceiptError::InvalidTimestamp {
+                        received_timestamp: receipt_timestamp_ns,
+                        timestamp_min: min_timestamp_ns,
+                    },
+                ));
             }
         }
         (checking, failed)
@@ -200,22 +858,631 @@ where
             if signatures.insert(unique_id) {
                 checking.push(received_receipt);
             } else {
-                failed.push(received_receipt.perform_state_error(ReceiptError::NonUniqueReceipt));
+                failed.push(received_receipt.perform_state_error(
+                    std::any::type_name::<Self>(),
+                    ReceiptError::NonUniqueReceipt,
+                ));
             }
         }
         (checking, failed)
     }
 }
 
+/// BloomNonceCheck rejects replayed receipts using a space-bounded bloom filter
+/// of the unique ids (see [`WithUniqueId`]) of the receipts it has accepted.
+///
+/// A bloom filter never forgets an id it has seen, but may report an id as
+/// seen when it is not (false positive). To avoid rejecting valid receipts,
+/// every bloom hit is confirmed with an exact check provided by the user (for
+/// example a lookup in the receipt storage), which is only called on hits.
+///
+/// # Memory/accuracy tradeoff
+///
+/// The filter uses `-n * ln(p) / ln(2)^2` bits for `n` expected receipts and
+/// false-positive rate `p`, i.e. about 1.2 bytes per receipt for `p = 0.01`
+/// and 1.8 bytes per receipt for `p = 0.001`, regardless of the receipt size.
+/// A lower `p` costs more memory but calls the exact check less often.
+/// Once more than `n` receipts have been inserted the false-positive rate
+/// grows, so the filter should be sized for the expected number of receipts
+/// between two RAVs.
+///
+/// This check records every receipt it accepts, so it is only run when the
+/// receipt is first received (see [`Check::is_ingest_only`]), and the exact
+/// check must tell whether the receipt was already *stored* before.
+pub struct BloomNonceCheck<Rcpt> {
+    bits: Mutex<Vec<u64>>,
+    num_bits: u64,
+    num_hashes: u32,
+    hashers: (RandomState, RandomState),
+    exact_check: ReceiptCheck<Rcpt>,
+}
+
+impl<Rcpt> BloomNonceCheck<Rcpt> {
+    /// Creates a new check sized for `expected_receipts` with the given
+    /// `false_positive_rate` (between 0 and 1, exclusive). Bloom hits are
+    /// confirmed with `exact_check`.
+    pub fn new(
+        expected_receipts: usize,
+        false_positive_rate: f64,
+        exact_check: ReceiptCheck<Rcpt>,
+    ) -> Self {
+        let expected_receipts = expected_receipts.max(1) as f64;
+        let false_positive_rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        let num_bits = (-expected_receipts * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / expected_receipts) * ln2)
+            .round()
+            .max(1.0) as u32;
+
+        Self {
+            bits: Mutex::new(vec![0; num_bits.div_ceil(64) as usize]),
+            num_bits,
+            num_hashes,
+            hashers: (RandomState::new(), RandomState::new()),
+            exact_check,
+        }
+    }
+
+    /// Size of the bloom filter in bits
+    pub fn num_bits(&self) -> u64 {
+        self.num_bits
+    }
+
+    fn bit_indexes(&self, item: &impl Hash) -> Vec<u64> {
+        let h1 = self.hashers.0.hash_one(item);
+        let h2 = self.hashers.1.hash_one(item);
+        (0..self.num_hashes as u64)
+            .map(|i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt> Check<Rcpt> for BloomNonceCheck<Rcpt>
+where
+    Rcpt: WithUniqueId + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Rcpt>,
+    ) -> CheckResult {
+        let indexes = self.bit_indexes(&receipt.signed_receipt().unique_id());
+
+        // The bits are tested and set under the same lock, so that only one
+        // of two copies of a receipt can miss the filter. On a hit all the
+        // bits were set already, so setting them changes nothing.
+        let is_hit = {
+            let mut bits = self.bits.lock().unwrap();
+            let mut is_hit = true;
+            for index in indexes {
+                let (word, mask) = ((index / 64) as usize, 1 << (index % 64));
+                is_hit &= bits[word] & mask != 0;
+                bits[word] |= mask;
+            }
+            is_hit
+        };
+
+        if is_hit {
+            // Possibly a false positive, let the exact check decide.
+            return self.exact_check.check(ctx, receipt).await;
+        }
+        Ok(CheckOutcome::passed())
+    }
+
+    // The exact check of the second of two copies checked concurrently
+    // would not find the first one, which is not stored yet.
+    fn is_parallel_safe(&self) -> bool {
+        false
+    }
+
+    // A stored receipt is in the filter already, and would always be
+    // confirmed as a replay by the exact check.
+    fn is_ingest_only(&self) -> bool {
+        true
+    }
+}
+
+/// Returns the signer of `signed_receipt`, taken from the [`RecoveredSigner`]
+/// of `ctx` if any.
+fn recover_signer<T: SolStruct>(
+    ctx: &Context,
+    domain_separator: &Eip712Domain,
+    signed_receipt: &Eip712SignedMessage<T>,
+) -> Result<Address, CheckError> {
+    match RecoveredSigner::get(ctx, signed_receipt) {
+        Some(address) => Ok(address),
+        None => signed_receipt
+            .recover_signer(domain_separator)
+            .map_err(invalid_signature),
+    }
+}
+
+fn invalid_signature(e: Eip712Error) -> CheckError {
+    CheckError::Failed(
+        ReceiptError::InvalidSignature {
+            source_error_message: e.to_string(),
+        }
+        .into(),
+    )
+}
+
+/// SignerCheck rejects receipts not signed by one of the accepted signers.
+///
+/// Uses the [`RecoveredSigner`] of the [`Context`], if any, instead of
+/// recovering the signer again.
+pub struct SignerCheck {
+    domain_separator: Eip712Domain,
+    accepted_signers: HashSet<Address>,
+}
+
+impl SignerCheck {
+    pub fn new(domain_separator: Eip712Domain, accepted_signers: HashSet<Address>) -> Self {
+        Self {
+            domain_separator,
+            accepted_signers,
+        }
+    }
+
+    fn check_signer<T: SolStruct>(
+        &self,
+        ctx: &Context,
+        signed_receipt: &Eip712SignedMessage<T>,
+    ) -> CheckResult {
+        let signer = recover_signer(ctx, &self.domain_separator, signed_receipt)?;
+        self.check_accepted(signer)
+    }
+
+    fn check_accepted(&self, signer: Address) -> CheckResult {
+        if !self.accepted_signers.contains(&signer) {
+            return Err(CheckError::Failed(
+                ReceiptError::InvalidSignature {
+                    source_error_message: format!("Invalid signer {signer}"),
+                }
+                .into(),
+            ));
+        }
+        Ok(CheckOutcome::passed())
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Check<Eip712SignedMessage<T>> for SignerCheck
+where
+    T: SolStruct + Clone + Send + Sync + 'static,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        self.check_signer(ctx, receipt.signed_receipt())
+    }
+
+    // Recovering a signer is CPU-bound, so the signers missing from `ctx` are
+    // recovered on the rayon thread pool, without blocking the async runtime.
+    async fn check_all(
+        &self,
+        ctx: &Context,
+        receipts: &[ReceiptWithState<Checking, Eip712SignedMessage<T>>],
+    ) -> Vec<CheckResult> {
+        let mut results = Vec::with_capacity(receipts.len());
+        let mut to_recover = Vec::new();
+        for (i, receipt) in receipts.iter().enumerate() {
+            let signed_receipt = receipt.signed_receipt();
+            match RecoveredSigner::get(ctx, signed_receipt) {
+                Some(signer) => results.push(self.check_accepted(signer)),
+                None => {
+                    // overwritten once the signer is recovered
+                    results.push(Ok(CheckOutcome::passed()));
+                    to_recover.push((i, signed_receipt.clone()));
+                }
+            }
+        }
+        if to_recover.is_empty() {
+            return results;
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let domain_separator = self.domain_separator.clone();
+        rayon::spawn(move || {
+            let signers: Vec<_> = to_recover
+                .into_par_iter()
+                .map(|(i, signed_receipt)| (i, signed_receipt.recover_signer(&domain_separator)))
+                .collect();
+            // the receiver is gone if the check was cancelled
+            let _ = sender.send(signers);
+        });
+        let signers = receiver.await.expect("signer recovery stopped");
+
+        for (i, signer) in signers {
+            results[i] = signer
+                .map_err(invalid_signature)
+                .and_then(|signer| self.check_accepted(signer));
+        }
+        results
+    }
+}
+
+/// AllocationIdCheck rejects receipts for allocations not in the given set.
+///
+/// The set is shared, so allocations can be added or removed while the
+/// check is in use.
+pub struct AllocationIdCheck {
+    allocation_ids: Arc<RwLock<HashSet<Address>>>,
+}
+
+impl AllocationIdCheck {
+    pub fn new(allocation_ids: Arc<RwLock<HashSet<Address>>>) -> Self {
+        Self { allocation_ids }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt> Check<Rcpt> for AllocationIdCheck
+where
+    Rcpt: WithAllocationId + Sync,
+{
+    async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
+        let received_allocation_id = receipt.signed_receipt().allocation_id();
+        if !self
+            .allocation_ids
+            .read()
+            .unwrap()
+            .contains(&received_allocation_id)
+        {
+            return Err(CheckError::Failed(
+                ReceiptError::InvalidAllocationID {
+                    received_allocation_id,
+                }
+                .into(),
+            ));
+        }
+        Ok(CheckOutcome::passed())
+    }
+}
+
+/// Provides the escrow figures needed by [`EscrowHeadroomCheck`].
+///
+/// Implemented by the lib user on top of their escrow and receipt storage.
+#[async_trait::async_trait]
+pub trait EscrowHeadroom<Rcpt> {
+    /// Returns the escrow balance of the sender of `receipt`.
+    async fn available_escrow(&self, receipt: &Rcpt) -> anyhow::Result<u128>;
+
+    /// Returns the value owed to the receiver by the sender of `receipt` that
+    /// is not redeemed yet, i.e. the value of the last RAV plus the value of
+    /// the stored receipts not aggregated into it.
+    async fn pending_value(&self, receipt: &Rcpt) -> anyhow::Result<u128>;
+}
+
+/// EscrowHeadroomCheck rejects a receipt when accepting it would make the
+/// value owed by its sender exceed the sender's escrow.
+///
+/// This allows failing early, when the receipt is received, instead of when
+/// requesting a RAV, and it is only run then (see [`Check::is_ingest_only`]).
+/// Errors while reading the escrow are retryable.
+pub struct EscrowHeadroomCheck<E>(pub E);
+
+#[async_trait::async_trait]
+impl<Rcpt, E> Check<Rcpt> for EscrowHeadroomCheck<E>
+where
+    Rcpt: WithValueAndTimestamp + Sync,
+    E: EscrowHeadroom<Rcpt> + Sync,
+{
+    async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
+        let signed_receipt = receipt.signed_receipt();
+        let available_escrow = self
+            .0
+            .available_escrow(signed_receipt)
+            .await
+            .map_err(CheckError::Retryable)?;
+        let pending_value = self
+            .0
+            .pending_value(signed_receipt)
+            .await
+            .map_err(CheckError::Retryable)?;
+
+        let required_escrow = pending_value.checked_add(signed_receipt.value());
+        match required_escrow {
+            Some(required_escrow) if required_escrow <= available_escrow => {
+                Ok(CheckOutcome::passed())
+            }
+            _ => Err(CheckError::Failed(
+                ReceiptError::InsufficientEscrow {
+                    required_escrow: required_escrow.unwrap_or(u128::MAX),
+                    available_escrow,
+                }
+                .into(),
+            )),
+        }
+    }
+
+    // The pending value of the sender includes the stored receipts, so a
+    // stored receipt would be counted twice.
+    fn is_ingest_only(&self) -> bool {
+        true
+    }
+}
+
+/// Number of buckets the window of a [`ValueRateLimitCheck`] is divided into.
+const RATE_LIMIT_BUCKETS: usize = 16;
+
+/// ValueRateLimitCheck rejects a receipt when accepting it would make the
+/// value received from its sender exceed `max_value_per_window` within the
+/// last `window`.
+///
+/// The window is divided into 16 buckets, and the value of a receipt is
+/// added to the bucket of the time it was accepted. The window slides one
+/// bucket at a time: the value of a receipt stops counting once its bucket
+/// leaves the window, between `window * 15 / 16` and `window` after it was
+/// accepted. The memory used per sender is fixed, and the senders without
+/// value in the window are evicted once per window.
+///
+/// The value of a receipt rejected by a later check of the list is removed
+/// from the window, see [`Check::revert`]. The check only runs when a receipt
+/// is received (see [`Check::is_ingest_only`]). Clones share the same
+/// windows.
+#[derive(Debug, Clone)]
+pub struct ValueRateLimitCheck {
+    domain_separator: Eip712Domain,
+    bucket_ns: u128,
+    max_value_per_window: u128,
+    start: Instant,
+    windows: Arc<Mutex<SenderWindows>>,
+}
+
+#[derive(Debug, Default)]
+struct SenderWindows {
+    senders: HashMap<Address, SenderWindow>,
+    /// Bucket at which the senders were last evicted
+    evicted_at: u64,
+}
+
+/// Values accepted from a sender, per bucket of the window.
+#[derive(Debug, Default)]
+struct SenderWindow {
+    /// Indexed by bucket modulo [`RATE_LIMIT_BUCKETS`]
+    values: [u128; RATE_LIMIT_BUCKETS],
+    /// Latest bucket the window was moved to
+    latest: u64,
+}
+
+impl SenderWindow {
+    /// Moves the window to `bucket`, clearing the buckets leaving it.
+    fn advance(&mut self, bucket: u64) {
+        let elapsed = bucket.saturating_sub(self.latest);
+        for offset in 1..=elapsed.min(RATE_LIMIT_BUCKETS as u64) {
+            self.values[Self::slot(self.latest + offset)] = 0;
+        }
+        self.latest = self.latest.max(bucket);
+    }
+
+    fn accumulated(&self) -> u128 {
+        self.values
+            .iter()
+            .fold(0, |accumulated, value| accumulated.saturating_add(*value))
+    }
+
+    fn is_expired(&self, bucket: u64) -> bool {
+        bucket.saturating_sub(self.latest) >= RATE_LIMIT_BUCKETS as u64
+    }
+
+    fn slot(bucket: u64) -> usize {
+        (bucket % RATE_LIMIT_BUCKETS as u64) as usize
+    }
+}
+
+impl ValueRateLimitCheck {
+    pub fn new(
+        domain_separator: Eip712Domain,
+        window: Duration,
+        max_value_per_window: u128,
+    ) -> Self {
+        Self {
+            domain_separator,
+            bucket_ns: (window.as_nanos() / RATE_LIMIT_BUCKETS as u128).max(1),
+            max_value_per_window,
+            start: Instant::now(),
+            windows: Default::default(),
+        }
+    }
+
+    /// Bucket of the current time, counted from the creation of the check.
+    fn current_bucket(&self) -> u64 {
+        (self.start.elapsed().as_nanos() / self.bucket_ns)
+            .try_into()
+            .unwrap_or(u64::MAX)
+    }
+
+    /// Accumulates `value` in the window of `sender`, unless it would exceed
+    /// the cap.
+    fn accumulate(&self, sender: Address, value: u128) -> CheckResult {
+        let bucket = self.current_bucket();
+        let mut windows = self.windows.lock().unwrap();
+        if bucket.saturating_sub(windows.evicted_at) >= RATE_LIMIT_BUCKETS as u64 {
+            windows
+                .senders
+                .retain(|_, sender_window| !sender_window.is_expired(bucket));
+            windows.evicted_at = bucket;
+        }
+        let sender_window = windows.senders.entry(sender).or_default();
+        sender_window.advance(bucket);
+        match sender_window.accumulated().checked_add(value) {
+            Some(total) if total <= self.max_value_per_window => {
+                sender_window.values[SenderWindow::slot(bucket)] += value;
+                Ok(CheckOutcome::passed())
+            }
+            _ => Err(CheckError::Failed(
+                ReceiptError::ValueRateLimitExceeded {
+                    sender,
+                    max_value_per_window: self.max_value_per_window,
+                }
+                .into(),
+            )),
+        }
+    }
+
+    /// Removes `value` from the window of `sender`, taking it from the latest
+    /// buckets first.
+    fn remove(&self, sender: Address, mut value: u128) {
+        let mut windows = self.windows.lock().unwrap();
+        let Some(sender_window) = windows.senders.get_mut(&sender) else {
+            return;
+        };
+        let latest = sender_window.latest;
+        for bucket in (0..=latest).rev().take(RATE_LIMIT_BUCKETS) {
+            let bucket_value = &mut sender_window.values[SenderWindow::slot(bucket)];
+            let removed = value.min(*bucket_value);
+            *bucket_value -= removed;
+            value -= removed;
+            if value == 0 {
+                break;
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Check<Eip712SignedMessage<T>> for ValueRateLimitCheck
+where
+    T: SolStruct + WithValueAndTimestamp + Sync,
+{
+    async fn check(
+        &self,
+        ctx: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>,
+    ) -> CheckResult {
+        let signed_receipt = receipt.signed_receipt();
+        let sender = recover_signer(ctx, &self.domain_separator, signed_receipt)?;
+        self.accumulate(sender, signed_receipt.value())
+    }
+
+    // The value of a stored receipt was accumulated when it was received.
+    fn is_ingest_only(&self) -> bool {
+        true
+    }
+
+    fn revert(&self, ctx: &Context, receipt: &ReceiptWithState<Checking, Eip712SignedMessage<T>>) {
+        let signed_receipt = receipt.signed_receipt();
+        if let Ok(sender) = recover_signer(ctx, &self.domain_separator, signed_receipt) {
+            self.remove(sender, signed_receipt.value());
+        }
+    }
+}
+
+/// Predicate of a [`RequiredFieldsCheck`], returning why a message is
+/// rejected.
+pub type FieldsPredicate<M> = Arc<dyn Fn(&M) -> Result<(), String> + Send + Sync>;
+
+/// RequiredFieldsCheck rejects receipts whose message does not satisfy a
+/// predicate.
+///
+/// This is the escape hatch for custom validation: any field-level policy,
+/// e.g. requiring optional metadata to be present, can be enforced with a
+/// closure instead of a bespoke check type. The message of the error returned
+/// by the predicate is the reason of the rejection.
+///
+/// Set a distinct [`Check::typetag_name`] with
+/// [`RequiredFieldsCheck::with_name`] to add several of them to a
+/// [`CheckList`].
+pub struct RequiredFieldsCheck<M> {
+    name: &'static str,
+    predicate: FieldsPredicate<M>,
+}
+
+impl<M> RequiredFieldsCheck<M> {
+    pub fn new(predicate: impl Fn(&M) -> Result<(), String> + Send + Sync + 'static) -> Self {
+        Self {
+            name: std::any::type_name::<Self>(),
+            predicate: Arc::new(predicate),
+        }
+    }
+
+    /// Names the check `name`, see [`Check::typetag_name`].
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<M> Check<Eip712SignedMessage<M>> for RequiredFieldsCheck<M>
+where
+    M: SolStruct + Sync,
+{
+    async fn check(
+        &self,
+        _: &Context,
+        receipt: &ReceiptWithState<Checking, Eip712SignedMessage<M>>,
+    ) -> CheckResult {
+        (self.predicate)(&receipt.signed_receipt().message)
+            .map(|()| CheckOutcome::passed())
+            .map_err(|reason| CheckError::Failed(anyhow::anyhow!(reason)))
+    }
+
+    fn typetag_name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// ClosedAllocationCheck rejects receipts for allocations that were closed.
+///
+/// Clones share the same set of closed allocations.
+#[derive(Debug, Clone, Default)]
+pub struct ClosedAllocationCheck {
+    closed_allocations: Arc<RwLock<HashSet<Address>>>,
+}
+
+impl ClosedAllocationCheck {
+    /// Marks `allocation_id` as closed, rejecting its receipts from now on.
+    pub fn close(&self, allocation_id: Address) {
+        self.closed_allocations
+            .write()
+            .unwrap()
+            .insert(allocation_id);
+    }
+
+    pub fn is_closed(&self, allocation_id: &Address) -> bool {
+        self.closed_allocations
+            .read()
+            .unwrap()
+            .contains(allocation_id)
+    }
+}
+
+#[async_trait::async_trait]
+impl<Rcpt> Check<Rcpt> for ClosedAllocationCheck
+where
+    Rcpt: WithAllocationId + Sync,
+{
+    async fn check(&self, _: &Context, receipt: &ReceiptWithState<Checking, Rcpt>) -> CheckResult {
+        let allocation_id = receipt.signed_receipt().allocation_id();
+        if self.is_closed(&allocation_id) {
+            return Err(CheckError::Failed(
+                ReceiptError::AllocationClosed { allocation_id }.into(),
+            ));
+        }
+        Ok(CheckOutcome::passed())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::time::{Duration, SystemTime};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, SystemTime},
+    };
 
     use alloy::{
         dyn_abi::Eip712Domain, primitives::Address, signers::local::PrivateKeySigner, sol,
         sol_types::eip712_domain,
     };
-    use tap_eip712_message::Eip712SignedMessage;
+    use tap_eip712_message::{Eip712SignedMessage, SignatureBytes};
+    use tokio::sync::Barrier;
 
     use super::*;
 
@@ -236,16 +1503,26 @@ mod tests {
         }
     }
 
-    fn create_signed_receipt_with_custom_value(
-        value: u128,
-    ) -> ReceiptWithState<Checking, Eip712SignedMessage<MyReceipt>> {
-        let wallet: PrivateKeySigner = PrivateKeySigner::random();
-        let eip712_domain_separator: Eip712Domain = eip712_domain! {
+    impl WithAllocationId for MyReceipt {
+        fn allocation_id(&self) -> Address {
+            Address::ZERO
+        }
+    }
+
+    fn domain_separator() -> Eip712Domain {
+        eip712_domain! {
             name: "TAP",
             version: "1",
             chain_id: 1,
             verifying_contract: Address:: from([0x11u8; 20]),
-        };
+        }
+    }
+
+    fn create_signed_receipt_with_custom_value(
+        value: u128,
+    ) -> ReceiptWithState<Checking, Eip712SignedMessage<MyReceipt>> {
+        let wallet: PrivateKeySigner = PrivateKeySigner::random();
+        let eip712_domain_separator = domain_separator();
 
         let timestamp = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -278,6 +1555,472 @@ mod tests {
         assert_eq!(invalid_receipts.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_bloom_nonce_check() {
+        /// Exact check backed by the set of "stored" receipts
+        struct StoredCheck(Arc<RwLock<HashSet<SignatureBytes>>>);
+
+        #[async_trait::async_trait]
+        impl Check<Eip712SignedMessage<MyReceipt>> for StoredCheck {
+            async fn check(
+                &self,
+                _: &Context,
+                receipt: &ReceiptWithState<Checking, Eip712SignedMessage<MyReceipt>>,
+            ) -> CheckResult {
+                if self
+                    .0
+                    .read()
+                    .unwrap()
+                    .contains(&receipt.signed_receipt().unique_id())
+                {
+                    Err(CheckError::Failed(ReceiptError::NonUniqueReceipt.into()))
+                } else {
+                    Ok(CheckOutcome::passed())
+                }
+            }
+        }
+
+        let stored = Arc::new(RwLock::new(HashSet::new()));
+        // Very small filter with a high false-positive rate to force bloom hits
+        let check = BloomNonceCheck::new(4, 0.5, Arc::new(StoredCheck(stored.clone())));
+        let ctx = Context::new();
+
+        let receipts: Vec<_> = (0..100)
+            .map(create_signed_receipt_with_custom_value)
+            .collect();
+
+        // No false rejections
+        for receipt in &receipts {
+            assert!(check.check(&ctx, receipt).await.is_ok());
+            stored
+                .write()
+                .unwrap()
+                .insert(receipt.signed_receipt().unique_id());
+        }
+
+        // True duplicates are caught
+        for receipt in &receipts {
+            assert!(check.check(&ctx, receipt).await.is_err());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_bloom_nonce_check_concurrent_copies() {
+        /// Exact check counting its calls, the receipts are never stored
+        struct CountingCheck(Arc<AtomicUsize>);
+
+        #[async_trait::async_trait]
+        impl Check<Eip712SignedMessage<MyReceipt>> for CountingCheck {
+            async fn check(
+                &self,
+                _: &Context,
+                _: &ReceiptWithState<Checking, Eip712SignedMessage<MyReceipt>>,
+            ) -> CheckResult {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(CheckOutcome::passed())
+            }
+        }
+
+        const COPIES: usize = 16;
+        let exact_checks = Arc::new(AtomicUsize::new(0));
+        let check = Arc::new(BloomNonceCheck::new(
+            1_000,
+            0.001,
+            Arc::new(CountingCheck(exact_checks.clone())),
+        ));
+        let receipt = create_signed_receipt_with_custom_value(42);
+        let barrier = Arc::new(Barrier::new(COPIES));
+
+        let tasks: Vec<_> = (0..COPIES)
+            .map(|_| {
+                let (check, receipt, barrier) = (check.clone(), receipt.clone(), barrier.clone());
+                tokio::spawn(async move {
+                    barrier.wait().await;
+                    check.check(&Context::new(), &receipt).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert!(task.await.unwrap().is_ok());
+        }
+
+        // only one copy missed the filter, the others were confirmed
+        assert_eq!(exact_checks.load(Ordering::SeqCst), COPIES - 1);
+    }
+
+    #[test]
+    fn test_check_list_presets() {
+        struct StoredCheck;
+        struct ValueCheck;
+        struct Escrow;
+
+        #[async_trait::async_trait]
+        impl<T> Check<T> for StoredCheck {
+            async fn check(&self, _: &Context, _: &ReceiptWithState<Checking, T>) -> CheckResult {
+                Ok(CheckOutcome::passed())
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl<T> Check<T> for ValueCheck {
+            async fn check(&self, _: &Context, _: &ReceiptWithState<Checking, T>) -> CheckResult {
+                Ok(CheckOutcome::passed())
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl<T: Sync> EscrowHeadroom<T> for Escrow {
+            async fn available_escrow(&self, _: &T) -> anyhow::Result<u128> {
+                Ok(u128::MAX)
+            }
+
+            async fn pending_value(&self, _: &T) -> anyhow::Result<u128> {
+                Ok(0)
+            }
+        }
+
+        let config = || AggregatorCheckConfig {
+            domain_separator: domain_separator(),
+            accepted_signers: HashSet::new(),
+            stored_receipt_check: Arc::new(StoredCheck),
+            expected_receipts: 1_000,
+            timestamp_check: Arc::new(StatefulTimestampCheck::new(0)),
+            max_timestamp_distance_years: 1,
+        };
+        let aggregator_checks = vec![
+            std::any::type_name::<TimestampSanityCheck>(),
+            std::any::type_name::<StatefulTimestampCheck>(),
+            std::any::type_name::<SignerCheck>(),
+            std::any::type_name::<BloomNonceCheck<Eip712SignedMessage<MyReceipt>>>(),
+        ];
+
+        let checks = CheckList::<Eip712SignedMessage<MyReceipt>>::aggregator_defaults(config());
+        let names: Vec<_> = checks.iter().map(|check| check.typetag_name()).collect();
+        assert_eq!(names, aggregator_checks);
+        assert_eq!(checks.validate(), Ok(()));
+
+        let checks = CheckList::<Eip712SignedMessage<MyReceipt>>::indexer_defaults(
+            config(),
+            IndexerCheckConfig {
+                allocation_ids: Arc::new(RwLock::new(HashSet::new())),
+                value_check: Arc::new(ValueCheck),
+                escrow: Escrow,
+            },
+        );
+        let names: Vec<_> = checks.iter().map(|check| check.typetag_name()).collect();
+        let indexer_checks: Vec<_> = aggregator_checks
+            .into_iter()
+            .chain([
+                std::any::type_name::<AllocationIdCheck>(),
+                std::any::type_name::<ValueCheck>(),
+                std::any::type_name::<EscrowHeadroomCheck<Escrow>>(),
+            ])
+            .collect();
+        assert_eq!(names, indexer_checks);
+        assert_eq!(checks.validate(), Ok(()));
+
+        // the nonce filter and the escrow headroom only run on receipt
+        let ingest_only: Vec<_> = checks
+            .iter()
+            .filter(|check| check.is_ingest_only())
+            .map(|check| check.typetag_name())
+            .collect();
+        assert_eq!(
+            ingest_only,
+            vec![
+                std::any::type_name::<BloomNonceCheck<Eip712SignedMessage<MyReceipt>>>(),
+                std::any::type_name::<EscrowHeadroomCheck<Escrow>>(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_list_merge() {
+        struct FirstCheck;
+        struct SecondCheck;
+
+        #[async_trait::async_trait]
+        impl<T> Check<T> for FirstCheck {
+            async fn check(&self, _: &Context, _: &ReceiptWithState<Checking, T>) -> CheckResult {
+                Ok(CheckOutcome::passed())
+            }
+        }
+
+        #[async_trait::async_trait]
+        impl<T> Check<T> for SecondCheck {
+            async fn check(&self, _: &Context, _: &ReceiptWithState<Checking, T>) -> CheckResult {
+                Ok(CheckOutcome::passed())
+            }
+        }
+
+        let base: CheckList<Eip712SignedMessage<MyReceipt>> = CheckList::new(vec![
+            Arc::new(FirstCheck) as ReceiptCheck<_>,
+            Arc::new(StatefulTimestampCheck::new(0)),
+        ]);
+        let allocation_checks = CheckList::new(vec![
+            Arc::new(StatefulTimestampCheck::new(0)) as ReceiptCheck<_>,
+            Arc::new(SecondCheck),
+        ]);
+
+        let mut checks = base.merge(allocation_checks);
+        let names: Vec<_> = checks.iter().map(|check| check.typetag_name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                std::any::type_name::<FirstCheck>(),
+                std::any::type_name::<StatefulTimestampCheck>(),
+                std::any::type_name::<SecondCheck>(),
+            ]
+        );
+
+        checks.extend(vec![Arc::new(SecondCheck)]);
+        assert_eq!(checks.len(), 3);
+    }
+
+    #[test]
+    fn test_check_list_validate() {
+        let checks: CheckList<Eip712SignedMessage<MyReceipt>> = CheckList::new(vec![
+            Arc::new(StatefulTimestampCheck::new(0)) as ReceiptCheck<_>,
+            Arc::new(TimestampSanityCheck::new(1)),
+        ]);
+        assert_eq!(checks.validate(), Ok(()));
+
+        // two floors, only one of them would be updated
+        let checks: CheckList<Eip712SignedMessage<MyReceipt>> = CheckList::new(vec![
+            Arc::new(StatefulTimestampCheck::new(0)) as ReceiptCheck<_>,
+            Arc::new(StatefulTimestampCheck::new(100)),
+        ]);
+        assert_eq!(
+            checks.validate(),
+            Err(CheckConfigError::DuplicateCheck {
+                name: std::any::type_name::<StatefulTimestampCheck>()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signer_check() {
+        let receipt = create_signed_receipt_with_custom_value(10);
+        let signer = receipt
+            .signed_receipt()
+            .recover_signer(&domain_separator())
+            .unwrap();
+        let ctx = Context::new();
+
+        let check = SignerCheck::new(domain_separator(), HashSet::from([signer]));
+        assert!(check.check(&ctx, &receipt).await.is_ok());
+
+        let check = SignerCheck::new(domain_separator(), HashSet::from([Address::ZERO]));
+        assert!(check.check(&ctx, &receipt).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_value_rate_limit_check() {
+        let wallet = PrivateKeySigner::random();
+        let receipt = |value| {
+            let message = MyReceipt {
+                timestamp_ns: 0,
+                value,
+            };
+            ReceiptWithState::new(
+                Eip712SignedMessage::new(&domain_separator(), message, &wallet).unwrap(),
+            )
+        };
+        let ctx = Context::new();
+        let window = Duration::from_secs(RATE_LIMIT_BUCKETS as u64);
+        let check = ValueRateLimitCheck::new(domain_separator(), window, 100);
+
+        // within a window, the cap is reached but not exceeded
+        assert!(check.check(&ctx, &receipt(60)).await.is_ok());
+        tokio::time::advance(window / 2).await;
+        assert!(check.check(&ctx, &receipt(40)).await.is_ok());
+        let err = check.check(&ctx, &receipt(1)).await.unwrap_err();
+        assert!(err.to_string().contains("rate limit"));
+
+        // other senders have their own window
+        let other = create_signed_receipt_with_custom_value(100);
+        assert!(check.check(&ctx, &other).await.is_ok());
+
+        // the window slides: the 60 expired, the 40 still counts
+        tokio::time::advance(window / 2).await;
+        assert!(check.check(&ctx, &receipt(60)).await.is_ok());
+        assert!(check.check(&ctx, &receipt(1)).await.is_err());
+
+        // once all values expired, the window starts from zero
+        tokio::time::advance(window).await;
+        assert!(check.check(&ctx, &receipt(100)).await.is_ok());
+        assert!(check.check(&ctx, &receipt(1)).await.is_err());
+
+        // a single receipt above the cap never passes
+        tokio::time::advance(window).await;
+        assert!(check.check(&ctx, &receipt(101)).await.is_err());
+
+        // the value of a receipt rejected by a later check does not count
+        let checks: CheckList<Eip712SignedMessage<MyReceipt>> = CheckList::new(vec![
+            Arc::new(check.clone()) as ReceiptCheck<_>,
+            Arc::new(SignerCheck::new(
+                domain_separator(),
+                HashSet::from([Address::ZERO]),
+            )),
+        ]);
+        assert!(checks.perform_checks(&ctx, &receipt(100)).await.is_err());
+        assert!(check.check(&ctx, &receipt(100)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_required_fields_check() {
+        sol! {
+            struct MetadataReceipt {
+                uint128 value;
+                bytes metadata;
+            }
+        }
+
+        let wallet = PrivateKeySigner::random();
+        let receipt = |metadata: &'static [u8]| {
+            let message = MetadataReceipt {
+                value: 42,
+                metadata: metadata.into(),
+            };
+            ReceiptWithState::new(
+                Eip712SignedMessage::new(&domain_separator(), message, &wallet).unwrap(),
+            )
+        };
+        let check = RequiredFieldsCheck::new(|message: &MetadataReceipt| {
+            if message.metadata.is_empty() {
+                return Err("metadata is required".to_string());
+            }
+            Ok(())
+        });
+        let ctx = Context::new();
+
+        assert!(check.check(&ctx, &receipt(b"query")).await.is_ok());
+        let err = check.check(&ctx, &receipt(b"")).await.unwrap_err();
+        assert_eq!(err.to_string(), "metadata is required");
+
+        let named = RequiredFieldsCheck::new(|_: &MetadataReceipt| Ok(())).with_name("other");
+        assert_eq!(
+            Check::<Eip712SignedMessage<MetadataReceipt>>::typetag_name(&named),
+            "other"
+        );
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn test_prometheus_check_metrics() {
+        let registry = prometheus::Registry::new();
+        let metrics = Arc::new(PrometheusCheckMetrics::new(&registry).unwrap());
+        let checks: CheckList<Eip712SignedMessage<MyReceipt>> = CheckList::new(vec![
+            Arc::new(TimestampSanityCheck::new(1)),
+            Arc::new(SignerCheck::new(
+                domain_separator(),
+                HashSet::from([Address::ZERO]),
+            )),
+        ])
+        .with_metrics(metrics.clone());
+        let ctx = Context::new();
+
+        let receipt = create_signed_receipt_with_custom_value(10);
+        assert!(checks.perform_checks(&ctx, &receipt).await.is_err());
+        assert!(checks.perform_checks(&ctx, &receipt).await.is_err());
+
+        assert_eq!(metrics.rejected(std::any::type_name::<SignerCheck>()), 2);
+        assert_eq!(
+            metrics.rejected(std::any::type_name::<TimestampSanityCheck>()),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_sanity_check() {
+        let check = TimestampSanityCheck::new(1);
+        let ctx = Context::new();
+
+        // receipts are stamped 33 seconds in the future
+        let sane_receipt = create_signed_receipt_with_custom_value(10);
+        assert!(check.check(&ctx, &sane_receipt).await.is_ok());
+
+        let timestamp_ns = sane_receipt.signed_receipt().message.timestamp_ns;
+        for wrong_timestamp in [
+            // milliseconds mistaken for nanoseconds
+            timestamp_ns / 1_000_000,
+            // seconds mistaken for nanoseconds
+            timestamp_ns / 1_000_000_000,
+            // nanoseconds multiplied again
+            timestamp_ns.saturating_mul(10),
+        ] {
+            let mut receipt = sane_receipt.clone();
+            receipt.receipt.message.timestamp_ns = wrong_timestamp;
+            assert!(check.check(&ctx, &receipt).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rav_window_check() {
+        let check = RavWindowCheck::new(Duration::from_secs(3600));
+        let ctx = Context::new();
+        let receipt = create_signed_receipt_with_custom_value(10);
+        let timestamp_ns = receipt.signed_receipt().message.timestamp_ns;
+
+        // no RAV yet, the window starts now
+        assert!(check.check(&ctx, &receipt).await.is_ok());
+        let mut far_receipt = receipt.clone();
+        far_receipt.receipt.message.timestamp_ns = u64::MAX;
+        assert!(check.check(&ctx, &far_receipt).await.is_err());
+
+        check.update_rav_timestamp_ns(timestamp_ns - 1);
+        assert!(check.check(&ctx, &receipt).await.is_ok());
+
+        // a year past the RAV
+        let mut far_receipt = receipt.clone();
+        far_receipt.receipt.message.timestamp_ns =
+            timestamp_ns + Duration::from_secs(365 * 24 * 3600).as_nanos() as u64;
+        let error = check.check(&ctx, &far_receipt).await.unwrap_err();
+        let CheckError::Failed(error) = error else {
+            panic!("unexpected error {error:?}");
+        };
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(ReceiptError::TimestampBeyondRavWindow { .. })
+        ));
+
+        // the window does not overflow
+        let check = RavWindowCheck::new(Duration::MAX);
+        check.update_rav_timestamp_ns(timestamp_ns);
+        far_receipt.receipt.message.timestamp_ns = u64::MAX;
+        assert!(check.check(&ctx, &far_receipt).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_clock_skew_policy() {
+        let ctx = Context::new();
+        // receipts are stamped 33 seconds in the future
+        let receipt = create_signed_receipt_with_custom_value(10);
+
+        let clock_skew = SharedClockSkewPolicy::new(ClockSkewPolicy {
+            max_future_ns: Duration::from_secs(32).as_nanos() as u64,
+            max_past_ns: Duration::from_secs(60).as_nanos() as u64,
+        });
+        let sanity_check = TimestampSanityCheck::with_clock_skew(clock_skew.clone());
+        let stateful_check = StatefulTimestampCheck::new(0).with_clock_skew(clock_skew.clone());
+
+        // just outside the future skew
+        assert!(sanity_check.check(&ctx, &receipt).await.is_err());
+        assert!(stateful_check.check(&ctx, &receipt).await.is_err());
+
+        // both checks see the adjusted policy
+        clock_skew.set(ClockSkewPolicy {
+            max_future_ns: Duration::from_secs(34).as_nanos() as u64,
+            ..clock_skew.get()
+        });
+        assert!(sanity_check.check(&ctx, &receipt).await.is_ok());
+        assert!(stateful_check.check(&ctx, &receipt).await.is_ok());
+
+        // the floor still applies within the window
+        stateful_check.update_min_timestamp_ns(receipt.signed_receipt().message.timestamp_ns);
+        assert!(stateful_check.check(&ctx, &receipt).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_receipt_timestamp_check() {
         let signed_receipt = create_signed_receipt_with_custom_value(10);
@@ -5,9 +5,15 @@ use alloy::primitives::Address;
 use serde::{Deserialize, Serialize};
 
 /// Error type for receipts
+///
+/// Serialized with the value of [`ReceiptError::code`] under `code` and the
+/// variant fields, if any, under `details`, so that clients can branch on
+/// the kind of error without parsing the message.
 #[derive(thiserror::Error, Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "code", content = "details", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ReceiptError {
     #[error("invalid allocation ID: {received_allocation_id}")]
+    #[serde(rename = "INVALID_ALLOCATION_ID")]
     InvalidAllocationID { received_allocation_id: Address },
     #[error("Signature check failed:\n{source_error_message}")]
     InvalidSignature { source_error_message: String },
@@ -16,14 +22,147 @@ pub enum ReceiptError {
         received_timestamp: u64,
         timestamp_min: u64,
     },
+    #[error(
+        "implausible timestamp: {received_timestamp}, too far from the current time \
+        (is it in nanoseconds?)"
+    )]
+    ImplausibleTimestamp { received_timestamp: u64 },
+    #[error("timestamp {received_timestamp} beyond the RAV window (expected max {timestamp_max})")]
+    TimestampBeyondRavWindow {
+        received_timestamp: u64,
+        timestamp_max: u64,
+    },
     #[error("Invalid Value: {received_value} ")]
     InvalidValue { received_value: u128 },
     #[error("Receipt is not unique")]
     NonUniqueReceipt,
     #[error("Attempt to collect escrow failed")]
     SubtractEscrowFailed,
+    #[error("Insufficient escrow: {required_escrow} required, {available_escrow} available")]
+    InsufficientEscrow {
+        required_escrow: u128,
+        available_escrow: u128,
+    },
+    #[error("Allocation {allocation_id} is closed")]
+    AllocationClosed { allocation_id: Address },
+    #[error("Value rate limit of {sender} exceeded: at most {max_value_per_window} per window")]
+    ValueRateLimitExceeded {
+        sender: Address,
+        max_value_per_window: u128,
+    },
     #[error("Issue encountered while performing check: {0}")]
     CheckFailure(String),
     #[error("Retryable check error encountered: {0}")]
     RetryableCheck(String),
 }
+
+impl ReceiptError {
+    /// Stable identifier of the error kind.
+    ///
+    /// Unlike the error message, the code is part of the API and will not
+    /// change between releases.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReceiptError::InvalidAllocationID { .. } => "INVALID_ALLOCATION_ID",
+            ReceiptError::InvalidSignature { .. } => "INVALID_SIGNATURE",
+            ReceiptError::InvalidTimestamp { .. } => "INVALID_TIMESTAMP",
+            ReceiptError::ImplausibleTimestamp { .. } => "IMPLAUSIBLE_TIMESTAMP",
+            ReceiptError::TimestampBeyondRavWindow { .. } => "TIMESTAMP_BEYOND_RAV_WINDOW",
+            ReceiptError::InvalidValue { .. } => "INVALID_VALUE",
+            ReceiptError::NonUniqueReceipt => "NON_UNIQUE_RECEIPT",
+            ReceiptError::SubtractEscrowFailed => "SUBTRACT_ESCROW_FAILED",
+            ReceiptError::InsufficientEscrow { .. } => "INSUFFICIENT_ESCROW",
+            ReceiptError::AllocationClosed { .. } => "ALLOCATION_CLOSED",
+            ReceiptError::ValueRateLimitExceeded { .. } => "VALUE_RATE_LIMIT_EXCEEDED",
+            ReceiptError::CheckFailure(_) => "CHECK_FAILURE",
+            ReceiptError::RetryableCheck(_) => "RETRYABLE_CHECK",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Address;
+
+    use super::ReceiptError;
+
+    #[test]
+    fn receipt_error_codes_are_stable() {
+        let errors = [
+            (
+                ReceiptError::InvalidAllocationID {
+                    received_allocation_id: Address::ZERO,
+                },
+                "INVALID_ALLOCATION_ID",
+            ),
+            (
+                ReceiptError::InvalidSignature {
+                    source_error_message: "bad signature".into(),
+                },
+                "INVALID_SIGNATURE",
+            ),
+            (
+                ReceiptError::InvalidTimestamp {
+                    received_timestamp: 1,
+                    timestamp_min: 2,
+                },
+                "INVALID_TIMESTAMP",
+            ),
+            (
+                ReceiptError::ImplausibleTimestamp {
+                    received_timestamp: 1,
+                },
+                "IMPLAUSIBLE_TIMESTAMP",
+            ),
+            (
+                ReceiptError::TimestampBeyondRavWindow {
+                    received_timestamp: 2,
+                    timestamp_max: 1,
+                },
+                "TIMESTAMP_BEYOND_RAV_WINDOW",
+            ),
+            (
+                ReceiptError::InvalidValue { received_value: 1 },
+                "INVALID_VALUE",
+            ),
+            (ReceiptError::NonUniqueReceipt, "NON_UNIQUE_RECEIPT"),
+            (ReceiptError::SubtractEscrowFailed, "SUBTRACT_ESCROW_FAILED"),
+            (
+                ReceiptError::InsufficientEscrow {
+                    required_escrow: 2,
+                    available_escrow: 1,
+                },
+                "INSUFFICIENT_ESCROW",
+            ),
+            (
+                ReceiptError::AllocationClosed {
+                    allocation_id: Address::ZERO,
+                },
+                "ALLOCATION_CLOSED",
+            ),
+            (
+                ReceiptError::ValueRateLimitExceeded {
+                    sender: Address::ZERO,
+                    max_value_per_window: 1,
+                },
+                "VALUE_RATE_LIMIT_EXCEEDED",
+            ),
+            (ReceiptError::CheckFailure("failed".into()), "CHECK_FAILURE"),
+            (
+                ReceiptError::RetryableCheck("retry".into()),
+                "RETRYABLE_CHECK",
+            ),
+        ];
+
+        for (error, code) in errors {
+            assert_eq!(error.code(), code);
+
+            let serialized = serde_json::to_value(&error).unwrap();
+            assert_eq!(serialized["code"], code);
+
+            let deserialized: ReceiptError = serde_json::from_value(serialized).unwrap();
+            assert_eq!(deserialized.code(), code);
+            assert_eq!(deserialized.to_string(), error.to_string());
+        }
+    }
+}
@@ -17,6 +17,7 @@
 //! - `Failed`: The receipt has failed a check or validation.
 //! - `AwaitingReserve`: The receipt has passed all checks and is awaiting escrow reservation.
 //! - `Reserved`: The receipt has successfully reserved escrow.
+//! - `PendingEscrow`: The receipt is held until the escrow of its sender is topped up.
 //!
 //!
 pub mod checks;
@@ -25,7 +26,10 @@ pub mod rav;
 mod received_receipt;
 pub mod state;
 
-use alloy::sol_types::SolStruct;
+use alloy::{
+    primitives::{Address, PrimitiveSignature as Signature},
+    sol_types::SolStruct,
+};
 pub use error::ReceiptError;
 pub use received_receipt::ReceiptWithState;
 use tap_eip712_message::{Eip712SignedMessage, SignatureBytes, SignatureBytesExt};
@@ -36,12 +40,55 @@ pub type ReceiptResult<T> = Result<T, ReceiptError>;
 /// Extra information for [checks::Check]
 pub type Context = anymap3::Map<dyn std::any::Any + Send + Sync>;
 
+/// Signer of a receipt already recovered by the caller, e.g. while
+/// authenticating the request carrying the receipt.
+///
+/// When inserted in the [`Context`], signature checks use it instead of
+/// recovering the signer again. It is bound to the signature it was
+/// recovered from and ignored for any other receipt checked with the same
+/// context.
+///
+/// Checks trust the address as is. Only insert it after recovering it from
+/// the receipt signature with the domain separator used by the checks,
+/// otherwise receipts from any signer could be accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveredSigner {
+    signature: Signature,
+    address: Address,
+}
+
+impl RecoveredSigner {
+    /// Binds `address`, recovered from the signature of `signed_message`,
+    /// to that signature.
+    pub fn new<T: SolStruct>(signed_message: &Eip712SignedMessage<T>, address: Address) -> Self {
+        Self {
+            signature: signed_message.signature,
+            address,
+        }
+    }
+
+    /// Returns the signer of `signed_message` found in `ctx`, if any.
+    pub fn get<T: SolStruct>(
+        ctx: &Context,
+        signed_message: &Eip712SignedMessage<T>,
+    ) -> Option<Address> {
+        ctx.get::<Self>()
+            .filter(|signer| signer.signature == signed_message.signature)
+            .map(|signer| signer.address)
+    }
+}
+
 /// Extension that allows TAP Aggregation for any SolStruct receipt
 pub trait WithValueAndTimestamp {
     fn value(&self) -> u128;
     fn timestamp_ns(&self) -> u64;
 }
 
+/// Extension that allows [checks::ClosedAllocationCheck] for any SolStruct receipt
+pub trait WithAllocationId {
+    fn allocation_id(&self) -> Address;
+}
+
 /// Extension that allows UniqueCheck for any SolStruct receipt
 pub trait WithUniqueId {
     type Output: Eq + std::hash::Hash;
@@ -61,6 +108,15 @@ where
     }
 }
 
+impl<T> WithAllocationId for Eip712SignedMessage<T>
+where
+    T: SolStruct + WithAllocationId,
+{
+    fn allocation_id(&self) -> Address {
+        self.message.allocation_id()
+    }
+}
+
 impl<T> WithUniqueId for Eip712SignedMessage<T>
 where
     T: SolStruct,
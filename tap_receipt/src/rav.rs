@@ -27,7 +27,55 @@ pub enum AggregationError {
     #[error("Failed to produce rav request, no valid receipts")]
     NoValidReceiptsForRavRequest,
 
+    /// Error when a receipt is not newer than the timestamp the aggregation starts from
+    #[error("Receipt timestamp ({receipt_ts}) is less or equal than base timestamp ({base_ts})")]
+    ReceiptTimestampNotAfterBase { base_ts: u64, receipt_ts: u64 },
+
+    /// Error when the metadata of a RAV is larger than allowed
+    #[error("RAV metadata is too large: {size} bytes (max {max_size})")]
+    MetadataTooLarge { size: usize, max_size: usize },
+
     /// Other user-defined error
     #[error(transparent)]
     Other(anyhow::Error),
 }
+
+/// Sum of receipt values that fails on overflow, instead of panicking in
+/// debug builds and wrapping in release builds like [`Iterator::sum`].
+pub trait CheckedSum: Iterator<Item = u128> + Sized {
+    /// Returns the sum of the values, or [`AggregationError::AggregateOverflow`]
+    /// if it does not fit in a `u128`.
+    fn checked_sum(self) -> Result<u128, AggregationError> {
+        self.checked_sum_from(0)
+    }
+
+    /// Same as [`CheckedSum::checked_sum`], starting from `initial_value`,
+    /// e.g. the value of the previous RAV.
+    fn checked_sum_from(self, initial_value: u128) -> Result<u128, AggregationError> {
+        self.try_fold(initial_value, u128::checked_add)
+            .ok_or(AggregationError::AggregateOverflow)
+    }
+}
+
+impl<I: Iterator<Item = u128>> CheckedSum for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_sum_detects_overflow() {
+        assert_eq!([1u128, 2, 3].into_iter().checked_sum().unwrap(), 6);
+        assert_eq!([1u128, 2].into_iter().checked_sum_from(4).unwrap(), 7);
+        assert_eq!(std::iter::empty::<u128>().checked_sum().unwrap(), 0);
+
+        assert!(matches!(
+            [u128::MAX, 1].into_iter().checked_sum(),
+            Err(AggregationError::AggregateOverflow)
+        ));
+        assert!(matches!(
+            [1u128].into_iter().checked_sum_from(u128::MAX),
+            Err(AggregationError::AggregateOverflow)
+        ));
+    }
+}
@@ -13,10 +13,13 @@
 //! This module is useful for managing and tracking the state of received receipts, as well as
 //! their progress through various checks and stages of inclusion in RAV requests and received RAVs.
 
-use super::{checks::CheckError, Context, ReceiptError, ReceiptResult};
+use super::{
+    checks::{CheckError, CheckWarning},
+    Context, ReceiptError, ReceiptResult,
+};
 use crate::{
     checks::ReceiptCheck,
-    state::{Checked, Checking, Failed, ReceiptState},
+    state::{Checked, Checking, Failed, PendingEscrow, ReceiptState},
 };
 
 pub type ResultReceipt<S, Rcpt> =
@@ -34,6 +37,8 @@ pub type ResultReceipt<S, Rcpt> =
 ///   passed all checks and is awaiting escrow reservation.
 /// - The [ `Reserved` ] state is used to represent a receipt that has
 ///   successfully reserved escrow.
+/// - The [ `PendingEscrow` ] state is used to represent a receipt held until
+///   the escrow of its sender is topped up.
 #[derive(Debug, Clone)]
 pub struct ReceiptWithState<S, Rcpt>
 where
@@ -67,52 +72,218 @@ impl<Rcpt> ReceiptWithState<Checking, Rcpt> {
         ctx: &Context,
         checks: &[ReceiptCheck<Rcpt>],
     ) -> ReceiptResult<()> {
-        for check in checks {
+        self.run_checks(ctx, checks)
+            .await
+            .map(|_| ())
+            .map_err(|(_, error)| error)
+    }
+
+    /// Same as [`ReceiptWithState::perform_checks`], returning the warnings
+    /// raised by the passing checks, and the name of the failing check on
+    /// error
+    pub(crate) async fn run_checks(
+        &self,
+        ctx: &Context,
+        checks: &[ReceiptCheck<Rcpt>],
+    ) -> Result<Vec<CheckWarning>, (&'static str, ReceiptError)> {
+        let mut warnings = Vec::new();
+        self.run_checks_raw(ctx, checks, &mut warnings)
+            .await
+            .map(|()| warnings)
+            .map_err(|(index, e)| {
+                self.revert_checks(ctx, &checks[..index]);
+                (
+                    checks[index].typetag_name(),
+                    check_error_to_receipt_error(e),
+                )
+            })
+    }
+
+    /// Same as [`ReceiptWithState::run_checks`], returning the error of the
+    /// failing check as is, along with the index of the check in `checks`.
+    /// The checks the receipt passed are not reverted, and their warnings are
+    /// appended to `warnings`.
+    pub(crate) async fn run_checks_raw(
+        &self,
+        ctx: &Context,
+        checks: &[ReceiptCheck<Rcpt>],
+        warnings: &mut Vec<CheckWarning>,
+    ) -> Result<(), (usize, CheckError)> {
+        for (index, check) in checks.iter().enumerate() {
             // return early on an error
-            check.check(ctx, self).await.map_err(|e| match e {
-                CheckError::Retryable(e) => ReceiptError::RetryableCheck(e.to_string()),
-                CheckError::Failed(e) => ReceiptError::CheckFailure(e.to_string()),
-            })?;
+            let outcome = check.check(ctx, self).await.map_err(|e| (index, e))?;
+            warnings.extend(outcome.warnings.into_iter().map(|message| CheckWarning {
+                check: check.typetag_name(),
+                message,
+            }));
         }
         Ok(())
     }
 
+    /// Reverts `checks`, the latest first, once the receipt failed a check
+    /// following them, see [`crate::checks::Check::revert`]
+    pub(crate) fn revert_checks(&self, ctx: &Context, checks: &[ReceiptCheck<Rcpt>]) {
+        for check in checks.iter().rev() {
+            check.revert(ctx, self);
+        }
+    }
+
+    /// Holds the receipt until the escrow of its sender is topped up, `error`
+    /// being the [`ReceiptError::InsufficientEscrow`] it failed `check` with,
+    /// and `warnings` the ones raised by the checks it passed before
+    pub(crate) fn hold_for_escrow(
+        self,
+        check: &'static str,
+        error: ReceiptError,
+        warnings: Vec<CheckWarning>,
+    ) -> ReceiptWithState<PendingEscrow, Rcpt> {
+        self.perform_state_changes(PendingEscrow {
+            error,
+            check,
+            warnings,
+        })
+    }
+
     /// Completes all checks and transitions the receipt to the next state
     ///
     /// Returns `Err` with a [`ReceiptWithState<Failed>`] in case of error,
     /// returns `Ok` with a [`ReceiptWithState<AwaitingReserve>`] in case of success.
     ///
     pub async fn finalize_receipt_checks(
-        mut self,
+        self,
         ctx: &Context,
         checks: &[ReceiptCheck<Rcpt>],
     ) -> Result<ResultReceipt<Checked, Rcpt>, String> {
-        let all_checks_passed = self.perform_checks(ctx, checks).await;
-        if let Err(ReceiptError::RetryableCheck(e)) = all_checks_passed {
-            Err(e.to_string())
-        } else if let Err(e) = all_checks_passed {
-            Ok(Err(self.perform_state_error(e)))
-        } else {
-            let checked = self.perform_state_changes(Checked);
-            Ok(Ok(checked))
+        Ok(self
+            .perform_checks_or_fail(ctx, checks)
+            .await?
+            .map(|receipt| receipt.perform_state_changes(Checked)))
+    }
+
+    /// Performs a list of checks on the receipt, leaving it in the
+    /// [`Checking`] state so that more checks can be performed afterwards
+    ///
+    /// Returns `Err` on a retryable error, and `Ok` with a
+    /// [`ReceiptWithState<Failed>`] if a check failed.
+    pub async fn perform_checks_or_fail(
+        self,
+        ctx: &Context,
+        checks: &[ReceiptCheck<Rcpt>],
+    ) -> Result<ResultReceipt<Checking, Rcpt>, String> {
+        match self.run_checks(ctx, checks).await {
+            Err((_, ReceiptError::RetryableCheck(e))) => Err(e),
+            Err((check, e)) => Ok(Err(self.perform_state_error(check, e))),
+            Ok(_) => Ok(Ok(self)),
         }
     }
+
+    /// Same as [`ReceiptWithState::perform_checks_or_fail`] on each of
+    /// `receipts`, running each check on all the receipts that passed the
+    /// previous ones at once, see [`crate::checks::Check::check_all`].
+    ///
+    /// The results are in the same order as `receipts`, and a failed receipt
+    /// reports the first check it failed in the order of `checks`, as if the
+    /// receipts were checked one by one.
+    pub async fn perform_checks_or_fail_batch(
+        ctx: &Context,
+        mut receipts: Vec<Self>,
+        checks: &[ReceiptCheck<Rcpt>],
+    ) -> Result<Vec<ResultReceipt<Checking, Rcpt>>, String>
+    where
+        Rcpt: Sync,
+    {
+        let mut results: Vec<Option<ResultReceipt<Checking, Rcpt>>> =
+            receipts.iter().map(|_| None).collect();
+        let mut indexes: Vec<usize> = (0..receipts.len()).collect();
+
+        for (check_index, check) in checks.iter().enumerate() {
+            let check_results = check.check_all(ctx, &receipts).await;
+            let mut passed_indexes = Vec::with_capacity(receipts.len());
+            let mut passed_receipts = Vec::with_capacity(receipts.len());
+            for ((index, receipt), result) in indexes.into_iter().zip(receipts).zip(check_results) {
+                match result.map_err(check_error_to_receipt_error) {
+                    Ok(_) => {
+                        passed_indexes.push(index);
+                        passed_receipts.push(receipt);
+                    }
+                    Err(ReceiptError::RetryableCheck(e)) => return Err(e),
+                    Err(e) => {
+                        receipt.revert_checks(ctx, &checks[..check_index]);
+                        results[index] =
+                            Some(Err(receipt.perform_state_error(check.typetag_name(), e)))
+                    }
+                }
+            }
+            indexes = passed_indexes;
+            receipts = passed_receipts;
+        }
+
+        for (index, receipt) in indexes.into_iter().zip(receipts) {
+            results[index] = Some(Ok(receipt));
+        }
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("every receipt has a result"))
+            .collect())
+    }
+}
+
+impl<Rcpt> ReceiptWithState<PendingEscrow, Rcpt> {
+    /// Returns the [`ReceiptError::InsufficientEscrow`] the receipt was held
+    /// for
+    pub fn error(&self) -> &ReceiptError {
+        &self._state.error
+    }
+
+    /// Name of the check the receipt was held by
+    pub fn check(&self) -> &'static str {
+        self._state.check
+    }
+
+    /// Warnings raised by the checks the receipt passed before being held
+    pub fn warnings(&self) -> &[CheckWarning] {
+        &self._state.warnings
+    }
+
+    /// Moves the receipt back to the [`Checking`] state, to check it again
+    /// once the escrow of its sender is topped up
+    pub fn retry(self) -> ReceiptWithState<Checking, Rcpt> {
+        self.perform_state_changes(Checking)
+    }
 }
 
 impl<Rcpt> ReceiptWithState<Failed, Rcpt> {
     pub fn error(self) -> ReceiptError {
         self._state.error
     }
+
+    /// Returns the names of the checks failed by the receipt, see
+    /// [`crate::checks::Check::typetag_name`].
+    ///
+    /// Checks stop at the first failure, so a single check is reported.
+    pub fn failed_checks(&self) -> Vec<&'static str> {
+        vec![self._state.check]
+    }
+
+    /// Returns the errors of the checks failed by the receipt, in the same
+    /// order as [`ReceiptWithState::failed_checks`].
+    pub fn errors(&self) -> Vec<&ReceiptError> {
+        vec![&self._state.error]
+    }
 }
 
 impl<S, Rcpt> ReceiptWithState<S, Rcpt>
 where
     S: ReceiptState,
 {
-    pub(super) fn perform_state_error(self, error: ReceiptError) -> ReceiptWithState<Failed, Rcpt> {
+    pub(super) fn perform_state_error(
+        self,
+        check: &'static str,
+        error: ReceiptError,
+    ) -> ReceiptWithState<Failed, Rcpt> {
         ReceiptWithState {
             receipt: self.receipt,
-            _state: Failed { error },
+            _state: Failed { error, check },
         }
     }
 
@@ -131,3 +302,10 @@ where
         &self.receipt
     }
 }
+
+pub(crate) fn check_error_to_receipt_error(error: CheckError) -> ReceiptError {
+    match error {
+        CheckError::Retryable(e) => ReceiptError::RetryableCheck(e.to_string()),
+        CheckError::Failed(e) => ReceiptError::CheckFailure(e.to_string()),
+    }
+}
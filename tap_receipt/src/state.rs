@@ -7,7 +7,7 @@
 //! state of a receipt.
 //! The `ReceiptState` trait represents the different states a receipt can be in.
 
-use crate::ReceiptError;
+use crate::{checks::CheckWarning, ReceiptError};
 
 /// Checking state represents a receipt that is currently being checked.
 #[derive(Debug, Clone)]
@@ -16,17 +16,33 @@ pub struct Checking;
 /// Failed state represents a receipt that has failed a check or validation.
 #[derive(Debug, Clone)]
 pub struct Failed {
-    /// A list of checks to be completed for the receipt, along with their
-    /// current result
+    /// Error returned by the failing check
     pub error: ReceiptError,
+    /// Name of the failing check, see [`crate::checks::Check::typetag_name`]
+    pub check: &'static str,
 }
 
 /// Reserved state represents a receipt that has successfully reserved escrow.
 #[derive(Debug, Clone)]
 pub struct Checked;
 
+/// PendingEscrow state represents a receipt held until the escrow of its
+/// sender is topped up, instead of failing.
+#[derive(Debug, Clone)]
+pub struct PendingEscrow {
+    /// [`ReceiptError::InsufficientEscrow`] returned when the receipt was
+    /// last checked
+    pub error: ReceiptError,
+    /// Name of the check the receipt failed, see
+    /// [`crate::checks::Check::typetag_name`]. The checks are resumed from it.
+    pub check: &'static str,
+    /// Warnings raised by the checks the receipt passed before being held
+    pub warnings: Vec<CheckWarning>,
+}
+
 /// Trait for the different states a receipt can be in.
 pub trait ReceiptState {}
 impl ReceiptState for Checking {}
 impl ReceiptState for Checked {}
 impl ReceiptState for Failed {}
+impl ReceiptState for PendingEscrow {}